@@ -6,6 +6,7 @@ use std::ops::{Deref, DerefMut};
 
 use crate::actor_id::{to_actor_id, AMactorId};
 use crate::byte_span::{to_str, AMbyteSpan};
+use crate::cursor::{to_cursor, AMcursor};
 use crate::items::AMitems;
 use crate::obj::{to_obj_id, AMobjId, AMobjType};
 use crate::result::{to_result, AMresult};
@@ -163,6 +164,74 @@ pub unsafe extern "C" fn AMcommit(
     to_result(doc.commit_with(options))
 }
 
+/// \memberof AMdoc
+/// \brief Gets the patches that transform a document from one historical
+///        point into another.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \param[in] before A pointer to an `AMitems` struct with
+///                   `AM_VAL_TYPE_CHANGE_HASH` items to select the starting
+///                   historical point or `NULL` to select the empty document.
+/// \param[in] after A pointer to an `AMitems` struct with
+///                  `AM_VAL_TYPE_CHANGE_HASH` items to select the ending
+///                  historical point or `NULL` to select its current point.
+/// \return A pointer to an `AMresult` struct with `AM_VAL_TYPE_PATCH` items.
+/// \pre \p doc `!= NULL`
+/// \note \p before and \p after don't have to be in chronological order.
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+/// before must be a valid pointer to an AMitems or std::ptr::null()
+/// after must be a valid pointer to an AMitems or std::ptr::null()
+#[no_mangle]
+pub unsafe extern "C" fn AMdiff(
+    doc: *mut AMdoc,
+    before: *const AMitems,
+    after: *const AMitems,
+) -> *mut AMresult {
+    let doc = to_doc_mut!(doc);
+    let before = match before.as_ref() {
+        None => Vec::<am::ChangeHash>::new(),
+        Some(before) => match <Vec<am::ChangeHash>>::try_from(before) {
+            Ok(before) => before,
+            Err(e) => return AMresult::error(&e.to_string()).into(),
+        },
+    };
+    let after = match after.as_ref() {
+        None => doc.get_heads(),
+        Some(after) => match <Vec<am::ChangeHash>>::try_from(after) {
+            Ok(after) => after,
+            Err(e) => return AMresult::error(&e.to_string()).into(),
+        },
+    };
+    to_result(doc.diff(&before, &after))
+}
+
+/// \memberof AMdoc
+/// \brief Gets the patches that transform a document from its diff cursor
+///        into its current point and then moves its diff cursor to its
+///        current point.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \return A pointer to an `AMresult` struct with `AM_VAL_TYPE_PATCH` items.
+/// \pre \p doc `!= NULL`
+/// \note The first call returns the patches that transform the empty document
+///       into its current point.
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+#[no_mangle]
+pub unsafe extern "C" fn AMdiffIncremental(doc: *mut AMdoc) -> *mut AMresult {
+    let doc = to_doc_mut!(doc);
+    to_result(doc.diff_incremental())
+}
+
 /// \memberof AMdoc
 /// \brief Creates an empty change with an optional message and/or *nix
 ///        timestamp (milliseconds).
@@ -386,6 +455,87 @@ pub unsafe extern "C" fn AMgetChangesAdded(doc1: *mut AMdoc, doc2: *mut AMdoc) -
     to_result(doc1.get_changes_added(doc2))
 }
 
+/// \memberof AMdoc
+/// \brief Gets a cursor for a position within a sequence object at its current
+///        or a historical point.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \param[in] obj_id A pointer to an `AMobjId` struct or `AM_ROOT`.
+/// \param[in] position The position of an element within the sequence object
+///                     identified by \p obj_id.
+/// \param[in] heads A pointer to an `AMitems` struct with `AM_VAL_TYPE_CHANGE_HASH`
+///                  items to select a historical position or `NULL` to select
+///                  the current position.
+/// \return A pointer to an `AMresult` struct with an `AM_VAL_TYPE_CURSOR` item.
+/// \pre \p doc `!= NULL`
+/// \pre \p position `< AMobjSize(`\p obj_id `, `\p heads `)`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+/// obj_id must be a valid pointer to an AMobjId or std::ptr::null()
+/// heads must be a valid pointer to an AMitems or std::ptr::null()
+#[no_mangle]
+pub unsafe extern "C" fn AMgetCursor(
+    doc: *const AMdoc,
+    obj_id: *const AMobjId,
+    position: usize,
+    heads: *const AMitems,
+) -> *mut AMresult {
+    let doc = to_doc!(doc);
+    let obj_id = to_obj_id!(obj_id);
+    match heads.as_ref() {
+        None => to_result(doc.get_cursor(obj_id, position, None)),
+        Some(heads) => match <Vec<am::ChangeHash>>::try_from(heads) {
+            Ok(heads) => to_result(doc.get_cursor(obj_id, position, Some(&heads))),
+            Err(e) => AMresult::error(&e.to_string()).into(),
+        },
+    }
+}
+
+/// \memberof AMdoc
+/// \brief Gets the current or historical position of a cursor within a
+///        sequence object.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \param[in] obj_id A pointer to an `AMobjId` struct or `AM_ROOT`.
+/// \param[in] cursor A pointer to an `AMcursor` struct.
+/// \param[in] heads A pointer to an `AMitems` struct with `AM_VAL_TYPE_CHANGE_HASH`
+///                  items to select a historical position or `NULL` to select
+///                  the current position.
+/// \return A pointer to an `AMresult` struct with an `AM_VAL_TYPE_UINT` item.
+/// \pre \p doc `!= NULL`
+/// \pre \p cursor `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+/// obj_id must be a valid pointer to an AMobjId or std::ptr::null()
+/// cursor must be a valid pointer to an AMcursor
+/// heads must be a valid pointer to an AMitems or std::ptr::null()
+#[no_mangle]
+pub unsafe extern "C" fn AMgetCursorPosition(
+    doc: *const AMdoc,
+    obj_id: *const AMobjId,
+    cursor: *const AMcursor,
+    heads: *const AMitems,
+) -> *mut AMresult {
+    let doc = to_doc!(doc);
+    let obj_id = to_obj_id!(obj_id);
+    let cursor = to_cursor!(cursor);
+    match heads.as_ref() {
+        None => to_result(doc.get_cursor_position(obj_id, cursor.as_ref(), None)),
+        Some(heads) => match <Vec<am::ChangeHash>>::try_from(heads) {
+            Ok(heads) => to_result(doc.get_cursor_position(obj_id, cursor.as_ref(), Some(&heads))),
+            Err(e) => AMresult::error(&e.to_string()).into(),
+        },
+    }
+}
+
 /// \memberof AMdoc
 /// \brief Gets the current heads of a document.
 ///
@@ -456,6 +606,65 @@ pub unsafe extern "C" fn AMgetLastLocalChange(doc: *mut AMdoc) -> *mut AMresult
     to_result(doc.get_last_local_change())
 }
 
+/// \memberof AMdoc
+/// \brief Ends the isolation of a document so that it reflects all of its
+///        changes again.
+///
+/// \details Any changes made while the document was isolated are retained and
+///          their operations are merged with those that were hidden by the
+///          isolation.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \return A pointer to an `AMresult` struct with an `AM_VAL_TYPE_VOID` item.
+/// \pre \p doc `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+#[no_mangle]
+pub unsafe extern "C" fn AMintegrate(doc: *mut AMdoc) -> *mut AMresult {
+    let doc = to_doc_mut!(doc);
+    doc.integrate();
+    to_result(Ok(()))
+}
+
+/// \memberof AMdoc
+/// \brief Isolates a document at its current or a historical point so that
+///        it ignores any changes that are applied to it afterwards.
+///
+/// \details Reads from and changes made to an isolated document are based
+///          upon the point at which it was isolated until `AMintegrate()` is
+///          called.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \param[in] heads A pointer to an `AMitems` struct with `AM_VAL_TYPE_CHANGE_HASH`
+///                  items to select a historical point or `NULL` to select its
+///                  current point.
+/// \return A pointer to an `AMresult` struct with an `AM_VAL_TYPE_VOID` item.
+/// \pre \p doc `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+/// heads must be a valid pointer to an AMitems or std::ptr::null()
+#[no_mangle]
+pub unsafe extern "C" fn AMisolate(doc: *mut AMdoc, heads: *const AMitems) -> *mut AMresult {
+    let doc = to_doc_mut!(doc);
+    let heads = match heads.as_ref() {
+        None => doc.get_heads(),
+        Some(heads) => match <Vec<am::ChangeHash>>::try_from(heads) {
+            Ok(heads) => heads,
+            Err(e) => return AMresult::error(&e.to_string()).into(),
+        },
+    };
+    doc.isolate(&heads);
+    to_result(Ok(()))
+}
+
 /// \memberof AMdoc
 /// \brief Gets the current or historical keys of a map object.
 ///
@@ -716,6 +925,26 @@ pub unsafe extern "C" fn AMreceiveSyncMessage(
     )
 }
 
+/// \memberof AMdoc
+/// \brief Erases the diff cursor of a document so that its changes are no
+///        longer indexed.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \return A pointer to an `AMresult` struct with an `AM_VAL_TYPE_VOID` item.
+/// \pre \p doc `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+#[no_mangle]
+pub unsafe extern "C" fn AMresetDiffCursor(doc: *mut AMdoc) -> *mut AMresult {
+    let doc = to_doc_mut!(doc);
+    doc.reset_diff_cursor();
+    to_result(Ok(()))
+}
+
 /// \memberof AMdoc
 /// \brief Cancels the pending operations added during a document's current
 ///        transaction and gets the number of cancellations.
@@ -921,3 +1150,28 @@ pub unsafe extern "C" fn AMtext(
         },
     }
 }
+
+/// \memberof AMdoc
+/// \brief Moves the diff cursor of a document to its current point and indexes
+///        its changes from then on.
+///
+/// \details Calling `AMdiff()` with the heads at the diff cursor as \p before
+///          and `NULL` as \p after or calling `AMdiffIncremental()` will
+///          then use the index instead of scanning all of the operations in
+///          the document.
+///
+/// \param[in] doc A pointer to an `AMdoc` struct.
+/// \return A pointer to an `AMresult` struct with an `AM_VAL_TYPE_VOID` item.
+/// \pre \p doc `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// doc must be a valid pointer to an AMdoc
+#[no_mangle]
+pub unsafe extern "C" fn AMupdateDiffCursor(doc: *mut AMdoc) -> *mut AMresult {
+    let doc = to_doc_mut!(doc);
+    doc.update_diff_cursor();
+    to_result(Ok(()))
+}
@@ -17,6 +17,15 @@ pub enum AMindex {
     Pos(usize),
 }
 
+impl From<&am::Prop> for AMindex {
+    fn from(prop: &am::Prop) -> Self {
+        match prop {
+            am::Prop::Map(key) => Self::Key(key.into()),
+            am::Prop::Seq(pos) => Self::Pos(*pos),
+        }
+    }
+}
+
 impl TryFrom<&AMindex> for AMbyteSpan {
     type Error = am::AutomergeError;
 
@@ -9,10 +9,12 @@ use std::rc::Rc;
 use crate::actor_id::AMactorId;
 use crate::byte_span::{to_str, AMbyteSpan};
 use crate::change::AMchange;
+use crate::cursor::AMcursor;
 use crate::doc::mark::AMmark;
 use crate::doc::AMdoc;
 use crate::index::{AMidxType, AMindex};
 use crate::obj::AMobjId;
+use crate::patch::AMpatch;
 use crate::result::{to_result, AMresult};
 use crate::sync::{AMsyncHave, AMsyncMessage, AMsyncState};
 
@@ -32,8 +34,10 @@ pub enum Value {
     ActorId(am::ActorId, UnsafeCell<Option<AMactorId>>),
     Change(Box<am::Change>, UnsafeCell<Option<AMchange>>),
     ChangeHash(am::ChangeHash),
+    Cursor(AMcursor),
     Doc(RefCell<AMdoc>),
     Mark(AMmark<'static>),
+    Patch(AMpatch),
     SyncHave(AMsyncHave),
     SyncMessage(AMsyncMessage),
     SyncState(RefCell<AMsyncState>),
@@ -164,6 +168,18 @@ impl From<&am::ScalarValue> for Value {
     }
 }
 
+impl From<am::Cursor> for Value {
+    fn from(cursor: am::Cursor) -> Self {
+        Self::Cursor(AMcursor::new(cursor))
+    }
+}
+
+impl From<am::Patch> for Value {
+    fn from(patch: am::Patch) -> Self {
+        Self::Patch(AMpatch::new(patch))
+    }
+}
+
 impl From<am::sync::Have> for Value {
     fn from(have: am::sync::Have) -> Self {
         Self::SyncHave(AMsyncHave::new(have))
@@ -309,6 +325,40 @@ impl<'a> TryFrom<&'a mut Value> for &'a mut AMdoc {
     }
 }
 
+impl<'a> TryFrom<&'a Value> for &'a AMcursor {
+    type Error = am::AutomergeError;
+
+    fn try_from(value: &'a Value) -> Result<Self, Self::Error> {
+        use self::Value::*;
+        use am::AutomergeError::InvalidValueType;
+
+        match value {
+            Cursor(cursor) => Ok(cursor),
+            _ => Err(InvalidValueType {
+                expected: type_name::<Self>().to_string(),
+                unexpected: type_name::<self::Value>().to_string(),
+            }),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Value> for &'a AMpatch {
+    type Error = am::AutomergeError;
+
+    fn try_from(value: &'a Value) -> Result<Self, Self::Error> {
+        use self::Value::*;
+        use am::AutomergeError::InvalidValueType;
+
+        match value {
+            Patch(patch) => Ok(patch),
+            _ => Err(InvalidValueType {
+                expected: type_name::<Self>().to_string(),
+                unexpected: type_name::<self::Value>().to_string(),
+            }),
+        }
+    }
+}
+
 impl<'a> TryFrom<&'a Value> for &'a AMsyncHave {
     type Error = am::AutomergeError;
 
@@ -472,7 +522,9 @@ impl PartialEq for Value {
             (ActorId(lhs, _), ActorId(rhs, _)) => *lhs == *rhs,
             (Change(lhs, _), Change(rhs, _)) => lhs == rhs,
             (ChangeHash(lhs), ChangeHash(rhs)) => lhs == rhs,
+            (Cursor(lhs), Cursor(rhs)) => *lhs == *rhs,
             (Doc(lhs), Doc(rhs)) => lhs.as_ptr() == rhs.as_ptr(),
+            (Patch(lhs), Patch(rhs)) => *lhs == *rhs,
             (SyncMessage(lhs), SyncMessage(rhs)) => *lhs == *rhs,
             (SyncState(lhs), SyncState(rhs)) => *lhs == *rhs,
             (Value(lhs), Value(rhs)) => lhs == rhs,
@@ -605,6 +657,18 @@ impl From<&am::ScalarValue> for Item {
     }
 }
 
+impl From<am::Cursor> for Item {
+    fn from(cursor: am::Cursor) -> Self {
+        Value::from(cursor).into()
+    }
+}
+
+impl From<am::Patch> for Item {
+    fn from(patch: am::Patch) -> Self {
+        Value::from(patch).into()
+    }
+}
+
 impl From<am::sync::Have> for Item {
     fn from(have: am::sync::Have) -> Self {
         Value::from(have).into()
@@ -768,6 +832,40 @@ impl From<&Item> for AMidxType {
     }
 }
 
+impl<'a> TryFrom<&'a Item> for &'a AMcursor {
+    type Error = am::AutomergeError;
+
+    fn try_from(item: &'a Item) -> Result<Self, Self::Error> {
+        use am::AutomergeError::InvalidValueType;
+
+        if let Some(value) = &item.value {
+            value.try_into()
+        } else {
+            Err(InvalidValueType {
+                expected: type_name::<Self>().to_string(),
+                unexpected: type_name::<Option<Value>>().to_string(),
+            })
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Item> for &'a AMpatch {
+    type Error = am::AutomergeError;
+
+    fn try_from(item: &'a Item) -> Result<Self, Self::Error> {
+        use am::AutomergeError::InvalidValueType;
+
+        if let Some(value) = &item.value {
+            value.try_into()
+        } else {
+            Err(InvalidValueType {
+                expected: type_name::<Self>().to_string(),
+                unexpected: type_name::<Option<Value>>().to_string(),
+            })
+        }
+    }
+}
+
 impl<'a> TryFrom<&'a Item> for &'a AMsyncHave {
     type Error = am::AutomergeError;
 
@@ -932,6 +1030,10 @@ impl TryFrom<&Item> for (am::Value<'static>, am::ObjId) {
                     expected,
                     unexpected: type_name::<AMchange>().to_string(),
                 }),
+                Cursor(_) => Err(InvalidValueType {
+                    expected,
+                    unexpected: type_name::<AMcursor>().to_string(),
+                }),
                 Doc(_) => Err(InvalidValueType {
                     expected,
                     unexpected: type_name::<AMdoc>().to_string(),
@@ -940,6 +1042,10 @@ impl TryFrom<&Item> for (am::Value<'static>, am::ObjId) {
                     expected,
                     unexpected: type_name::<AMmark>().to_string(),
                 }),
+                Patch(_) => Err(InvalidValueType {
+                    expected,
+                    unexpected: type_name::<AMpatch>().to_string(),
+                }),
                 SyncHave(_) => Err(InvalidValueType {
                     expected,
                     unexpected: type_name::<AMsyncHave>().to_string(),
@@ -980,6 +1086,14 @@ impl AMitem {
             value: Some(value),
         }))
     }
+
+    pub fn located(index: AMindex, obj_id: am::ObjId) -> Self {
+        Self(Rc::new(Item {
+            index: Some(index),
+            obj_id: Some(AMobjId::new(obj_id)),
+            value: None,
+        }))
+    }
 }
 
 impl AsRef<Item> for AMitem {
@@ -1034,6 +1148,18 @@ impl From<&am::ScalarValue> for AMitem {
     }
 }
 
+impl From<am::Cursor> for AMitem {
+    fn from(cursor: am::Cursor) -> Self {
+        Value::from(cursor).into()
+    }
+}
+
+impl From<am::Patch> for AMitem {
+    fn from(patch: am::Patch) -> Self {
+        Value::from(patch).into()
+    }
+}
+
 impl From<am::sync::Have> for AMitem {
     fn from(have: am::sync::Have) -> Self {
         Value::from(have).into()
@@ -1146,6 +1272,22 @@ impl<'a> TryFrom<&'a AMitem> for &'a AMmark<'a> {
     }
 }
 
+impl<'a> TryFrom<&'a AMitem> for &'a AMcursor {
+    type Error = am::AutomergeError;
+
+    fn try_from(item: &'a AMitem) -> Result<Self, Self::Error> {
+        item.as_ref().try_into()
+    }
+}
+
+impl<'a> TryFrom<&'a AMitem> for &'a AMpatch {
+    type Error = am::AutomergeError;
+
+    fn try_from(item: &'a AMitem) -> Result<Self, Self::Error> {
+        item.as_ref().try_into()
+    }
+}
+
 impl<'a> TryFrom<&'a AMitem> for &'a AMsyncHave {
     type Error = am::AutomergeError;
 
@@ -1233,34 +1375,38 @@ pub enum AMvalType {
     ChangeHash = 1 << 5,
     /// A CRDT counter value.
     Counter = 1 << 6,
+    /// A cursor value.
+    Cursor = 1 << 7,
     /// The default tag, not a type signifier.
     Default = 0,
     /// A document value.
-    Doc = 1 << 7,
+    Doc = 1 << 8,
     /// A 64-bit float value.
-    F64 = 1 << 8,
+    F64 = 1 << 9,
     /// A 64-bit signed integer value.
-    Int = 1 << 9,
+    Int = 1 << 10,
     /// A mark.
-    Mark = 1 << 10,
+    Mark = 1 << 11,
     /// A null value.
-    Null = 1 << 11,
+    Null = 1 << 12,
     /// An object type value.
-    ObjType = 1 << 12,
+    ObjType = 1 << 13,
+    /// A patch value.
+    Patch = 1 << 14,
     /// A UTF-8 string view value.
-    Str = 1 << 13,
+    Str = 1 << 15,
     /// A synchronization have value.
-    SyncHave = 1 << 14,
+    SyncHave = 1 << 16,
     /// A synchronization message value.
-    SyncMessage = 1 << 15,
+    SyncMessage = 1 << 17,
     /// A synchronization state value.
-    SyncState = 1 << 16,
+    SyncState = 1 << 18,
     /// A *nix timestamp (milliseconds) value.
-    Timestamp = 1 << 17,
+    Timestamp = 1 << 19,
     /// A 64-bit unsigned integer value.
-    Uint = 1 << 18,
+    Uint = 1 << 20,
     /// An unknown type of value.
-    Unknown = 1 << 19,
+    Unknown = 1 << 21,
     /// A void.
     Void = 1 << 0,
 }
@@ -1302,8 +1448,10 @@ impl From<&Value> for AMvalType {
             ActorId(_, _) => Self::ActorId,
             Change(_, _) => Self::Change,
             ChangeHash(_) => Self::ChangeHash,
+            Cursor(_) => Self::Cursor,
             Doc(_) => Self::Doc,
             Mark(_) => Self::Mark,
+            Patch(_) => Self::Patch,
             SyncHave(_) => Self::SyncHave,
             SyncMessage(_) => Self::SyncMessage,
             SyncState(_) => Self::SyncState,
@@ -1772,6 +1920,31 @@ pub unsafe extern "C" fn AMitemToCounter(item: *const AMitem, value: *mut i64) -
     false
 }
 
+/// \memberof AMitem
+/// \brief Gets the cursor value of an item.
+///
+/// \param[in] item A pointer to an `AMitem` struct.
+/// \param[out] value A pointer to an `AMcursor` struct pointer.
+/// \return `true` if `AMitemValType(`\p item `) == AM_VAL_TYPE_CURSOR` and
+///         \p *value has been reassigned, `false` otherwise.
+/// \pre \p item `!= NULL`
+/// \internal
+///
+/// # Safety
+/// item must be a valid pointer to an AMitem
+#[no_mangle]
+pub unsafe extern "C" fn AMitemToCursor(item: *const AMitem, value: *mut *const AMcursor) -> bool {
+    if let Some(item) = item.as_ref() {
+        if let Ok(cursor) = <&AMcursor>::try_from(item) {
+            if !value.is_null() {
+                *value = cursor;
+                return true;
+            }
+        }
+    }
+    false
+}
+
 /// \memberof AMitem
 /// \brief Gets the document value of an item.
 ///
@@ -1875,6 +2048,31 @@ pub unsafe extern "C" fn AMitemToMark<'a>(
     false
 }
 
+/// \memberof AMitem
+/// \brief Gets the patch value of an item.
+///
+/// \param[in] item A pointer to an `AMitem` struct.
+/// \param[out] value A pointer to an `AMpatch` struct pointer.
+/// \return `true` if `AMitemValType(`\p item `) == AM_VAL_TYPE_PATCH` and
+///         \p *value has been reassigned, `false` otherwise.
+/// \pre \p item `!= NULL`
+/// \internal
+///
+/// # Safety
+/// item must be a valid pointer to an AMitem
+#[no_mangle]
+pub unsafe extern "C" fn AMitemToPatch(item: *const AMitem, value: *mut *const AMpatch) -> bool {
+    if let Some(item) = item.as_ref() {
+        if let Ok(patch) = <&AMpatch>::try_from(item) {
+            if !value.is_null() {
+                *value = patch;
+                return true;
+            }
+        }
+    }
+    false
+}
+
 /// \memberof AMitem
 /// \brief Gets the UTF-8 string view value of an item.
 ///
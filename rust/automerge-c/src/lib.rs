@@ -1,11 +1,13 @@
 mod actor_id;
 mod byte_span;
 mod change;
+mod cursor;
 mod doc;
 mod index;
 mod item;
 mod items;
 mod obj;
+mod patch;
 mod result;
 mod sync;
 
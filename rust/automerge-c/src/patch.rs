@@ -0,0 +1,402 @@
+use automerge as am;
+
+use crate::byte_span::AMbyteSpan;
+use crate::index::AMindex;
+use crate::item::AMitem;
+use crate::obj::AMobjId;
+use crate::result::{to_result, AMresult};
+
+/// \ingroup enumerations
+/// \enum AMpatchActionType
+/// \installed_headerfile
+/// \brief The type of action performed by a patch.
+#[derive(PartialEq, Eq)]
+#[repr(u8)]
+pub enum AMpatchActionType {
+    /// A new conflict has appeared at a key or position.
+    Conflict = 1,
+    /// The default tag, not a patch action type signifier.
+    Default = 0,
+    /// A key was deleted from a map.
+    DeleteMap = 2,
+    /// One or more positions were removed from a sequence.
+    DeleteSeq = 3,
+    /// A counter was incremented.
+    Increment = 4,
+    /// One or more values were inserted into a sequence.
+    Insert = 5,
+    /// Some marks within a text object were added or removed.
+    Mark = 6,
+    /// A key was created or updated in a map.
+    PutMap = 7,
+    /// A position in a sequence was updated.
+    PutSeq = 8,
+    /// Some text was spliced into a text object.
+    SpliceText = 9,
+}
+
+impl Default for AMpatchActionType {
+    fn default() -> Self {
+        Self::Default
+    }
+}
+
+impl From<&am::PatchAction> for AMpatchActionType {
+    fn from(action: &am::PatchAction) -> Self {
+        use am::PatchAction::*;
+
+        match action {
+            Conflict { .. } => Self::Conflict,
+            DeleteMap { .. } => Self::DeleteMap,
+            DeleteSeq { .. } => Self::DeleteSeq,
+            Increment { .. } => Self::Increment,
+            Insert { .. } => Self::Insert,
+            Mark { .. } => Self::Mark,
+            PutMap { .. } => Self::PutMap,
+            PutSeq { .. } => Self::PutSeq,
+            SpliceText { .. } => Self::SpliceText,
+        }
+    }
+}
+
+/// \struct AMpatch
+/// \installed_headerfile
+/// \brief A change to the current state of a document.
+#[derive(PartialEq)]
+pub struct AMpatch {
+    body: am::Patch,
+    obj_id: AMobjId,
+}
+
+impl AMpatch {
+    pub fn new(patch: am::Patch) -> Self {
+        let obj_id = AMobjId::new(patch.obj.clone());
+        Self {
+            body: patch,
+            obj_id,
+        }
+    }
+
+    fn prop(&self) -> Option<AMindex> {
+        use am::PatchAction::*;
+
+        match &self.body.action {
+            PutMap { key, .. } | DeleteMap { key } => Some(AMindex::Key(key.into())),
+            PutSeq { index, .. }
+            | Insert { index, .. }
+            | SpliceText { index, .. }
+            | DeleteSeq { index, .. } => Some(AMindex::Pos(*index)),
+            Increment { prop, .. } | Conflict { prop } => Some(prop.into()),
+            Mark { .. } => None,
+        }
+    }
+}
+
+impl AsRef<am::Patch> for AMpatch {
+    fn as_ref(&self) -> &am::Patch {
+        &self.body
+    }
+}
+
+/// \memberof AMpatch
+/// \brief Gets the type of action performed by a patch.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \return An `AMpatchActionType` enum tag.
+/// \pre \p patch `!= NULL`
+/// \post `(`\p patch `== NULL) -> 0`
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchAction(patch: *const AMpatch) -> AMpatchActionType {
+    if let Some(patch) = patch.as_ref() {
+        return (&patch.body.action).into();
+    }
+    Default::default()
+}
+
+/// \memberof AMpatch
+/// \brief Tests whether a patch reports a conflict.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \return `true` if `AMpatchAction(`\p patch `)` is `AM_PATCH_ACTION_TYPE_CONFLICT`
+///         or if it's `AM_PATCH_ACTION_TYPE_PUT_MAP` or
+///         `AM_PATCH_ACTION_TYPE_PUT_SEQ` and the value that was put is in
+///         conflict, `false` otherwise.
+/// \pre \p patch `!= NULL`
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchConflict(patch: *const AMpatch) -> bool {
+    use am::PatchAction::*;
+
+    if let Some(patch) = patch.as_ref() {
+        return match &patch.body.action {
+            PutMap { conflict, .. } | PutSeq { conflict, .. } => *conflict,
+            Conflict { .. } => true,
+            _ => false,
+        };
+    }
+    false
+}
+
+/// \memberof AMpatch
+/// \brief Gets the UTF-8 string view key within the object modified by a
+///        patch.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \param[out] value A pointer to a UTF-8 string view as an `AMbyteSpan` struct.
+/// \return `true` if \p patch modifies a key within a map object and \p *value
+///         has been reassigned, `false` otherwise.
+/// \pre \p patch `!= NULL`
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchKey(patch: *const AMpatch, value: *mut AMbyteSpan) -> bool {
+    use am::PatchAction::*;
+
+    if let Some(patch) = patch.as_ref() {
+        let key = match &patch.body.action {
+            PutMap { key, .. } | DeleteMap { key } => Some(key),
+            Increment {
+                prop: am::Prop::Map(key),
+                ..
+            }
+            | Conflict {
+                prop: am::Prop::Map(key),
+            } => Some(key),
+            _ => None,
+        };
+        if let Some(key) = key {
+            if !value.is_null() {
+                *value = key.as_bytes().into();
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// \memberof AMpatch
+/// \brief Gets the count of positions affected by a patch.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \return The count of positions removed by an `AM_PATCH_ACTION_TYPE_DELETE_SEQ`
+///         patch, inserted by an `AM_PATCH_ACTION_TYPE_INSERT` or
+///         `AM_PATCH_ACTION_TYPE_SPLICE_TEXT` patch, `1` for an
+///         `AM_PATCH_ACTION_TYPE_PUT_SEQ` patch or `0` otherwise.
+///         For an `AM_PATCH_ACTION_TYPE_SPLICE_TEXT` patch, if
+///         `AUTOMERGE_C_UTF8` is defined then the positions are bytes but if
+///         `AUTOMERGE_C_UTF32` is defined then the positions are Unicode code
+///         points.
+/// \pre \p patch `!= NULL`
+/// \post `(`\p patch `== NULL) -> 0`
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchLength(patch: *const AMpatch) -> usize {
+    use am::PatchAction::*;
+
+    if let Some(patch) = patch.as_ref() {
+        return match &patch.body.action {
+            DeleteSeq { length, .. } => *length,
+            Insert { values, .. } => values.len(),
+            SpliceText { value, .. } => value.len(),
+            PutSeq { .. } => 1,
+            _ => 0,
+        };
+    }
+    0
+}
+
+/// \memberof AMpatch
+/// \brief Gets the marks added or removed by a patch or the marks that are
+///        active for the values that it inserted.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \return A pointer to an `AMresult` struct with `AM_VAL_TYPE_MARK` items.
+/// \pre \p patch `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchMarks(patch: *const AMpatch) -> *mut AMresult {
+    use am::PatchAction::*;
+
+    let patch = match patch.as_ref() {
+        Some(patch) => patch,
+        None => return AMresult::error("Invalid `AMpatch*`").into(),
+    };
+    let marks: Vec<am::marks::Mark<'static>> = match &patch.body.action {
+        Mark { marks } => marks.clone(),
+        Insert {
+            index,
+            values,
+            marks: Some(mark_set),
+        } => mark_set
+            .iter()
+            .map(|(name, value)| {
+                am::marks::Mark::new(
+                    name.to_string(),
+                    value.clone(),
+                    *index,
+                    index + values.len(),
+                )
+            })
+            .collect(),
+        SpliceText {
+            index,
+            value: text,
+            marks: Some(mark_set),
+        } => mark_set
+            .iter()
+            .map(|(name, value)| {
+                am::marks::Mark::new(name.to_string(), value.clone(), *index, index + text.len())
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    to_result(Ok::<Vec<am::marks::Mark<'static>>, am::AutomergeError>(
+        marks,
+    ))
+}
+
+/// \memberof AMpatch
+/// \brief Gets the identifier of the object modified by a patch.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \return A pointer to an `AMobjId` struct.
+/// \pre \p patch `!= NULL`
+/// \post `(`\p patch `== NULL) -> NULL`
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchObjId(patch: *const AMpatch) -> *const AMobjId {
+    if let Some(patch) = patch.as_ref() {
+        return &patch.obj_id;
+    }
+    std::ptr::null()
+}
+
+/// \memberof AMpatch
+/// \brief Gets the path from the root object of a document to the object
+///        modified by a patch.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \return A pointer to an `AMresult` struct with `AM_VAL_TYPE_VOID` items whose
+///         object identifiers are those of the ancestors of the modified object
+///         and whose indices are the keys or positions within those ancestors
+///         leading to it, ordered from the root object downward.
+/// \pre \p patch `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchPath(patch: *const AMpatch) -> *mut AMresult {
+    match patch.as_ref() {
+        Some(patch) => AMresult::items(
+            patch
+                .body
+                .path
+                .iter()
+                .map(|(obj_id, prop)| AMitem::located(prop.into(), obj_id.clone()))
+                .collect(),
+        )
+        .into(),
+        None => AMresult::error("Invalid `AMpatch*`").into(),
+    }
+}
+
+/// \memberof AMpatch
+/// \brief Gets the unsigned integer position within the object modified by a
+///        patch.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \param[out] value A pointer to a `size_t`.
+/// \return `true` if \p patch modifies a position within a sequence object and
+///         \p *value has been reassigned, `false` otherwise.
+///         For a text object, if `AUTOMERGE_C_UTF8` is defined then \p *value
+///         is in units of bytes but if `AUTOMERGE_C_UTF32` is defined then
+///         \p *value is in units of Unicode code points.
+/// \pre \p patch `!= NULL`
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchPos(patch: *const AMpatch, value: *mut usize) -> bool {
+    if let Some(patch) = patch.as_ref() {
+        if let Some(AMindex::Pos(pos)) = patch.prop() {
+            if !value.is_null() {
+                *value = pos;
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// \memberof AMpatch
+/// \brief Gets the values put, inserted or spliced by a patch.
+///
+/// \param[in] patch A pointer to an `AMpatch` struct.
+/// \return A pointer to an `AMresult` struct with
+///         - one item for an `AM_PATCH_ACTION_TYPE_PUT_MAP` or
+///           `AM_PATCH_ACTION_TYPE_PUT_SEQ` patch,
+///         - one item per inserted value for an `AM_PATCH_ACTION_TYPE_INSERT`
+///           patch,
+///         - one `AM_VAL_TYPE_STR` item for an
+///           `AM_PATCH_ACTION_TYPE_SPLICE_TEXT` patch,
+///         - one `AM_VAL_TYPE_INT` item holding the (possibly negative) amount
+///           of an `AM_PATCH_ACTION_TYPE_INCREMENT` patch or
+///         - zero items otherwise.
+/// \pre \p patch `!= NULL`
+/// \warning The returned `AMresult` struct pointer must be passed to
+///          `AMresultFree()` in order to avoid a memory leak.
+/// \internal
+///
+/// # Safety
+/// patch must be a valid pointer to an AMpatch
+#[no_mangle]
+pub unsafe extern "C" fn AMpatchValues(patch: *const AMpatch) -> *mut AMresult {
+    use am::PatchAction::*;
+
+    let patch = match patch.as_ref() {
+        Some(patch) => patch,
+        None => return AMresult::error("Invalid `AMpatch*`").into(),
+    };
+    let items = match &patch.body.action {
+        PutMap {
+            value: (value, obj_id),
+            ..
+        }
+        | PutSeq {
+            value: (value, obj_id),
+            ..
+        } => vec![AMitem::exact(obj_id.clone(), value.clone().into())],
+        Insert { values, .. } => values
+            .iter()
+            .map(|(value, obj_id, _)| AMitem::exact(obj_id.clone(), value.clone().into()))
+            .collect(),
+        SpliceText { value, .. } => vec![value.make_string().into()],
+        Increment { value, .. } => vec![am::Value::int(*value).into()],
+        Conflict { .. } | DeleteMap { .. } | DeleteSeq { .. } | Mark { .. } => Vec::new(),
+    };
+    AMresult::items(items).into()
+}
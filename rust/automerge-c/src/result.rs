@@ -232,6 +232,15 @@ impl From<(Result<am::ObjId, am::AutomergeError>, am::ObjType)> for AMresult {
     }
 }
 
+impl From<Result<am::Cursor, am::AutomergeError>> for AMresult {
+    fn from(maybe: Result<am::Cursor, am::AutomergeError>) -> Self {
+        match maybe {
+            Ok(cursor) => Self::item(cursor.into()),
+            Err(e) => Self::error(&e.to_string()),
+        }
+    }
+}
+
 impl From<Result<am::sync::Message, am::sync::ReadMessageError>> for AMresult {
     fn from(maybe: Result<am::sync::Message, am::sync::ReadMessageError>) -> Self {
         match maybe {
@@ -424,6 +433,12 @@ impl From<Vec<am::ChangeHash>> for AMresult {
     }
 }
 
+impl From<Vec<am::Patch>> for AMresult {
+    fn from(patches: Vec<am::Patch>) -> Self {
+        Self::items(patches.into_iter().map(|patch| patch.into()).collect())
+    }
+}
+
 impl From<Vec<am::sync::Have>> for AMresult {
     fn from(haves: Vec<am::sync::Have>) -> Self {
         Self::items(haves.into_iter().map(|have| have.into()).collect())
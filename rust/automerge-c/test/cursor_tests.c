@@ -0,0 +1,125 @@
+#include <setjmp.h>
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <string.h>
+
+/* third-party */
+#include <cmocka.h>
+
+/* local */
+#include <automerge-c/automerge.h>
+#include <automerge-c/utils/stack_callback_data.h>
+#include "base_state.h"
+#include "cmocka_utils.h"
+#include "doc_state.h"
+
+static void test_AMgetCursor_and_AMgetCursorPosition(void** state) {
+    DocState* doc_state = *state;
+    AMstack** stack_ptr = &doc_state->base_state->stack;
+
+    AMobjId const* const text =
+        AMitemObjId(AMstackItem(stack_ptr, AMmapPutObject(doc_state->doc, AM_ROOT, AMstr("text"), AM_OBJ_TYPE_TEXT),
+                                cmocka_cb, AMexpect(AM_VAL_TYPE_OBJ_TYPE)));
+    AMstackItem(NULL, AMspliceText(doc_state->doc, text, 0, 0, AMstr("hello world")), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc_state->doc, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMitems const heads =
+        AMstackItems(stack_ptr, AMgetHeads(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    /* Point a cursor at the "w". */
+    AMcursor const* cursor;
+    assert_true(AMitemToCursor(AMstackItem(stack_ptr, AMgetCursor(doc_state->doc, text, 6, NULL), cmocka_cb,
+                                           AMexpect(AM_VAL_TYPE_CURSOR)),
+                               &cursor));
+    uint64_t position;
+    assert_true(AMitemToUint(AMstackItem(stack_ptr, AMgetCursorPosition(doc_state->doc, text, cursor, NULL),
+                                         cmocka_cb, AMexpect(AM_VAL_TYPE_UINT)),
+                             &position));
+    assert_int_equal(position, 6);
+    /* The cursor follows the "w" as text is inserted before it. */
+    AMstackItem(NULL, AMspliceText(doc_state->doc, text, 0, 0, AMstr("big ")), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    assert_true(AMitemToUint(AMstackItem(stack_ptr, AMgetCursorPosition(doc_state->doc, text, cursor, NULL),
+                                         cmocka_cb, AMexpect(AM_VAL_TYPE_UINT)),
+                             &position));
+    assert_int_equal(position, 10);
+    /* The cursor's historical position is unaffected. */
+    assert_true(AMitemToUint(AMstackItem(stack_ptr, AMgetCursorPosition(doc_state->doc, text, cursor, &heads),
+                                         cmocka_cb, AMexpect(AM_VAL_TYPE_UINT)),
+                             &position));
+    assert_int_equal(position, 6);
+    /* A cursor obtained from a historical point is the same cursor. */
+    AMcursor const* historical_cursor;
+    assert_true(AMitemToCursor(AMstackItem(stack_ptr, AMgetCursor(doc_state->doc, text, 6, &heads), cmocka_cb,
+                                           AMexpect(AM_VAL_TYPE_CURSOR)),
+                               &historical_cursor));
+    assert_true(AMcursorEqual(cursor, historical_cursor));
+    /* An out-of-bounds position has no cursor. */
+    AMresult* result = AMstackResult(stack_ptr, AMgetCursor(doc_state->doc, text, 100, NULL), NULL, NULL);
+    if (AMresultStatus(result) == AM_STATUS_OK) {
+        fail_msg("AMcursor from an out-of-bounds position.");
+    }
+}
+
+static void test_AMcursor_round_trip(void** state) {
+    DocState* doc_state = *state;
+    AMstack** stack_ptr = &doc_state->base_state->stack;
+
+    AMobjId const* const list =
+        AMitemObjId(AMstackItem(stack_ptr, AMmapPutObject(doc_state->doc, AM_ROOT, AMstr("list"), AM_OBJ_TYPE_LIST),
+                                cmocka_cb, AMexpect(AM_VAL_TYPE_OBJ_TYPE)));
+    AMstackItem(NULL, AMlistPutStr(doc_state->doc, list, SIZE_MAX, true, AMstr("a")), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMlistPutStr(doc_state->doc, list, SIZE_MAX, true, AMstr("b")), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMcursor const* cursor;
+    assert_true(AMitemToCursor(AMstackItem(stack_ptr, AMgetCursor(doc_state->doc, list, 1, NULL), cmocka_cb,
+                                           AMexpect(AM_VAL_TYPE_CURSOR)),
+                               &cursor));
+    /* Through a string. */
+    AMbyteSpan const str = AMcursorStr(cursor);
+    assert_true(str.count > 0);
+    AMcursor const* str_cursor;
+    assert_true(AMitemToCursor(
+        AMstackItem(stack_ptr, AMcursorFromStr(str), cmocka_cb, AMexpect(AM_VAL_TYPE_CURSOR)), &str_cursor));
+    assert_true(AMcursorEqual(cursor, str_cursor));
+    AMbyteSpan const str_str = AMcursorStr(str_cursor);
+    assert_int_equal(str_str.count, str.count);
+    assert_memory_equal(str_str.src, str.src, str.count);
+    /* Through an array of bytes. */
+    AMbyteSpan const bytes = AMcursorBytes(cursor);
+    assert_true(bytes.count > 0);
+    AMcursor const* bytes_cursor;
+    assert_true(AMitemToCursor(AMstackItem(stack_ptr, AMcursorFromBytes(bytes.src, bytes.count), cmocka_cb,
+                                           AMexpect(AM_VAL_TYPE_CURSOR)),
+                               &bytes_cursor));
+    assert_true(AMcursorEqual(cursor, bytes_cursor));
+    /* A deserialized cursor still refers to the same element. */
+    uint64_t position;
+    assert_true(AMitemToUint(AMstackItem(stack_ptr, AMgetCursorPosition(doc_state->doc, list, bytes_cursor, NULL),
+                                         cmocka_cb, AMexpect(AM_VAL_TYPE_UINT)),
+                             &position));
+    assert_int_equal(position, 1);
+    /* Malformed input. */
+    AMresult* result = AMstackResult(stack_ptr, AMcursorFromStr(AMstr("not a cursor")), NULL, NULL);
+    if (AMresultStatus(result) == AM_STATUS_OK) {
+        fail_msg("AMcursor from a malformed string.");
+    }
+    result = AMstackResult(stack_ptr, AMcursorFromBytes(bytes.src, 1), NULL, NULL);
+    if (AMresultStatus(result) == AM_STATUS_OK) {
+        fail_msg("AMcursor from a truncated array of bytes.");
+    }
+    result = AMstackResult(stack_ptr, AMcursorFromBytes(NULL, bytes.count), NULL, NULL);
+    if (AMresultStatus(result) == AM_STATUS_OK) {
+        fail_msg("AMcursor from NULL.");
+    }
+}
+
+int run_cursor_tests(void) {
+    struct CMUnitTest const tests[] = {
+        cmocka_unit_test_setup_teardown(test_AMgetCursor_and_AMgetCursorPosition, setup_doc, teardown_doc),
+        cmocka_unit_test_setup_teardown(test_AMcursor_round_trip, setup_doc, teardown_doc),
+    };
+
+    return cmocka_run_group_tests(tests, NULL, NULL);
+}
@@ -43,6 +43,42 @@ static int teardown(void** state) {
     return 0;
 }
 
+static void test_AMisolate_and_AMintegrate(void** state) {
+    TestState* test_state = *state;
+    AMstack** stack_ptr = &test_state->doc_state->base_state->stack;
+    AMdoc* doc1;
+    assert_true(AMitemToDoc(AMstackItem(stack_ptr, AMcreate(NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_DOC)), &doc1));
+    AMstackItem(NULL, AMmapPutInt(doc1, AM_ROOT, AMstr("a"), 1), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc1, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMdoc* doc2;
+    assert_true(AMitemToDoc(AMstackItem(stack_ptr, AMfork(doc1, NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_DOC)), &doc2));
+    /* Isolate the first document at its current point. */
+    AMstackItem(NULL, AMisolate(doc1, NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMmapPutInt(doc2, AM_ROOT, AMstr("b"), 2), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc2, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMstackItem(NULL, AMmerge(doc1, doc2), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    /* The merged change is hidden from the isolated document... */
+    AMstackItem(NULL, AMmapGet(doc1, AM_ROOT, AMstr("b"), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    /* ...which can still be changed. */
+    AMstackItem(NULL, AMmapPutInt(doc1, AM_ROOT, AMstr("c"), 3), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc1, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMitems keys = AMstackItems(stack_ptr, AMkeys(doc1, AM_ROOT, NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_STR));
+    assert_int_equal(AMitemsSize(&keys), 2);
+    /* Integrating the document reveals both changes. */
+    AMstackItem(NULL, AMintegrate(doc1), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    keys = AMstackItems(stack_ptr, AMkeys(doc1, AM_ROOT, NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_STR));
+    assert_int_equal(AMitemsSize(&keys), 3);
+    int64_t value;
+    assert_true(AMitemToInt(
+        AMstackItem(stack_ptr, AMmapGet(doc1, AM_ROOT, AMstr("b"), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_INT)),
+        &value));
+    assert_int_equal(value, 2);
+    assert_true(AMitemToInt(
+        AMstackItem(stack_ptr, AMmapGet(doc1, AM_ROOT, AMstr("c"), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_INT)),
+        &value));
+    assert_int_equal(value, 3);
+}
+
 static void test_AMkeys_empty(void** state) {
     TestState* test_state = *state;
     AMstack** stack_ptr = &test_state->doc_state->base_state->stack;
@@ -273,6 +309,7 @@ static void test_AMspliceText(void** state) {
 
 int run_doc_tests(void) {
     const struct CMUnitTest tests[] = {
+        cmocka_unit_test_setup_teardown(test_AMisolate_and_AMintegrate, setup, teardown),
         cmocka_unit_test_setup_teardown(test_AMkeys_empty, setup, teardown),
         cmocka_unit_test_setup_teardown(test_AMkeys_list, setup, teardown),
         cmocka_unit_test_setup_teardown(test_AMkeys_map, setup, teardown),
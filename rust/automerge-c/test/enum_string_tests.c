@@ -62,6 +62,40 @@ static void test_AMobjTypeFromString(void** state) {
     assert_int_equal(out, (AMobjType)-1);
 }
 
+static void test_AMpatchActionTypeToString(void** state) {
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_DEFAULT);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_CONFLICT);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_DELETE_MAP);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_DELETE_SEQ);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_INCREMENT);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_INSERT);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_MARK);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_PUT_MAP);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_PUT_SEQ);
+    assert_to_string(AMpatchActionTypeToString, AM_PATCH_ACTION_TYPE_SPLICE_TEXT);
+    /* Zero tag */
+    assert_string_equal(AMpatchActionTypeToString(0), "AM_PATCH_ACTION_TYPE_DEFAULT");
+    /* Invalid tag */
+    assert_string_equal(AMpatchActionTypeToString(-1), "???");
+}
+
+static void test_AMpatchActionTypeFromString(void** state) {
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_DEFAULT);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_CONFLICT);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_DELETE_MAP);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_DELETE_SEQ);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_INCREMENT);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_INSERT);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_MARK);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_PUT_MAP);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_PUT_SEQ);
+    assert_from_string(AMpatchActionTypeFromString, AMpatchActionType, AM_PATCH_ACTION_TYPE_SPLICE_TEXT);
+    /* Invalid tag */
+    AMpatchActionType out = -1;
+    assert_false(AMpatchActionTypeFromString(&out, "???"));
+    assert_int_equal(out, (AMpatchActionType)-1);
+}
+
 static void test_AMstatusToString(void** state) {
     assert_to_string(AMstatusToString, AM_STATUS_ERROR);
     assert_to_string(AMstatusToString, AM_STATUS_INVALID_RESULT);
@@ -89,12 +123,14 @@ static void test_AMvalTypeToString(void** state) {
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_CHANGE);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_CHANGE_HASH);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_COUNTER);
+    assert_to_string(AMvalTypeToString, AM_VAL_TYPE_CURSOR);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_DEFAULT);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_DOC);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_F64);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_INT);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_NULL);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_OBJ_TYPE);
+    assert_to_string(AMvalTypeToString, AM_VAL_TYPE_PATCH);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_STR);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_SYNC_HAVE);
     assert_to_string(AMvalTypeToString, AM_VAL_TYPE_SYNC_MESSAGE);
@@ -116,12 +152,14 @@ static void test_AMvalTypeFromString(void** state) {
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_CHANGE);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_CHANGE_HASH);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_COUNTER);
+    assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_CURSOR);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_DEFAULT);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_DOC);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_F64);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_INT);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_NULL);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_OBJ_TYPE);
+    assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_PATCH);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_STR);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_SYNC_HAVE);
     assert_from_string(AMvalTypeFromString, AMvalType, AM_VAL_TYPE_SYNC_MESSAGE);
@@ -140,7 +178,8 @@ int run_enum_string_tests(void) {
     const struct CMUnitTest tests[] = {
         cmocka_unit_test(test_AMidxTypeToString), cmocka_unit_test(test_AMidxTypeFromString),
         cmocka_unit_test(test_AMobjTypeToString), cmocka_unit_test(test_AMobjTypeFromString),
-        cmocka_unit_test(test_AMstatusToString),  cmocka_unit_test(test_AMstatusFromString),
+        cmocka_unit_test(test_AMpatchActionTypeToString), cmocka_unit_test(test_AMpatchActionTypeFromString),
+        cmocka_unit_test(test_AMstatusToString),          cmocka_unit_test(test_AMstatusFromString),
         cmocka_unit_test(test_AMvalTypeToString), cmocka_unit_test(test_AMvalTypeFromString),
     };
 
@@ -0,0 +1,266 @@
+#include <setjmp.h>
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <string.h>
+
+/* third-party */
+#include <cmocka.h>
+
+/* local */
+#include <automerge-c/automerge.h>
+#include <automerge-c/utils/stack_callback_data.h>
+#include "base_state.h"
+#include "cmocka_utils.h"
+#include "doc_state.h"
+
+static void test_AMdiffIncremental_map(void** state) {
+    DocState* doc_state = *state;
+    AMstack** stack_ptr = &doc_state->base_state->stack;
+
+    AMstackItem(NULL, AMmapPutStr(doc_state->doc, AM_ROOT, AMstr("str"), AMstr("hello")), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMmapPutCounter(doc_state->doc, AM_ROOT, AMstr("counter"), 1), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMitems patches =
+        AMstackItems(stack_ptr, AMdiffIncremental(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 2);
+    AMpatch const* patch;
+    AMbyteSpan key;
+    /* The patches are ordered by key. */
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_PUT_MAP);
+    assert_int_equal(AMobjIdIndex(AMpatchObjId(patch)), 0);
+    assert_true(AMpatchKey(patch, &key));
+    assert_int_equal(key.count, strlen("counter"));
+    assert_memory_equal(key.src, "counter", key.count);
+    assert_false(AMpatchConflict(patch));
+    int64_t counter;
+    assert_true(AMitemToCounter(
+        AMstackItem(stack_ptr, AMpatchValues(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_COUNTER)), &counter));
+    assert_int_equal(counter, 1);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_PUT_MAP);
+    assert_true(AMpatchKey(patch, &key));
+    assert_int_equal(key.count, strlen("str"));
+    assert_memory_equal(key.src, "str", key.count);
+    AMbyteSpan str;
+    assert_true(
+        AMitemToStr(AMstackItem(stack_ptr, AMpatchValues(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_STR)), &str));
+    assert_int_equal(str.count, strlen("hello"));
+    assert_memory_equal(str.src, "hello", str.count);
+    /* Only the changes made since the last diff are reported. */
+    AMstackItem(NULL, AMmapIncrement(doc_state->doc, AM_ROOT, AMstr("counter"), -3), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMmapDelete(doc_state->doc, AM_ROOT, AMstr("str")), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    patches = AMstackItems(stack_ptr, AMdiffIncremental(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 2);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_INCREMENT);
+    assert_true(AMpatchKey(patch, &key));
+    assert_memory_equal(key.src, "counter", key.count);
+    int64_t increment;
+    assert_true(
+        AMitemToInt(AMstackItem(stack_ptr, AMpatchValues(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_INT)), &increment));
+    assert_int_equal(increment, -3);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_DELETE_MAP);
+    assert_true(AMpatchKey(patch, &key));
+    assert_memory_equal(key.src, "str", key.count);
+    size_t pos;
+    assert_false(AMpatchPos(patch, &pos));
+    AMitems values = AMstackItems(stack_ptr, AMpatchValues(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    assert_int_equal(AMitemsSize(&values), 0);
+    /* No changes means no patches. */
+    patches = AMstackItems(stack_ptr, AMdiffIncremental(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 0);
+}
+
+static void test_AMdiff_list(void** state) {
+    DocState* doc_state = *state;
+    AMstack** stack_ptr = &doc_state->base_state->stack;
+
+    AMobjId const* const list =
+        AMitemObjId(AMstackItem(stack_ptr, AMmapPutObject(doc_state->doc, AM_ROOT, AMstr("list"), AM_OBJ_TYPE_LIST),
+                                cmocka_cb, AMexpect(AM_VAL_TYPE_OBJ_TYPE)));
+    AMstackItem(NULL, AMlistPutInt(doc_state->doc, list, SIZE_MAX, true, 1), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMlistPutInt(doc_state->doc, list, SIZE_MAX, true, 2), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMlistPutInt(doc_state->doc, list, SIZE_MAX, true, 3), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc_state->doc, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMitems const before =
+        AMstackItems(stack_ptr, AMgetHeads(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMstackItem(NULL, AMlistDelete(doc_state->doc, list, 0), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMlistPutUint(doc_state->doc, list, 1, false, 4), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc_state->doc, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMitems const after =
+        AMstackItems(stack_ptr, AMgetHeads(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    /* From the empty document to the first historical point. */
+    AMitems patches =
+        AMstackItems(stack_ptr, AMdiff(doc_state->doc, NULL, &before), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 2);
+    AMpatch const* patch;
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_PUT_MAP);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_INSERT);
+    assert_true(AMobjIdEqual(AMpatchObjId(patch), list));
+    size_t pos;
+    assert_true(AMpatchPos(patch, &pos));
+    assert_int_equal(pos, 0);
+    assert_int_equal(AMpatchLength(patch), 3);
+    AMitems values = AMstackItems(stack_ptr, AMpatchValues(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_INT));
+    assert_int_equal(AMitemsSize(&values), 3);
+    int64_t int_;
+    assert_true(AMitemToInt(AMitemsNext(&values, 1), &int_));
+    assert_int_equal(int_, 1);
+    assert_true(AMitemToInt(AMitemsNext(&values, 1), &int_));
+    assert_int_equal(int_, 2);
+    assert_true(AMitemToInt(AMitemsNext(&values, 1), &int_));
+    assert_int_equal(int_, 3);
+    /* The path leads from the root object to the list object. */
+    AMitems path = AMstackItems(stack_ptr, AMpatchPath(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    assert_int_equal(AMitemsSize(&path), 1);
+    AMitem* path_item = AMitemsNext(&path, 1);
+    assert_int_equal(AMobjIdIndex(AMitemObjId(path_item)), 0);
+    assert_int_equal(AMitemIdxType(path_item), AM_IDX_TYPE_KEY);
+    AMbyteSpan key;
+    assert_true(AMitemKey(path_item, &key));
+    assert_int_equal(key.count, strlen("list"));
+    assert_memory_equal(key.src, "list", key.count);
+    /* From the first historical point to the second historical point. */
+    patches = AMstackItems(stack_ptr, AMdiff(doc_state->doc, &before, &after), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 2);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_DELETE_SEQ);
+    assert_true(AMpatchPos(patch, &pos));
+    assert_int_equal(pos, 0);
+    assert_int_equal(AMpatchLength(patch), 1);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_PUT_SEQ);
+    assert_true(AMpatchPos(patch, &pos));
+    assert_int_equal(pos, 1);
+    AMbyteSpan str;
+    assert_false(AMpatchKey(patch, &str));
+    uint64_t uint;
+    assert_true(
+        AMitemToUint(AMstackItem(stack_ptr, AMpatchValues(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_UINT)), &uint));
+    assert_int_equal(uint, 4);
+    /* Backward from the second historical point to the first historical point. */
+    patches = AMstackItems(stack_ptr, AMdiff(doc_state->doc, &after, &before), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 2);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_INSERT);
+    assert_true(AMpatchPos(patch, &pos));
+    assert_int_equal(pos, 0);
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_PUT_SEQ);
+}
+
+static void test_AMdiff_text(void** state) {
+    DocState* doc_state = *state;
+    AMstack** stack_ptr = &doc_state->base_state->stack;
+
+    AMobjId const* const map =
+        AMitemObjId(AMstackItem(stack_ptr, AMmapPutObject(doc_state->doc, AM_ROOT, AMstr("map"), AM_OBJ_TYPE_MAP),
+                                cmocka_cb, AMexpect(AM_VAL_TYPE_OBJ_TYPE)));
+    AMobjId const* const text =
+        AMitemObjId(AMstackItem(stack_ptr, AMmapPutObject(doc_state->doc, map, AMstr("text"), AM_OBJ_TYPE_TEXT),
+                                cmocka_cb, AMexpect(AM_VAL_TYPE_OBJ_TYPE)));
+    AMstackItem(NULL, AMspliceText(doc_state->doc, text, 0, 0, AMstr("hello")), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMupdateDiffCursor(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMitem* const bold = AMstackItem(stack_ptr, AMitemFromBool(true), cmocka_cb, AMexpect(AM_VAL_TYPE_BOOL));
+    AMstackItem(NULL, AMmarkCreate(doc_state->doc, text, 0, 5, AM_MARK_EXPAND_AFTER, AMstr("bold"), bold), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMspliceText(doc_state->doc, text, 5, 0, AMstr(" world")), cmocka_cb,
+                AMexpect(AM_VAL_TYPE_VOID));
+    AMitems patches =
+        AMstackItems(stack_ptr, AMdiffIncremental(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 2);
+    /* The mark. */
+    AMpatch const* patch;
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_MARK);
+    size_t pos;
+    assert_false(AMpatchPos(patch, &pos));
+    AMitems marks = AMstackItems(stack_ptr, AMpatchMarks(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_MARK));
+    assert_int_equal(AMitemsSize(&marks), 1);
+    AMmark* mark;
+    assert_true(AMitemToMark(AMitemsNext(&marks, 1), &mark));
+    AMbyteSpan name = AMmarkName(mark);
+    assert_int_equal(name.count, strlen("bold"));
+    assert_memory_equal(name.src, "bold", name.count);
+    assert_int_equal(AMmarkStart(mark), 0);
+    assert_int_equal(AMmarkEnd(mark), 5);
+    /* The insertion, which is reported per character. */
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    assert_int_equal(AMpatchAction(patch), AM_PATCH_ACTION_TYPE_INSERT);
+    assert_true(AMobjIdEqual(AMpatchObjId(patch), text));
+    assert_true(AMpatchPos(patch, &pos));
+    assert_int_equal(pos, 5);
+    assert_int_equal(AMpatchLength(patch), strlen(" world"));
+    AMitems values = AMstackItems(stack_ptr, AMpatchValues(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_STR));
+    assert_int_equal(AMitemsSize(&values), strlen(" world"));
+    AMbyteSpan str;
+    assert_true(AMitemToStr(AMitemsNext(&values, 1), &str));
+    assert_int_equal(str.count, 1);
+    assert_memory_equal(str.src, " ", str.count);
+    assert_true(AMitemToStr(AMitemsNext(&values, 1), &str));
+    assert_memory_equal(str.src, "w", str.count);
+    /* The path leads from the root object through the map object to the text
+     * object. */
+    AMitems path = AMstackItems(stack_ptr, AMpatchPath(patch), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    assert_int_equal(AMitemsSize(&path), 2);
+    AMbyteSpan key;
+    AMitem* path_item = AMitemsNext(&path, 1);
+    assert_int_equal(AMobjIdIndex(AMitemObjId(path_item)), 0);
+    assert_true(AMitemKey(path_item, &key));
+    assert_int_equal(key.count, strlen("map"));
+    assert_memory_equal(key.src, "map", key.count);
+    path_item = AMitemsNext(&path, 1);
+    assert_true(AMobjIdEqual(AMitemObjId(path_item), map));
+    assert_true(AMitemKey(path_item, &key));
+    assert_int_equal(key.count, strlen("text"));
+    assert_memory_equal(key.src, "text", key.count);
+    /* Resetting the diff cursor doesn't affect an explicit diff. */
+    AMstackItem(NULL, AMresetDiffCursor(doc_state->doc), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    patches = AMstackItems(stack_ptr, AMdiff(doc_state->doc, NULL, NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 3);
+}
+
+static void test_AMdiff_conflict(void** state) {
+    DocState* doc_state = *state;
+    AMstack** stack_ptr = &doc_state->base_state->stack;
+
+    AMdoc* doc1 = doc_state->doc;
+    AMstackItem(NULL, AMmapPutStr(doc1, AM_ROOT, AMstr("key"), AMstr("one")), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc1, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMdoc* doc2;
+    assert_true(AMitemToDoc(AMstackItem(stack_ptr, AMcreate(NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_DOC)), &doc2));
+    AMstackItem(NULL, AMmapPutStr(doc2, AM_ROOT, AMstr("key"), AMstr("two")), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMcommit(doc2, AMstr(NULL), NULL), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMstackItem(NULL, AMupdateDiffCursor(doc1), cmocka_cb, AMexpect(AM_VAL_TYPE_VOID));
+    AMstackItem(NULL, AMmerge(doc1, doc2), cmocka_cb, AMexpect(AM_VAL_TYPE_CHANGE_HASH));
+    AMitems patches = AMstackItems(stack_ptr, AMdiffIncremental(doc1), cmocka_cb, AMexpect(AM_VAL_TYPE_PATCH));
+    assert_int_equal(AMitemsSize(&patches), 1);
+    AMpatch const* patch;
+    assert_true(AMitemToPatch(AMitemsNext(&patches, 1), &patch));
+    AMpatchActionType const action = AMpatchAction(patch);
+    assert_true(action == AM_PATCH_ACTION_TYPE_PUT_MAP || action == AM_PATCH_ACTION_TYPE_CONFLICT);
+    assert_true(AMpatchConflict(patch));
+    AMbyteSpan key;
+    assert_true(AMpatchKey(patch, &key));
+    assert_int_equal(key.count, strlen("key"));
+    assert_memory_equal(key.src, "key", key.count);
+}
+
+int run_patch_tests(void) {
+    struct CMUnitTest const tests[] = {
+        cmocka_unit_test_setup_teardown(test_AMdiffIncremental_map, setup_doc, teardown_doc),
+        cmocka_unit_test_setup_teardown(test_AMdiff_list, setup_doc, teardown_doc),
+        cmocka_unit_test_setup_teardown(test_AMdiff_text, setup_doc, teardown_doc),
+        cmocka_unit_test_setup_teardown(test_AMdiff_conflict, setup_doc, teardown_doc),
+    };
+
+    return cmocka_run_group_tests(tests, NULL, NULL);
+}
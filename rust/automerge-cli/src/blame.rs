@@ -0,0 +1,240 @@
+use automerge as am;
+use automerge::ReadDoc;
+use thiserror::Error;
+
+use crate::{
+    change::{self, lookup, ChangeError},
+    export::{summary, value_to_json},
+    report::{write_json, write_table},
+    ReportFormat,
+};
+
+#[derive(Error, Debug)]
+pub enum BlameError {
+    #[error(transparent)]
+    Path(#[from] ChangeError),
+    #[error("no change in the document created {0}")]
+    UnknownOp(am::ObjId),
+    #[error("Error writing to output: {:?}", source)]
+    WritingToOutput {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// The change responsible for a value in an object
+struct Blamed {
+    /// The key or index of the value, or the range of a run of characters in a text object
+    prop: Prop,
+    text: String,
+    json: serde_json::Value,
+    change: am::ChangeHash,
+    actor: am::ActorId,
+    seq: u64,
+    time: i64,
+}
+
+enum Prop {
+    Key(String),
+    Index(usize),
+    Range(usize, usize),
+}
+
+/// Print the change responsible for each value in the object at `path`, or for each run of
+/// characters in a text object
+pub(crate) fn blame(
+    doc: &am::Automerge,
+    path: &str,
+    output: impl std::io::Write,
+    format: ReportFormat,
+    is_tty: bool,
+) -> Result<(), BlameError> {
+    let props = change::parse_path(path)?.resolve(&[])?;
+    let (obj, typ) = lookup(doc, &props, None)?;
+    let mut blamed = Vec::new();
+    match typ {
+        am::ObjType::Map | am::ObjType::Table => {
+            for key in doc.keys(&obj) {
+                if let Some((value, id)) = doc.get(&obj, key.as_str()).map_err(ChangeError::from)? {
+                    blamed.push(blame_value(doc, Prop::Key(key), &value, &id)?);
+                }
+            }
+        }
+        am::ObjType::List => {
+            for item in doc.list_range(&obj, ..) {
+                blamed.push(blame_value(
+                    doc,
+                    Prop::Index(item.index),
+                    &item.value,
+                    &item.id,
+                )?);
+            }
+        }
+        am::ObjType::Text => {
+            for item in doc.list_range(&obj, ..) {
+                let next = blame_value(doc, Prop::Index(item.index), &item.value, &item.id)?;
+                let s = item.value.to_str().unwrap_or_default();
+                let end = item.index + doc.text_encoding().width(s);
+                match blamed.last_mut() {
+                    Some(Blamed {
+                        prop: Prop::Range(_, run_end),
+                        text,
+                        change,
+                        ..
+                    }) if *change == next.change && *run_end == item.index => {
+                        *run_end = end;
+                        text.push_str(s);
+                    }
+                    _ => blamed.push(Blamed {
+                        prop: Prop::Range(item.index, end),
+                        text: s.to_string(),
+                        ..next
+                    }),
+                }
+            }
+            for run in &mut blamed {
+                run.json = serde_json::Value::String(run.text.clone());
+                run.text = serde_json::Value::String(std::mem::take(&mut run.text)).to_string();
+            }
+        }
+    }
+    match format {
+        ReportFormat::Json => {
+            let json = blamed.iter().map(to_json).collect();
+            write_json(output, &serde_json::Value::Array(json), is_tty)
+        }
+        ReportFormat::Table => {
+            let rows = blamed
+                .into_iter()
+                .map(|b| {
+                    let prop = match b.prop {
+                        Prop::Key(key) => key,
+                        Prop::Index(index) => index.to_string(),
+                        Prop::Range(start, end) => format!("{}..{}", start, end),
+                    };
+                    vec![
+                        prop,
+                        b.text,
+                        b.actor.to_string(),
+                        b.seq.to_string(),
+                        b.change.to_string(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            write_table(output, &["prop", "value", "actor", "seq", "change"], &rows)
+        }
+    }
+    .map_err(|e| BlameError::WritingToOutput { source: e })
+}
+
+fn blame_value(
+    doc: &am::Automerge,
+    prop: Prop,
+    value: &am::Value<'_>,
+    id: &am::ObjId,
+) -> Result<Blamed, BlameError> {
+    let change = doc
+        .hash_for_opid(id)
+        .and_then(|hash| doc.get_change_by_hash(&hash))
+        .ok_or_else(|| BlameError::UnknownOp(id.clone()))?;
+    Ok(Blamed {
+        prop,
+        text: summary(doc, value, id),
+        json: value_to_json(doc, value, id),
+        change: change.hash(),
+        actor: change.actor_id().clone(),
+        seq: change.seq(),
+        time: change.timestamp(),
+    })
+}
+
+fn to_json(blamed: &Blamed) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "value": blamed.json,
+        "change": blamed.change,
+        "actor": blamed.actor,
+        "seq": blamed.seq,
+        "time": blamed.time,
+    });
+    match blamed.prop {
+        Prop::Key(ref key) => json["key"] = key.as_str().into(),
+        Prop::Index(index) => json["index"] = index.into(),
+        Prop::Range(start, end) => {
+            json["start"] = start.into();
+            json["end"] = end.into();
+        }
+    }
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::transaction::Transactable;
+
+    fn blame_table(doc: &mut am::AutoCommit, path: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        blame(doc.document(), path, &mut out, ReportFormat::Table, false).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn blame_map_keys() {
+        let mut doc = am::AutoCommit::new().with_actor(am::ActorId::from([1]));
+        let birds = doc.put_object(am::ROOT, "birds", am::ObjType::Map).unwrap();
+        doc.put(&birds, "wrens", 3).unwrap();
+        let first = doc.commit().unwrap();
+        let mut fork = doc.fork().with_actor(am::ActorId::from([2]));
+        fork.put(&birds, "owls", 1).unwrap();
+        let second = fork.commit().unwrap();
+        doc.merge(&mut fork).unwrap();
+
+        assert_eq!(
+            blame_table(&mut doc, "$[\"birds\"]"),
+            vec![
+                "prop   value  actor  seq  change".to_string(),
+                format!("owls   1      02     1    {}", second),
+                format!("wrens  3      01     1    {}", first),
+            ]
+        );
+    }
+
+    #[test]
+    fn blame_text_runs() {
+        let mut doc = am::AutoCommit::new().with_actor(am::ActorId::from([1]));
+        let text = doc.put_object(am::ROOT, "text", am::ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello world").unwrap();
+        let first = doc.commit().unwrap();
+        doc.splice_text(&text, 5, 0, " big").unwrap();
+        let second = doc.commit().unwrap();
+
+        let mut out = Vec::new();
+        blame(doc.document(), "text", &mut out, ReportFormat::Json, false).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let runs = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|run| {
+                (
+                    run["start"].as_u64().unwrap(),
+                    run["end"].as_u64().unwrap(),
+                    run["value"].as_str().unwrap().to_string(),
+                    run["change"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            runs,
+            vec![
+                (0, 5, "hello".to_string(), first.to_string()),
+                (5, 9, " big".to_string(), second.to_string()),
+                (9, 15, " world".to_string(), first.to_string()),
+            ]
+        );
+    }
+}
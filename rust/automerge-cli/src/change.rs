@@ -1,26 +1,197 @@
+use std::fmt;
+
 use automerge as am;
-use combine::{parser::char as charparser, EasyParser, ParseError, Parser};
+use automerge::{transaction::Transactable, ReadDoc};
+use combine::{
+    error::StreamError,
+    parser::{char as charparser, combinator::no_partial},
+    stream::StreamErrorFor,
+    EasyParser, ParseError, Parser,
+};
 use thiserror::Error;
 
+use crate::import::{insert_json, put_json};
+
 #[derive(Error, Debug)]
 pub enum ChangeError {
     #[error("Invalid change script: {message}")]
     InvalidChangeScript { message: String },
-    #[error("Error reading changes: {:?}", source)]
-    ErrReadingChanges {
-        #[source]
-        source: std::io::Error,
-    },
-    #[error("Error loading changes: {:?}", source)]
-    ErrApplyingInitialChanges {
-        #[source]
-        source: am::AutomergeError,
-    },
-    #[error("Error writing changes to output file: {:?}", source)]
-    ErrWritingChanges {
-        #[source]
-        source: std::io::Error,
-    },
+    #[error("Invalid path: {message}")]
+    InvalidPath { message: String },
+    #[error("{path} is not an object")]
+    NotAnObject { path: String },
+    #[error("the root of the document has no parent")]
+    AboveRoot,
+    #[error("{path} does not refer to a property")]
+    NoProperty { path: String },
+    #[error(transparent)]
+    Automerge(#[from] am::AutomergeError),
+    #[error("Error importing value: {0}")]
+    Import(#[from] anyhow::Error),
+}
+
+/// One step of a [`Path`]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment {
+    Prop(am::Prop),
+    Parent,
+}
+
+/// A path to a value in a document, either from the root (`$["birds"][0]`) or relative to some
+/// current object (`birds[0]`, `..["owls"]`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Path {
+    pub(crate) absolute: bool,
+    pub(crate) segments: Vec<Segment>,
+}
+
+#[cfg(test)]
+impl Path {
+    fn root() -> Path {
+        Path {
+            absolute: true,
+            segments: Vec::new(),
+        }
+    }
+
+    fn key<S: Into<String>>(mut self, key: S) -> Path {
+        self.segments.push(Segment::Prop(am::Prop::Map(key.into())));
+        self
+    }
+
+    fn index(mut self, index: usize) -> Path {
+        self.segments.push(Segment::Prop(am::Prop::Seq(index)));
+        self
+    }
+
+    fn parent(mut self) -> Path {
+        self.segments.push(Segment::Parent);
+        self
+    }
+}
+
+impl Path {
+    /// The properties from the root of the document to the value this path refers to, if this
+    /// path is relative to `cwd`
+    pub(crate) fn resolve(&self, cwd: &[am::Prop]) -> Result<Vec<am::Prop>, ChangeError> {
+        let mut props = if self.absolute {
+            Vec::new()
+        } else {
+            cwd.to_vec()
+        };
+        for segment in &self.segments {
+            match segment {
+                Segment::Prop(p) => props.push(p.clone()),
+                Segment::Parent => {
+                    props.pop().ok_or(ChangeError::AboveRoot)?;
+                }
+            }
+        }
+        Ok(props)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.absolute {
+            write!(f, "$")?;
+        }
+        for segment in &self.segments {
+            match segment {
+                Segment::Prop(am::Prop::Map(k)) => write!(f, "[\"{}\"]", k)?,
+                Segment::Prop(am::Prop::Seq(i)) => write!(f, "[{}]", i)?,
+                Segment::Parent => write!(f, "..")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format the properties from the root of a document as an absolute path
+pub(crate) fn display_props(props: &[am::Prop]) -> String {
+    Path {
+        absolute: true,
+        segments: props.iter().cloned().map(Segment::Prop).collect(),
+    }
+    .to_string()
+}
+
+/// Find the object at `props`, as at `heads` if given
+pub(crate) fn lookup<R: ReadDoc>(
+    doc: &R,
+    props: &[am::Prop],
+    heads: Option<&[am::ChangeHash]>,
+) -> Result<(am::ObjId, am::ObjType), ChangeError> {
+    let mut obj = (am::ROOT, am::ObjType::Map);
+    for (i, prop) in props.iter().enumerate() {
+        let value = match heads {
+            Some(heads) => doc.get_at(&obj.0, prop.clone(), heads)?,
+            None => doc.get(&obj.0, prop.clone())?,
+        };
+        match value {
+            Some((am::Value::Object(typ), id)) => obj = (id, typ),
+            _ => {
+                return Err(ChangeError::NotAnObject {
+                    path: display_props(&props[..=i]),
+                })
+            }
+        }
+    }
+    Ok(obj)
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum LocalChange {
+    Set(Path, serde_json::Value),
+    Insert(Path, serde_json::Value),
+    Delete(Path),
+    Increment(Path, i64),
+}
+
+impl LocalChange {
+    /// Apply this change to `doc`, resolving relative paths against `cwd`
+    pub(crate) fn apply(
+        &self,
+        doc: &mut am::AutoCommit,
+        cwd: &[am::Prop],
+    ) -> Result<(), ChangeError> {
+        let path = match self {
+            LocalChange::Set(path, _)
+            | LocalChange::Insert(path, _)
+            | LocalChange::Delete(path)
+            | LocalChange::Increment(path, _) => path,
+        };
+        let mut props = path.resolve(cwd)?;
+        let prop = props.pop().ok_or_else(|| ChangeError::NoProperty {
+            path: path.to_string(),
+        })?;
+        let (obj, typ) = lookup(doc, &props, None)?;
+        match (self, prop) {
+            (LocalChange::Set(_, serde_json::Value::String(s)), am::Prop::Seq(i))
+                if typ == am::ObjType::Text =>
+            {
+                doc.splice_text(&obj, i, 1, s)?;
+            }
+            (LocalChange::Set(_, value), prop) => put_json(doc, &obj, prop, value)?,
+            (LocalChange::Insert(_, serde_json::Value::String(s)), am::Prop::Seq(i))
+                if typ == am::ObjType::Text =>
+            {
+                doc.splice_text(&obj, i, 0, s)?;
+            }
+            (LocalChange::Insert(_, value), am::Prop::Seq(i)) => insert_json(doc, &obj, i, value)?,
+            (LocalChange::Insert(..), am::Prop::Map(_)) => {
+                return Err(ChangeError::InvalidPath {
+                    message: format!("cannot insert into a map at {}", path),
+                })
+            }
+            (LocalChange::Delete(_), am::Prop::Seq(i)) if typ == am::ObjType::Text => {
+                doc.splice_text(&obj, i, 1, "")?;
+            }
+            (LocalChange::Delete(_), prop) => doc.delete(&obj, prop)?,
+            (LocalChange::Increment(_, by), prop) => doc.increment(&obj, prop, *by)?,
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -63,98 +234,143 @@ where
     combine::many1(key_char_parser).map(|chars: Vec<char>| chars.into_iter().collect())
 }
 
-fn index_parser<Input>() -> impl Parser<Input, Output = u32>
+fn quoted_key_parser<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: combine::Stream<Token = char>,
+{
+    charparser::string("[\"")
+        .with(combine::many(combine::none_of("\"".chars())))
+        .skip(charparser::string("\"]"))
+}
+
+fn index_parser<Input>() -> impl Parser<Input, Output = usize>
 where
     Input: combine::Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    combine::many1::<Vec<char>, Input, _>(charparser::digit()).map(|digits| {
-        let num_string: String = digits.iter().collect();
-        num_string.parse::<u32>().unwrap()
+    combine::many1::<String, Input, _>(charparser::digit()).and_then(|digits| {
+        digits.parse::<usize>().map_err(|_| {
+            StreamErrorFor::<Input>::message_format(format!("index {} is too large", digits))
+        })
     })
 }
 
-combine::parser! {
-    fn path_segment_parser[Input](path_so_far: amf::Path)(Input) -> amf::Path
-    where [Input:  combine::Stream<Token=char>]
-    {
-        let key_path_so_far = path_so_far.clone();
-        let key_segment_parser = charparser::string("[\"")
-            .with(key_parser())
-            .skip(charparser::string("\"]"))
-            .then(move |key| path_segment_parser(key_path_so_far.clone().key(key)));
-
-        let index_path_so_far = path_so_far.clone();
-        let index_segment_parser = charparser::char('[')
-            .with(index_parser())
-            .skip(charparser::char(']'))
-            .then(move |index| path_segment_parser(index_path_so_far.clone().index(index)));
-
-        combine::choice((
-            combine::attempt(key_segment_parser),
-            combine::attempt(index_segment_parser),
-            combine::value(path_so_far.clone())
-        ))
-    }
+fn integer_parser<Input>() -> impl Parser<Input, Output = i64>
+where
+    Input: combine::Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    combine::optional(charparser::char('-'))
+        .and(combine::many1::<String, Input, _>(charparser::digit()))
+        .and_then(|(sign, digits)| {
+            let number = match sign {
+                Some(_) => format!("-{}", digits),
+                None => digits,
+            };
+            number.parse::<i64>().map_err(|_| {
+                StreamErrorFor::<Input>::message_format(format!("{} is out of range", number))
+            })
+        })
+}
+
+fn segment_parser<Input>() -> impl Parser<Input, Output = Segment>
+where
+    Input: combine::Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    combine::choice((
+        combine::attempt(charparser::string("..")).map(|_| Segment::Parent),
+        combine::attempt(charparser::char('.').with(key_parser()))
+            .map(|key| Segment::Prop(am::Prop::Map(key))),
+        combine::attempt(quoted_key_parser()).map(|key| Segment::Prop(am::Prop::Map(key))),
+        combine::attempt(
+            charparser::char('[')
+                .with(index_parser())
+                .skip(charparser::char(']')),
+        )
+        .map(|index| Segment::Prop(am::Prop::Seq(index))),
+    ))
+}
+
+fn path_parser<Input>() -> impl Parser<Input, Output = Path>
+where
+    Input: combine::Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    combine::choice((
+        charparser::char('$').map(|_| (true, None)),
+        key_parser().map(|key| (false, Some(Segment::Prop(am::Prop::Map(key))))),
+        combine::value((false, None)),
+    ))
+    .and(combine::many::<Vec<_>, _, _>(segment_parser()))
+    .map(|((absolute, first), rest)| Path {
+        absolute,
+        segments: first.into_iter().chain(rest).collect(),
+    })
 }
 
 fn value_parser<'a, Input>(
-) -> Box<dyn combine::Parser<Input, Output = amf::Value, PartialState = ()> + 'a>
+) -> Box<dyn combine::Parser<Input, Output = serde_json::Value, PartialState = ()> + 'a>
 where
     Input: 'a,
     Input: combine::Stream<Token = char>,
     Input::Error: combine::ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    combine::parser::combinator::no_partial(
-        //combine::position().and(combine::many1::<Vec<char>, _, _>(combine::any())).and_then(
-        combine::position().and(combine::many1::<Vec<char>, _, _>(combine::any())).flat_map(
-        |(position, chars): (Input::Position, Vec<char>)| -> Result<amf::Value, Input::Error> {
-            let json_str: String = chars.into_iter().collect();
-            let json: serde_json::Value = serde_json::from_str(json_str.as_str()).map_err(|e| {
-                //let pe = <Input::Error as ParseError<_, _, _>>::StreamError::message::<combine::error::Format<String>>(combine::error::Format(e.to_string()));
-                //let pe = <Input::Error as ParseError<Input::Token, Input::Range, Input::Position>>::StreamError::message(e.to_string().into());
-                let mut pe = Input::Error::empty(position);
-                pe.add_message(combine::error::Format(e.to_string()));
-                //let pe = combine::ParseError:::wmpty(position);
-                pe
-            })?;
-            Ok(amf::Value::from_json(&json))
-        },
-        )
-    ).boxed()
+    no_partial(
+        combine::position()
+            .and(combine::many1::<Vec<char>, _, _>(combine::any()))
+            .flat_map(
+                |(position, chars): (Input::Position, Vec<char>)| -> Result<
+                    serde_json::Value,
+                    Input::Error,
+                > {
+                    let json_str: String = chars.into_iter().collect();
+                    serde_json::from_str(json_str.as_str()).map_err(|e| {
+                        let mut pe = Input::Error::empty(position);
+                        pe.add_message(combine::error::Format(e.to_string()));
+                        pe
+                    })
+                },
+            ),
+    )
+    .boxed()
 }
 
-fn change_parser<'a, Input: 'a>() -> impl combine::Parser<Input, Output = amf::LocalChange> + 'a
+fn change_parser<'a, Input>() -> impl combine::Parser<Input, Output = LocalChange> + 'a
 where
     Input: 'a,
     Input: combine::stream::Stream<Token = char>,
     Input::Error: combine::ParseError<Input::Token, Input::Range, Input::Position>,
 {
     charparser::spaces()
-        .with(
-            op_parser()
-                .skip(charparser::spaces())
-                .skip(charparser::string("$"))
-                .and(path_segment_parser(am::Path::root())),
-        )
+        .with(op_parser().skip(charparser::spaces()).and(path_parser()))
         .skip(charparser::spaces())
         .then(|(operation, path)| {
-            let onwards: Box<
-                dyn combine::Parser<Input, Output = amf::LocalChange, PartialState = _>,
-            > = match operation {
-                Op::Set => value_parser::<'a>()
-                    .map(move |value| amf::LocalChange::set(path.clone(), value))
+            let onwards: Box<dyn combine::Parser<Input, Output = LocalChange, PartialState = _>> =
+                match operation {
+                    Op::Set => value_parser::<'a>()
+                        .map(move |value| LocalChange::Set(path.clone(), value))
+                        .boxed(),
+                    Op::Insert => value_parser::<'a>()
+                        .map(move |value| LocalChange::Insert(path.clone(), value))
+                        .boxed(),
+                    Op::Delete => {
+                        no_partial(combine::eof().map(move |_| LocalChange::Delete(path.clone())))
+                            .boxed()
+                    }
+                    Op::Increment => no_partial(
+                        combine::optional(integer_parser())
+                            .skip(charparser::spaces())
+                            .skip(combine::eof())
+                            .map(move |by| LocalChange::Increment(path.clone(), by.unwrap_or(1))),
+                    )
                     .boxed(),
-                Op::Insert => value_parser::<'a>()
-                    .map(move |value| amf::LocalChange::insert(path.clone(), value))
-                    .boxed(),
-                Op::Delete => combine::value(amf::LocalChange::delete(path)).boxed(),
-                Op::Increment => combine::value(amf::LocalChange::increment(path)).boxed(),
-            };
+                };
             onwards
         })
 }
 
-fn parse_change_script(input: &str) -> Result<amf::LocalChange, ChangeError> {
+pub(crate) fn parse_change_script(input: &str) -> Result<LocalChange, ChangeError> {
     let (change, _) =
         change_parser()
             .easy_parse(input)
@@ -164,69 +380,64 @@ fn parse_change_script(input: &str) -> Result<amf::LocalChange, ChangeError> {
     Ok(change)
 }
 
-pub fn change(
-    mut reader: impl std::io::Read,
-    mut writer: impl std::io::Write,
-    script: &str,
-) -> Result<(), ChangeError> {
-    let mut buf: Vec<u8> = Vec::new();
-    reader
-        .read_to_end(&mut buf)
-        .map_err(|e| ChangeError::ErrReadingChanges { source: e })?;
-    let backend = am::Automerge::load(&buf)
-        .map_err(|e| ChangeError::ErrApplyingInitialChanges { source: e })?;
-    let local_change = parse_change_script(script)?;
-    let ((), new_changes) = frontend.change::<_, _, amf::InvalidChangeRequest>(None, |d| {
-        d.add_change(local_change)?;
-        Ok(())
-    })?;
-    let change_bytes = backend.save().unwrap();
-    writer
-        .write_all(&change_bytes)
-        .map_err(|e| ChangeError::ErrWritingChanges { source: e })?;
-    Ok(())
+pub(crate) fn parse_path(input: &str) -> Result<Path, ChangeError> {
+    let (path, _) = path_parser()
+        .skip(charparser::spaces())
+        .skip(combine::eof())
+        .easy_parse(input.trim_start())
+        .map_err(|e| ChangeError::InvalidPath {
+            message: e.to_string(),
+        })?;
+    Ok(path)
 }
 
 #[cfg(test)]
 mod tests {
-    use maplit::hashmap;
-
     use super::*;
 
     #[test]
     fn test_parse_change_script() {
         struct Scenario {
             input: &'static str,
-            expected: amf::LocalChange,
+            expected: LocalChange,
         }
         let scenarios = vec![
             Scenario {
                 input: "set $[\"map\"][0] {\"some\": \"value\"}",
-                expected: amf::LocalChange::set(
-                    amf::Path::root().key("map").index(0),
-                    amf::Value::from(hashmap! {"some" => "value"}),
+                expected: LocalChange::Set(
+                    Path::root().key("map").index(0),
+                    serde_json::json!({"some": "value"}),
                 ),
             },
             Scenario {
                 input: "insert $[\"map\"][0] {\"some\": \"value\"}",
-                expected: amf::LocalChange::insert(
-                    amf::Path::root().key("map").index(0),
-                    hashmap! {"some" => "value"}.into(),
+                expected: LocalChange::Insert(
+                    Path::root().key("map").index(0),
+                    serde_json::json!({"some": "value"}),
                 ),
             },
             Scenario {
                 input: "delete $[\"map\"][0]",
-                expected: amf::LocalChange::delete(amf::Path::root().key("map").index(0)),
+                expected: LocalChange::Delete(Path::root().key("map").index(0)),
             },
             Scenario {
                 input: "increment $[\"map\"][0]",
-                expected: amf::LocalChange::increment(amf::Path::root().key("map").index(0)),
+                expected: LocalChange::Increment(Path::root().key("map").index(0), 1),
+            },
+            Scenario {
+                input: "increment map.count -3",
+                expected: LocalChange::Increment(Path::default().key("map").key("count"), -3),
+            },
+            Scenario {
+                input: "set ..[\"a key\"] 12.5",
+                expected: LocalChange::Set(
+                    Path::default().parent().key("a key"),
+                    serde_json::json!(12.5),
+                ),
             },
         ];
         for (index, scenario) in scenarios.into_iter().enumerate() {
-            let result: Result<(amf::LocalChange, _), _> =
-                change_parser().easy_parse(scenario.input);
-            let change = result.unwrap().0;
+            let change = parse_change_script(scenario.input).unwrap();
             assert_eq!(
                 change,
                 scenario.expected,
@@ -235,4 +446,40 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_numbers_out_of_range_are_errors() {
+        assert!(matches!(
+            parse_path("a[99999999999999999999999]"),
+            Err(ChangeError::InvalidPath { .. })
+        ));
+        assert!(matches!(
+            parse_change_script("increment a[0] 99999999999999999999"),
+            Err(ChangeError::InvalidChangeScript { .. })
+        ));
+        assert_eq!(
+            parse_change_script("increment a -9223372036854775808").unwrap(),
+            LocalChange::Increment(Path::default().key("a"), i64::MIN)
+        );
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let cwd = vec![am::Prop::Map("birds".into()), am::Prop::Seq(1)];
+        let path = parse_path("..[0].name").unwrap();
+        assert_eq!(
+            path.resolve(&cwd).unwrap(),
+            vec![
+                am::Prop::Map("birds".into()),
+                am::Prop::Seq(0),
+                am::Prop::Map("name".into())
+            ]
+        );
+        assert_eq!(parse_path("$").unwrap().resolve(&cwd).unwrap(), vec![]);
+        assert!(matches!(
+            parse_path("$..").unwrap().resolve(&cwd),
+            Err(ChangeError::AboveRoot)
+        ));
+        assert_eq!(display_props(&cwd), "$[\"birds\"][1]");
+    }
 }
@@ -0,0 +1,359 @@
+use std::path::{Path, PathBuf};
+
+use automerge as am;
+use automerge::ReadDoc;
+use thiserror::Error;
+
+use crate::{
+    change::display_props,
+    export::{scalar_to_json, summary},
+    report::{write_json, write_table},
+    ReportFormat, VerifyFlag,
+};
+
+#[derive(Error, Debug)]
+pub enum DiffError {
+    #[error("Error reading {}: {:?}", path.display(), source)]
+    Reading {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Error loading {}: {:?}", path.display(), source)]
+    Loading {
+        path: PathBuf,
+        #[source]
+        source: am::AutomergeError,
+    },
+    #[error("Error merging documents: {:?}", source)]
+    Merging {
+        #[source]
+        source: am::AutomergeError,
+    },
+    #[error("unknown heads {0:?}")]
+    UnknownHeads(Vec<am::ChangeHash>),
+    #[error("Error writing to output: {:?}", source)]
+    WritingToOutput {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// What to compare
+pub(crate) enum Compare {
+    /// The state of one document at two sets of heads. The heads default to the empty document
+    /// and the current heads respectively
+    Heads {
+        file: PathBuf,
+        from: Vec<am::ChangeHash>,
+        to: Vec<am::ChangeHash>,
+    },
+    /// Two documents, by merging them and comparing the heads of each with the merged heads
+    Files(PathBuf, PathBuf),
+}
+
+/// One patch as a row of the report
+struct Row {
+    path: String,
+    action: &'static str,
+    value: String,
+    json: serde_json::Value,
+}
+
+pub(crate) fn diff(
+    compare: Compare,
+    output: impl std::io::Write,
+    skip: VerifyFlag,
+    format: ReportFormat,
+    is_tty: bool,
+) -> Result<(), DiffError> {
+    match compare {
+        Compare::Heads { file, from, to } => {
+            let doc = load(&file, skip)?;
+            let to = if to.is_empty() { doc.get_heads() } else { to };
+            let rows = diff_rows(&doc, &from, &to)?;
+            match format {
+                ReportFormat::Json => write_json(output, &rows_to_json(&rows), is_tty),
+                ReportFormat::Table => write_rows(output, &rows),
+            }
+        }
+        Compare::Files(first, second) => {
+            let mut merged = load(&first, skip)?;
+            let first_heads = merged.get_heads();
+            let mut other = load(&second, skip)?;
+            let second_heads = other.get_heads();
+            merged
+                .merge(&mut other)
+                .map_err(|e| DiffError::Merging { source: e })?;
+            let merged_heads = merged.get_heads();
+            let diffs = vec![(first, first_heads), (second, second_heads)]
+                .into_iter()
+                .map(|(path, heads)| {
+                    let rows = diff_rows(&merged, &heads, &merged_heads)?;
+                    Ok((path, heads, rows))
+                })
+                .collect::<Result<Vec<_>, DiffError>>()?;
+            match format {
+                ReportFormat::Json => {
+                    let json = diffs
+                        .iter()
+                        .map(|(path, heads, rows)| {
+                            serde_json::json!({
+                                "file": path.display().to_string(),
+                                "from": heads,
+                                "to": merged_heads,
+                                "patches": rows_to_json(rows),
+                            })
+                        })
+                        .collect();
+                    write_json(output, &serde_json::Value::Array(json), is_tty)
+                }
+                ReportFormat::Table => write_merged(output, &diffs),
+            }
+        }
+    }
+    .map_err(|e| DiffError::WritingToOutput { source: e })
+}
+
+fn load(path: &Path, skip: VerifyFlag) -> Result<am::Automerge, DiffError> {
+    let buf = std::fs::read(path).map_err(|e| DiffError::Reading {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+    skip.load(&buf).map_err(|e| DiffError::Loading {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+fn diff_rows(
+    doc: &am::Automerge,
+    from: &[am::ChangeHash],
+    to: &[am::ChangeHash],
+) -> Result<Vec<Row>, DiffError> {
+    let unknown = from
+        .iter()
+        .chain(to)
+        .filter(|hash| doc.get_change_by_hash(hash).is_none())
+        .copied()
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Err(DiffError::UnknownHeads(unknown));
+    }
+    Ok(doc
+        .diff(from, to, am::patches::TextRepresentation::String)
+        .into_iter()
+        .map(|patch| patch_row(doc, patch))
+        .collect())
+}
+
+fn patch_row(doc: &am::Automerge, patch: am::Patch) -> Row {
+    let mut props = patch
+        .path
+        .into_iter()
+        .map(|(_, prop)| prop)
+        .collect::<Vec<_>>();
+    let (action, value, json) = match patch.action {
+        am::PatchAction::PutMap {
+            key,
+            value,
+            conflict,
+        } => {
+            props.push(am::Prop::Map(key));
+            put(doc, &value, conflict)
+        }
+        am::PatchAction::PutSeq {
+            index,
+            value,
+            conflict,
+        } => {
+            props.push(am::Prop::Seq(index));
+            put(doc, &value, conflict)
+        }
+        am::PatchAction::Insert { index, values, .. } => {
+            props.push(am::Prop::Seq(index));
+            let (text, json): (Vec<_>, Vec<_>) = values
+                .iter()
+                .map(|(value, id, _)| describe(doc, value, id))
+                .unzip();
+            (
+                "insert",
+                text.join(", "),
+                serde_json::json!({ "values": json }),
+            )
+        }
+        am::PatchAction::SpliceText { index, value, .. } => {
+            props.push(am::Prop::Seq(index));
+            let text = serde_json::Value::String(value.make_string());
+            (
+                "splice",
+                text.to_string(),
+                serde_json::json!({ "value": text }),
+            )
+        }
+        am::PatchAction::Increment { prop, value } => {
+            props.push(prop);
+            (
+                "increment",
+                format!("{:+}", value),
+                serde_json::json!({ "value": value }),
+            )
+        }
+        am::PatchAction::Conflict { prop } => {
+            props.push(prop);
+            ("conflict", String::new(), serde_json::json!({}))
+        }
+        am::PatchAction::DeleteMap { key } => {
+            props.push(am::Prop::Map(key));
+            ("delete", String::new(), serde_json::json!({}))
+        }
+        am::PatchAction::DeleteSeq { index, length } => {
+            props.push(am::Prop::Seq(index));
+            (
+                "delete",
+                format!("{} items", length),
+                serde_json::json!({ "length": length }),
+            )
+        }
+        am::PatchAction::Mark { marks } => {
+            let text = marks
+                .iter()
+                .map(|m| format!("{}={} {}..{}", m.name(), m.value(), m.start, m.end))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let json = marks
+                .iter()
+                .map(|m| {
+                    serde_json::json!({
+                        "name": m.name(),
+                        "value": scalar_to_json(m.value()),
+                        "start": m.start,
+                        "end": m.end,
+                    })
+                })
+                .collect::<Vec<_>>();
+            ("mark", text, serde_json::json!({ "marks": json }))
+        }
+    };
+    let mut json = json;
+    json["path"] = props
+        .iter()
+        .map(|prop| match prop {
+            am::Prop::Map(key) => serde_json::Value::from(key.as_str()),
+            am::Prop::Seq(index) => serde_json::Value::from(*index),
+        })
+        .collect();
+    json["action"] = action.into();
+    Row {
+        path: display_props(&props),
+        action,
+        value,
+        json,
+    }
+}
+
+fn put(
+    doc: &am::Automerge,
+    (value, id): &(am::Value<'static>, am::ObjId),
+    conflict: bool,
+) -> (&'static str, String, serde_json::Value) {
+    let (mut text, value) = describe(doc, value, id);
+    let mut json = serde_json::json!({ "value": value });
+    if conflict {
+        text.push_str(" (conflict)");
+        json["conflict"] = true.into();
+    }
+    ("put", text, json)
+}
+
+/// Describe a value in a patch. Objects are described as empty, since the patches which follow
+/// fill them in
+fn describe(
+    doc: &am::Automerge,
+    value: &am::Value<'_>,
+    id: &am::ObjId,
+) -> (String, serde_json::Value) {
+    match value {
+        am::Value::Scalar(s) => (summary(doc, value, id), scalar_to_json(s)),
+        am::Value::Object(am::ObjType::Map | am::ObjType::Table) => {
+            ("{}".to_string(), serde_json::json!({}))
+        }
+        am::Value::Object(am::ObjType::List) => ("[]".to_string(), serde_json::json!([])),
+        am::Value::Object(am::ObjType::Text) => ("text \"\"".to_string(), serde_json::json!("")),
+    }
+}
+
+fn rows_to_json(rows: &[Row]) -> serde_json::Value {
+    rows.iter().map(|row| row.json.clone()).collect()
+}
+
+fn write_rows(output: impl std::io::Write, rows: &[Row]) -> std::io::Result<()> {
+    let rows = rows
+        .iter()
+        .map(|row| vec![row.path.clone(), row.action.to_string(), row.value.clone()])
+        .collect::<Vec<_>>();
+    write_table(output, &["path", "action", "value"], &rows)
+}
+
+fn write_merged(
+    mut output: impl std::io::Write,
+    diffs: &[(PathBuf, Vec<am::ChangeHash>, Vec<Row>)],
+) -> std::io::Result<()> {
+    for (i, (path, _, rows)) in diffs.iter().enumerate() {
+        if i > 0 {
+            writeln!(output)?;
+        }
+        writeln!(output, "merging into {}:", path.display())?;
+        if rows.is_empty() {
+            writeln!(output, "no changes")?;
+        } else {
+            write_rows(&mut output, rows)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::transaction::Transactable;
+
+    fn table(doc: &am::Automerge, from: &[am::ChangeHash], to: &[am::ChangeHash]) -> Vec<String> {
+        let mut out = Vec::new();
+        write_rows(&mut out, &diff_rows(doc, from, to).unwrap()).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn diff_between_heads() {
+        let mut doc = am::AutoCommit::new();
+        let birds = doc
+            .put_object(am::ROOT, "birds", am::ObjType::List)
+            .unwrap();
+        doc.insert(&birds, 0, "wren").unwrap();
+        let text = doc.put_object(am::ROOT, "text", am::ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello").unwrap();
+        doc.put(am::ROOT, "count", am::ScalarValue::counter(1))
+            .unwrap();
+        let before = doc.get_heads();
+        doc.insert(&birds, 1, "owl").unwrap();
+        doc.splice_text(&text, 5, 0, " world").unwrap();
+        doc.increment(am::ROOT, "count", 2).unwrap();
+        doc.delete(am::ROOT, "birds").unwrap();
+        let after = doc.get_heads();
+
+        assert_eq!(
+            table(doc.document(), &before, &after),
+            vec![
+                "path          action     value",
+                "$[\"birds\"]    delete",
+                "$[\"count\"]    increment  +2",
+                "$[\"text\"][5]  splice     \" world\"",
+            ]
+        );
+    }
+}
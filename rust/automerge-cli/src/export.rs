@@ -4,7 +4,7 @@ use automerge::ReadDoc;
 
 use crate::{color_json::print_colored_json, VerifyFlag};
 
-pub(crate) fn map_to_json(doc: &am::Automerge, obj: &am::ObjId) -> serde_json::Value {
+pub(crate) fn map_to_json<R: ReadDoc>(doc: &R, obj: &am::ObjId) -> serde_json::Value {
     let keys = doc.keys(obj);
     let mut map = serde_json::Map::new();
     for k in keys {
@@ -27,7 +27,7 @@ pub(crate) fn map_to_json(doc: &am::Automerge, obj: &am::ObjId) -> serde_json::V
     serde_json::Value::Object(map)
 }
 
-fn list_to_json(doc: &am::Automerge, obj: &am::ObjId) -> serde_json::Value {
+fn list_to_json<R: ReadDoc>(doc: &R, obj: &am::ObjId) -> serde_json::Value {
     let len = doc.length(obj);
     let mut array = Vec::new();
     for i in 0..len {
@@ -50,7 +50,7 @@ fn list_to_json(doc: &am::Automerge, obj: &am::ObjId) -> serde_json::Value {
     serde_json::Value::Array(array)
 }
 
-fn scalar_to_json(val: &am::ScalarValue) -> serde_json::Value {
+pub(crate) fn scalar_to_json(val: &am::ScalarValue) -> serde_json::Value {
     match val {
         am::ScalarValue::Str(s) => serde_json::Value::String(s.to_string()),
         am::ScalarValue::Bytes(b) | am::ScalarValue::Unknown { bytes: b, .. } => {
@@ -72,6 +72,40 @@ fn scalar_to_json(val: &am::ScalarValue) -> serde_json::Value {
     }
 }
 
+/// The JSON representation of `value`, which has the ID `id`
+pub(crate) fn value_to_json<R: ReadDoc>(
+    doc: &R,
+    value: &am::Value<'_>,
+    id: &am::ObjId,
+) -> serde_json::Value {
+    match value {
+        am::Value::Scalar(s) => scalar_to_json(s),
+        am::Value::Object(am::ObjType::Map | am::ObjType::Table) => map_to_json(doc, id),
+        am::Value::Object(am::ObjType::List) => list_to_json(doc, id),
+        am::Value::Object(am::ObjType::Text) => {
+            serde_json::Value::String(doc.text(id).unwrap_or_default())
+        }
+    }
+}
+
+/// A one line description of `value`, which has the ID `id`
+pub(crate) fn summary<R: ReadDoc>(doc: &R, value: &am::Value<'_>, id: &am::ObjId) -> String {
+    match value {
+        am::Value::Scalar(s) => match s.as_ref() {
+            am::ScalarValue::Counter(_) => format!("counter {}", scalar_to_json(s)),
+            am::ScalarValue::Timestamp(_) => format!("timestamp {}", scalar_to_json(s)),
+            am::ScalarValue::Bytes(_) => format!("bytes {}", scalar_to_json(s)),
+            _ => scalar_to_json(s).to_string(),
+        },
+        am::Value::Object(am::ObjType::Map | am::ObjType::Table) => "{...}".to_string(),
+        am::Value::Object(am::ObjType::List) => format!("[...] ({} items)", doc.length(id)),
+        am::Value::Object(am::ObjType::Text) => format!(
+            "text {}",
+            serde_json::Value::String(doc.text(id).unwrap_or_default())
+        ),
+    }
+}
+
 fn get_state_json(input_data: Vec<u8>, skip: VerifyFlag) -> Result<serde_json::Value> {
     let doc = skip.load(&input_data).unwrap(); // FIXME
     Ok(map_to_json(&doc, &am::ObjId::Root))
@@ -79,7 +113,7 @@ fn get_state_json(input_data: Vec<u8>, skip: VerifyFlag) -> Result<serde_json::V
 
 pub(crate) fn export_json(
     mut changes_reader: impl std::io::Read,
-    mut writer: impl std::io::Write,
+    writer: impl std::io::Write,
     skip: VerifyFlag,
     is_tty: bool,
 ) -> Result<()> {
@@ -87,14 +121,38 @@ pub(crate) fn export_json(
     changes_reader.read_to_end(&mut input_data)?;
 
     let state_json = get_state_json(input_data, skip)?;
+    write_state_json(&state_json, writer, is_tty)
+}
+
+/// Export the document as JSON which tags counters, timestamps, bytes, text and tables so that
+/// it can be imported again without losing their types
+pub(crate) fn export_typed_json(
+    mut changes_reader: impl std::io::Read,
+    writer: impl std::io::Write,
+    skip: VerifyFlag,
+    is_tty: bool,
+) -> Result<()> {
+    let mut input_data = vec![];
+    changes_reader.read_to_end(&mut input_data)?;
+
+    let doc = skip.load(&input_data)?;
+    let state_json = serde_json::to_value(am::TypedSerde::from(&doc))?;
+    write_state_json(&state_json, writer, is_tty)
+}
+
+fn write_state_json(
+    state_json: &serde_json::Value,
+    mut writer: impl std::io::Write,
+    is_tty: bool,
+) -> Result<()> {
     if is_tty {
-        print_colored_json(&state_json).unwrap();
+        print_colored_json(state_json).unwrap();
         writeln!(writer).unwrap();
     } else {
         writeln!(
             writer,
             "{}",
-            serde_json::to_string_pretty(&state_json).unwrap()
+            serde_json::to_string_pretty(state_json).unwrap()
         )?;
     }
     Ok(())
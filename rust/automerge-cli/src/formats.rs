@@ -0,0 +1,542 @@
+//! Import and export of documents as YAML, CBOR and MessagePack
+//!
+//! Unlike JSON these formats can tag values, so automerge types which have no natural
+//! representation in the format are preserved as follows:
+//!
+//! | automerge   | YAML                     | CBOR                           | MessagePack                      |
+//! |-------------|--------------------------|--------------------------------|----------------------------------|
+//! | `Bytes`     | `!bytes <base64>`        | byte string                    | bin                              |
+//! | `Counter`   | `!counter <int>`         | tag 58001 wrapping an integer  | ext type 1, big endian `i64`     |
+//! | `Timestamp` | `!timestamp <millis>`    | tag 1 (epoch seconds)          | ext type -1 (timestamp)          |
+//! | text object | `!text <string>`         | tag 58002 wrapping a string    | ext type 2, UTF-8                |
+//!
+//! Unsigned integers are imported as signed integers if they fit and `Unknown` scalar values are
+//! exported as their raw bytes.
+
+use std::{
+    convert::{TryFrom, TryInto},
+    io::{Read, Write},
+};
+
+use anyhow::{anyhow, bail, Result};
+use automerge as am;
+use automerge::{transaction::Transactable, ReadDoc};
+use base64::Engine;
+use serde_yaml::value::{Tag, TaggedValue};
+
+use crate::VerifyFlag;
+
+/// CBOR tag for counters, from the unassigned first come first served range
+const CBOR_COUNTER_TAG: u64 = 58001;
+/// CBOR tag for text objects, from the unassigned first come first served range
+const CBOR_TEXT_TAG: u64 = 58002;
+/// The standard CBOR tag for a date as seconds since the epoch
+const CBOR_EPOCH_TAG: u64 = 1;
+
+const MSGPACK_TIMESTAMP_EXT: i8 = -1;
+const MSGPACK_COUNTER_EXT: i8 = 1;
+const MSGPACK_TEXT_EXT: i8 = 2;
+
+/// A format which [`export`] and [`import`] understand
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    Yaml,
+    Cbor,
+    MessagePack,
+}
+
+/// The state of an object in a document, independent of any format
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Map(Vec<(String, Node)>),
+    List(Vec<Node>),
+    Text(String),
+    Scalar(am::ScalarValue),
+}
+
+pub(crate) fn export(
+    mut input: impl Read,
+    mut output: impl Write,
+    skip: VerifyFlag,
+    format: Format,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    input.read_to_end(&mut buf)?;
+    let doc = skip.load(&buf)?;
+    let root = Node::export(&doc, &am::ROOT, am::ObjType::Map);
+    match format {
+        Format::Yaml => serde_yaml::to_writer(output, &to_yaml(root))?,
+        Format::Cbor => ciborium::ser::into_writer(&to_cbor(root), output)?,
+        Format::MessagePack => rmpv::encode::write_value(&mut output, &to_msgpack(root))?,
+    }
+    Ok(())
+}
+
+pub(crate) fn import(mut input: impl Read, mut output: impl Write, format: Format) -> Result<()> {
+    let root = match format {
+        Format::Yaml => from_yaml(serde_yaml::from_reader(input)?)?,
+        Format::Cbor => from_cbor(ciborium::de::from_reader(input)?)?,
+        Format::MessagePack => from_msgpack(rmpv::decode::read_value(&mut input)?)?,
+    };
+    output.write_all(&root.import()?.save())?;
+    Ok(())
+}
+
+impl Node {
+    fn export<R: ReadDoc>(doc: &R, obj: &am::ObjId, obj_type: am::ObjType) -> Node {
+        match obj_type {
+            am::ObjType::Map | am::ObjType::Table => Node::Map(
+                doc.map_range(obj, ..)
+                    .map(|item| {
+                        (
+                            item.key.to_string(),
+                            Node::export_value(doc, item.value, &item.id),
+                        )
+                    })
+                    .collect(),
+            ),
+            am::ObjType::List => Node::List(
+                doc.list_range(obj, ..)
+                    .map(|item| Node::export_value(doc, item.value, &item.id))
+                    .collect(),
+            ),
+            am::ObjType::Text => Node::Text(doc.text(obj).unwrap_or_default()),
+        }
+    }
+
+    fn export_value<R: ReadDoc>(doc: &R, value: am::Value<'_>, id: &am::ObjId) -> Node {
+        match value {
+            am::Value::Object(obj_type) => Node::export(doc, id, obj_type),
+            am::Value::Scalar(s) => Node::Scalar(s.into_owned()),
+        }
+    }
+
+    fn import(self) -> Result<am::AutoCommit> {
+        let mut doc = am::AutoCommit::new();
+        match self {
+            Node::Map(entries) => {
+                for (key, node) in entries {
+                    node.put(&mut doc, &am::ROOT, key.into())?;
+                }
+                Ok(doc)
+            }
+            _ => bail!("expected a map at the root of the document"),
+        }
+    }
+
+    fn put(self, doc: &mut am::AutoCommit, obj: &am::ObjId, prop: am::Prop) -> Result<()> {
+        let id = match &self {
+            Node::Scalar(s) => return Ok(doc.put(obj, prop, s.clone())?),
+            Node::Map(_) => doc.put_object(obj, prop, am::ObjType::Map)?,
+            Node::List(_) => doc.put_object(obj, prop, am::ObjType::List)?,
+            Node::Text(_) => doc.put_object(obj, prop, am::ObjType::Text)?,
+        };
+        self.fill(doc, &id)
+    }
+
+    fn insert(self, doc: &mut am::AutoCommit, obj: &am::ObjId, index: usize) -> Result<()> {
+        let id = match &self {
+            Node::Scalar(s) => return Ok(doc.insert(obj, index, s.clone())?),
+            Node::Map(_) => doc.insert_object(obj, index, am::ObjType::Map)?,
+            Node::List(_) => doc.insert_object(obj, index, am::ObjType::List)?,
+            Node::Text(_) => doc.insert_object(obj, index, am::ObjType::Text)?,
+        };
+        self.fill(doc, &id)
+    }
+
+    /// Fill the newly created object `obj` with the contents of this node
+    fn fill(self, doc: &mut am::AutoCommit, obj: &am::ObjId) -> Result<()> {
+        match self {
+            Node::Map(entries) => {
+                for (key, node) in entries {
+                    node.put(doc, obj, key.into())?;
+                }
+            }
+            Node::List(nodes) => {
+                for (index, node) in nodes.into_iter().enumerate() {
+                    node.insert(doc, obj, index)?;
+                }
+            }
+            Node::Text(text) => doc.splice_text(obj, 0, 0, &text)?,
+            Node::Scalar(_) => {}
+        }
+        Ok(())
+    }
+}
+
+fn to_yaml(node: Node) -> serde_yaml::Value {
+    use serde_yaml::Value;
+    let tagged = |tag: &str, value: Value| {
+        Value::Tagged(Box::new(TaggedValue {
+            tag: Tag::new(tag),
+            value,
+        }))
+    };
+    match node {
+        Node::Map(entries) => Value::Mapping(
+            entries
+                .into_iter()
+                .map(|(key, node)| (Value::String(key), to_yaml(node)))
+                .collect(),
+        ),
+        Node::List(nodes) => Value::Sequence(nodes.into_iter().map(to_yaml).collect()),
+        Node::Text(text) => tagged("text", Value::String(text)),
+        Node::Scalar(s) => match s {
+            am::ScalarValue::Bytes(b) | am::ScalarValue::Unknown { bytes: b, .. } => tagged(
+                "bytes",
+                Value::String(base64::engine::general_purpose::STANDARD.encode(b)),
+            ),
+            am::ScalarValue::Str(s) => Value::String(s.to_string()),
+            am::ScalarValue::Int(n) => Value::Number(n.into()),
+            am::ScalarValue::Uint(n) => Value::Number(n.into()),
+            am::ScalarValue::F64(n) => Value::Number(n.into()),
+            am::ScalarValue::Counter(c) => tagged("counter", Value::Number(i64::from(&c).into())),
+            am::ScalarValue::Timestamp(n) => tagged("timestamp", Value::Number(n.into())),
+            am::ScalarValue::Boolean(b) => Value::Bool(b),
+            am::ScalarValue::Null => Value::Null,
+        },
+    }
+}
+
+fn from_yaml(value: serde_yaml::Value) -> Result<Node> {
+    use serde_yaml::Value;
+    Ok(match value {
+        Value::Null => Node::Scalar(am::ScalarValue::Null),
+        Value::Bool(b) => Node::Scalar(b.into()),
+        Value::Number(n) => Node::Scalar(yaml_number(&n)?),
+        Value::String(s) => Node::Scalar(s.into()),
+        Value::Sequence(values) => {
+            Node::List(values.into_iter().map(from_yaml).collect::<Result<_>>()?)
+        }
+        Value::Mapping(mapping) => Node::Map(
+            mapping
+                .into_iter()
+                .map(|(key, value)| match key {
+                    Value::String(key) => Ok((key, from_yaml(value)?)),
+                    other => Err(anyhow!("map keys must be strings, got {:?}", other)),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        Value::Tagged(tagged) => {
+            let TaggedValue { tag, value } = *tagged;
+            match value {
+                Value::String(s) if tag == "text" => Node::Text(s),
+                Value::String(s) if tag == "bytes" => {
+                    let bytes = base64::engine::general_purpose::STANDARD
+                        .decode(s.split_whitespace().collect::<String>())?;
+                    Node::Scalar(am::ScalarValue::Bytes(bytes))
+                }
+                Value::Number(n) if tag == "counter" => {
+                    Node::Scalar(am::ScalarValue::counter(yaml_int(&n)?))
+                }
+                Value::Number(n) if tag == "timestamp" => {
+                    Node::Scalar(am::ScalarValue::Timestamp(yaml_int(&n)?))
+                }
+                value => bail!("unexpected YAML tag {} on {:?}", tag, value),
+            }
+        }
+    })
+}
+
+fn yaml_number(n: &serde_yaml::Number) -> Result<am::ScalarValue> {
+    if let Some(n) = n.as_i64() {
+        Ok(n.into())
+    } else if let Some(n) = n.as_u64() {
+        Ok(n.into())
+    } else if let Some(n) = n.as_f64() {
+        Ok(n.into())
+    } else {
+        bail!("not a number")
+    }
+}
+
+fn yaml_int(n: &serde_yaml::Number) -> Result<i64> {
+    n.as_i64()
+        .ok_or_else(|| anyhow!("expected an integer, got {}", n))
+}
+
+fn to_cbor(node: Node) -> ciborium::value::Value {
+    use ciborium::value::Value;
+    match node {
+        Node::Map(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, node)| (Value::Text(key), to_cbor(node)))
+                .collect(),
+        ),
+        Node::List(nodes) => Value::Array(nodes.into_iter().map(to_cbor).collect()),
+        Node::Text(text) => Value::Tag(CBOR_TEXT_TAG, Box::new(Value::Text(text))),
+        Node::Scalar(s) => match s {
+            am::ScalarValue::Bytes(b) | am::ScalarValue::Unknown { bytes: b, .. } => {
+                Value::Bytes(b)
+            }
+            am::ScalarValue::Str(s) => Value::Text(s.to_string()),
+            am::ScalarValue::Int(n) => Value::Integer(n.into()),
+            am::ScalarValue::Uint(n) => Value::Integer(n.into()),
+            am::ScalarValue::F64(n) => Value::Float(n),
+            am::ScalarValue::Counter(c) => Value::Tag(
+                CBOR_COUNTER_TAG,
+                Box::new(Value::Integer(i64::from(&c).into())),
+            ),
+            am::ScalarValue::Timestamp(millis) => {
+                let seconds = if millis % 1000 == 0 {
+                    Value::Integer((millis / 1000).into())
+                } else {
+                    Value::Float(millis as f64 / 1000.0)
+                };
+                Value::Tag(CBOR_EPOCH_TAG, Box::new(seconds))
+            }
+            am::ScalarValue::Boolean(b) => Value::Bool(b),
+            am::ScalarValue::Null => Value::Null,
+        },
+    }
+}
+
+fn from_cbor(value: ciborium::value::Value) -> Result<Node> {
+    use ciborium::value::Value;
+    Ok(match value {
+        Value::Null => Node::Scalar(am::ScalarValue::Null),
+        Value::Bool(b) => Node::Scalar(b.into()),
+        Value::Integer(n) => Node::Scalar(cbor_integer(n)?),
+        Value::Float(n) => Node::Scalar(n.into()),
+        Value::Text(s) => Node::Scalar(s.into()),
+        Value::Bytes(b) => Node::Scalar(am::ScalarValue::Bytes(b)),
+        Value::Array(values) => {
+            Node::List(values.into_iter().map(from_cbor).collect::<Result<_>>()?)
+        }
+        Value::Map(entries) => Node::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| match key {
+                    Value::Text(key) => Ok((key, from_cbor(value)?)),
+                    other => Err(anyhow!("map keys must be strings, got {:?}", other)),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        Value::Tag(tag, value) => match (tag, *value) {
+            (CBOR_TEXT_TAG, Value::Text(s)) => Node::Text(s),
+            (CBOR_COUNTER_TAG, Value::Integer(n)) => {
+                Node::Scalar(am::ScalarValue::counter(i64::try_from(n)?))
+            }
+            (CBOR_EPOCH_TAG, Value::Integer(seconds)) => {
+                let millis = i64::try_from(seconds)?
+                    .checked_mul(1000)
+                    .ok_or_else(|| anyhow!("timestamp out of range"))?;
+                Node::Scalar(am::ScalarValue::Timestamp(millis))
+            }
+            (CBOR_EPOCH_TAG, Value::Float(seconds)) => {
+                Node::Scalar(am::ScalarValue::Timestamp((seconds * 1000.0).round() as i64))
+            }
+            // Other tags carry no meaning for automerge, so keep the value they wrap
+            (_, value) => from_cbor(value)?,
+        },
+        other => bail!("unsupported CBOR value {:?}", other),
+    })
+}
+
+fn cbor_integer(n: ciborium::value::Integer) -> Result<am::ScalarValue> {
+    if let Ok(n) = i64::try_from(n) {
+        Ok(n.into())
+    } else {
+        Ok(u64::try_from(n)?.into())
+    }
+}
+
+fn to_msgpack(node: Node) -> rmpv::Value {
+    use rmpv::Value;
+    match node {
+        Node::Map(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, node)| (Value::from(key), to_msgpack(node)))
+                .collect(),
+        ),
+        Node::List(nodes) => Value::Array(nodes.into_iter().map(to_msgpack).collect()),
+        Node::Text(text) => Value::Ext(MSGPACK_TEXT_EXT, text.into_bytes()),
+        Node::Scalar(s) => match s {
+            am::ScalarValue::Bytes(b) | am::ScalarValue::Unknown { bytes: b, .. } => {
+                Value::Binary(b)
+            }
+            am::ScalarValue::Str(s) => Value::from(s.as_str()),
+            am::ScalarValue::Int(n) => Value::from(n),
+            am::ScalarValue::Uint(n) => Value::from(n),
+            am::ScalarValue::F64(n) => Value::from(n),
+            am::ScalarValue::Counter(c) => {
+                Value::Ext(MSGPACK_COUNTER_EXT, i64::from(&c).to_be_bytes().to_vec())
+            }
+            am::ScalarValue::Timestamp(millis) => {
+                // The 96 bit form of the timestamp extension, which can hold any i64 seconds
+                let nanos = (millis.rem_euclid(1000) * 1_000_000) as u32;
+                let seconds = millis.div_euclid(1000);
+                let mut bytes = nanos.to_be_bytes().to_vec();
+                bytes.extend_from_slice(&seconds.to_be_bytes());
+                Value::Ext(MSGPACK_TIMESTAMP_EXT, bytes)
+            }
+            am::ScalarValue::Boolean(b) => Value::Boolean(b),
+            am::ScalarValue::Null => Value::Nil,
+        },
+    }
+}
+
+fn from_msgpack(value: rmpv::Value) -> Result<Node> {
+    use rmpv::Value;
+    Ok(match value {
+        Value::Nil => Node::Scalar(am::ScalarValue::Null),
+        Value::Boolean(b) => Node::Scalar(b.into()),
+        Value::Integer(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => Node::Scalar(n.into()),
+            (None, Some(n)) => Node::Scalar(n.into()),
+            (None, None) => bail!("integer out of range: {}", n),
+        },
+        Value::F32(n) => Node::Scalar(f64::from(n).into()),
+        Value::F64(n) => Node::Scalar(n.into()),
+        Value::String(s) => match s.into_str() {
+            Some(s) => Node::Scalar(s.into()),
+            None => bail!("string is not valid UTF-8"),
+        },
+        Value::Binary(b) => Node::Scalar(am::ScalarValue::Bytes(b)),
+        Value::Array(values) => Node::List(
+            values
+                .into_iter()
+                .map(from_msgpack)
+                .collect::<Result<_>>()?,
+        ),
+        Value::Map(entries) => Node::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| match key {
+                    Value::String(key) => match key.into_str() {
+                        Some(key) => Ok((key, from_msgpack(value)?)),
+                        None => Err(anyhow!("map key is not valid UTF-8")),
+                    },
+                    other => Err(anyhow!("map keys must be strings, got {}", other)),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        Value::Ext(MSGPACK_TEXT_EXT, bytes) => Node::Text(String::from_utf8(bytes)?),
+        Value::Ext(MSGPACK_COUNTER_EXT, bytes) => {
+            let bytes: [u8; 8] = bytes
+                .try_into()
+                .map_err(|_| anyhow!("a counter must be 8 bytes"))?;
+            Node::Scalar(am::ScalarValue::counter(i64::from_be_bytes(bytes)))
+        }
+        Value::Ext(MSGPACK_TIMESTAMP_EXT, bytes) => {
+            Node::Scalar(am::ScalarValue::Timestamp(msgpack_timestamp(&bytes)?))
+        }
+        Value::Ext(ext, _) => bail!("unsupported MessagePack extension type {}", ext),
+    })
+}
+
+/// Milliseconds since the epoch of any of the three forms of the MessagePack timestamp extension
+fn msgpack_timestamp(bytes: &[u8]) -> Result<i64> {
+    let (seconds, nanos) = match bytes.len() {
+        4 => (i64::from(u32::from_be_bytes(bytes.try_into()?)), 0),
+        8 => {
+            let data = u64::from_be_bytes(bytes.try_into()?);
+            ((data & 0x3_ffff_ffff) as i64, (data >> 34) as u32)
+        }
+        12 => (
+            i64::from_be_bytes(bytes[4..].try_into()?),
+            u32::from_be_bytes(bytes[..4].try_into()?),
+        ),
+        n => bail!("a timestamp must be 4, 8 or 12 bytes, got {}", n),
+    };
+    seconds
+        .checked_mul(1000)
+        .and_then(|millis| millis.checked_add(i64::from(nanos / 1_000_000)))
+        .ok_or_else(|| anyhow!("timestamp out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Vec<u8> {
+        let mut doc = am::AutoCommit::new();
+        doc.put(am::ROOT, "bytes", am::ScalarValue::Bytes(vec![0, 1, 255]))
+            .unwrap();
+        doc.put(am::ROOT, "count", am::ScalarValue::counter(3))
+            .unwrap();
+        doc.put(
+            am::ROOT,
+            "at",
+            am::ScalarValue::Timestamp(1_600_000_000_123),
+        )
+        .unwrap();
+        doc.put(am::ROOT, "before", am::ScalarValue::Timestamp(-1500))
+            .unwrap();
+        let birds = doc
+            .put_object(am::ROOT, "birds", am::ObjType::List)
+            .unwrap();
+        doc.insert(&birds, 0, "wren").unwrap();
+        doc.insert(&birds, 1, 2.5).unwrap();
+        doc.insert(&birds, 2, am::ScalarValue::Null).unwrap();
+        let note = doc.insert_object(&birds, 3, am::ObjType::Text).unwrap();
+        doc.splice_text(&note, 0, 0, "hello").unwrap();
+        let nested = doc
+            .put_object(am::ROOT, "nested", am::ObjType::Map)
+            .unwrap();
+        doc.put(&nested, "flag", true).unwrap();
+        doc.put(&nested, "n", -7).unwrap();
+        doc.save()
+    }
+
+    fn round_trip(format: Format) {
+        let saved = example();
+        let mut exported = Vec::new();
+        export(
+            saved.as_slice(),
+            &mut exported,
+            VerifyFlag::default(),
+            format,
+        )
+        .unwrap();
+        let mut imported = Vec::new();
+        import(exported.as_slice(), &mut imported, format).unwrap();
+
+        let before = am::Automerge::load(&saved).unwrap();
+        let after = am::Automerge::load(&imported).unwrap();
+        assert_eq!(
+            Node::export(&before, &am::ROOT, am::ObjType::Map),
+            Node::export(&after, &am::ROOT, am::ObjType::Map)
+        );
+    }
+
+    #[test]
+    fn yaml_round_trip() {
+        round_trip(Format::Yaml);
+    }
+
+    #[test]
+    fn cbor_round_trip() {
+        round_trip(Format::Cbor);
+    }
+
+    #[test]
+    fn msgpack_round_trip() {
+        round_trip(Format::MessagePack);
+    }
+
+    #[test]
+    fn yaml_tags() {
+        let yaml = "count: !counter 3\nat: !timestamp 1000\ndata: !bytes AAH/\nnote: !text hi\n";
+        assert_eq!(
+            from_yaml(serde_yaml::from_str(yaml).unwrap()).unwrap(),
+            Node::Map(vec![
+                (
+                    "count".to_string(),
+                    Node::Scalar(am::ScalarValue::counter(3))
+                ),
+                (
+                    "at".to_string(),
+                    Node::Scalar(am::ScalarValue::Timestamp(1000))
+                ),
+                (
+                    "data".to_string(),
+                    Node::Scalar(am::ScalarValue::Bytes(vec![0, 1, 255]))
+                ),
+                ("note".to_string(), Node::Text("hi".to_string())),
+            ])
+        );
+    }
+}
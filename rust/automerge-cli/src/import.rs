@@ -20,36 +20,7 @@ fn import_map(
     map: &serde_json::Map<String, serde_json::Value>,
 ) -> anyhow::Result<()> {
     for (key, value) in map {
-        match value {
-            serde_json::Value::Null => {
-                doc.put(obj, key, ())?;
-            }
-            serde_json::Value::Bool(b) => {
-                doc.put(obj, key, *b)?;
-            }
-            serde_json::Value::String(s) => {
-                doc.put(obj, key, s)?;
-            }
-            serde_json::Value::Array(vec) => {
-                let id = doc.put_object(obj, key, am::ObjType::List)?;
-                import_list(doc, &id, vec)?;
-            }
-            serde_json::Value::Number(n) => {
-                if let Some(m) = n.as_i64() {
-                    doc.put(obj, key, m)?;
-                } else if let Some(m) = n.as_u64() {
-                    doc.put(obj, key, m)?;
-                } else if let Some(m) = n.as_f64() {
-                    doc.put(obj, key, m)?;
-                } else {
-                    anyhow::bail!("not a number");
-                }
-            }
-            serde_json::Value::Object(map) => {
-                let id = doc.put_object(obj, key, am::ObjType::Map)?;
-                import_map(doc, &id, map)?;
-            }
-        }
+        put_json(doc, obj, key.as_str().into(), value)?;
     }
     Ok(())
 }
@@ -60,40 +31,73 @@ fn import_list(
     list: &[serde_json::Value],
 ) -> anyhow::Result<()> {
     for (i, value) in list.iter().enumerate() {
-        match value {
-            serde_json::Value::Null => {
-                doc.insert(obj, i, ())?;
-            }
-            serde_json::Value::Bool(b) => {
-                doc.insert(obj, i, *b)?;
-            }
-            serde_json::Value::String(s) => {
-                doc.insert(obj, i, s)?;
-            }
-            serde_json::Value::Array(vec) => {
-                let id = doc.insert_object(obj, i, am::ObjType::List)?;
-                import_list(doc, &id, vec)?;
-            }
-            serde_json::Value::Number(n) => {
-                if let Some(m) = n.as_i64() {
-                    doc.insert(obj, i, m)?;
-                } else if let Some(m) = n.as_u64() {
-                    doc.insert(obj, i, m)?;
-                } else if let Some(m) = n.as_f64() {
-                    doc.insert(obj, i, m)?;
-                } else {
-                    anyhow::bail!("not a number");
-                }
-            }
-            serde_json::Value::Object(map) => {
-                let id = doc.insert_object(obj, i, am::ObjType::Map)?;
-                import_map(doc, &id, map)?;
-            }
+        insert_json(doc, obj, i, value)?;
+    }
+    Ok(())
+}
+
+/// Set `prop` in `obj` to `value`, creating maps and lists for JSON objects and arrays
+pub(crate) fn put_json(
+    doc: &mut am::AutoCommit,
+    obj: &am::ObjId,
+    prop: am::Prop,
+    value: &serde_json::Value,
+) -> anyhow::Result<()> {
+    match value {
+        serde_json::Value::Array(vec) => {
+            let id = doc.put_object(obj, prop, am::ObjType::List)?;
+            import_list(doc, &id, vec)?;
+        }
+        serde_json::Value::Object(map) => {
+            let id = doc.put_object(obj, prop, am::ObjType::Map)?;
+            import_map(doc, &id, map)?;
         }
+        scalar => doc.put(obj, prop, json_to_scalar(scalar)?)?,
     }
     Ok(())
 }
 
+/// Insert `value` at `index` in `obj`, creating maps and lists for JSON objects and arrays
+pub(crate) fn insert_json(
+    doc: &mut am::AutoCommit,
+    obj: &am::ObjId,
+    index: usize,
+    value: &serde_json::Value,
+) -> anyhow::Result<()> {
+    match value {
+        serde_json::Value::Array(vec) => {
+            let id = doc.insert_object(obj, index, am::ObjType::List)?;
+            import_list(doc, &id, vec)?;
+        }
+        serde_json::Value::Object(map) => {
+            let id = doc.insert_object(obj, index, am::ObjType::Map)?;
+            import_map(doc, &id, map)?;
+        }
+        scalar => doc.insert(obj, index, json_to_scalar(scalar)?)?,
+    }
+    Ok(())
+}
+
+fn json_to_scalar(value: &serde_json::Value) -> anyhow::Result<am::ScalarValue> {
+    match value {
+        serde_json::Value::Null => Ok(am::ScalarValue::Null),
+        serde_json::Value::Bool(b) => Ok((*b).into()),
+        serde_json::Value::String(s) => Ok(s.as_str().into()),
+        serde_json::Value::Number(n) => {
+            if let Some(m) = n.as_i64() {
+                Ok(m.into())
+            } else if let Some(m) = n.as_u64() {
+                Ok(m.into())
+            } else if let Some(m) = n.as_f64() {
+                Ok(m.into())
+            } else {
+                anyhow::bail!("not a number");
+            }
+        }
+        _ => anyhow::bail!("not a scalar"),
+    }
+}
+
 pub fn import_json(
     mut reader: impl std::io::Read,
     mut writer: impl std::io::Write,
@@ -106,3 +110,15 @@ pub fn import_json(
     writer.write_all(&doc.save())?;
     Ok(())
 }
+
+/// Import JSON written by `export --format typed-json`, restoring the types of tagged values
+pub fn import_typed_json(
+    reader: impl std::io::Read,
+    mut writer: impl std::io::Write,
+) -> anyhow::Result<()> {
+    let snapshot: am::TypedDoc = serde_json::from_reader(reader)?;
+    let mut doc = am::AutoCommit::new();
+    snapshot.apply(&mut doc)?;
+    writer.write_all(&doc.save())?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use automerge as am;
+use thiserror::Error;
+
+use crate::{
+    report::{write_json, write_table},
+    ReportFormat, VerifyFlag,
+};
+
+#[derive(Error, Debug)]
+pub enum LogError {
+    #[error("Error reading change file: {:?}", source)]
+    ReadingChanges {
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Error loading changes: {:?}", source)]
+    ApplyingInitialChanges {
+        #[source]
+        source: am::AutomergeError,
+    },
+    #[error("Error writing to output: {:?}", source)]
+    WritingToOutput {
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+pub(crate) fn log(
+    mut input: impl std::io::Read,
+    output: impl std::io::Write,
+    skip: VerifyFlag,
+    format: ReportFormat,
+    graph: bool,
+    is_tty: bool,
+) -> Result<(), LogError> {
+    let mut buf: Vec<u8> = Vec::new();
+    input
+        .read_to_end(&mut buf)
+        .map_err(|e| LogError::ReadingChanges { source: e })?;
+    let doc = skip
+        .load(&buf)
+        .map_err(|e| LogError::ApplyingInitialChanges { source: e })?;
+    // Changes are stored in the order they were applied, which is a topological order
+    let changes = doc.get_changes(&[]);
+    match format {
+        ReportFormat::Json => {
+            let json = changes.iter().map(|c| change_to_json(c)).collect();
+            write_json(output, &serde_json::Value::Array(json), is_tty)
+        }
+        ReportFormat::Table => {
+            let mut headers = vec!["hash", "actor", "seq", "time", "deps", "message"];
+            let mut rows = changes
+                .iter()
+                .map(|c| {
+                    vec![
+                        c.hash().to_string(),
+                        c.actor_id().to_string(),
+                        c.seq().to_string(),
+                        c.timestamp().to_string(),
+                        c.deps()
+                            .iter()
+                            .map(|d| d.to_string())
+                            .collect::<Vec<_>>()
+                            .join(","),
+                        c.message().cloned().unwrap_or_default(),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            if graph {
+                headers.insert(0, "");
+                for (row, lanes) in rows.iter_mut().zip(draw_graph(&changes)) {
+                    row.insert(0, lanes);
+                }
+            }
+            write_table(output, &headers, &rows)
+        }
+    }
+    .map_err(|e| LogError::WritingToOutput { source: e })
+}
+
+fn change_to_json(change: &am::Change) -> serde_json::Value {
+    serde_json::json!({
+        "hash": change.hash(),
+        "actor": change.actor_id(),
+        "seq": change.seq(),
+        "startOp": change.start_op().get(),
+        "time": change.timestamp(),
+        "message": change.message(),
+        "deps": change.deps(),
+    })
+}
+
+/// Draw the lanes of the change graph for each of `changes`, which must be in topological order
+///
+/// Each lane ends at a change which no change seen so far depends on. A change continues the
+/// leftmost lane ending at one of its dependencies, marked with `*`, and any other lanes ending
+/// at its dependencies merge into it.
+fn draw_graph(changes: &[&am::Change]) -> Vec<String> {
+    let mut lanes: Vec<am::ChangeHash> = Vec::new();
+    changes
+        .iter()
+        .map(|change| {
+            let deps = change.deps();
+            let position = match lanes.iter().position(|lane| deps.contains(lane)) {
+                Some(position) => position,
+                None => {
+                    lanes.push(change.hash());
+                    lanes.len() - 1
+                }
+            };
+            lanes[position] = change.hash();
+            let mut current = 0;
+            let mut kept = Vec::with_capacity(lanes.len());
+            for (i, lane) in lanes.iter().enumerate() {
+                if i == position {
+                    current = kept.len();
+                    kept.push(*lane);
+                } else if !deps.contains(lane) {
+                    kept.push(*lane);
+                }
+            }
+            lanes = kept;
+            (0..lanes.len())
+                .map(|i| if i == current { "*" } else { "|" })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use automerge::transaction::Transactable;
+
+    #[test]
+    fn graph_follows_forks_and_merges() {
+        let mut doc = am::AutoCommit::new();
+        doc.put(am::ROOT, "a", 1).unwrap();
+        doc.commit();
+        let mut fork = doc.fork();
+        fork.put(am::ROOT, "b", 1).unwrap();
+        fork.commit();
+        doc.put(am::ROOT, "c", 1).unwrap();
+        doc.commit();
+        doc.merge(&mut fork).unwrap();
+        doc.put(am::ROOT, "d", 1).unwrap();
+        doc.commit();
+
+        let changes = doc.get_changes(&[]);
+        assert_eq!(draw_graph(&changes), vec!["*", "*", "| *", "*"]);
+    }
+
+    #[test]
+    fn log_as_json() {
+        let mut doc = am::AutoCommit::new();
+        doc.put(am::ROOT, "a", 1).unwrap();
+        let first = doc
+            .commit_with(am::transaction::CommitOptions::default().with_message("first"))
+            .unwrap();
+        doc.put(am::ROOT, "a", 2).unwrap();
+        let second = doc.commit().unwrap();
+
+        let mut out = Vec::new();
+        log(
+            doc.save().as_slice(),
+            &mut out,
+            VerifyFlag::default(),
+            ReportFormat::Json,
+            false,
+            false,
+        )
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json[0]["hash"], first.to_string());
+        assert_eq!(json[0]["message"], "first");
+        assert_eq!(json[1]["hash"], second.to_string());
+        assert_eq!(json[1]["deps"], serde_json::json!([first.to_string()]));
+        assert_eq!(json[1]["seq"], 2);
+    }
+}
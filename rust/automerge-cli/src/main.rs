@@ -7,12 +7,19 @@ use clap::{
 };
 use is_terminal::IsTerminal;
 
+mod blame;
+mod change;
 mod color_json;
+mod diff;
 mod examine;
 mod examine_sync;
 mod export;
+mod formats;
 mod import;
+mod log;
 mod merge;
+mod report;
+mod shell;
 
 #[derive(Parser, Debug)]
 #[clap(about = "Automerge CLI")]
@@ -24,7 +31,22 @@ struct Opts {
 #[derive(clap::ValueEnum, Clone, Debug)]
 enum ExportFormat {
     Json,
+    /// JSON which tags counters, timestamps, bytes, text and tables so they survive a round trip
+    TypedJson,
     Toml,
+    Yaml,
+    Cbor,
+    #[clap(name = "msgpack")]
+    MessagePack,
+}
+
+/// How reports about a document, such as its log, are printed
+#[derive(clap::ValueEnum, Copy, Clone, Debug)]
+pub(crate) enum ReportFormat {
+    /// A table for humans
+    Table,
+    /// JSON for other programs
+    Json,
 }
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -38,6 +60,17 @@ impl VerifyFlag {
             automerge::Automerge::load_unverified_heads(buf)
         }
     }
+
+    fn load_autocommit(
+        &self,
+        buf: &[u8],
+    ) -> Result<automerge::AutoCommit, automerge::AutomergeError> {
+        if self.0 {
+            automerge::AutoCommit::load(buf)
+        } else {
+            automerge::AutoCommit::load_unverified_heads(buf)
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -71,7 +104,11 @@ impl FromStr for ExportFormat {
     fn from_str(input: &str) -> Result<ExportFormat> {
         match input {
             "json" => Ok(ExportFormat::Json),
+            "typed-json" => Ok(ExportFormat::TypedJson),
             "toml" => Ok(ExportFormat::Toml),
+            "yaml" => Ok(ExportFormat::Yaml),
+            "cbor" => Ok(ExportFormat::Cbor),
+            "msgpack" => Ok(ExportFormat::MessagePack),
             _ => Err(anyhow!("Invalid export format: {}", input)),
         }
     }
@@ -81,7 +118,7 @@ impl FromStr for ExportFormat {
 enum Command {
     /// Output current state of an Automerge document in a specified format
     Export {
-        /// Format for output: json, toml
+        /// Format for output: json, typed-json, toml, yaml, cbor, msgpack
         #[clap(long, short, default_value = "json")]
         format: ExportFormat,
 
@@ -98,7 +135,7 @@ enum Command {
     },
 
     Import {
-        /// Format for input: json, toml
+        /// Format for input: json, typed-json, toml, yaml, cbor, msgpack
         #[clap(long, short, default_value = "json")]
         format: ExportFormat,
 
@@ -130,6 +167,76 @@ enum Command {
         /// The file(s) to compact. If empty assumes stdin
         input: Vec<PathBuf>,
     },
+
+    /// Print the changes in an automerge document in topological order
+    Log {
+        input_file: Option<PathBuf>,
+
+        /// Format for output: table, json
+        #[clap(long, short, value_enum, default_value = "table")]
+        format: ReportFormat,
+
+        /// Draw the graph of changes and their dependencies next to the table
+        #[clap(long)]
+        graph: bool,
+
+        /// Whether to verify the head hashes of a compressed document
+        #[clap(long, action = clap::ArgAction::SetFalse)]
+        skip_verifying_heads: VerifyFlag,
+    },
+
+    /// Print the change responsible for each value in an object, or for each run of characters
+    /// in a text object
+    Blame {
+        input_file: PathBuf,
+
+        /// The path of the object, e.g. `$["birds"][0]`
+        path: String,
+
+        /// Format for output: table, json
+        #[clap(long, short, value_enum, default_value = "table")]
+        format: ReportFormat,
+
+        /// Whether to verify the head hashes of a compressed document
+        #[clap(long, action = clap::ArgAction::SetFalse)]
+        skip_verifying_heads: VerifyFlag,
+    },
+
+    /// Print the patches which take a document from one set of heads to another. Given a second
+    /// file, merge the two and print the patches which take each file to the merged document
+    Diff {
+        input_file: PathBuf,
+
+        /// A second document to merge with the first
+        #[clap(conflicts_with_all = ["from", "to"])]
+        other_file: Option<PathBuf>,
+
+        /// Comma separated heads to diff from. Defaults to the empty document
+        #[clap(long, value_delimiter = ',')]
+        from: Vec<automerge::ChangeHash>,
+
+        /// Comma separated heads to diff to. Defaults to the current heads
+        #[clap(long, value_delimiter = ',')]
+        to: Vec<automerge::ChangeHash>,
+
+        /// Format for output: table, json
+        #[clap(long, short, value_enum, default_value = "table")]
+        format: ReportFormat,
+
+        /// Whether to verify the head hashes of a compressed document
+        #[clap(long, action = clap::ArgAction::SetFalse)]
+        skip_verifying_heads: VerifyFlag,
+    },
+
+    /// Open an automerge document in an interactive shell for inspecting and editing it
+    Shell {
+        /// The document to open. It is created when first saved if it does not exist
+        input_file: PathBuf,
+
+        /// Whether to verify the head hashes of a compressed document
+        #[clap(long, action = clap::ArgAction::SetFalse)]
+        skip_verifying_heads: VerifyFlag,
+    },
 }
 
 fn open_file_or_stdin(maybe_path: Option<PathBuf>) -> Result<Box<dyn std::io::Read>> {
@@ -183,7 +290,31 @@ fn main() -> Result<()> {
                         std::io::stdout().is_terminal(),
                     )
                 }
+                ExportFormat::TypedJson => export::export_typed_json(
+                    open_file_or_stdin(changes_file)?,
+                    output,
+                    skip_verifying_heads,
+                    std::io::stdout().is_terminal(),
+                ),
                 ExportFormat::Toml => unimplemented!(),
+                ExportFormat::Yaml => formats::export(
+                    open_file_or_stdin(changes_file)?,
+                    output,
+                    skip_verifying_heads,
+                    formats::Format::Yaml,
+                ),
+                ExportFormat::Cbor => formats::export(
+                    open_file_or_stdin(changes_file)?,
+                    output,
+                    skip_verifying_heads,
+                    formats::Format::Cbor,
+                ),
+                ExportFormat::MessagePack => formats::export(
+                    open_file_or_stdin(changes_file)?,
+                    output,
+                    skip_verifying_heads,
+                    formats::Format::MessagePack,
+                ),
             }
         }
         Command::Import {
@@ -196,7 +327,26 @@ fn main() -> Result<()> {
                 let mut in_buffer = open_file_or_stdin(input_file)?;
                 import::import_json(&mut in_buffer, &mut out_buffer)
             }
+            ExportFormat::TypedJson => import::import_typed_json(
+                open_file_or_stdin(input_file)?,
+                create_file_or_stdout(changes_file)?,
+            ),
             ExportFormat::Toml => unimplemented!(),
+            ExportFormat::Yaml => formats::import(
+                open_file_or_stdin(input_file)?,
+                create_file_or_stdout(changes_file)?,
+                formats::Format::Yaml,
+            ),
+            ExportFormat::Cbor => formats::import(
+                open_file_or_stdin(input_file)?,
+                create_file_or_stdout(changes_file)?,
+                formats::Format::Cbor,
+            ),
+            ExportFormat::MessagePack => formats::import(
+                open_file_or_stdin(input_file)?,
+                create_file_or_stdout(changes_file)?,
+                formats::Format::MessagePack,
+            ),
         },
         Command::Examine {
             input_file,
@@ -239,5 +389,81 @@ fn main() -> Result<()> {
             };
             Ok(())
         }
+        Command::Log {
+            input_file,
+            format,
+            graph,
+            skip_verifying_heads,
+        } => {
+            let in_buffer = open_file_or_stdin(input_file)?;
+            log::log(
+                in_buffer,
+                std::io::stdout(),
+                skip_verifying_heads,
+                format,
+                graph,
+                std::io::stdout().is_terminal(),
+            )?;
+            Ok(())
+        }
+        Command::Blame {
+            input_file,
+            path,
+            format,
+            skip_verifying_heads,
+        } => {
+            let doc = skip_verifying_heads.load(&std::fs::read(input_file)?)?;
+            blame::blame(
+                &doc,
+                &path,
+                std::io::stdout(),
+                format,
+                std::io::stdout().is_terminal(),
+            )?;
+            Ok(())
+        }
+        Command::Diff {
+            input_file,
+            other_file,
+            from,
+            to,
+            format,
+            skip_verifying_heads,
+        } => {
+            let compare = match other_file {
+                Some(other_file) => diff::Compare::Files(input_file, other_file),
+                None => diff::Compare::Heads {
+                    file: input_file,
+                    from,
+                    to,
+                },
+            };
+            diff::diff(
+                compare,
+                std::io::stdout(),
+                skip_verifying_heads,
+                format,
+                std::io::stdout().is_terminal(),
+            )?;
+            Ok(())
+        }
+        Command::Shell {
+            input_file,
+            skip_verifying_heads,
+        } => {
+            let doc = if input_file.exists() {
+                skip_verifying_heads.load_autocommit(&std::fs::read(&input_file)?)?
+            } else {
+                automerge::AutoCommit::new()
+            };
+            let stdin = std::io::stdin();
+            let prompt = stdin.is_terminal();
+            shell::Shell::new(doc, Some(input_file)).run(
+                stdin.lock(),
+                std::io::stdout(),
+                prompt,
+            )?;
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,48 @@
+use std::io::Write;
+
+use crate::color_json::print_colored_json;
+
+/// Write `rows` as a table with a column for each of `headers`, padding every column but the last
+/// to the width of its widest cell
+pub(crate) fn write_table(
+    mut output: impl Write,
+    headers: &[&str],
+    rows: &[Vec<String>],
+) -> std::io::Result<()> {
+    let mut widths = headers
+        .iter()
+        .map(|h| h.chars().count())
+        .collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headers = headers.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    for row in std::iter::once(&headers).chain(rows) {
+        let mut line = String::new();
+        for (i, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            if i + 1 == row.len() {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{:width$}  ", cell, width = width));
+            }
+        }
+        writeln!(output, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Write `value` as coloured JSON to stdout if `is_tty`, otherwise as plain JSON to `output`
+pub(crate) fn write_json(
+    mut output: impl Write,
+    value: &serde_json::Value,
+    is_tty: bool,
+) -> std::io::Result<()> {
+    if is_tty {
+        print_colored_json(value)?;
+        writeln!(output)
+    } else {
+        writeln!(output, "{}", serde_json::to_string_pretty(value).unwrap())
+    }
+}
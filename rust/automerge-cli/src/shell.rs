@@ -0,0 +1,414 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use automerge as am;
+use automerge::{
+    transaction::{CommitOptions, Transactable},
+    ReadDoc,
+};
+use thiserror::Error;
+
+use crate::{
+    change::{self, display_props, lookup, ChangeError},
+    export::{map_to_json, summary, value_to_json},
+};
+
+const HELP: &str = r#"Paths are either absolute, starting at the root with `$`, or relative to the
+current object. Map keys are written as `["key"]`, `.key` or, at the start of a
+relative path, `key`. List indices are written as `[0]` and `..` is the parent.
+
+  ls [path]                   list the properties of an object
+  cd [path]                   change the current object, `cd` alone goes to the root
+  pwd                         print the path of the current object
+  get [path]                  print a value, objects are printed as JSON
+  conflicts <path>            print every conflicting value of a property
+  heads                       print the heads of the document
+  changes                     list the changes in the document
+  history <path>              list the changes which altered a property
+  set <path> <json>           set a property to a JSON value
+  insert <path> <json>        insert a JSON value into a list or text
+  delete <path>               delete a property
+  increment <path> [n]        increment a counter by n, or 1
+  commit [message]            commit the pending edits
+  rollback                    discard the pending edits
+  save [file]                 save the document, by default to the file it was loaded from
+  quit                        leave the shell, discarding pending edits"#;
+
+#[derive(Error, Debug)]
+pub enum ShellError {
+    #[error("Error reading input: {:?}", source)]
+    ReadingInput {
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Error writing to output: {:?}", source)]
+    WritingToOutput {
+        #[from]
+        source: std::io::Error,
+    },
+    #[error("Error saving to {path}: {source}")]
+    Saving {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Change(#[from] ChangeError),
+    #[error(transparent)]
+    Automerge(#[from] am::AutomergeError),
+    #[error("unknown command `{0}`, try `help`")]
+    UnknownCommand(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("there are {0} uncommitted operations, `commit` or `rollback` them first")]
+    Uncommitted(usize),
+    #[error("no file to save to, use `save <file>`")]
+    NoFile,
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// An interactive session editing a single document
+pub(crate) struct Shell {
+    doc: am::AutoCommit,
+    cwd: Vec<am::Prop>,
+    file: Option<PathBuf>,
+}
+
+impl Shell {
+    pub(crate) fn new(doc: am::AutoCommit, file: Option<PathBuf>) -> Self {
+        Self {
+            doc,
+            cwd: Vec::new(),
+            file,
+        }
+    }
+
+    /// Execute each line of `input` as a command, writing results and errors to `output`
+    pub(crate) fn run(
+        &mut self,
+        input: impl BufRead,
+        mut output: impl Write,
+        prompt: bool,
+    ) -> Result<(), ShellError> {
+        let mut lines = input.lines();
+        loop {
+            if prompt {
+                write!(output, "{}> ", display_props(&self.cwd))?;
+                output.flush()?;
+            }
+            let line = match lines.next() {
+                Some(line) => line.map_err(|e| ShellError::ReadingInput { source: e })?,
+                None => break,
+            };
+            match self.execute(&line, &mut output) {
+                Ok(Flow::Quit) => break,
+                Ok(Flow::Continue) => {}
+                Err(e @ ShellError::WritingToOutput { .. }) => return Err(e),
+                Err(e) => writeln!(output, "error: {}", e)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, line: &str, out: &mut impl Write) -> Result<Flow, ShellError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(Flow::Continue);
+        }
+        let (command, rest) = line
+            .split_once(char::is_whitespace)
+            .map(|(c, r)| (c, r.trim()))
+            .unwrap_or((line, ""));
+        match command {
+            "help" => writeln!(out, "{}", HELP)?,
+            "pwd" => writeln!(out, "{}", display_props(&self.cwd))?,
+            "cd" => {
+                let props = self.props(rest)?;
+                lookup(&self.doc, &props, None)?;
+                self.cwd = props;
+            }
+            "ls" => {
+                let props = self.props(rest)?;
+                self.ls(&props, out)?;
+            }
+            "get" => {
+                let props = self.props(rest)?;
+                let json = match props.split_last() {
+                    None => map_to_json(&self.doc, &am::ROOT),
+                    Some((prop, parent)) => {
+                        let (obj, _) = lookup(&self.doc, parent, None)?;
+                        match self.doc.get(&obj, prop.clone())? {
+                            Some((value, id)) => value_to_json(&self.doc, &value, &id),
+                            None => serde_json::Value::Null,
+                        }
+                    }
+                };
+                writeln!(out, "{}", serde_json::to_string_pretty(&json).unwrap())?;
+            }
+            "conflicts" => {
+                let (obj, prop) = self.property(rest, "conflicts <path>")?;
+                self.doc
+                    .get_all(&obj, prop)?
+                    .into_iter()
+                    .try_for_each(|(value, id)| {
+                        writeln!(out, "{}: {}", id, summary(&self.doc, &value, &id))
+                    })?;
+            }
+            "heads" => {
+                self.require_committed()?;
+                self.doc
+                    .get_heads()
+                    .into_iter()
+                    .try_for_each(|head| writeln!(out, "{}", head))?;
+            }
+            "changes" => {
+                self.require_committed()?;
+                self.doc.get_changes(&[]).into_iter().try_for_each(|c| {
+                    writeln!(
+                        out,
+                        "{} {} {} {}",
+                        c.hash(),
+                        c.actor_id(),
+                        c.seq(),
+                        c.message().map(String::as_str).unwrap_or("")
+                    )
+                })?;
+            }
+            "history" => {
+                self.require_committed()?;
+                let props = self.props(rest)?;
+                if props.is_empty() {
+                    return Err(ShellError::Usage("history <path>"));
+                }
+                self.history(&props, out)?;
+            }
+            "set" | "insert" | "delete" | "increment" => {
+                change::parse_change_script(line)?.apply(&mut self.doc, &self.cwd)?;
+            }
+            "commit" => {
+                let mut options = CommitOptions::default();
+                if !rest.is_empty() {
+                    options.set_message(rest);
+                }
+                match self.doc.commit_with(options) {
+                    Some(hash) => writeln!(out, "{}", hash)?,
+                    None => writeln!(out, "nothing to commit")?,
+                }
+            }
+            "rollback" => writeln!(out, "discarded {} operations", self.doc.rollback())?,
+            "save" => {
+                let path = if rest.is_empty() {
+                    self.file.clone().ok_or(ShellError::NoFile)?
+                } else {
+                    PathBuf::from(rest)
+                };
+                std::fs::write(&path, self.doc.save()).map_err(|source| ShellError::Saving {
+                    path: path.clone(),
+                    source,
+                })?;
+                writeln!(out, "saved to {}", path.display())?;
+            }
+            "quit" | "exit" => {
+                let pending = self.doc.pending_ops();
+                if pending > 0 {
+                    writeln!(out, "discarding {} uncommitted operations", pending)?;
+                }
+                return Ok(Flow::Quit);
+            }
+            other => return Err(ShellError::UnknownCommand(other.to_string())),
+        }
+        Ok(Flow::Continue)
+    }
+
+    /// The properties from the root to the value at `path`, or to the current object if `path`
+    /// is empty
+    fn props(&self, path: &str) -> Result<Vec<am::Prop>, ShellError> {
+        if path.is_empty() {
+            Ok(self.cwd.clone())
+        } else {
+            Ok(change::parse_path(path)?.resolve(&self.cwd)?)
+        }
+    }
+
+    fn property(
+        &self,
+        path: &str,
+        usage: &'static str,
+    ) -> Result<(am::ObjId, am::Prop), ShellError> {
+        if path.is_empty() {
+            return Err(ShellError::Usage(usage));
+        }
+        let mut props = self.props(path)?;
+        let prop = props.pop().ok_or(ShellError::Usage(usage))?;
+        let (obj, _) = lookup(&self.doc, &props, None)?;
+        Ok((obj, prop))
+    }
+
+    fn require_committed(&self) -> Result<(), ShellError> {
+        match self.doc.pending_ops() {
+            0 => Ok(()),
+            n => Err(ShellError::Uncommitted(n)),
+        }
+    }
+
+    fn ls(&self, props: &[am::Prop], out: &mut impl Write) -> Result<(), ShellError> {
+        let (obj, typ) = lookup(&self.doc, props, None)?;
+        match typ {
+            am::ObjType::Map | am::ObjType::Table => {
+                for key in self.doc.keys(&obj) {
+                    self.ls_entry(&obj, key.as_str().into(), &key, out)?;
+                }
+            }
+            am::ObjType::List => {
+                for index in 0..self.doc.length(&obj) {
+                    self.ls_entry(&obj, index.into(), &format!("[{}]", index), out)?;
+                }
+            }
+            am::ObjType::Text => {
+                let text = self.doc.text(&obj).unwrap_or_default();
+                writeln!(out, "{}", serde_json::Value::String(text))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn ls_entry(
+        &self,
+        obj: &am::ObjId,
+        prop: am::Prop,
+        label: &str,
+        out: &mut impl Write,
+    ) -> std::io::Result<()> {
+        let values = self.doc.get_all(obj, prop).unwrap_or_default();
+        if let Some((value, id)) = values.last() {
+            let conflict = if values.len() > 1 { " (conflict)" } else { "" };
+            writeln!(
+                out,
+                "{}: {}{}",
+                label,
+                summary(&self.doc, value, id),
+                conflict
+            )?;
+        }
+        Ok(())
+    }
+
+    fn history(&mut self, props: &[am::Prop], out: &mut impl Write) -> std::io::Result<()> {
+        let (prop, parent) = props.split_last().unwrap();
+        let changes = self
+            .doc
+            .get_changes(&[])
+            .into_iter()
+            .map(|c| (c.hash(), c.deps().to_vec(), c.actor_id().clone(), c.seq()))
+            .collect::<Vec<_>>();
+        let mut heads: Vec<am::ChangeHash> = Vec::new();
+        let mut previous = Vec::new();
+        for (hash, deps, actor, seq) in changes {
+            heads.retain(|h| !deps.contains(h));
+            heads.push(hash);
+            let values = lookup(&self.doc, parent, Some(&heads))
+                .and_then(|(obj, _)| Ok(self.doc.get_all_at(&obj, prop.clone(), &heads)?))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(value, id)| (summary(&self.doc, &value, &id), id))
+                .collect::<Vec<_>>();
+            if values != previous {
+                let shown = if values.is_empty() {
+                    "<deleted>".to_string()
+                } else {
+                    values
+                        .iter()
+                        .map(|(summary, _)| summary.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                };
+                writeln!(out, "{} {} {}: {}", hash, actor, seq, shown)?;
+                previous = values;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(shell: &mut Shell, script: &str) -> String {
+        let mut out = Vec::new();
+        shell.run(script.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn edit_navigate_and_inspect() {
+        let mut shell = Shell::new(am::AutoCommit::new(), None);
+        let out = run(
+            &mut shell,
+            r#"set birds {"wrens": 3, "names": ["jay"]}
+               cd birds
+               insert names[1] "owl"
+               set ..["note"] "hello"
+               commit add birds
+               pwd
+               ls
+               get names
+               cd $
+               get note
+               rollback"#,
+        );
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0].len(), 64, "commit prints the change hash");
+        assert_eq!(
+            &lines[1..],
+            &[
+                r#"$["birds"]"#,
+                "names: [...] (2 items)",
+                "wrens: 3",
+                "[",
+                r#"  "jay","#,
+                r#"  "owl""#,
+                "]",
+                r#""hello""#,
+                "discarded 0 operations",
+            ]
+        );
+        assert_eq!(
+            shell.doc.get_changes(&[])[0].message().map(String::as_str),
+            Some("add birds")
+        );
+    }
+
+    #[test]
+    fn conflicts_and_history() {
+        let mut doc = am::AutoCommit::new().with_actor(am::ActorId::from([1]));
+        doc.put(am::ROOT, "bird", "wren").unwrap();
+        doc.commit();
+        let mut other = doc.fork().with_actor(am::ActorId::from([2]));
+        other.put(am::ROOT, "bird", "owl").unwrap();
+        doc.put(am::ROOT, "bird", "jay").unwrap();
+        doc.merge(&mut other).unwrap();
+
+        let mut shell = Shell::new(doc, None);
+        let out = run(
+            &mut shell,
+            "ls\nconflicts bird\nhistory bird\nset bird 1\nheads",
+        );
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], r#"bird: "owl" (conflict)"#);
+        assert!(lines[1].ends_with(r#"@01: "jay""#));
+        assert!(lines[2].ends_with(r#"@02: "owl""#));
+        assert!(lines[3].ends_with(r#" 01 1: "wren""#));
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[6],
+            "error: there are 1 uncommitted operations, `commit` or `rollback` them first"
+        );
+    }
+}
@@ -61,6 +61,94 @@ fn import_export_isomorphic() {
     assert_eq!(stdout, json_bytes);
 }
 
+#[test]
+fn import_edit_in_shell_export() {
+    let bin = env!("CARGO_BIN_EXE_automerge");
+    let initial_state_json = serde_json::json!({
+        "birds": {
+            "wrens": 3.0,
+            "sparrows": 15.0
+        }
+    });
+    let json_bytes = serde_json::to_string_pretty(&initial_state_json).unwrap();
+    let mut doc_file = std::env::temp_dir();
+    doc_file.push(format!("shell_test_{}.automerge", std::process::id()));
+
+    cmd!(bin, "import")
+        .stdin_bytes(json_bytes)
+        .stdout_path(&doc_file)
+        .run()
+        .unwrap();
+    let shell_output = cmd!(bin, "shell", &doc_file)
+        .stdin_bytes(
+            "cd birds\nset owls 12.0\ndelete ..[\"birds\"][\"wrens\"]\ncommit owls\nsave\n",
+        )
+        .read()
+        .unwrap();
+    assert!(shell_output.ends_with(&format!("saved to {}", doc_file.display())));
+
+    let stdout = cmd!(bin, "export").stdin_path(&doc_file).read().unwrap();
+    std::fs::remove_file(doc_file).unwrap();
+    let result: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+    let expected = serde_json::json!({
+        "birds": {
+            "sparrows": 15.0,
+            "owls": 12.0,
+        }
+    });
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn typed_json_round_trip() {
+    let bin = env!("CARGO_BIN_EXE_automerge");
+    let typed_json = serde_json::json!({
+        "views": {"$counter": 3},
+        "at": {"$timestamp": 1000},
+        "note": {"$text": "hello"},
+        "$$price": 1.5,
+    });
+
+    let stdout = cmd!(bin, "import", "--format", "typed-json")
+        .stdin_bytes(serde_json::to_vec(&typed_json).unwrap())
+        .pipe(cmd!(bin, "export", "--format", "typed-json"))
+        .read()
+        .unwrap();
+    let result: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+    assert_eq!(result, typed_json);
+}
+
+#[test]
+fn diff_merged_files() {
+    use automerge::transaction::Transactable;
+
+    let bin = env!("CARGO_BIN_EXE_automerge");
+    let mut doc = automerge::AutoCommit::new();
+    doc.put(automerge::ROOT, "wrens", 1).unwrap();
+    let mut fork = doc.fork();
+    doc.put(automerge::ROOT, "owls", 2).unwrap();
+    fork.put(automerge::ROOT, "wrens", 3).unwrap();
+    let first = std::env::temp_dir().join(format!("diff_first_{}.automerge", std::process::id()));
+    let second = std::env::temp_dir().join(format!("diff_second_{}.automerge", std::process::id()));
+    std::fs::write(&first, doc.save()).unwrap();
+    std::fs::write(&second, fork.save()).unwrap();
+
+    let stdout = cmd!(bin, "diff", &first, &second, "--format", "json")
+        .read()
+        .unwrap();
+    std::fs::remove_file(first).unwrap();
+    std::fs::remove_file(second).unwrap();
+    let result: serde_json::Value = serde_json::from_str(stdout.as_str()).unwrap();
+    assert_eq!(
+        result[0]["patches"],
+        serde_json::json!([{"path": ["wrens"], "action": "put", "value": 3}])
+    );
+    assert_eq!(
+        result[1]["patches"],
+        serde_json::json!([{"path": ["owls"], "action": "put", "value": 2}])
+    );
+}
+
 /*
 #[test]
 fn import_change_export() {
@@ -71,6 +71,9 @@ export type DecodedSyncMessage = {
   need: Heads,
   have: SyncHave[]
   changes: Change[]
+  capabilities?: number[]
+  snapshot?: Uint8Array
+  actorSeqs?: { [actor: string]: number }
 }
 
 export type DecodedChange = {
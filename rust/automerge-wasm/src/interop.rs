@@ -288,15 +288,16 @@ impl TryFrom<JS> for am::sync::State {
             .0
             .as_bool()
             .ok_or(error::BadSyncState::InFlightNotBoolean)?;
-        Ok(am::sync::State {
-            shared_heads,
-            last_sent_heads,
-            their_heads,
-            their_need,
-            their_have,
-            sent_hashes,
-            in_flight,
-        })
+        // The capabilities are renegotiated by the next message the peer sends
+        let mut state = am::sync::State::new();
+        state.shared_heads = shared_heads;
+        state.last_sent_heads = last_sent_heads;
+        state.their_heads = their_heads;
+        state.their_need = their_need;
+        state.their_have = their_have;
+        state.sent_hashes = sent_hashes;
+        state.in_flight = in_flight;
+        Ok(state)
     }
 }
 
@@ -369,12 +370,102 @@ impl TryFrom<JS> for am::sync::Message {
             .map_err(error::BadSyncMessage::BadNeed)?;
         let changes = js_get(&value.0, "changes")?.try_into()?;
         let have = js_get(&value.0, "have")?.try_into()?;
-        Ok(am::sync::Message {
-            heads,
-            need,
-            have,
-            changes,
-        })
+        let mut message = am::sync::Message::default();
+        message.heads = heads;
+        message.need = need;
+        message.have = have;
+        message.changes = changes;
+        message.supported_capabilities = optional(js_get(&value.0, "capabilities")?)
+            .map(|c| c.try_into())
+            .transpose()?;
+        message.snapshot = optional(js_get(&value.0, "snapshot")?)
+            .map(|s| {
+                s.0.dyn_into::<Uint8Array>()
+                    .map(|s| s.to_vec())
+                    .map_err(|_| error::BadSyncMessage::SnapshotNotU8Array)
+            })
+            .transpose()?;
+        message.actor_seqs = optional(js_get(&value.0, "actorSeqs")?)
+            .map(|s| s.try_into())
+            .transpose()?;
+        Ok(message)
+    }
+}
+
+/// `None` if `value` is `null` or `undefined`, for fields which older code doesn't set
+fn optional(value: JS) -> Option<JS> {
+    if value.0.is_null() || value.0.is_undefined() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+impl TryFrom<JS> for Vec<am::sync::Capability> {
+    type Error = error::BadSyncMessage;
+
+    fn try_from(value: JS) -> Result<Self, Self::Error> {
+        let value = value
+            .0
+            .dyn_into::<Array>()
+            .map_err(|_| error::BadSyncMessage::BadCapabilities)?;
+        value
+            .iter()
+            .map(|c| match c.as_f64() {
+                Some(c) if (0.0..=255.0).contains(&c) && c.fract() == 0.0 => {
+                    Ok(am::sync::Capability::from(c as u8))
+                }
+                _ => Err(error::BadSyncMessage::BadCapabilities),
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<JS> for am::sync::ActorSeqs {
+    type Error = error::BadSyncMessage;
+
+    fn try_from(value: JS) -> Result<Self, Self::Error> {
+        let value = value
+            .0
+            .dyn_into::<Object>()
+            .map_err(|_| error::BadSyncMessage::ActorSeqsNotObject)?;
+        js_sys::Object::entries(&value)
+            .iter()
+            .map(|entry| {
+                let entry = Array::from(&entry);
+                let actor = entry
+                    .get(0)
+                    .as_string()
+                    .and_then(|a| am::ActorId::try_from(a).ok());
+                let seq = entry
+                    .get(1)
+                    .as_f64()
+                    .filter(|s| *s >= 0.0 && s.fract() == 0.0);
+                match (actor, seq) {
+                    (Some(actor), Some(seq)) => Ok((actor, seq as u64)),
+                    _ => Err(error::BadSyncMessage::BadActorSeq(
+                        entry.get(0).as_string().unwrap_or_default(),
+                    )),
+                }
+            })
+            .collect()
+    }
+}
+
+impl From<&[am::sync::Capability]> for AR {
+    fn from(value: &[am::sync::Capability]) -> Self {
+        AR(value.iter().map(|c| JsValue::from(u8::from(*c))).collect())
+    }
+}
+
+impl From<&am::sync::ActorSeqs> for JS {
+    fn from(value: &am::sync::ActorSeqs) -> Self {
+        let result: JsValue = Object::new().into();
+        for (actor, seq) in value.iter() {
+            // we can unwrap here b/c we made the object and know its not frozen
+            Reflect::set(&result, &actor.to_hex_string().into(), &(seq as f64).into()).unwrap();
+        }
+        JS(result)
     }
 }
 
@@ -1610,6 +1701,14 @@ pub(crate) mod error {
         BadHeads(BadChangeHashes),
         #[error("could not read need: {0}")]
         BadNeed(BadChangeHashes),
+        #[error("capabilities should be an array of integers between 0 and 255")]
+        BadCapabilities,
+        #[error("snapshot should be a Uint8Array")]
+        SnapshotNotU8Array,
+        #[error("actorSeqs should be an object")]
+        ActorSeqsNotObject,
+        #[error("bad actorSeqs entry for actor {0:?}")]
+        BadActorSeq(String),
     }
 
     impl From<BadSyncMessage> for JsValue {
@@ -1032,6 +1032,16 @@ pub fn decode_sync_message(msg: Uint8Array) -> Result<JsValue, error::BadSyncMes
     js_set(&obj, "need", need).unwrap();
     js_set(&obj, "have", have).unwrap();
     js_set(&obj, "changes", changes).unwrap();
+    // The fields added to the protocol since are only set if the sender sent them
+    if let Some(capabilities) = &msg.supported_capabilities {
+        js_set(&obj, "capabilities", AR::from(capabilities.as_slice())).unwrap();
+    }
+    if let Some(snapshot) = &msg.snapshot {
+        js_set(&obj, "snapshot", Uint8Array::from(snapshot.as_slice())).unwrap();
+    }
+    if let Some(actor_seqs) = &msg.actor_seqs {
+        js_set(&obj, "actorSeqs", JS::from(actor_seqs)).unwrap();
+    }
     Ok(obj)
 }
 
@@ -954,6 +954,24 @@ describe('Automerge', () => {
       assert.deepStrictEqual(message.have[0].lastSync, [])
       assert.deepStrictEqual(message.have[0].bloom.byteLength, 0)
       assert.deepStrictEqual(message.changes, [])
+      assert.deepStrictEqual(message.capabilities, [2])
+      assert.deepStrictEqual(message.snapshot, undefined)
+      assert.deepStrictEqual(message.actorSeqs, undefined)
+    })
+
+    it('should round trip the optional fields of a sync message', () => {
+      const doc = create()
+      const m1 = doc.generateSyncMessage(initSyncState())
+      if (m1 === null) { throw new RangeError("message should not be null") }
+      const message: DecodedSyncMessage = decodeSyncMessage(m1)
+      assert.deepStrictEqual(encodeSyncMessage(message), m1)
+
+      message.snapshot = doc.save()
+      message.actorSeqs = { "aabbcc": 3 }
+      const decoded = decodeSyncMessage(encodeSyncMessage(message))
+      assert.deepStrictEqual(decoded.capabilities, [2])
+      assert.deepStrictEqual(decoded.snapshot, message.snapshot)
+      assert.deepStrictEqual(decoded.actorSeqs, { "aabbcc": 3 })
     })
 
     it('should not reply if we have no data as well', () => {
@@ -1,7 +1,7 @@
 use automerge::{
-    sync::{self, SyncDoc},
+    sync::{self, BloomConfig, Reconciliation, SyncDoc},
     transaction::Transactable,
-    Automerge, ROOT,
+    ActorId, Automerge, ROOT,
 };
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 
@@ -45,6 +45,68 @@ fn sync(doc1: &mut DocWithSync, doc2: &mut DocWithSync) {
     }
 }
 
+// Two peers which share `shared` changes and have then each made `n` more
+fn divergent(shared: u64, n: u64) -> (Automerge, Automerge) {
+    let mut doc1 = Automerge::new();
+    for i in 0..shared {
+        let mut tx = doc1.transaction();
+        tx.put(ROOT, "shared", i).unwrap();
+        tx.commit();
+    }
+    let mut doc2 = doc1.fork().with_actor(ActorId::random());
+    for (doc, key) in [(&mut doc1, "one"), (&mut doc2, "two")] {
+        for i in 0..n {
+            let mut tx = doc.transaction();
+            tx.put(ROOT, key, i).unwrap();
+            tx.commit();
+        }
+    }
+    (doc1, doc2)
+}
+
+fn strategies() -> Vec<(&'static str, sync::State)> {
+    vec![
+        ("bloom 1%", sync::State::new()),
+        (
+            "bloom 0.01%",
+            sync::State::new().with_bloom_config(BloomConfig::with_false_positive_rate(0.0001)),
+        ),
+        (
+            "actor seqs",
+            sync::State::new().with_reconciliation(Reconciliation::ActorSeqs),
+        ),
+        (
+            "adaptive",
+            sync::State::new().with_reconciliation(Reconciliation::Adaptive),
+        ),
+    ]
+}
+
+// Sync two peers using `state` as the initial state of both, sending encoded messages, and return
+// the number of round trips and the number of bytes sent
+fn reconcile(doc1: &mut Automerge, doc2: &mut Automerge, state: &sync::State) -> (usize, usize) {
+    let (mut s1, mut s2) = (state.clone(), state.clone());
+    let (mut round_trips, mut bytes) = (0, 0);
+    loop {
+        let one = doc1.generate_sync_message(&mut s1).map(|m| m.encode());
+        let two = doc2.generate_sync_message(&mut s2).map(|m| m.encode());
+        if one.is_none() && two.is_none() {
+            return (round_trips, bytes);
+        }
+        round_trips += 1;
+        if let Some(msg) = one {
+            bytes += msg.len();
+            doc2.receive_sync_message(&mut s2, sync::Message::decode(&msg).unwrap())
+                .unwrap();
+        }
+        if let Some(msg) = two {
+            bytes += msg.len();
+            doc1.receive_sync_message(&mut s1, sync::Message::decode(&msg).unwrap())
+                .unwrap();
+        }
+    }
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let sizes = [100, 1_000, 10_000];
 
@@ -89,6 +151,26 @@ fn criterion_benchmark(c: &mut Criterion) {
         );
     }
     group.finish();
+
+    let mut group = c.benchmark_group("sync divergent");
+    for size in [100, 1_000] {
+        for (name, state) in strategies() {
+            let (mut doc1, mut doc2) = divergent(1_000, size);
+            let (round_trips, bytes) = reconcile(&mut doc1, &mut doc2, &state);
+            println!(
+                "sync divergent/{}/{}: {} round trips, {} bytes",
+                name, size, round_trips, bytes
+            );
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |b, &size| {
+                b.iter_batched(
+                    || divergent(1_000, size),
+                    |(mut doc1, mut doc2)| reconcile(&mut doc1, &mut doc2, &state),
+                    criterion::BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -6,11 +6,18 @@ use crate::exid::ExId;
 use crate::hydrate;
 use crate::iter::{Keys, ListRange, MapRange, Values};
 use crate::marks::{ExpandMark, Mark};
+use crate::observer::{ObserverFilter, ObserverId};
 use crate::patches::{PatchLog, TextRepresentation};
+use crate::policy::ChangePolicy;
+use crate::schema::Schema;
+#[cfg(feature = "signing")]
+use crate::signing::{SigningKey, TrustStore};
+use crate::storage::Codec;
 use crate::sync::SyncDoc;
+use crate::text_value::TextEncoding;
 use crate::transaction::{CommitOptions, Transactable};
 use crate::types::Clock;
-use crate::{sync, ObjType, Parents, Patch, ReadDoc, ScalarValue};
+use crate::{sync, KeyProvider, ObjType, Parents, Patch, ReadDoc, ScalarValue};
 use crate::{
     transaction::TransactionInner, ActorId, Automerge, AutomergeError, Change, ChangeHash, Cursor,
     Prop, Value,
@@ -239,6 +246,29 @@ impl AutoCommit {
         })
     }
 
+    /// Squash the history of this document up to `heads` into a single baseline change
+    ///
+    /// See [`Automerge::compact`] for details. Unlike [`Automerge::compact`] the observers of
+    /// this document are moved to the returned document.
+    pub fn compact(&mut self, heads: &[ChangeHash]) -> Result<Self, AutomergeError> {
+        self.ensure_transaction_closed();
+        let mut doc = self.doc.compact(heads)?;
+        doc.take_observers(&mut self.doc);
+        Ok(Self {
+            doc,
+            transaction: self.transaction.clone(),
+            patch_log: PatchLog::inactive(self.patch_log.text_rep()),
+            diff_cursor: vec![],
+            save_cursor: vec![],
+            isolation: None,
+        })
+    }
+
+    /// The baseline this document was compacted to, if any
+    pub fn baseline(&self) -> Option<&crate::Baseline> {
+        self.doc.baseline()
+    }
+
     /// Get the inner document.
     #[doc(hidden)]
     pub fn document(&mut self) -> &Automerge {
@@ -262,6 +292,126 @@ impl AutoCommit {
         self.doc.get_actor()
     }
 
+    /// Check local operations against `schema`
+    ///
+    /// See [`crate::schema`]
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.doc.set_schema(Some(schema));
+        self
+    }
+
+    /// Check local operations against `schema`, or stop checking them if `schema` is `None`
+    pub fn set_schema(&mut self, schema: Option<Schema>) -> &mut Self {
+        self.doc.set_schema(schema);
+        self
+    }
+
+    pub fn schema(&self) -> Option<&Schema> {
+        self.doc.schema()
+    }
+
+    /// Check the changes this document receives from other actors against `policy`
+    ///
+    /// See [`crate::policy`]
+    pub fn with_change_policy<P: ChangePolicy + 'static>(mut self, policy: P) -> Self {
+        self.doc.set_change_policy(policy);
+        self
+    }
+
+    /// Check the changes this document receives from other actors against `policy`, replacing
+    /// any existing policy
+    pub fn set_change_policy<P: ChangePolicy + 'static>(&mut self, policy: P) -> &mut Self {
+        self.doc.set_change_policy(policy);
+        self
+    }
+
+    /// Stop checking the changes this document receives against a change policy
+    pub fn clear_change_policy(&mut self) -> &mut Self {
+        self.doc.clear_change_policy();
+        self
+    }
+
+    /// Let a baseline received from a peer replace the history of this document if `accept` is
+    /// true
+    ///
+    /// See [`Automerge::set_accept_baselines`]
+    pub fn with_accept_baselines(mut self, accept: bool) -> Self {
+        self.doc.set_accept_baselines(accept);
+        self
+    }
+
+    /// Let a baseline received from a peer replace the history of this document if `accept` is
+    /// true
+    ///
+    /// See [`Automerge::set_accept_baselines`]
+    pub fn set_accept_baselines(&mut self, accept: bool) -> &mut Self {
+        self.doc.set_accept_baselines(accept);
+        self
+    }
+
+    /// Whether a baseline received from a peer may replace the history of this document
+    pub fn accepts_baselines(&self) -> bool {
+        self.doc.accepts_baselines()
+    }
+
+    /// Sign the changes committed to this document with `key`
+    ///
+    /// See [`crate::signing`]
+    #[cfg(feature = "signing")]
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.doc.set_signing_key(Some(key));
+        self
+    }
+
+    /// Sign the changes committed to this document with `key`, or stop signing them if `key` is
+    /// `None`
+    #[cfg(feature = "signing")]
+    pub fn set_signing_key(&mut self, key: Option<SigningKey>) -> &mut Self {
+        self.doc.set_signing_key(key);
+        self
+    }
+
+    /// Verify the signatures of changes received by this document against `trust_store`
+    ///
+    /// See [`crate::signing`]
+    #[cfg(feature = "signing")]
+    pub fn with_trust_store(mut self, trust_store: TrustStore) -> Self {
+        self.doc.set_trust_store(Some(trust_store));
+        self
+    }
+
+    /// Verify the signatures of changes received by this document against `trust_store`, or stop
+    /// verifying them if `trust_store` is `None`
+    #[cfg(feature = "signing")]
+    pub fn set_trust_store(&mut self, trust_store: Option<TrustStore>) -> &mut Self {
+        self.doc.set_trust_store(trust_store);
+        self
+    }
+
+    #[cfg(feature = "signing")]
+    pub fn trust_store(&self) -> Option<&TrustStore> {
+        self.doc.trust_store()
+    }
+
+    /// Call `callback` with the patches matching `filter` whenever this document changes, see
+    /// [`crate::observer`]. Patches use the text representation of this document at the time the
+    /// observer is added.
+    ///
+    /// Local changes are observed when the pending transaction is committed, either explicitly
+    /// with [`Self::commit`] or implicitly by methods like [`Self::save`] and [`Self::merge`].
+    pub fn observe<F>(&mut self, filter: impl Into<ObserverFilter>, callback: F) -> ObserverId
+    where
+        F: FnMut(&[Patch]) + Send + 'static,
+    {
+        self.doc
+            .observe(filter, self.patch_log.text_rep(), callback)
+    }
+
+    /// Remove an observer added with [`Self::observe`], returning whether it was registered
+    pub fn unobserve(&mut self, id: ObserverId) -> bool {
+        self.doc.unobserve(id)
+    }
+
     pub fn isolate(&mut self, heads: &[ChangeHash]) {
         self.ensure_transaction_closed();
         self.patch_to(heads);
@@ -310,6 +460,22 @@ impl AutoCommit {
         }
     }
 
+    /// Like [`Self::load_incremental`] but decrypt any encrypted chunks with `keys`
+    pub fn load_incremental_with_keys(
+        &mut self,
+        data: &[u8],
+        keys: &dyn KeyProvider,
+    ) -> Result<usize, AutomergeError> {
+        self.ensure_transaction_closed();
+        if self.isolation.is_some() {
+            self.doc
+                .load_incremental_log_patches_with_keys(data, &mut PatchLog::null(), keys)
+        } else {
+            self.doc
+                .load_incremental_log_patches_with_keys(data, &mut self.patch_log, keys)
+        }
+    }
+
     pub fn apply_changes(
         &mut self,
         changes: impl IntoIterator<Item = Change>,
@@ -381,6 +547,29 @@ impl AutoCommit {
         bytes
     }
 
+    /// Like [`Self::save_incremental`] but compress large changes with `codec`, see
+    /// [`Change::compress_with`]
+    pub fn save_incremental_with(&mut self, codec: Codec) -> Vec<u8> {
+        self.ensure_transaction_closed();
+        let bytes = self.doc.save_after_with(&self.save_cursor, codec);
+        if !bytes.is_empty() {
+            self.save_cursor = self.doc.get_heads()
+        }
+        bytes
+    }
+
+    /// Like [`Self::save_incremental`] but encrypt each change with the current key of `keys`. The
+    /// result can be loaded with [`Self::load_incremental_with_keys`].
+    #[cfg(feature = "encryption")]
+    pub fn save_incremental_encrypted(&mut self, keys: &dyn KeyProvider) -> Vec<u8> {
+        self.ensure_transaction_closed();
+        let bytes = self.doc.save_after_encrypted(&self.save_cursor, keys);
+        if !bytes.is_empty() {
+            self.save_cursor = self.doc.get_heads()
+        }
+        bytes
+    }
+
     pub fn get_missing_deps(&mut self, heads: &[ChangeHash]) -> Vec<ChangeHash> {
         self.ensure_transaction_closed();
         self.doc.get_missing_deps(heads)
@@ -461,6 +650,41 @@ impl AutoCommit {
         self
     }
 
+    /// Measure indices into text objects in `encoding`
+    ///
+    /// See [`TextEncoding`]
+    pub fn set_text_encoding(&mut self, encoding: TextEncoding) {
+        self.doc.set_text_encoding(encoding);
+    }
+
+    /// The unit indices into text objects are measured in
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.doc.text_encoding()
+    }
+
+    /// Measure indices into text objects in `encoding`
+    ///
+    /// See [`TextEncoding`]
+    pub fn with_text_encoding(mut self, encoding: TextEncoding) -> Self {
+        self.doc.set_text_encoding(encoding);
+        self
+    }
+
+    /// Convert `index`, an index into the text object `obj` in the `from` encoding, to an index
+    /// into the same text in the `to` encoding
+    ///
+    /// See [`TextEncoding::convert_index`]
+    pub fn convert_text_index<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        index: usize,
+        from: TextEncoding,
+        to: TextEncoding,
+    ) -> Result<usize, AutomergeError> {
+        let text = self.text(obj)?;
+        Ok(from.convert_index(&text, index, to))
+    }
+
     /// Commit any uncommitted changes
     ///
     /// Returns `None` if there were no operations to commit
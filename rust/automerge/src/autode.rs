@@ -0,0 +1,532 @@
+use std::fmt;
+
+use serde::de::{self, IntoDeserializer, Visitor};
+
+use crate::schema::display_path;
+use crate::{ChangeHash, ObjId, ObjType, Prop, ReadDoc, ScalarValue, Value};
+
+/// A wrapper type which implements [`serde::Deserializer`] for a [`ReadDoc`].
+///
+/// This reads values directly out of the document, either as it is now or, with [`Self::at`], as
+/// it was at some point in its history. Maps and tables are deserialized as maps, lists as
+/// sequences and text objects as strings. Counters are deserialized as their current value and
+/// timestamps as milliseconds since the epoch, both as `i64`. Byte values can be deserialized
+/// into `Vec<u8>` or anything which accepts bytes.
+///
+/// Errors report the path to the value which could not be deserialized.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use automerge::{AutoCommit, AutoDe, ObjType, transaction::Transactable};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize, Debug, PartialEq)]
+/// struct Contact {
+///     name: String,
+///     email: Option<String>,
+/// }
+///
+/// let mut doc = AutoCommit::new();
+/// let contact = doc.put_object(automerge::ROOT, "contact", ObjType::Map)?;
+/// doc.put(&contact, "name", "Alice")?;
+///
+/// #[derive(Deserialize)]
+/// struct State {
+///     contact: Contact,
+/// }
+/// let state = State::deserialize(AutoDe::from(&doc))?;
+/// assert_eq!(state.contact, Contact { name: "Alice".to_string(), email: None });
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AutoDe<'a, R: ReadDoc> {
+    doc: &'a R,
+    heads: Option<&'a [ChangeHash]>,
+    value: Value<'a>,
+    obj: ObjId,
+}
+
+/// An error deserializing a document with [`AutoDe`]
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{message} at {}", display_path(.path))]
+pub struct DeserializeError {
+    path: Vec<Prop>,
+    message: String,
+}
+
+impl<'a, R: ReadDoc> From<&'a R> for AutoDe<'a, R> {
+    fn from(doc: &'a R) -> Self {
+        AutoDe {
+            doc,
+            heads: None,
+            value: Value::Object(ObjType::Map),
+            obj: ObjId::Root,
+        }
+    }
+}
+
+impl<'a, R: ReadDoc> AutoDe<'a, R> {
+    /// Deserialize the document as it was at `heads`
+    pub fn at(doc: &'a R, heads: &'a [ChangeHash]) -> Self {
+        AutoDe {
+            heads: Some(heads),
+            ..AutoDe::from(doc)
+        }
+    }
+
+    fn child(&self, value: Value<'a>, obj: ObjId) -> Self {
+        AutoDe {
+            doc: self.doc,
+            heads: self.heads,
+            value,
+            obj,
+        }
+    }
+
+    fn map_entries(&self) -> Vec<(String, Value<'a>, ObjId)> {
+        let range = match self.heads {
+            Some(heads) => self.doc.map_range_at(&self.obj, .., heads),
+            None => self.doc.map_range(&self.obj, ..),
+        };
+        range
+            .map(|item| (item.key.to_string(), item.value, item.id))
+            .collect()
+    }
+
+    fn list_items(&self) -> Vec<(Value<'a>, ObjId)> {
+        let range = match self.heads {
+            Some(heads) => self.doc.list_range_at(&self.obj, .., heads),
+            None => self.doc.list_range(&self.obj, ..),
+        };
+        range.map(|item| (item.value, item.id)).collect()
+    }
+
+    fn text(&self) -> Result<String, DeserializeError> {
+        let text = match self.heads {
+            Some(heads) => self.doc.text_at(&self.obj, heads),
+            None => self.doc.text(&self.obj),
+        };
+        text.map_err(de::Error::custom)
+    }
+
+    fn unexpected(&self) -> de::Unexpected<'_> {
+        match &self.value {
+            Value::Object(ObjType::Map | ObjType::Table) => de::Unexpected::Map,
+            Value::Object(ObjType::List) => de::Unexpected::Seq,
+            Value::Object(ObjType::Text) => de::Unexpected::Other("text"),
+            Value::Scalar(s) => match s.as_ref() {
+                ScalarValue::Str(s) => de::Unexpected::Str(s),
+                ScalarValue::Int(i) | ScalarValue::Timestamp(i) => de::Unexpected::Signed(*i),
+                ScalarValue::Uint(u) => de::Unexpected::Unsigned(*u),
+                ScalarValue::F64(f) => de::Unexpected::Float(*f),
+                ScalarValue::Counter(_) => de::Unexpected::Other("counter"),
+                ScalarValue::Boolean(b) => de::Unexpected::Bool(*b),
+                ScalarValue::Bytes(b) => de::Unexpected::Bytes(b),
+                ScalarValue::Unknown { .. } => de::Unexpected::Other("unknown value"),
+                ScalarValue::Null => de::Unexpected::Unit,
+            },
+        }
+    }
+}
+
+impl DeserializeError {
+    /// The path from the root of the document to the value which could not be deserialized
+    pub fn path(&self) -> &[Prop] {
+        &self.path
+    }
+
+    fn within(mut self, prop: Prop) -> Self {
+        self.path.insert(0, prop);
+        self
+    }
+}
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeserializeError {
+            path: Vec::new(),
+            message: msg.to_string(),
+        }
+    }
+}
+
+impl<'de, 'a, R: ReadDoc> de::Deserializer<'de> for AutoDe<'a, R> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match &self.value {
+            Value::Object(ObjType::Map | ObjType::Table) => {
+                let entries = self.map_entries();
+                visitor.visit_map(MapAccess {
+                    de: &self,
+                    entries: entries.into_iter(),
+                    value: None,
+                })
+            }
+            Value::Object(ObjType::List) => {
+                let items = self.list_items();
+                visitor.visit_seq(SeqAccess {
+                    de: &self,
+                    items: items.into_iter().enumerate(),
+                })
+            }
+            Value::Object(ObjType::Text) => visitor.visit_string(self.text()?),
+            Value::Scalar(s) => match s.as_ref() {
+                ScalarValue::Str(s) => visitor.visit_str(s),
+                ScalarValue::Int(i) | ScalarValue::Timestamp(i) => visitor.visit_i64(*i),
+                ScalarValue::Uint(u) => visitor.visit_u64(*u),
+                ScalarValue::F64(f) => visitor.visit_f64(*f),
+                ScalarValue::Counter(c) => visitor.visit_i64(i64::from(c)),
+                ScalarValue::Boolean(b) => visitor.visit_bool(*b),
+                ScalarValue::Bytes(b) => visitor.visit_bytes(b),
+                ScalarValue::Null => visitor.visit_unit(),
+                ScalarValue::Unknown { .. } => {
+                    Err(de::Error::invalid_type(self.unexpected(), &visitor))
+                }
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match &self.value {
+            Value::Scalar(s) if s.is_null() => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match &self.value {
+            Value::Scalar(s) => match s.as_ref() {
+                ScalarValue::Bytes(b) => {
+                    visitor.visit_seq(de::value::SeqDeserializer::new(b.iter().copied()))
+                }
+                _ => self.deserialize_any(visitor),
+            },
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match &self.value {
+            // A unit variant is just its name
+            Value::Scalar(s) if s.is_str() => {
+                let name = s.to_str().unwrap_or_default().to_string();
+                visitor.visit_enum(name.into_deserializer())
+            }
+            Value::Object(ObjType::Text) => visitor.visit_enum(self.text()?.into_deserializer()),
+            // Any other variant is a map from its name to its contents
+            Value::Object(ObjType::Map | ObjType::Table) => {
+                let mut entries = self.map_entries();
+                if entries.len() != 1 {
+                    return Err(de::Error::invalid_length(
+                        entries.len(),
+                        &"a map with a single key",
+                    ));
+                }
+                let (variant, value, obj) = entries.pop().unwrap();
+                visitor.visit_enum(EnumAccess {
+                    variant,
+                    de: self.child(value, obj),
+                })
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier
+    }
+}
+
+struct MapAccess<'d, 'a, R: ReadDoc> {
+    de: &'d AutoDe<'a, R>,
+    entries: std::vec::IntoIter<(String, Value<'a>, ObjId)>,
+    value: Option<(String, Value<'a>, ObjId)>,
+}
+
+impl<'de, 'd, 'a, R: ReadDoc> de::MapAccess<'de> for MapAccess<'d, 'a, R> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.entries.next() {
+            Some(entry) => {
+                let key = seed.deserialize(entry.0.as_str().into_deserializer())?;
+                self.value = Some(entry);
+                Ok(Some(key))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value, obj) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before key"))?;
+        seed.deserialize(self.de.child(value, obj))
+            .map_err(|e| e.within(Prop::Map(key)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct SeqAccess<'d, 'a, R: ReadDoc> {
+    de: &'d AutoDe<'a, R>,
+    items: std::iter::Enumerate<std::vec::IntoIter<(Value<'a>, ObjId)>>,
+}
+
+impl<'de, 'd, 'a, R: ReadDoc> de::SeqAccess<'de> for SeqAccess<'d, 'a, R> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        match self.items.next() {
+            Some((index, (value, obj))) => seed
+                .deserialize(self.de.child(value, obj))
+                .map(Some)
+                .map_err(|e| e.within(Prop::Seq(index))),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct EnumAccess<'a, R: ReadDoc> {
+    variant: String,
+    de: AutoDe<'a, R>,
+}
+
+impl<'de, 'a, R: ReadDoc> de::EnumAccess<'de> for EnumAccess<'a, R> {
+    type Error = DeserializeError;
+    type Variant = VariantAccess<'a, R>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let value = seed.deserialize(self.variant.as_str().into_deserializer())?;
+        Ok((
+            value,
+            VariantAccess {
+                variant: self.variant,
+                de: self.de,
+            },
+        ))
+    }
+}
+
+struct VariantAccess<'a, R: ReadDoc> {
+    variant: String,
+    de: AutoDe<'a, R>,
+}
+
+impl<'de, 'a, R: ReadDoc> de::VariantAccess<'de> for VariantAccess<'a, R> {
+    type Error = DeserializeError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        de::Deserialize::deserialize(self.de).map_err(|e| e.within(Prop::Map(self.variant)))
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        seed.deserialize(self.de)
+            .map_err(|e| e.within(Prop::Map(self.variant)))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+            .map_err(|e| e.within(Prop::Map(self.variant)))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        de::Deserializer::deserialize_map(self.de, visitor)
+            .map_err(|e| e.within(Prop::Map(self.variant)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ROOT};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Todo {
+        title: String,
+        done: bool,
+        tags: Vec<String>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Status {
+        Draft,
+        Published { at: i64 },
+        Archived(String),
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct State {
+        todos: Vec<Todo>,
+        views: u64,
+        score: i64,
+        ratio: f64,
+        created: i64,
+        avatar: Vec<u8>,
+        note: Option<String>,
+        missing: Option<String>,
+        statuses: Vec<Status>,
+        extra: HashMap<String, i64>,
+    }
+
+    fn doc() -> AutoCommit {
+        let mut doc = AutoCommit::new();
+        let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+        let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+        let title = doc.put_object(&todo, "title", ObjType::Text).unwrap();
+        doc.splice_text(&title, 0, 0, "write tests").unwrap();
+        doc.put(&todo, "done", false).unwrap();
+        let tags = doc.put_object(&todo, "tags", ObjType::List).unwrap();
+        doc.insert(&tags, 0, "work").unwrap();
+        doc.put(ROOT, "views", ScalarValue::counter(1)).unwrap();
+        doc.increment(ROOT, "views", 2).unwrap();
+        doc.put(ROOT, "score", -5).unwrap();
+        doc.put(ROOT, "ratio", 0.5).unwrap();
+        doc.put(ROOT, "created", ScalarValue::Timestamp(1000))
+            .unwrap();
+        doc.put(ROOT, "avatar", vec![1_u8, 2, 3]).unwrap();
+        doc.put(ROOT, "note", ScalarValue::Null).unwrap();
+        let statuses = doc.put_object(ROOT, "statuses", ObjType::List).unwrap();
+        doc.insert(&statuses, 0, "Draft").unwrap();
+        let published = doc.insert_object(&statuses, 1, ObjType::Map).unwrap();
+        let published = doc
+            .put_object(&published, "Published", ObjType::Map)
+            .unwrap();
+        doc.put(&published, "at", 42).unwrap();
+        let archived = doc.insert_object(&statuses, 2, ObjType::Map).unwrap();
+        doc.put(&archived, "Archived", "old").unwrap();
+        let extra = doc.put_object(ROOT, "extra", ObjType::Map).unwrap();
+        doc.put(&extra, "a", 1).unwrap();
+        doc
+    }
+
+    #[test]
+    fn deserialize_a_document() {
+        let doc = doc();
+        let state = State::deserialize(AutoDe::from(&doc)).unwrap();
+        assert_eq!(
+            state,
+            State {
+                todos: vec![Todo {
+                    title: "write tests".to_string(),
+                    done: false,
+                    tags: vec!["work".to_string()],
+                }],
+                views: 3,
+                score: -5,
+                ratio: 0.5,
+                created: 1000,
+                avatar: vec![1, 2, 3],
+                note: None,
+                missing: None,
+                statuses: vec![
+                    Status::Draft,
+                    Status::Published { at: 42 },
+                    Status::Archived("old".to_string())
+                ],
+                extra: HashMap::from([("a".to_string(), 1)]),
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_at_heads() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Views {
+            views: i64,
+        }
+
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "views", ScalarValue::counter(1)).unwrap();
+        let heads = doc.get_heads();
+        doc.increment(ROOT, "views", 2).unwrap();
+
+        let then = Views::deserialize(AutoDe::at(&doc, &heads)).unwrap();
+        assert_eq!(then, Views { views: 1 });
+        let now = Views::deserialize(AutoDe::from(&doc)).unwrap();
+        assert_eq!(now, Views { views: 3 });
+    }
+
+    #[test]
+    fn errors_report_the_path() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Wrong {
+            todos: Vec<HashMap<String, bool>>,
+        }
+
+        let doc = doc();
+        let err = Wrong::deserialize(AutoDe::from(&doc)).unwrap_err();
+        assert_eq!(err.path(), &[Prop::from("todos"), 0.into(), "tags".into()]);
+        assert_eq!(
+            err.to_string(),
+            "invalid type: sequence, expected a boolean at /todos/0/tags"
+        );
+
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Missing {
+            todos: Vec<Todo>,
+            absent: String,
+        }
+        let err = Missing::deserialize(AutoDe::from(&doc)).unwrap_err();
+        assert_eq!(err.to_string(), "missing field `absent` at /");
+    }
+}
@@ -3,6 +3,7 @@ use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::Debug;
 use std::num::NonZeroU64;
 use std::ops::RangeBounds;
+use std::sync::Arc;
 
 use itertools::Itertools;
 
@@ -12,10 +13,16 @@ use crate::exid::ExId;
 use crate::hydrate;
 use crate::iter::{Keys, ListRange, MapRange, Values};
 use crate::marks::{Mark, MarkAccumulator, MarkStateMachine};
+use crate::observer::{ObserverFilter, ObserverId, Observers};
 use crate::op_set::OpSet;
 use crate::parents::Parents;
 use crate::patches::{Patch, PatchLog, TextRepresentation};
-use crate::storage::{self, load, CompressConfig, VerificationMode};
+use crate::policy::{ChangePolicy, Policy, RejectReason, Rejected, RejectedChange};
+use crate::schema::Schema;
+#[cfg(feature = "signing")]
+use crate::signing::{SigningKey, TrustStore};
+use crate::storage::{self, load, Codec, CompressConfig, KeyProvider, VerificationMode};
+use crate::text_value::TextEncoding;
 use crate::transaction::{self, CommitOptions, Failure, Success, Transaction, TransactionArgs};
 use crate::types::{
     ActorId, ChangeHash, Clock, ElemId, Export, Exportable, Key, MarkData, ObjId, ObjMeta, Op,
@@ -23,9 +30,13 @@ use crate::types::{
 };
 use crate::{AutomergeError, Change, Cursor, ObjType, Prop, ReadDoc};
 
+mod compact;
 pub(crate) mod current_state;
 pub(crate) mod diff;
 
+pub(crate) use compact::rebased_original;
+pub use compact::Baseline;
+
 #[cfg(test)]
 mod tests;
 
@@ -92,6 +103,27 @@ pub struct Automerge {
     actor: Actor,
     /// The maximum operation counter this document has seen.
     max_op: u64,
+    /// The baseline this document was compacted to, if any.
+    baseline: Option<Arc<Baseline>>,
+    /// The changes which were replaced by the baseline or rebased onto it, and the change in this
+    /// document which replaced each of them.
+    replaced: HashMap<ChangeHash, ChangeHash>,
+    /// The schema local operations are checked against, if any.
+    schema: Option<Arc<Schema>>,
+    /// Callbacks to notify of changes to the document.
+    observers: Observers,
+    /// The key local changes are signed with, if any.
+    #[cfg(feature = "signing")]
+    signing_key: Option<Arc<SigningKey>>,
+    /// The keys received changes are verified against, if any.
+    #[cfg(feature = "signing")]
+    trust_store: Option<Arc<TrustStore>>,
+    /// The policy received changes are checked against, if any.
+    change_policy: Option<Policy>,
+    /// The hashes of the changes rejected by the change policy.
+    rejected: HashSet<ChangeHash>,
+    /// Whether a baseline received from a peer may replace the history of this document.
+    accept_baselines: bool,
 }
 
 impl Automerge {
@@ -107,6 +139,17 @@ impl Automerge {
             deps: Default::default(),
             actor: Actor::Unused(ActorId::random()),
             max_op: 0,
+            baseline: None,
+            replaced: HashMap::new(),
+            schema: None,
+            observers: Observers::default(),
+            #[cfg(feature = "signing")]
+            signing_key: None,
+            #[cfg(feature = "signing")]
+            trust_store: None,
+            change_policy: None,
+            rejected: HashSet::new(),
+            accept_baselines: false,
         }
     }
 
@@ -162,6 +205,198 @@ impl Automerge {
         self
     }
 
+    /// Check local operations against `schema`
+    ///
+    /// See [`crate::schema`]
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.set_schema(Some(schema));
+        self
+    }
+
+    /// Check local operations against `schema`, or stop checking them if `schema` is `None`
+    pub fn set_schema(&mut self, schema: Option<Schema>) -> &mut Self {
+        self.schema = schema.map(Arc::new);
+        self
+    }
+
+    /// The schema local operations are checked against
+    pub fn schema(&self) -> Option<&Schema> {
+        self.schema.as_deref()
+    }
+
+    /// Sign the changes committed to this document with `key`
+    ///
+    /// See [`crate::signing`]
+    #[cfg(feature = "signing")]
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.set_signing_key(Some(key));
+        self
+    }
+
+    /// Sign the changes committed to this document with `key`, or stop signing them if `key` is
+    /// `None`
+    #[cfg(feature = "signing")]
+    pub fn set_signing_key(&mut self, key: Option<SigningKey>) -> &mut Self {
+        self.signing_key = key.map(Arc::new);
+        self
+    }
+
+    /// The key the changes committed to this document are signed with
+    #[cfg(feature = "signing")]
+    pub fn signing_key(&self) -> Option<&SigningKey> {
+        self.signing_key.as_deref()
+    }
+
+    /// Verify the signatures of changes received by this document against `trust_store`
+    ///
+    /// See [`crate::signing`]
+    #[cfg(feature = "signing")]
+    pub fn with_trust_store(mut self, trust_store: TrustStore) -> Self {
+        self.set_trust_store(Some(trust_store));
+        self
+    }
+
+    /// Verify the signatures of changes received by this document against `trust_store`, or stop
+    /// verifying them if `trust_store` is `None`
+    #[cfg(feature = "signing")]
+    pub fn set_trust_store(&mut self, trust_store: Option<TrustStore>) -> &mut Self {
+        self.trust_store = trust_store.map(Arc::new);
+        self
+    }
+
+    /// The trust store the signatures of received changes are verified against
+    #[cfg(feature = "signing")]
+    pub fn trust_store(&self) -> Option<&TrustStore> {
+        self.trust_store.as_deref()
+    }
+
+    /// Sign `change` if this document has a signing key
+    #[cfg(feature = "signing")]
+    pub(crate) fn sign(&self, change: Change) -> Change {
+        match &self.signing_key {
+            Some(key) => key.sign(&change),
+            None => change,
+        }
+    }
+
+    /// Check the changes this document receives from other actors against `policy`
+    ///
+    /// See [`crate::policy`]
+    pub fn with_change_policy<P: ChangePolicy + 'static>(mut self, policy: P) -> Self {
+        self.set_change_policy(policy);
+        self
+    }
+
+    /// Check the changes this document receives from other actors against `policy`, replacing
+    /// any existing policy
+    pub fn set_change_policy<P: ChangePolicy + 'static>(&mut self, policy: P) -> &mut Self {
+        self.change_policy = Some(Policy::new(policy));
+        self
+    }
+
+    /// Stop checking the changes this document receives against a change policy
+    pub fn clear_change_policy(&mut self) -> &mut Self {
+        self.change_policy = None;
+        self
+    }
+
+    /// Let a baseline received from a peer replace the history of this document if `accept` is
+    /// true
+    ///
+    /// See [`Self::set_accept_baselines`]
+    pub fn with_accept_baselines(mut self, accept: bool) -> Self {
+        self.set_accept_baselines(accept);
+        self
+    }
+
+    /// Let a baseline received from a peer replace the history of this document if `accept` is
+    /// true
+    ///
+    /// Baselines are off by default: a document with history rejects any baseline it receives
+    /// with [`RejectReason::NotAccepted`](crate::policy::RejectReason::NotAccepted), and a
+    /// document which hasn't been compacted doesn't tell sync peers it understands baselines, so
+    /// they don't send it any. See [`Self::compact`].
+    pub fn set_accept_baselines(&mut self, accept: bool) -> &mut Self {
+        self.accept_baselines = accept;
+        self
+    }
+
+    /// Whether a baseline received from a peer may replace the history of this document
+    pub fn accepts_baselines(&self) -> bool {
+        self.accept_baselines
+    }
+
+    /// Call `callback` with the patches matching `filter` whenever this document changes, see
+    /// [`crate::observer`]
+    pub fn observe<F>(
+        &mut self,
+        filter: impl Into<ObserverFilter>,
+        text_rep: TextRepresentation,
+        callback: F,
+    ) -> ObserverId
+    where
+        F: FnMut(&[Patch]) + Send + 'static,
+    {
+        self.observers.add(filter.into(), text_rep, callback)
+    }
+
+    /// Remove an observer added with [`Self::observe`], returning whether it was registered
+    pub fn unobserve(&mut self, id: ObserverId) -> bool {
+        self.observers.remove(id)
+    }
+
+    pub(crate) fn observing(&self) -> bool {
+        !self.observers.is_empty()
+    }
+
+    /// Notify observers of the changes since `before`
+    pub(crate) fn notify_observers(&mut self, before: &[ChangeHash]) {
+        if self.observing() {
+            let mut observers = std::mem::take(&mut self.observers);
+            observers.notify(self, before);
+            self.observers = observers;
+        }
+    }
+
+    /// Measure indices into text objects in `encoding`
+    ///
+    /// See [`TextEncoding`]
+    pub fn with_text_encoding(mut self, encoding: TextEncoding) -> Self {
+        self.set_text_encoding(encoding);
+        self
+    }
+
+    /// Measure indices into text objects in `encoding`
+    ///
+    /// The encoding can be changed at any time, but indices already recorded in a
+    /// [`PatchLog`] are not converted, so patches should be generated before changing it.
+    ///
+    /// See [`TextEncoding`]
+    pub fn set_text_encoding(&mut self, encoding: TextEncoding) -> &mut Self {
+        self.ops.set_text_encoding(encoding);
+        self
+    }
+
+    /// The unit indices into text objects are measured in
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.ops.text_encoding()
+    }
+
+    /// Convert `index`, an index into the text object `obj` in the `from` encoding, to an index
+    /// into the same text in the `to` encoding
+    ///
+    /// See [`TextEncoding::convert_index`]
+    pub fn convert_text_index<O: AsRef<ExId>>(
+        &self,
+        obj: O,
+        index: usize,
+        from: TextEncoding,
+        to: TextEncoding,
+    ) -> Result<usize, AutomergeError> {
+        let text = self.text(obj)?;
+        Ok(from.convert_index(&text, index, to))
+    }
+
     /// Get the current actor id of this document.
     pub fn get_actor(&self) -> &ActorId {
         match &self.actor {
@@ -397,6 +632,10 @@ impl Automerge {
         let mut f = Self::new();
         f.set_actor(ActorId::random());
         f.apply_changes(changes.into_iter().rev().cloned())?;
+        f.schema = self.schema.clone();
+        f.change_policy = self.change_policy.clone();
+        f.accept_baselines = self.accept_baselines;
+        f.set_text_encoding(self.text_encoding());
         Ok(f)
     }
 
@@ -404,7 +643,9 @@ impl Automerge {
         match id {
             ExId::Root => Ok(OpId::new(0, 0)),
             ExId::Id(ctr, actor, idx) => {
-                let opid = if self.ops.m.actors.cache.get(*idx) == Some(actor) {
+                let opid = if let Some(alias) = self.baseline_alias(actor, *ctr) {
+                    alias
+                } else if self.ops.m.actors.cache.get(*idx) == Some(actor) {
                     OpId::new(*ctr, *idx)
                 } else if let Some(backup_idx) = self.ops.m.actors.lookup(actor) {
                     OpId::new(*ctr, backup_idx)
@@ -431,8 +672,16 @@ impl Automerge {
         cursor: &Cursor,
         clock: Option<&Clock>,
     ) -> Result<OpId, AutomergeError> {
-        if let Some(idx) = self.ops.m.actors.lookup(cursor.actor()) {
-            let opid = OpId::new(cursor.ctr(), idx);
+        let opid = self
+            .baseline_alias(cursor.actor(), cursor.ctr())
+            .or_else(|| {
+                self.ops
+                    .m
+                    .actors
+                    .lookup(cursor.actor())
+                    .map(|idx| OpId::new(cursor.ctr(), idx))
+            });
+        if let Some(opid) = opid {
             match clock {
                 Some(clock) if !clock.covers(&opid) => {
                     Err(AutomergeError::InvalidCursor(cursor.clone()))
@@ -480,6 +729,55 @@ impl Automerge {
         )
     }
 
+    /// Load a document, verifying the signature of every change in it against `trust_store`
+    ///
+    /// The returned document goes on verifying the changes it receives against `trust_store`. See
+    /// [`crate::signing`].
+    #[cfg(feature = "signing")]
+    pub fn load_trusted(data: &[u8], trust_store: TrustStore) -> Result<Self, AutomergeError> {
+        let mut doc = Self::load(data)?;
+        doc.set_trust_store(Some(trust_store));
+        doc.verify_history()
+    }
+
+    /// Check the signatures of all the changes in a freshly loaded document against its trust
+    /// store, rebuilding the document without the invalid changes if they are to be ignored
+    #[cfg(feature = "signing")]
+    fn verify_history(self) -> Result<Self, AutomergeError> {
+        let trust_store = match &self.trust_store {
+            Some(t) => t.clone(),
+            None => return Ok(self),
+        };
+        let mut all_valid = true;
+        for change in self.history.iter().chain(self.queue.iter()) {
+            if let Err(e) = trust_store.verify(change) {
+                if trust_store.on_invalid() == crate::signing::OnInvalidSignature::Error {
+                    return Err(e.into());
+                }
+                all_valid = false;
+            }
+        }
+        if all_valid {
+            return Ok(self);
+        }
+        let mut doc = Self::new()
+            .with_actor(self.actor_id())
+            .with_text_encoding(self.text_encoding());
+        doc.trust_store = Some(trust_store);
+        match doc.apply_changes(self.history.into_iter().chain(self.queue)) {
+            // The dependents of the ignored changes are dropped with them
+            Ok(()) | Err(AutomergeError::Rejected(_)) => {}
+            Err(e) => return Err(e),
+        }
+        doc.schema = self.schema;
+        doc.signing_key = self.signing_key;
+        doc.change_policy = self.change_policy;
+        doc.accept_baselines = self.accept_baselines;
+        doc.rejected.extend(self.rejected);
+        doc.observers = self.observers;
+        Ok(doc)
+    }
+
     /// Load a document, with options
     ///
     /// # Arguments
@@ -495,14 +793,37 @@ impl Automerge {
         on_error: OnPartialLoad,
         mode: VerificationMode,
         patch_log: &mut PatchLog,
+    ) -> Result<Self, AutomergeError> {
+        Self::load_inner(data, on_error, mode, patch_log, None)
+    }
+
+    /// Like [`Self::load_with`] but decrypt any encrypted chunks with `keys`
+    #[tracing::instrument(skip(data, keys), err)]
+    pub fn load_with_keys(
+        data: &[u8],
+        on_error: OnPartialLoad,
+        mode: VerificationMode,
+        patch_log: &mut PatchLog,
+        keys: &dyn KeyProvider,
+    ) -> Result<Self, AutomergeError> {
+        Self::load_inner(data, on_error, mode, patch_log, Some(keys))
+    }
+
+    fn load_inner(
+        data: &[u8],
+        on_error: OnPartialLoad,
+        mode: VerificationMode,
+        patch_log: &mut PatchLog,
+        keys: Option<&dyn KeyProvider>,
     ) -> Result<Self, AutomergeError> {
         if data.is_empty() {
             tracing::trace!("no data, initializing empty document");
             return Ok(Self::new());
         }
         tracing::trace!("loading first chunk");
-        let (remaining, first_chunk) = storage::Chunk::parse(storage::parse::Input::new(data))
-            .map_err(|e| load::Error::Parse(Box::new(e)))?;
+        let (remaining, first_chunk) =
+            storage::Chunk::parse_with(storage::parse::Input::new(data), keys)
+                .map_err(|e| load::Error::Parse(Box::new(e)))?;
         if !first_chunk.checksum_valid() {
             return Err(load::Error::BadChecksum.into());
         }
@@ -532,7 +853,7 @@ impl Automerge {
                     change_graph.add_change(change, actor_index)?;
                 }
                 let history_index = hashes_by_index.into_iter().map(|(k, v)| (v, k)).collect();
-                Self {
+                let mut am = Self {
                     queue: vec![],
                     history: changes,
                     history_index,
@@ -542,7 +863,20 @@ impl Automerge {
                     deps: heads.into_iter().collect(),
                     actor: Actor::Unused(ActorId::random()),
                     max_op,
-                }
+                    baseline: None,
+                    replaced: HashMap::new(),
+                    schema: None,
+                    observers: Observers::default(),
+                    #[cfg(feature = "signing")]
+                    signing_key: None,
+                    #[cfg(feature = "signing")]
+                    trust_store: None,
+                    change_policy: None,
+                    rejected: HashSet::new(),
+                    accept_baselines: false,
+                };
+                am.restore_baseline();
+                am
             }
             storage::Chunk::Change(stored_change) => {
                 tracing::trace!("first chunk is change chunk");
@@ -565,7 +899,7 @@ impl Automerge {
             }
         };
         tracing::trace!("loading change chunks");
-        match load::load_changes(remaining.reset()) {
+        match load::load_changes(remaining.reset(), keys) {
             load::LoadedChanges::Complete(c) => {
                 am.apply_changes(change.into_iter().chain(c))?;
                 // Only allow missing deps if the first chunk was a document chunk
@@ -623,21 +957,67 @@ impl Automerge {
         data: &[u8],
         patch_log: &mut PatchLog,
     ) -> Result<usize, AutomergeError> {
-        if self.is_empty() {
-            let mut doc = Self::load_with(
+        self.load_incremental_inner(data, patch_log, None)
+    }
+
+    /// Like [`Self::load_incremental`] but decrypt any encrypted chunks with `keys`
+    pub fn load_incremental_with_keys(
+        &mut self,
+        data: &[u8],
+        keys: &dyn KeyProvider,
+    ) -> Result<usize, AutomergeError> {
+        self.load_incremental_log_patches_with_keys(
+            data,
+            &mut PatchLog::inactive(TextRepresentation::default()),
+            keys,
+        )
+    }
+
+    /// Like [`Self::load_incremental_log_patches`] but decrypt any encrypted chunks with `keys`
+    pub fn load_incremental_log_patches_with_keys(
+        &mut self,
+        data: &[u8],
+        patch_log: &mut PatchLog,
+        keys: &dyn KeyProvider,
+    ) -> Result<usize, AutomergeError> {
+        self.load_incremental_inner(data, patch_log, Some(keys))
+    }
+
+    fn load_incremental_inner(
+        &mut self,
+        data: &[u8],
+        patch_log: &mut PatchLog,
+        keys: Option<&dyn KeyProvider>,
+    ) -> Result<usize, AutomergeError> {
+        // Changes have to be applied one by one to check them against the change policy
+        if self.is_empty() && self.change_policy.is_none() {
+            let mut doc = Self::load_inner(
                 data,
                 OnPartialLoad::Ignore,
                 VerificationMode::Check,
                 &mut PatchLog::inactive(TextRepresentation::default()),
+                keys,
             )?;
-            doc = doc.with_actor(self.actor_id());
+            doc = doc
+                .with_actor(self.actor_id())
+                .with_text_encoding(self.text_encoding());
+            doc.schema = self.schema.clone();
+            doc.accept_baselines = self.accept_baselines;
+            #[cfg(feature = "signing")]
+            {
+                doc.signing_key = self.signing_key.clone();
+                doc.trust_store = self.trust_store.clone();
+                doc = doc.verify_history()?;
+            }
+            doc.observers = std::mem::take(&mut self.observers);
             if patch_log.is_active() {
                 current_state::log_current_state_patches(&doc, patch_log);
             }
             *self = doc;
+            self.notify_observers(&[]);
             return Ok(self.ops.len());
         }
-        let changes = match load::load_changes(storage::parse::Input::new(data)) {
+        let changes = match load::load_changes(storage::parse::Input::new(data), keys) {
             load::LoadedChanges::Complete(c) => c,
             load::LoadedChanges::Partial { error, loaded, .. } => {
                 tracing::warn!(successful_chunks=loaded.len(), err=?error, "partial load");
@@ -650,6 +1030,13 @@ impl Automerge {
         Ok(delta)
     }
 
+    /// The number of changes by each actor in this document
+    pub(crate) fn actor_seqs(&self) -> impl Iterator<Item = (&ActorId, u64)> {
+        self.states
+            .iter()
+            .map(|(actor, changes)| (self.ops.m.actors.get(*actor), changes.len() as u64))
+    }
+
     fn duplicate_seq(&self, change: &Change) -> bool {
         let mut dup = false;
         if let Some(actor_index) = self.ops.m.actors.lookup(change.actor_id()) {
@@ -680,34 +1067,134 @@ impl Automerge {
         &mut self,
         changes: I,
         patch_log: &mut PatchLog,
+    ) -> Result<(), AutomergeError> {
+        let before = self.observing().then(|| self.get_heads());
+        let result = self.apply_changes_inner(changes, patch_log);
+        if let Some(before) = before {
+            // The changes may have been rebased onto a baseline we adopted
+            let before = self.translate_heads(&before);
+            self.notify_observers(&before);
+        }
+        result
+    }
+
+    fn apply_changes_inner<I: IntoIterator<Item = Change>>(
+        &mut self,
+        changes: I,
+        patch_log: &mut PatchLog,
     ) -> Result<(), AutomergeError> {
         // Record this so we can avoid observing each individual change and instead just observe
         // the final state after all the changes have been applied. We can only do this for an
         // empty document right now, once we have logic to produce the diffs between arbitrary
         // states of the OpSet we can make this cleaner.
+        let rejected_before = self.rejected.len();
+        #[cfg(feature = "signing")]
+        let changes = match &self.trust_store {
+            Some(trust_store) => {
+                let (valid, invalid) = trust_store.clone().filter(self, changes)?;
+                // Changes which depend on an ignored change are rejected like the dependents of
+                // changes the policy rejects
+                self.rejected.extend(invalid);
+                valid
+            }
+            None => changes.into_iter().collect(),
+        };
+        let mut rejected = Vec::new();
         for c in changes {
-            if !self.history_index.contains_key(&c.hash()) {
-                if self.duplicate_seq(&c) {
-                    return Err(AutomergeError::DuplicateSeqNumber(
-                        c.seq(),
-                        c.actor_id().clone(),
-                    ));
-                }
-                if self.is_causally_ready(&c) {
-                    self.apply_change(c, patch_log)?;
-                } else {
-                    self.queue.push(c);
-                }
+            let c = match self.prepare_change(c) {
+                Some(c) => c,
+                None => continue,
+            };
+            if let Some(r) = self.rejected_dependency(&c) {
+                rejected.push(r);
+                continue;
+            }
+            if self.duplicate_seq(&c) {
+                return Err(AutomergeError::DuplicateSeqNumber(
+                    c.seq(),
+                    c.actor_id().clone(),
+                ));
+            }
+            if self.is_causally_ready(&c) {
+                self.apply_ready_change(c, patch_log, &mut rejected)?;
+            } else {
+                self.queue.push(c);
             }
         }
+        self.apply_queued_changes(patch_log, &mut rejected)?;
+        if self.rejected.len() == rejected_before {
+            return Ok(());
+        }
+        // Queued changes which depend on a rejected change would otherwise wait forever
+        while let Some(index) = self
+            .queue
+            .iter()
+            .position(|c| c.deps().iter().any(|d| self.rejected.contains(d)))
+        {
+            let c = self.queue.swap_remove(index);
+            rejected.extend(self.rejected_dependency(&c));
+        }
+        if rejected.is_empty() {
+            return Ok(());
+        }
+        Err(Rejected { changes: rejected }.into())
+    }
+
+    /// Apply the queued changes which have become causally ready
+    fn apply_queued_changes(
+        &mut self,
+        patch_log: &mut PatchLog,
+        rejected: &mut Vec<RejectedChange>,
+    ) -> Result<(), AutomergeError> {
         while let Some(c) = self.pop_next_causally_ready_change() {
-            if !self.history_index.contains_key(&c.hash()) {
-                self.apply_change(c, patch_log)?;
+            if let Some(c) = self.prepare_change(c) {
+                self.apply_ready_change(c, patch_log, rejected)?;
             }
         }
         Ok(())
     }
 
+    /// Apply `change` if the change policy accepts it, otherwise add it to `rejected`
+    fn check_and_apply_change(
+        &mut self,
+        change: Change,
+        patch_log: &mut PatchLog,
+        rejected: &mut Vec<RejectedChange>,
+    ) -> Result<(), AutomergeError> {
+        if self.check_policy(&change, rejected) {
+            self.apply_change(change, patch_log)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the change policy accepts `change`, adding it to `rejected` if not
+    fn check_policy(&mut self, change: &Change, rejected: &mut Vec<RejectedChange>) -> bool {
+        if let Some(policy) = &self.change_policy {
+            if let Err(r) = policy.check(self, change) {
+                self.rejected.insert(r.hash);
+                rejected.push(r);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether the change with `hash` was rejected by the change policy
+    pub(crate) fn is_rejected(&self, hash: &ChangeHash) -> bool {
+        self.rejected.contains(hash)
+    }
+
+    /// If `change` depends on a rejected change then reject it too
+    fn rejected_dependency(&mut self, change: &Change) -> Option<RejectedChange> {
+        let dep = *change.deps().iter().find(|d| self.rejected.contains(d))?;
+        self.rejected.insert(change.hash());
+        Some(RejectedChange {
+            hash: change.hash(),
+            actor: change.actor_id().clone(),
+            reason: RejectReason::Dependency(dep),
+        })
+    }
+
     fn apply_change(
         &mut self,
         change: Change,
@@ -722,10 +1209,19 @@ impl Automerge {
     }
 
     fn is_causally_ready(&self, change: &Change) -> bool {
+        // A baseline has no dependencies but is checked against the history it replaced
+        if let Some(baseline) = Baseline::from_change(change) {
+            return self.history.is_empty()
+                || self.trusts_baseline(&change.hash())
+                || baseline
+                    .horizon()
+                    .iter()
+                    .all(|h| self.history_index.contains_key(h));
+        }
         change
             .deps()
             .iter()
-            .all(|d| self.history_index.contains_key(d))
+            .all(|d| self.history_index.contains_key(d) || self.replaced.contains_key(d))
     }
 
     fn pop_next_causally_ready_change(&mut self) -> Option<Change> {
@@ -825,7 +1321,7 @@ impl Automerge {
     pub fn save_with_options(&self, options: SaveOptions) -> Vec<u8> {
         let heads = self.get_heads();
         let c = self.history.iter();
-        let compress = if options.deflate {
+        let compress = if options.deflate && options.codec.is_none() {
             None
         } else {
             Some(CompressConfig::None)
@@ -838,11 +1334,21 @@ impl Automerge {
             &heads,
             compress,
         );
+        if let Some(codec) = options.codec {
+            bytes = crate::storage::compress_chunk(codec, &bytes);
+        }
         if options.retain_orphans {
             for orphaned in self.queue.iter() {
-                bytes.extend(orphaned.raw_bytes());
+                match options.codec {
+                    Some(codec) => bytes.extend(orphaned.compress_with(codec).iter()),
+                    None => bytes.extend(orphaned.raw_bytes()),
+                }
             }
         }
+        #[cfg(feature = "encryption")]
+        if let Some(keys) = &options.encryption {
+            bytes = crate::storage::encrypt_chunks(keys.as_ref(), &bytes);
+        }
         bytes
     }
 
@@ -881,6 +1387,24 @@ impl Automerge {
         bytes
     }
 
+    /// Like [`Self::save_after`] but compress large changes with `codec`, see
+    /// [`Change::compress_with`]
+    pub fn save_after_with(&self, heads: &[ChangeHash], codec: Codec) -> Vec<u8> {
+        let changes = self.get_changes(heads);
+        let mut bytes = vec![];
+        for c in changes {
+            bytes.extend(c.compress_with(codec).iter());
+        }
+        bytes
+    }
+
+    /// Like [`Self::save_after`] but encrypt each change with the current key of `keys`. The
+    /// result can be loaded with [`Self::load_incremental_with_keys`].
+    #[cfg(feature = "encryption")]
+    pub fn save_after_encrypted(&self, heads: &[ChangeHash], keys: &dyn KeyProvider) -> Vec<u8> {
+        crate::storage::encrypt_chunks(keys, &self.save_after(heads))
+    }
+
     /// Filter the changes down to those that are not transitive dependencies of the heads.
     ///
     /// Thus a graph with these heads has not seen the remaining changes.
@@ -996,6 +1520,10 @@ impl Automerge {
 
         self.update_deps(&change);
 
+        if let Some(original) = rebased_original(&change) {
+            self.replaced.insert(original.hash(), change.hash());
+        }
+
         let history_index = self.history.len();
 
         let actor_index = self.ops.m.actors.cache(change.actor_id().clone());
@@ -1620,15 +2148,33 @@ impl ReadDoc for Automerge {
             }
         }
 
+        // A queued baseline is waiting for the history it replaced
+        let horizons = self
+            .queue
+            .iter()
+            .filter_map(Baseline::from_change)
+            .collect::<Vec<_>>();
+        for head in horizons.iter().flat_map(|b| b.horizon()) {
+            if !self.history_index.contains_key(head) {
+                missing.insert(head);
+            }
+        }
+
         for head in heads {
             if !self.history_index.contains_key(head) {
                 missing.insert(head);
             }
         }
 
+        // Changes rejected by the change policy will never be applied and changes replaced by
+        // our baseline are not needed, so neither are missing
         let mut missing = missing
             .into_iter()
-            .filter(|hash| !in_queue.contains(hash))
+            .filter(|hash| {
+                !in_queue.contains(hash)
+                    && !self.rejected.contains(hash)
+                    && !self.replaced.contains_key(hash)
+            })
             .copied()
             .collect::<Vec<_>>();
         missing.sort();
@@ -1655,6 +2201,14 @@ pub struct SaveOptions {
     pub deflate: bool,
     /// Whether to save changes which we do not have the dependencies for
     pub retain_orphans: bool,
+    /// Compress the whole document with this codec instead of compressing each column with
+    /// DEFLATE, in which case `deflate` is ignored. See [`Codec`] for compatibility.
+    pub codec: Option<Codec>,
+    /// Encrypt the document, and any orphaned changes, with the current key of this provider. The
+    /// result can only be loaded by passing a provider with the same key to
+    /// [`Automerge::load_with_keys`].
+    #[cfg(feature = "encryption")]
+    pub encryption: Option<Arc<dyn KeyProvider>>,
 }
 
 impl std::default::Default for SaveOptions {
@@ -1662,6 +2216,9 @@ impl std::default::Default for SaveOptions {
         Self {
             deflate: true,
             retain_orphans: true,
+            codec: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
         }
     }
 }
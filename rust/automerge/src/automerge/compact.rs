@@ -0,0 +1,1136 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::num::NonZeroU64;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+use crate::exid::ExId;
+use crate::legacy;
+use crate::marks::ExpandMark;
+use crate::patches::{PatchLog, TextRepresentation};
+use crate::policy::{RejectReason, RejectedChange};
+use crate::storage::parse;
+use crate::transaction::{CommitOptions, Transactable, Transaction};
+use crate::types::{Key, ListEncoding, ObjId, OpId, OpType, ScalarValue};
+use crate::{
+    ActorId, Automerge, AutomergeError, Change, ChangeHash, ExpandedChange, ObjType, ReadDoc,
+};
+
+// The extra bytes of a baseline change are laid out as
+//
+// .--------------------------------------------------------------------------------------.
+// | magic   | version | horizon           | actors                 | aliases              |
+// +--------------------------------------------------------------------------------------+
+// | 4 bytes | 1 byte  | leb128 count +    | leb128 count +         | leb128 count +       |
+// |         |         | 32 byte hashes    | (actor, seq, max op)   | (actor, old, new)    |
+// '--------------------------------------------------------------------------------------'
+//
+// where the actors table holds every actor with changes before the horizon as length prefixed
+// bytes followed by the number of changes it had made and the largest counter of its operations
+// at the horizon, and each alias is the index of an actor in the actors table, the counter of an
+// operation by that actor before compaction and the counter of the baseline operation which
+// replaced it. The numbers are all unsigned leb128.
+const BASELINE_MAGIC: [u8; 4] = *b"AMbl";
+const BASELINE_VERSION: u8 = 0;
+
+// The extra bytes of a change rebased onto a baseline are the magic bytes, a version byte and
+// then the raw bytes of the original change
+const REBASED_MAGIC: [u8; 4] = *b"AMrb";
+const REBASED_VERSION: u8 = 0;
+
+/// The single change which replaces the history of a document compacted with
+/// [`Automerge::compact`]
+///
+/// A baseline records the heads of the history it replaced (the "horizon") and how the IDs of
+/// the objects and sequence elements which survived compaction map onto the IDs of the
+/// operations in the baseline. This allows object IDs and cursors obtained before compaction to
+/// keep working afterwards, and allows the sync protocol to recognise peers which are still on
+/// the old history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Baseline {
+    hash: ChangeHash,
+    horizon: Vec<ChangeHash>,
+    actor: ActorId,
+    /// The number of changes and the largest operation counter of each actor at the horizon
+    clock: HashMap<ActorId, (u64, u64)>,
+    aliases: HashMap<ActorId, HashMap<u64, u64>>,
+    /// How far the counters of the operations after the horizon move when they are rebased, so
+    /// that they stay above the counters of the baseline
+    shift: u64,
+}
+
+impl Baseline {
+    /// The hash of the baseline change
+    pub fn hash(&self) -> ChangeHash {
+        self.hash
+    }
+
+    /// The heads of the history which the baseline replaced
+    pub fn horizon(&self) -> &[ChangeHash] {
+        &self.horizon
+    }
+
+    /// The actor of the baseline change
+    pub fn actor(&self) -> &ActorId {
+        &self.actor
+    }
+
+    /// The counter of the baseline operation which replaced the operation `ctr@actor`
+    pub(crate) fn alias(&self, actor: &ActorId, ctr: u64) -> Option<u64> {
+        self.aliases.get(actor)?.get(&ctr).copied()
+    }
+
+    /// Whether the operation `ctr@actor` was made before the horizon
+    fn covers(&self, actor: &ActorId, ctr: u64) -> bool {
+        self.clock
+            .get(actor)
+            .map_or(false, |(_, max_op)| ctr <= *max_op)
+    }
+
+    /// The number of changes `actor` had made at the horizon
+    fn seq(&self, actor: &ActorId) -> u64 {
+        self.clock.get(actor).map_or(0, |(seq, _)| *seq)
+    }
+
+    /// Add the horizon to `heads` if they contain the baseline hash, so that they can be
+    /// understood by a peer which hasn't compacted its history
+    pub(crate) fn expand_heads(&self, heads: &[ChangeHash]) -> Vec<ChangeHash> {
+        if !heads.contains(&self.hash) {
+            return heads.to_vec();
+        }
+        let mut expanded = heads
+            .iter()
+            .chain(self.horizon.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        expanded.sort();
+        expanded.dedup();
+        expanded
+    }
+
+    /// Parse the baseline from `change`, returning `None` if it isn't a baseline change
+    pub(crate) fn from_change(change: &Change) -> Option<Self> {
+        if !change.deps().is_empty() || change.seq() != 1 {
+            return None;
+        }
+        let (horizon, actors, aliases) = decode(change.extra_bytes())?;
+        let mut by_actor: HashMap<ActorId, HashMap<u64, u64>> = HashMap::new();
+        for (actor, old, new) in aliases {
+            by_actor
+                .entry(actors.get(actor)?.0.clone())
+                .or_default()
+                .insert(old, new);
+        }
+        let horizon_max_op = actors.iter().map(|(_, _, max_op)| *max_op).max();
+        Some(Self {
+            hash: change.hash(),
+            horizon,
+            actor: change.actor_id().clone(),
+            clock: actors
+                .into_iter()
+                .map(|(actor, seq, max_op)| (actor, (seq, max_op)))
+                .collect(),
+            aliases: by_actor,
+            shift: change.max_op().saturating_sub(horizon_max_op.unwrap_or(0)),
+        })
+    }
+}
+
+/// The change which `change` was rebased from, if it is a change rebased onto a baseline
+pub(crate) fn rebased_original(change: &Change) -> Option<Change> {
+    let rest = change.extra_bytes().strip_prefix(&REBASED_MAGIC[..])?;
+    let (version, bytes) = rest.split_first()?;
+    if *version != REBASED_VERSION {
+        return None;
+    }
+    Change::try_from(bytes).ok()
+}
+
+type Decoded = (
+    Vec<ChangeHash>,
+    Vec<(ActorId, u64, u64)>,
+    Vec<(usize, u64, u64)>,
+);
+
+fn decode(data: &[u8]) -> Option<Decoded> {
+    fn leb(i: parse::Input<'_>) -> Option<(parse::Input<'_>, u64)> {
+        parse::leb128_u64::<parse::leb128::Error>(i).ok()
+    }
+
+    let i = parse::Input::new(data);
+    let (i, magic) = parse::take_n::<()>(BASELINE_MAGIC.len(), i).ok()?;
+    if magic != BASELINE_MAGIC {
+        return None;
+    }
+    let (i, version) = parse::take1::<()>(i).ok()?;
+    if version != BASELINE_VERSION {
+        return None;
+    }
+    let (mut i, count) = leb(i)?;
+    let mut horizon = Vec::new();
+    for _ in 0..count {
+        let (next, hash) = parse::change_hash::<()>(i).ok()?;
+        horizon.push(hash);
+        i = next;
+    }
+    let (mut i, count) = leb(i)?;
+    let mut actors = Vec::new();
+    for _ in 0..count {
+        let (next, len) = leb(i)?;
+        let (next, bytes) = parse::take_n::<()>(len as usize, next).ok()?;
+        let (next, seq) = leb(next)?;
+        let (next, max_op) = leb(next)?;
+        actors.push((ActorId::from(bytes), seq, max_op));
+        i = next;
+    }
+    let (mut i, count) = leb(i)?;
+    let mut aliases = Vec::new();
+    for _ in 0..count {
+        let (next, actor) = leb(i)?;
+        let (next, old) = leb(next)?;
+        let (next, new) = leb(next)?;
+        aliases.push((actor as usize, old, new));
+        i = next;
+    }
+    Some((horizon, actors, aliases))
+}
+
+fn encode(
+    horizon: &[ChangeHash],
+    clock: &BTreeMap<ActorId, (u64, u64)>,
+    aliases: &BTreeMap<(ActorId, u64), u64>,
+) -> Vec<u8> {
+    let mut buf = BASELINE_MAGIC.to_vec();
+    buf.push(BASELINE_VERSION);
+    leb128::write::unsigned(&mut buf, horizon.len() as u64).unwrap();
+    for hash in horizon {
+        buf.extend(hash.as_bytes());
+    }
+    leb128::write::unsigned(&mut buf, clock.len() as u64).unwrap();
+    let mut actor_index = HashMap::new();
+    for (index, (actor, (seq, max_op))) in clock.iter().enumerate() {
+        leb128::write::unsigned(&mut buf, actor.to_bytes().len() as u64).unwrap();
+        buf.extend(actor.to_bytes());
+        leb128::write::unsigned(&mut buf, *seq).unwrap();
+        leb128::write::unsigned(&mut buf, *max_op).unwrap();
+        actor_index.insert(actor, index);
+    }
+    leb128::write::unsigned(&mut buf, aliases.len() as u64).unwrap();
+    for ((actor, old), new) in aliases {
+        // Every operation which survived compaction was made before the horizon, so its actor is
+        // in the clock
+        leb128::write::unsigned(&mut buf, actor_index[actor] as u64).unwrap();
+        leb128::write::unsigned(&mut buf, *old).unwrap();
+        leb128::write::unsigned(&mut buf, *new).unwrap();
+    }
+    buf
+}
+
+/// The actor of the baseline for `horizon`. This is derived from the horizon so that every peer
+/// which compacts at the same horizon produces the same baseline.
+fn baseline_actor(horizon: &[ChangeHash]) -> ActorId {
+    let mut hasher = Sha256::new();
+    hasher.update(b"automerge baseline");
+    for hash in horizon {
+        hasher.update(hash.as_bytes());
+    }
+    ActorId::from(&hasher.finalize()[..16])
+}
+
+impl Automerge {
+    /// Squash the history of this document up to `heads` into a single baseline change
+    ///
+    /// `heads` are the heads which every peer has acknowledged (the "horizon"). The returned
+    /// document contains a single change which recreates the state of this document at `heads`,
+    /// followed by the changes of this document which came after `heads`, rebased onto the
+    /// baseline. Deleted values, tombstones, conflicting values and the history of counters from
+    /// before `heads` are dropped. The returned document keeps the actor, text encoding, schema,
+    /// change policy and rejected changes of this document, as well as the signing key and trust
+    /// store, which trusts the new baseline. Observers are not carried over.
+    ///
+    /// Object IDs and cursors obtained from the old document continue to refer to the same
+    /// objects and sequence elements in the new document for as long as those still exist (see
+    /// [`Baseline`]). The expand behaviour of marks is not preserved; every mark is recreated
+    /// with [`ExpandMark::default`]. The operations of the rebased changes keep their IDs unless
+    /// the baseline has more operations than the history it replaced, in which case their
+    /// counters move up by the difference.
+    ///
+    /// Compaction is deterministic, so every peer which compacts at the same heads produces the
+    /// same baseline and the same rebased changes. A peer only adopts a baseline it receives if
+    /// the application has asked it to with [`Self::set_accept_baselines`] or it has no history
+    /// to replace, and rejects it with [`crate::policy::RejectReason::NotAccepted`] otherwise. A
+    /// peer which has the history a baseline replaced checks the baseline by compacting its own
+    /// history in the same way. A peer which doesn't have that history only adopts a baseline it
+    /// trusts: an empty document without a trust store trusts any baseline, otherwise the
+    /// baseline must be in its trust store (see [`crate::signing::TrustStore::insert_baseline`]).
+    /// Baselines which can't be checked are rejected with
+    /// [`crate::policy::RejectReason::Unverified`]. The sync protocol only sends a compacted
+    /// document to peers which understand baselines, see [`crate::sync`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AutomergeError::MissingHash`] if any of `heads` are not in the document.
+    pub fn compact(&self, heads: &[ChangeHash]) -> Result<Automerge, AutomergeError> {
+        if let Some(missing) = heads.iter().find(|h| self.get_change_by_hash(h).is_none()) {
+            return Err(AutomergeError::MissingHash(*missing));
+        }
+        let mut horizon = heads.to_vec();
+        horizon.sort();
+        horizon.dedup();
+        if horizon.is_empty() {
+            return Ok(self.clone());
+        }
+        let baseline = self.build_baseline(&horizon)?;
+        let mut compacted = self.rebuild(baseline)?;
+        let mut patch_log = PatchLog::inactive(TextRepresentation::default());
+        // Rejections are recorded in the compacted document
+        compacted.apply_queued_changes(&mut patch_log, &mut Vec::new())?;
+        Ok(compacted)
+    }
+
+    /// The baseline this document was compacted to, if any
+    pub fn baseline(&self) -> Option<&Baseline> {
+        self.baseline.as_deref()
+    }
+
+    /// The operation which replaced `ctr@actor` when this document was compacted
+    pub(crate) fn baseline_alias(&self, actor: &ActorId, ctr: u64) -> Option<OpId> {
+        let baseline = self.baseline.as_ref()?;
+        let new_ctr = baseline.alias(actor, ctr)?;
+        let idx = self.ops.m.actors.lookup(&baseline.actor)?;
+        Some(OpId::new(new_ctr, idx))
+    }
+
+    /// Whether the change with `hash` was replaced by the baseline or rebased onto it
+    pub(crate) fn is_replaced(&self, hash: &ChangeHash) -> bool {
+        self.replaced.contains_key(hash)
+    }
+
+    /// The changes which were rebased into any of `hashes`
+    pub(crate) fn originals_of<'a>(
+        &'a self,
+        hashes: &'a HashSet<ChangeHash>,
+    ) -> impl Iterator<Item = ChangeHash> + 'a {
+        self.replaced
+            .iter()
+            .filter(move |(_, new)| hashes.contains(new))
+            .map(|(old, _)| *old)
+    }
+
+    /// `heads` in terms of the changes in this document, replacing the changes which were
+    /// compacted into the baseline or rebased onto it
+    pub(crate) fn translate_heads(&self, heads: &[ChangeHash]) -> Vec<ChangeHash> {
+        let mut translated = heads
+            .iter()
+            .map(|h| self.replaced.get(h).unwrap_or(h))
+            .copied()
+            .collect::<Vec<_>>();
+        translated.sort();
+        translated.dedup();
+        translated
+    }
+
+    /// Move the observers of `other` to this document
+    pub(crate) fn take_observers(&mut self, other: &mut Automerge) {
+        self.observers = std::mem::take(&mut other.observers);
+    }
+
+    /// Find the baseline of a freshly loaded document and the changes which it and the changes
+    /// rebased onto it replaced
+    pub(crate) fn restore_baseline(&mut self) {
+        if let Some(baseline) = self.history.iter().find_map(Baseline::from_change) {
+            self.set_baseline(baseline);
+            for change in &self.history {
+                if let Some(original) = rebased_original(change) {
+                    self.replaced.insert(original.hash(), change.hash());
+                }
+            }
+        }
+    }
+
+    fn set_baseline(&mut self, baseline: Baseline) {
+        for hash in baseline.horizon() {
+            self.replaced.insert(*hash, baseline.hash());
+        }
+        self.baseline = Some(Arc::new(baseline));
+    }
+
+    /// Skip `change` if we already have it or it was replaced by our baseline, and rebase it onto
+    /// the baseline if it was made on top of the history the baseline replaced
+    pub(crate) fn prepare_change(&mut self, change: Change) -> Option<Change> {
+        let hash = change.hash();
+        if self.history_index.contains_key(&hash) || self.replaced.contains_key(&hash) {
+            return None;
+        }
+        let baseline = match &self.baseline {
+            Some(baseline) => baseline.clone(),
+            None => return Some(change),
+        };
+        let deps = change.deps();
+        if !deps.is_empty() && deps.iter().all(|d| self.history_index.contains_key(d)) {
+            return Some(change);
+        }
+        if change.seq() <= baseline.seq(change.actor_id()) {
+            self.replaced.insert(hash, baseline.hash());
+            return None;
+        }
+        let known =
+            |d: &ChangeHash| self.history_index.contains_key(d) || self.replaced.contains_key(d);
+        if Baseline::from_change(&change).is_some() || !deps.iter().all(known) {
+            return Some(change);
+        }
+        Some(self.rebase(&baseline, &change))
+    }
+
+    /// Rewrite `change`, which was made on top of the history `baseline` replaced, so that it
+    /// applies on top of the baseline
+    ///
+    /// Dependencies are replaced with the changes which replaced them, or with the baseline if
+    /// they aren't in this document. References to operations before the horizon are replaced
+    /// with their aliases; operations on objects or sequence elements which didn't survive
+    /// compaction become deletes of nothing so that the counters of the rest of the change don't
+    /// move. The original change is kept in the extra bytes so that the result can be checked by
+    /// rebasing it again.
+    fn rebase(&self, baseline: &Baseline, change: &Change) -> Change {
+        let map_id = |id: &legacy::OpId| {
+            if baseline.covers(id.actor(), id.counter()) {
+                let ctr = baseline.alias(id.actor(), id.counter())?;
+                Some(legacy::OpId::new(ctr, &baseline.actor))
+            } else {
+                Some(legacy::OpId::new(id.counter() + baseline.shift, id.actor()))
+            }
+        };
+        let mut expanded = ExpandedChange::from(change);
+        for op in &mut expanded.operations {
+            let obj = match &op.obj {
+                legacy::ObjectId::Root => Some(legacy::ObjectId::Root),
+                legacy::ObjectId::Id(id) => map_id(id).map(legacy::ObjectId::Id),
+            };
+            let key = match &op.key {
+                legacy::Key::Seq(legacy::ElementId::Id(id)) => {
+                    map_id(id).map(|id| legacy::Key::Seq(legacy::ElementId::Id(id)))
+                }
+                key => Some(key.clone()),
+            };
+            match (obj, key) {
+                (Some(obj), Some(key)) => {
+                    op.obj = obj;
+                    op.key = key;
+                    op.pred = op.pred.iter().filter_map(map_id).collect();
+                }
+                _ => {
+                    *op = legacy::Op {
+                        action: legacy::OpType::Delete,
+                        obj: legacy::ObjectId::Root,
+                        key: legacy::Key::Map("".into()),
+                        pred: legacy::SortedVec::new(),
+                        insert: false,
+                    }
+                }
+            }
+        }
+
+        let mut deps = change
+            .deps()
+            .iter()
+            .map(|d| match self.replaced.get(d) {
+                Some(new) => *new,
+                None if self.history_index.contains_key(d) => *d,
+                None => baseline.hash,
+            })
+            .collect::<Vec<_>>();
+        deps.sort();
+        deps.dedup();
+        // Everything else in the document descends from the baseline
+        if deps.len() > 1 {
+            deps.retain(|d| *d != baseline.hash);
+        }
+        if deps.is_empty() {
+            deps.push(baseline.hash);
+        }
+        expanded.deps = deps;
+        expanded.seq = change.seq() - baseline.seq(change.actor_id());
+        expanded.start_op = NonZeroU64::new(change.start_op().get() + baseline.shift)
+            .expect("shifting a non zero counter up keeps it non zero");
+        expanded.hash = None;
+        let mut extra_bytes = REBASED_MAGIC.to_vec();
+        extra_bytes.push(REBASED_VERSION);
+        extra_bytes.extend(change.raw_bytes());
+        expanded.extra_bytes = extra_bytes;
+        Change::from(expanded)
+    }
+
+    /// Apply `change`, which is causally ready, checking it first if it is a baseline or a change
+    /// rebased onto one
+    pub(crate) fn apply_ready_change(
+        &mut self,
+        change: Change,
+        patch_log: &mut PatchLog,
+        rejected: &mut Vec<RejectedChange>,
+    ) -> Result<(), AutomergeError> {
+        if let Some(baseline) = Baseline::from_change(&change) {
+            return self.apply_baseline(change, baseline, patch_log, rejected);
+        }
+        if let Some(original) = rebased_original(&change) {
+            let matches = match self.baseline.clone() {
+                Some(baseline) => self.rebase(&baseline, &original).hash() == change.hash(),
+                None => false,
+            };
+            if !matches {
+                self.reject_unverified(&change, rejected);
+                return Ok(());
+            }
+        }
+        self.check_and_apply_change(change, patch_log, rejected)
+    }
+
+    fn apply_baseline(
+        &mut self,
+        change: Change,
+        baseline: Baseline,
+        patch_log: &mut PatchLog,
+        rejected: &mut Vec<RejectedChange>,
+    ) -> Result<(), AutomergeError> {
+        if !self.history.is_empty() && !self.accept_baselines {
+            self.reject_change(&change, RejectReason::NotAccepted, rejected);
+            return Ok(());
+        }
+        let has_horizon = !self.history.is_empty()
+            && baseline
+                .horizon()
+                .iter()
+                .all(|h| self.history_index.contains_key(h));
+        if has_horizon {
+            // We can check a baseline for history we have by compacting that history ourselves
+            if self.build_baseline(baseline.horizon())?.hash() != change.hash() {
+                self.reject_unverified(&change, rejected);
+                return Ok(());
+            }
+        } else if !self.trusts_baseline(&change.hash()) {
+            self.reject_unverified(&change, rejected);
+            return Ok(());
+        } else if !self.check_policy(&change, rejected) {
+            return Ok(());
+        }
+
+        if self.history.is_empty() {
+            self.apply_change(change, patch_log)?;
+            self.set_baseline(baseline);
+        } else {
+            let mut adopted = self.rebuild(change)?;
+            adopted.take_observers(self);
+            *self = adopted;
+        }
+        Ok(())
+    }
+
+    /// Whether a baseline with `hash` may be adopted without checking it against our history
+    #[cfg_attr(not(feature = "signing"), allow(unused_variables))]
+    pub(crate) fn trusts_baseline(&self, hash: &ChangeHash) -> bool {
+        #[cfg(feature = "signing")]
+        if let Some(trust_store) = &self.trust_store {
+            return trust_store.trusts_baseline(hash);
+        }
+        self.history.is_empty()
+    }
+
+    fn reject_unverified(&mut self, change: &Change, rejected: &mut Vec<RejectedChange>) {
+        self.reject_change(change, RejectReason::Unverified, rejected);
+    }
+
+    fn reject_change(
+        &mut self,
+        change: &Change,
+        reason: RejectReason,
+        rejected: &mut Vec<RejectedChange>,
+    ) {
+        tracing::warn!(hash=?change.hash(), ?reason, "rejecting baseline or rebased change");
+        self.rejected.insert(change.hash());
+        rejected.push(RejectedChange {
+            hash: change.hash(),
+            actor: change.actor_id().clone(),
+            reason,
+        });
+    }
+
+    /// Build the baseline change which recreates the state of this document at `horizon`
+    fn build_baseline(&self, horizon: &[ChangeHash]) -> Result<Change, AutomergeError> {
+        let at_horizon;
+        let source = if horizon == self.get_heads() {
+            self
+        } else {
+            at_horizon = self.fork_at(horizon)?;
+            &at_horizon
+        };
+        let clock = source
+            .states
+            .iter()
+            .filter_map(|(actor, changes)| {
+                let last = &source.history[*changes.last()?];
+                let actor = source.ops.m.actors.get(*actor).clone();
+                Some((actor, (changes.len() as u64, last.max_op())))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        // Build the baseline in a scratch document. Its operations are numbered from one so that
+        // they sort before the operations after the horizon.
+        let mut scratch = Automerge::new()
+            .with_actor(baseline_actor(horizon))
+            .with_text_encoding(self.text_encoding());
+        let mut aliases = BTreeMap::new();
+        let mut tx = scratch.transaction();
+        source.copy_object(&mut tx, &ObjId::root(), &ExId::Root, &mut aliases)?;
+        let options = CommitOptions::default().with_time(0);
+        if tx.pending_ops() == 0 {
+            tx.rollback();
+            scratch.empty_commit(options);
+        } else {
+            tx.commit_with(options);
+        }
+
+        let mut expanded = ExpandedChange::from(&scratch.history[0]);
+        expanded.extra_bytes = encode(horizon, &clock, &aliases);
+        Ok(Change::from(expanded))
+    }
+
+    /// A document with the configuration of this one which contains `baseline` followed by the
+    /// changes of this document rebased onto it. The queue of this document is carried over
+    /// without being applied.
+    fn rebuild(&self, baseline: Change) -> Result<Automerge, AutomergeError> {
+        let mut rebuilt = Automerge::new()
+            .with_actor(self.get_actor().clone())
+            .with_text_encoding(self.text_encoding());
+        rebuilt.schema = self.schema.clone();
+        rebuilt.change_policy = self.change_policy.clone();
+        rebuilt.rejected = self.rejected.clone();
+        rebuilt.accept_baselines = self.accept_baselines;
+        #[cfg(feature = "signing")]
+        {
+            rebuilt.signing_key = self.signing_key.clone();
+            rebuilt.trust_store = self.trust_store.clone();
+            if let Some(trust_store) = &mut rebuilt.trust_store {
+                Arc::make_mut(trust_store).insert_baseline(baseline.hash());
+            }
+        }
+
+        let parsed = Baseline::from_change(&baseline).expect("a baseline change");
+        let mut patch_log = PatchLog::inactive(TextRepresentation::default());
+        rebuilt.apply_change(baseline, &mut patch_log)?;
+        rebuilt.set_baseline(parsed);
+        // The changes in our history have already been checked
+        for change in &self.history {
+            if let Some(change) = rebuilt.prepare_change(change.clone()) {
+                if Baseline::from_change(&change).is_some() {
+                    continue;
+                }
+                if rebuilt.is_causally_ready(&change) {
+                    rebuilt.apply_change(change, &mut patch_log)?;
+                } else {
+                    rebuilt.queue.push(change);
+                }
+            }
+        }
+        rebuilt.queue = self.queue.clone();
+        Ok(rebuilt)
+    }
+
+    fn copy_object(
+        &self,
+        tx: &mut Transaction<'_>,
+        obj: &ObjId,
+        new_obj: &ExId,
+        aliases: &mut BTreeMap<(ActorId, u64), u64>,
+    ) -> Result<(), AutomergeError> {
+        let (typ, encoding) = if obj.is_root() {
+            (ObjType::Map, ListEncoding::List)
+        } else {
+            self.ops
+                .type_and_encoding(obj)
+                .ok_or(AutomergeError::NotAnObject)?
+        };
+        let mut alias = |old: OpId, new_ctr: u64| {
+            let actor = self.ops.m.actors.get(old.actor()).clone();
+            aliases.insert((actor, old.counter()), new_ctr);
+        };
+        let mut children = Vec::new();
+        let mut index = 0;
+        for top in self.ops.top_ops(obj, None) {
+            let op = top.op;
+            let value = match &op.action {
+                OpType::Make(_) => None,
+                OpType::Put(ScalarValue::Counter(c)) => Some(ScalarValue::counter(i64::from(c))),
+                OpType::Put(v) => Some(v.clone()),
+                _ => continue,
+            };
+            if typ.is_sequence() {
+                match (value, &op.action) {
+                    (Some(ScalarValue::Str(s)), _) if typ == ObjType::Text => {
+                        tx.splice_text(new_obj, index, 0, &s)?
+                    }
+                    (Some(v), _) => tx.insert(new_obj, index, v)?,
+                    (None, OpType::Make(t)) => {
+                        let new_id = tx.insert_object(new_obj, index, *t)?;
+                        children.push((ObjId(op.id), new_id));
+                    }
+                    (None, _) => continue,
+                }
+                // The operation which inserted the element is what cursors and object IDs refer
+                // to, but a cursor may also have been obtained from an operation which updated it
+                let new_elem = tx.get_cursor(new_obj, index, None)?.ctr();
+                if let Some(elem) = op.elemid() {
+                    alias(elem.0, new_elem);
+                }
+                alias(op.id, new_elem);
+                index += op.width(encoding);
+            } else {
+                let prop = match op.elemid_or_key() {
+                    Key::Map(m) => self.ops.m.props[m].clone(),
+                    Key::Seq(_) => continue,
+                };
+                match (value, &op.action) {
+                    (Some(v), _) => {
+                        tx.put(new_obj, &prop, v)?;
+                        // Later operations on the value, such as increments, refer to it
+                        if let Some((_, ExId::Id(new_ctr, _, _))) = tx.get(new_obj, &prop)? {
+                            alias(op.id, new_ctr);
+                        }
+                    }
+                    (None, OpType::Make(t)) => {
+                        let new_id = tx.put_object(new_obj, &prop, *t)?;
+                        if let ExId::Id(new_ctr, _, _) = &new_id {
+                            alias(op.id, *new_ctr);
+                        }
+                        children.push((ObjId(op.id), new_id));
+                    }
+                    (None, _) => continue,
+                }
+            }
+        }
+        if typ == ObjType::Text {
+            for mark in self.marks_for(&self.id_to_exid(obj.0), None)? {
+                tx.mark(new_obj, mark, ExpandMark::default())?;
+            }
+        }
+        for (child, new_child) in children {
+            self.copy_object(tx, &child, &new_child, aliases)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marks::Mark;
+    use crate::sync::{self, SyncDoc};
+    use crate::{AutoCommit, ROOT};
+
+    fn doc_with_history() -> AutoCommit {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "hello big world").unwrap();
+        doc.commit();
+        doc.splice_text(&text, 6, 4, "").unwrap();
+        doc.mark(
+            &text,
+            Mark::new("bold".to_string(), true, 0, 5),
+            ExpandMark::After,
+        )
+        .unwrap();
+        let list = doc.put_object(ROOT, "list", ObjType::List).unwrap();
+        for i in 0..5 {
+            doc.insert(&list, i, i as i64).unwrap();
+            doc.commit();
+        }
+        doc.delete(&list, 0).unwrap();
+        doc.put(&list, 0, "one").unwrap();
+        let nested = doc.insert_object(&list, 1, ObjType::Map).unwrap();
+        doc.put(&nested, "key", "value").unwrap();
+        doc.put(ROOT, "counter", ScalarValue::counter(1)).unwrap();
+        doc.increment(ROOT, "counter", 2).unwrap();
+        doc.put(ROOT, "deleted", "gone").unwrap();
+        doc.commit();
+        doc.delete(ROOT, "deleted").unwrap();
+        doc.commit();
+        doc
+    }
+
+    fn sync(a: &mut AutoCommit, b: &mut AutoCommit) -> Result<(), AutomergeError> {
+        let (mut a_state, mut b_state) = (sync::State::new(), sync::State::new());
+        for _ in 0..20 {
+            let a_msg = a.sync().generate_sync_message(&mut a_state);
+            if let Some(msg) = a_msg.clone() {
+                b.sync().receive_sync_message(&mut b_state, msg)?;
+            }
+            let b_msg = b.sync().generate_sync_message(&mut b_state);
+            if let Some(msg) = b_msg.clone() {
+                a.sync().receive_sync_message(&mut a_state, msg)?;
+            }
+            if a_msg.is_none() && b_msg.is_none() {
+                return Ok(());
+            }
+        }
+        panic!("failed to sync");
+    }
+
+    #[test]
+    fn compaction_preserves_state_ids_and_cursors() {
+        let mut doc = doc_with_history();
+        let text = doc.get(ROOT, "text").unwrap().unwrap().1;
+        let list = doc.get(ROOT, "list").unwrap().unwrap().1;
+        let nested = doc.get(&list, 1).unwrap().unwrap().1;
+        let text_cursor = doc.get_cursor(&text, 6, None).unwrap();
+        let list_cursor = doc.get_cursor(&list, 2, None).unwrap();
+
+        let heads = doc.get_heads();
+        let mut compacted = doc.compact(&heads).unwrap();
+        assert_eq!(compacted.get_changes(&[]).len(), 1);
+        assert_eq!(compacted.get_actor(), doc.get_actor());
+        assert_eq!(compacted.hydrate(None), doc.hydrate(None));
+        assert_eq!(compacted.marks(&text).unwrap(), doc.marks(&text).unwrap());
+        let baseline = compacted.baseline().unwrap();
+        assert_eq!(baseline.horizon(), doc.get_heads());
+        assert_eq!(baseline.hash(), compacted.get_heads()[0]);
+
+        // IDs from before compaction still work
+        assert_eq!(compacted.text(&text).unwrap(), "hello world");
+        assert_eq!(
+            compacted.get(&nested, "key").unwrap().unwrap().0.to_str(),
+            Some("value")
+        );
+        assert_eq!(
+            compacted.get_cursor_position(&text, &text_cursor, None),
+            Ok(6)
+        );
+        assert_eq!(
+            compacted.get_cursor_position(&list, &list_cursor, None),
+            Ok(2)
+        );
+
+        // Editing after compaction moves the old cursors along
+        compacted.splice_text(&text, 0, 0, "oh ").unwrap();
+        compacted.commit();
+        assert_eq!(
+            compacted.get_cursor_position(&text, &text_cursor, None),
+            Ok(9)
+        );
+
+        // As does saving and loading
+        let loaded = AutoCommit::load(&compacted.save()).unwrap();
+        assert_eq!(loaded.baseline(), compacted.baseline());
+        assert_eq!(loaded.get_cursor_position(&text, &text_cursor, None), Ok(9));
+    }
+
+    #[test]
+    fn compaction_is_deterministic() {
+        let mut doc = doc_with_history();
+        let mut fork = doc.fork();
+        let heads = doc.get_heads();
+        let mut one = doc.compact(&heads).unwrap();
+        let mut two = fork.compact(&heads).unwrap();
+        assert_eq!(one.get_heads(), two.get_heads());
+        assert_ne!(one.get_actor(), two.get_actor());
+    }
+
+    #[test]
+    fn changes_after_the_horizon_are_rebased() {
+        let mut doc = doc_with_history();
+        let text = doc.get(ROOT, "text").unwrap().unwrap().1;
+        let list = doc.get(ROOT, "list").unwrap().unwrap().1;
+        let heads = doc.get_heads();
+        let mut concurrent = doc.fork();
+        doc.splice_text(&text, 5, 0, " there").unwrap();
+        doc.insert(&list, 1, "new").unwrap();
+        doc.commit();
+        doc.increment(ROOT, "counter", 5).unwrap();
+        doc.put(ROOT, "more", 1).unwrap();
+        doc.commit();
+        concurrent.delete(&list, 2).unwrap();
+        concurrent.commit();
+        doc.merge(&mut concurrent).unwrap();
+
+        let mut compacted = doc.compact(&heads).unwrap();
+        assert_eq!(compacted.baseline().unwrap().horizon(), heads);
+        assert_eq!(compacted.get_changes(&[]).len(), 4);
+        assert_eq!(compacted.hydrate(None), doc.hydrate(None));
+        assert_eq!(compacted.text(&text).unwrap(), "hello there world");
+
+        // Every peer rebases the same changes in the same way
+        let mut other = doc.fork().compact(&heads).unwrap();
+        assert_eq!(other.get_heads(), compacted.get_heads());
+
+        assert_eq!(
+            doc.compact(&[ChangeHash([0; 32])]).unwrap_err(),
+            AutomergeError::MissingHash(ChangeHash([0; 32]))
+        );
+    }
+
+    #[test]
+    fn rebased_operations_stay_after_a_larger_baseline() {
+        // Concurrent changes give the baseline more operations than the largest counter at the
+        // horizon
+        let mut doc = AutoCommit::new();
+        let mut other = AutoCommit::new();
+        for i in 0..10 {
+            doc.put(ROOT, format!("a{}", i), i).unwrap();
+            other.put(ROOT, format!("b{}", i), i).unwrap();
+        }
+        doc.commit();
+        other.commit();
+        doc.merge(&mut other).unwrap();
+        let heads = doc.get_heads();
+        let list = doc.put_object(ROOT, "list", ObjType::List).unwrap();
+        doc.insert(&list, 0, "x").unwrap();
+        doc.put(ROOT, "a0", "changed").unwrap();
+        doc.commit();
+
+        let mut compacted = doc.compact(&heads).unwrap();
+        assert_eq!(compacted.hydrate(None), doc.hydrate(None));
+        compacted.put(ROOT, "a1", "again").unwrap();
+        compacted.commit();
+        assert_eq!(
+            compacted.get(ROOT, "a1").unwrap().unwrap().0.to_str(),
+            Some("again")
+        );
+        assert_eq!(compacted.get_all(ROOT, "a1").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn peers_only_adopt_a_baseline_when_they_accept_baselines() {
+        let mut doc = doc_with_history();
+        let mut old = doc.fork();
+        let heads = doc.get_heads();
+        let mut compacted = doc.compact(&heads).unwrap();
+        compacted.put(ROOT, "after", "compaction").unwrap();
+        compacted.commit();
+        old.put(ROOT, "concurrent", true).unwrap();
+        old.commit();
+        let history = old.get_changes(&[]).len();
+
+        // The compacted document takes changes from a peer which doesn't accept baselines but
+        // doesn't send it any
+        sync(&mut compacted, &mut old).unwrap();
+        assert!(old.baseline().is_none());
+        assert_eq!(old.get_changes(&[]).len(), history);
+        assert!(old.get(ROOT, "after").unwrap().is_none());
+        assert_eq!(
+            compacted
+                .get(ROOT, "concurrent")
+                .unwrap()
+                .unwrap()
+                .0
+                .to_bool(),
+            Some(true)
+        );
+
+        old.set_accept_baselines(true);
+        sync(&mut compacted, &mut old).unwrap();
+        assert_eq!(old.get_heads(), compacted.get_heads());
+        assert_eq!(old.baseline(), compacted.baseline());
+        assert_eq!(old.get_changes(&[]).len(), 3);
+        assert_eq!(old.hydrate(None), compacted.hydrate(None));
+        assert_eq!(
+            old.get(ROOT, "concurrent").unwrap().unwrap().0.to_bool(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn peers_without_the_capability_are_not_sent_the_baseline() {
+        let mut doc = doc_with_history();
+        let heads = doc.get_heads();
+        let mut old = doc.fork();
+        let mut compacted = doc.compact(&heads).unwrap();
+        compacted.put(ROOT, "after", "compaction").unwrap();
+        compacted.commit();
+        old.put(ROOT, "concurrent", true).unwrap();
+        old.commit();
+        let history = old.get_changes(&[]).len();
+
+        // A peer running an older version of the library doesn't send any capabilities. The
+        // compacted peer sends very inaccurate Bloom filters, so that the old peer often doesn't
+        // send the concurrent change until it is asked for.
+        let mut s1 = sync::State::new().with_bloom_config(sync::BloomConfig::new(1, 1));
+        let mut s2 = sync::State::new();
+        let mut finished = false;
+        for _ in 0..20 {
+            let one = compacted.sync().generate_sync_message(&mut s1);
+            if let Some(msg) = &one {
+                assert!(msg.changes.is_empty());
+                old.sync()
+                    .receive_sync_message(&mut s2, msg.clone())
+                    .unwrap();
+            }
+            let two = old.sync().generate_sync_message(&mut s2).map(|msg| {
+                let mut msg = sync::Message::decode(&msg.encode()).unwrap();
+                msg.supported_capabilities = None;
+                msg
+            });
+            if let Some(msg) = two.clone() {
+                compacted.sync().receive_sync_message(&mut s1, msg).unwrap();
+            }
+            if one.is_none() && two.is_none() {
+                finished = true;
+                break;
+            }
+        }
+        assert!(finished);
+        assert!(old.baseline().is_none());
+        assert_eq!(old.get_changes(&[]).len(), history);
+        assert_eq!(old.get_all(ROOT, "text").unwrap().len(), 1);
+        assert_eq!(
+            compacted
+                .get(ROOT, "concurrent")
+                .unwrap()
+                .unwrap()
+                .0
+                .to_bool(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn forged_baselines_are_rejected() {
+        let mut doc = doc_with_history();
+        let heads = doc.get_heads();
+        let mut compacted = doc.compact(&heads).unwrap();
+        let genuine = compacted.get_changes(&[])[0].clone();
+        let mut forged = ExpandedChange::from(&genuine);
+        let op = forged
+            .operations
+            .iter_mut()
+            .find(|op| matches!(op.action, legacy::OpType::Put(_)))
+            .unwrap();
+        op.action = legacy::OpType::Put(ScalarValue::Str("forged".into()));
+        let forged = Change::from(forged);
+        assert!(Baseline::from_change(&forged).is_some());
+
+        // A peer which doesn't accept baselines doesn't check them
+        let mut old = doc.fork();
+        match old.apply_changes([genuine.clone()]) {
+            Err(AutomergeError::Rejected(r)) => {
+                assert_eq!(r.changes[0].reason, RejectReason::NotAccepted)
+            }
+            other => panic!("expected the baseline to be rejected, got {:?}", other),
+        }
+        assert!(old.baseline().is_none());
+
+        // A peer with the history the baseline claims to replace checks it against that history
+        let mut old = doc.fork().with_accept_baselines(true);
+        match old.apply_changes([forged]) {
+            Err(AutomergeError::Rejected(r)) => {
+                assert_eq!(r.changes[0].reason, RejectReason::Unverified)
+            }
+            other => panic!(
+                "expected the forged baseline to be rejected, got {:?}",
+                other
+            ),
+        }
+        assert_eq!(old.get_heads(), heads);
+        assert!(old.baseline().is_none());
+
+        old.apply_changes([genuine]).unwrap();
+        assert_eq!(old.get_heads(), compacted.get_heads());
+        assert_eq!(old.hydrate(None), doc.hydrate(None));
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn compaction_keeps_the_configuration() {
+        use crate::policy::IncomingChange;
+        use crate::schema::{MapSchema, ScalarType, Schema};
+        use crate::signing::{SigningKey, TrustStore};
+
+        let key = SigningKey::generate();
+        let alice = ActorId::random();
+        let trust = TrustStore::new().with_key(alice.clone(), key.verifying_key());
+        let schema = MapSchema::new().with_property("title", Schema::scalar([ScalarType::Str]));
+        let policy = |change: &IncomingChange<'_>| {
+            if change
+                .ops()
+                .iter()
+                .any(|op| op.key.as_deref() == Some("forbidden"))
+            {
+                Err("forbidden".to_string())
+            } else {
+                Ok(())
+            }
+        };
+        let mut doc = AutoCommit::new()
+            .with_actor(alice.clone())
+            .with_signing_key(key.clone())
+            .with_trust_store(trust.clone())
+            .with_schema(schema.into())
+            .with_change_policy(policy);
+        doc.put(ROOT, "title", "hello").unwrap();
+        doc.commit();
+        let heads = doc.get_heads();
+        let mut compacted = doc.compact(&heads).unwrap();
+        let baseline = compacted.get_changes(&[])[0].clone();
+
+        assert!(matches!(
+            compacted.put(ROOT, "title", 1),
+            Err(AutomergeError::SchemaViolation(_))
+        ));
+        compacted.put(ROOT, "title", "world").unwrap();
+        compacted.commit();
+        let store = compacted.trust_store().unwrap().clone();
+        assert!(store.trusts_baseline(&baseline.hash()));
+        assert_eq!(
+            store.verify(compacted.get_last_local_change().unwrap()),
+            Ok(())
+        );
+        let loaded = Automerge::load_trusted(&compacted.save(), store).unwrap();
+        assert_eq!(loaded.get_heads(), compacted.get_heads());
+
+        let mut remote = compacted
+            .fork()
+            .with_actor(alice)
+            .with_signing_key(key.clone());
+        remote.put(ROOT, "forbidden", true).unwrap();
+        remote.commit();
+        let change = remote.get_last_local_change().unwrap().clone();
+        match compacted.apply_changes([change]) {
+            Err(AutomergeError::Rejected(r)) => {
+                assert_eq!(
+                    r.changes[0].reason,
+                    RejectReason::Policy("forbidden".into())
+                )
+            }
+            other => panic!("expected the change to be rejected, got {:?}", other),
+        }
+
+        // A document without the history only adopts baselines its trust store trusts
+        let mut stranger = AutoCommit::new().with_trust_store(trust.clone());
+        assert!(stranger.apply_changes([baseline.clone()]).is_err());
+        assert!(stranger.baseline().is_none());
+        let mut friend = AutoCommit::new().with_trust_store(trust.with_baseline(baseline.hash()));
+        friend.apply_changes([baseline]).unwrap();
+        assert!(friend.baseline().is_some());
+    }
+
+    #[test]
+    fn compacted_peers_sync_with_each_other() {
+        let mut doc = doc_with_history();
+        let heads = doc.get_heads();
+        let mut one = doc.compact(&heads).unwrap();
+        let mut two = doc.fork().compact(&heads).unwrap();
+        one.put(ROOT, "one", 1).unwrap();
+        one.commit();
+        two.put(ROOT, "two", 2).unwrap();
+        two.commit();
+
+        sync(&mut one, &mut two).unwrap();
+        assert_eq!(one.get_heads(), two.get_heads());
+        assert_eq!(two.get_changes(&[]).len(), 3);
+
+        // A new peer is sent the baseline if it accepts baselines
+        let mut new = AutoCommit::new();
+        sync(&mut one, &mut new).unwrap();
+        assert!(new.get_heads().is_empty());
+        new.set_accept_baselines(true);
+        sync(&mut one, &mut new).unwrap();
+        assert_eq!(new.get_heads(), one.get_heads());
+        assert_eq!(new.baseline(), one.baseline());
+    }
+}
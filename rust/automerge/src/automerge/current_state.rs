@@ -98,7 +98,7 @@ fn log_text_patches<'a, I: Iterator<Item = &'a Op>>(
     ops: I,
 ) {
     let ops_by_key = ops.group_by(|o| o.elemid_or_key());
-    let encoding = ListEncoding::Text;
+    let encoding = ListEncoding::Text(doc.text_encoding());
     let state = TextState::default();
     let state = ops_by_key
         .into_iter()
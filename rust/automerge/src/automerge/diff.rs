@@ -129,7 +129,7 @@ pub(crate) fn log_diff(doc: &Automerge, before: &Clock, after: &Clock, patch_log
             .filter_map(|(_key, key_ops)| process(key_ops, before, after, &mut diff));
 
         if typ == ObjType::Text && matches!(patch_log.text_rep(), TextRepresentation::String) {
-            log_text_diff(patch_log, obj, diffs)
+            log_text_diff(doc, patch_log, obj, diffs)
         } else if typ.is_sequence() {
             log_list_diff(patch_log, obj, diffs);
         } else {
@@ -184,11 +184,12 @@ fn log_list_diff<'a, I: Iterator<Item = Patch<'a>>>(
 }
 
 fn log_text_diff<'a, I: Iterator<Item = Patch<'a>>>(
+    doc: &Automerge,
     patch_log: &mut PatchLog,
     obj: &ObjId,
     patches: I,
 ) {
-    let encoding = ListEncoding::Text;
+    let encoding = ListEncoding::Text(doc.text_encoding());
     patches.fold(0, |index, patch| match &patch {
         Patch::New(op, marks) => {
             patch_log.splice(*obj, index, op.to_str(), marks.clone());
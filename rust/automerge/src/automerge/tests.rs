@@ -1780,3 +1780,26 @@ fn hash_for_opid() {
     assert_eq!(doc.hash_for_opid(&id1), hash1);
     assert_eq!(doc.hash_for_opid(&id2), hash2);
 }
+
+#[test]
+fn put_and_delete_in_tables() -> Result<(), AutomergeError> {
+    // Tables are maps keyed by row ID, which other implementations write rows into
+    let mut doc = Automerge::new();
+    let mut tx = doc.transaction();
+    let table = tx.put_object(ROOT, "rows", ObjType::Table)?;
+    let row = tx.put_object(&table, "row-1", ObjType::Map)?;
+    tx.put(&row, "name", "alice")?;
+    tx.put(&table, "row-2", "bob")?;
+    tx.delete(&table, "row-2")?;
+    assert!(matches!(
+        tx.put(&table, 0, "carol"),
+        Err(AutomergeError::InvalidOp(ObjType::Table))
+    ));
+    tx.commit();
+
+    let loaded = Automerge::load(&doc.save())?;
+    assert_eq!(loaded.object_type(&table)?, ObjType::Table);
+    assert_eq!(loaded.keys(&table).collect::<Vec<_>>(), vec!["row-1"]);
+    assert_eq!(loaded.get(&row, "name")?.unwrap().0, Value::str("alice"));
+    Ok(())
+}
@@ -3,8 +3,9 @@ use std::{borrow::Cow, num::NonZeroU64};
 use crate::{
     columnar::Key as StoredKey,
     storage::{
-        change::{Unverified, Verified},
-        parse, Change as StoredChange, ChangeOp, Chunk, Compressed, ReadChangeOpError,
+        change::{Unverified, Verified, DEFLATE_MIN_SIZE},
+        compress_chunk, parse, Change as StoredChange, ChangeOp, Chunk, Codec, Compressed,
+        ReadChangeOpError,
     },
     types::{ActorId, ChangeHash, ElemId},
 };
@@ -108,6 +109,17 @@ impl Change {
         self.stored.bytes()
     }
 
+    /// The bytes of this change compressed with `codec`, or the uncompressed bytes if the change
+    /// is too small to be worth compressing or `codec` is [`Codec::Uncompressed`]. See [`Codec`]
+    /// for compatibility.
+    pub fn compress_with(&self, codec: Codec) -> Cow<'_, [u8]> {
+        if codec != Codec::Uncompressed && self.stored.bytes().len() > DEFLATE_MIN_SIZE {
+            Cow::Owned(compress_chunk(codec, self.stored.bytes()))
+        } else {
+            Cow::Borrowed(self.stored.bytes())
+        }
+    }
+
     pub(crate) fn iter_ops(&self) -> impl Iterator<Item = ChangeOp> + '_ {
         self.stored.iter_ops()
     }
@@ -116,6 +128,18 @@ impl Change {
         self.stored.extra_bytes()
     }
 
+    /// The bytes of this change which are covered by its signature, see [`crate::signing`]
+    #[cfg(feature = "signing")]
+    pub(crate) fn signed_bytes(&self) -> &[u8] {
+        self.stored.body_bytes_without_extra()
+    }
+
+    /// A copy of this change with its extra bytes replaced by `extra_bytes`
+    #[cfg(feature = "signing")]
+    pub(crate) fn with_extra_bytes(&self, extra_bytes: &[u8]) -> Self {
+        Self::new(self.stored.with_extra_bytes(extra_bytes))
+    }
+
     // TODO replace all uses of this with TryFrom<&[u8]>
     pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, LoadError> {
         Self::try_from(&bytes[..])
@@ -5,6 +5,7 @@ use crate::{ChangeHash, Cursor, LoadChangeError, ObjType, PatchAction};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum AutomergeError {
     #[error(transparent)]
     ChangeGraph(#[from] crate::change_graph::MissingDep),
@@ -32,8 +33,16 @@ pub enum AutomergeError {
     InvalidObjIdFormat(String),
     #[error("invalid op for object of type `{0}`")]
     InvalidOp(ObjType),
+    #[error("invalid path {path}: {reason}")]
+    InvalidPath {
+        path: crate::path::Path,
+        reason: &'static str,
+    },
     #[error("seq {0} is out of bounds")]
     InvalidSeq(u64),
+    #[cfg(feature = "signing")]
+    #[error(transparent)]
+    InvalidSignature(#[from] crate::signing::SignatureError),
     #[error("cursor {0} is invalid")]
     InvalidCursor(Cursor),
     #[error("cursor format is invalid")]
@@ -58,6 +67,10 @@ pub enum AutomergeError {
     #[error("id was not an object id")]
     NotAnObject,
     #[error(transparent)]
+    Rejected(#[from] crate::policy::Rejected),
+    #[error(transparent)]
+    SchemaViolation(#[from] crate::schema::Violation),
+    #[error(transparent)]
     HydrateError(#[from] HydrateError),
 }
 
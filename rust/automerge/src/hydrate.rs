@@ -123,6 +123,7 @@ impl From<HashMap<&str, Value>> for Value {
     }
 }
 
+use crate::text_value::TextValue;
 use crate::Automerge;
 
 impl Automerge {
@@ -151,7 +152,7 @@ impl Automerge {
 
     pub(crate) fn hydrate_text(&self, obj: &ObjId, clock: Option<&Clock>) -> Value {
         let text = self.ops().text(obj, clock.cloned());
-        Value::Text(Text::new(text.into()))
+        Value::Text(Text::new(TextValue::new(&text, self.text_encoding())))
     }
 
     pub(crate) fn hydrate_op(&self, op: &Op, clock: Option<&Clock>) -> Value {
@@ -22,7 +22,7 @@ fn simple_hydrate() -> Result<(), AutomergeError> {
         hydrated,
         hydrate_map!(
             "list" => hydrate_list!(5,6,7,"hello", ScalarValue::counter(100), hydrate_map!(), hydrate_list![]),
-            "text" => TextValue::new("hello world"),
+            "text" => TextValue::from("hello world"),
         )
     );
     doc.splice_text(&text, 6, 0, "big bad ")?;
@@ -34,7 +34,7 @@ fn simple_hydrate() -> Result<(), AutomergeError> {
     hydrated.apply_patches(patches)?;
     assert_eq!(
         hydrated.as_map().unwrap().get("text"),
-        Some(&TextValue::new("hello big bad world").into())
+        Some(&TextValue::from("hello big bad world").into())
     );
     Ok(())
 }
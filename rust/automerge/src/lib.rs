@@ -83,7 +83,9 @@
 //!
 //! ### Text Encoding
 //!
-//! Text is encoded in utf8 by default but uses Utf16 when using the wasm target.
+//! Indices into text objects count unicode code points by default, UTF-16 code units when
+//! using the wasm target and UTF-8 bytes with the `utf8-indexing` feature. The unit can be
+//! changed per document at runtime with [`Automerge::set_text_encoding`], see [`TextEncoding`].
 //!
 //! ## Sync Protocol
 //!
@@ -107,6 +109,13 @@
 //! this you can use [`AutoSerde`], which implements `serde::Serialize` for an
 //! automerge document.
 //!
+//! To read a document straight into your own types use [`AutoDe`], which
+//! implements `serde::Deserializer` for an automerge document.
+//!
+//! [`AutoSerde`] flattens counters, timestamps and text into plain values. To
+//! snapshot a document without losing type information use [`TypedSerde`],
+//! which tags such values, and restore the snapshot with [`TypedDoc`].
+//!
 //! ## Example
 //!
 //! Let's create a document representing an address book.
@@ -258,6 +267,7 @@ macro_rules! __log {
  }
 
 mod autocommit;
+mod autode;
 mod automerge;
 mod autoserde;
 mod change;
@@ -273,24 +283,36 @@ mod indexed_cache;
 pub mod iter;
 mod legacy;
 pub mod marks;
+pub mod observer;
 mod op_set;
 pub mod op_tree;
 mod parents;
 pub mod patches;
+pub mod path;
+pub mod policy;
 mod query;
 mod read;
+pub mod repo;
+pub mod schema;
+pub mod selector;
 mod sequence_tree;
+#[cfg(feature = "signing")]
+pub mod signing;
 mod storage;
+pub mod store;
 pub mod sync;
 mod text_value;
 pub mod transaction;
+mod typed_serde;
 mod types;
+pub mod undo;
 mod value;
 #[cfg(feature = "optree-visualisation")]
 mod visualisation;
 
-pub use crate::automerge::{Automerge, OnPartialLoad, SaveOptions};
+pub use crate::automerge::{Automerge, Baseline, OnPartialLoad, SaveOptions};
 pub use autocommit::AutoCommit;
+pub use autode::{AutoDe, DeserializeError};
 pub use autoserde::AutoSerde;
 pub use change::{Change, LoadError as LoadChangeError};
 pub use cursor::Cursor;
@@ -303,6 +325,9 @@ pub use parents::{Parent, Parents};
 pub use patches::{Patch, PatchAction, PatchLog};
 pub use read::ReadDoc;
 pub use sequence_tree::SequenceTree;
+pub use storage::{Codec, EncryptionKey, KeyProvider};
+pub use text_value::TextEncoding;
+pub use typed_serde::{TypedDoc, TypedSerde};
 pub use types::{ActorId, ChangeHash, ObjType, OpType, ParseChangeHashError, Prop};
 pub use value::{ScalarValue, Value};
 
@@ -0,0 +1,299 @@
+//! Callbacks invoked with the patches produced by changes to a document
+//!
+//! Rather than polling [`crate::AutoCommit::diff_incremental`] or passing a
+//! [`PatchLog`](crate::PatchLog) to each `_log_patches` method, an observer can be registered with
+//! [`crate::AutoCommit::observe`] or [`crate::Automerge::observe`]. Observers are called after a
+//! transaction is committed and after changes are received by `merge`, `load_incremental`,
+//! `apply_changes` or `receive_sync_message`, with the patches which fall under their
+//! [`ObserverFilter`]. Observers which have nothing to see are not called.
+//!
+//! Observers belong to a particular document value and are not carried over by `fork` or
+//! `clone`.
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::sync::{Arc, Mutex};
+//! use automerge::{path::Path, transaction::Transactable, AutoCommit, ObjType, PatchAction};
+//!
+//! let mut doc = AutoCommit::new();
+//! let seen = Arc::new(Mutex::new(Vec::new()));
+//! let log = seen.clone();
+//! let settings: Path = "settings".parse()?;
+//! doc.observe(settings, move |patches| {
+//!     log.lock().unwrap().extend(patches.iter().map(|p| p.action.clone()));
+//! });
+//!
+//! let obj = doc.put_object(automerge::ROOT, "settings", ObjType::Map)?;
+//! doc.put(&obj, "theme", "dark")?;
+//! doc.put(automerge::ROOT, "unrelated", 1)?;
+//! doc.commit();
+//!
+//! let seen = seen.lock().unwrap();
+//! assert_eq!(seen.len(), 2);
+//! assert!(matches!(&seen[1], PatchAction::PutMap { key, .. } if key == "theme"));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::exid::ExId;
+use crate::patches::TextRepresentation;
+use crate::path::Path;
+use crate::{Automerge, ChangeHash, Patch, PatchAction, Prop};
+
+/// Identifies an observer so it can be removed again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+/// Which patches an observer is called with
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObserverFilter {
+    /// Patches to this object or any object nested inside it
+    Object(ExId),
+    /// Patches to any value at or below this path, along with patches which replace or delete
+    /// one of its ancestors. Paths are compared with the path of each patch at the time it was
+    /// made, so an insertion earlier in a list does not count as a change to later indices.
+    Path(Path),
+}
+
+impl From<ExId> for ObserverFilter {
+    fn from(obj: ExId) -> Self {
+        ObserverFilter::Object(obj)
+    }
+}
+
+impl From<&ExId> for ObserverFilter {
+    fn from(obj: &ExId) -> Self {
+        ObserverFilter::Object(obj.clone())
+    }
+}
+
+impl From<Path> for ObserverFilter {
+    fn from(path: Path) -> Self {
+        ObserverFilter::Path(path)
+    }
+}
+
+impl ObserverFilter {
+    fn matches(&self, patch: &Patch) -> bool {
+        match self {
+            ObserverFilter::Object(obj) => {
+                &patch.obj == obj || patch.path.iter().any(|(parent, _)| parent == obj)
+            }
+            ObserverFilter::Path(path) => {
+                let target = patch
+                    .path
+                    .iter()
+                    .map(|(_, prop)| prop.clone())
+                    .chain(action_prop(&patch.action));
+                path.props()
+                    .iter()
+                    .zip(target)
+                    .all(|(wanted, actual)| prop_matches(wanted, &actual))
+            }
+        }
+    }
+}
+
+/// The prop in `Patch::obj` which `action` modifies, if there is a single one
+fn action_prop(action: &PatchAction) -> Option<Prop> {
+    match action {
+        PatchAction::PutMap { key, .. } | PatchAction::DeleteMap { key } => {
+            Some(Prop::Map(key.clone()))
+        }
+        PatchAction::PutSeq { index, .. }
+        | PatchAction::Insert { index, .. }
+        | PatchAction::SpliceText { index, .. }
+        | PatchAction::DeleteSeq { index, .. } => Some(Prop::Seq(*index)),
+        PatchAction::Increment { prop, .. } | PatchAction::Conflict { prop } => Some(prop.clone()),
+        PatchAction::Mark { .. } => None,
+    }
+}
+
+/// Whether a prop in a filter path refers to `actual`. As with [`Path`] an index in the filter
+/// refers to the key with the same digits in a map.
+fn prop_matches(wanted: &Prop, actual: &Prop) -> bool {
+    match (wanted, actual) {
+        (Prop::Seq(index), Prop::Map(key)) => key == &index.to_string(),
+        (wanted, actual) => wanted == actual,
+    }
+}
+
+type Callback = Box<dyn FnMut(&[Patch]) + Send>;
+
+struct Observer {
+    id: ObserverId,
+    filter: ObserverFilter,
+    text_rep: TextRepresentation,
+    // Only ever accessed through `&mut`, the mutex keeps the document `Sync`
+    callback: Mutex<Callback>,
+}
+
+/// The observers registered with a document
+#[derive(Default)]
+pub(crate) struct Observers {
+    next_id: u64,
+    observers: Vec<Observer>,
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Observers::default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.observers.iter().map(|o| (&o.id, &o.filter)))
+            .finish()
+    }
+}
+
+impl Observers {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub(crate) fn add<F>(
+        &mut self,
+        filter: ObserverFilter,
+        text_rep: TextRepresentation,
+        callback: F,
+    ) -> ObserverId
+    where
+        F: FnMut(&[Patch]) + Send + 'static,
+    {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.push(Observer {
+            id,
+            filter,
+            text_rep,
+            callback: Mutex::new(Box::new(callback)),
+        });
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: ObserverId) -> bool {
+        let before = self.observers.len();
+        self.observers.retain(|o| o.id != id);
+        self.observers.len() != before
+    }
+
+    /// Call each observer with the patches between `before` and the current heads of `doc`
+    pub(crate) fn notify(&mut self, doc: &Automerge, before: &[ChangeHash]) {
+        let after = doc.get_heads();
+        if after == before {
+            return;
+        }
+        let mut as_array = None;
+        let mut as_string = None;
+        for observer in &mut self.observers {
+            let patches = match observer.text_rep {
+                TextRepresentation::Array => &mut as_array,
+                TextRepresentation::String => &mut as_string,
+            }
+            .get_or_insert_with(|| doc.diff(before, &after, observer.text_rep));
+            let matching = patches
+                .iter()
+                .filter(|p| observer.filter.matches(p))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !matching.is_empty() {
+                let callback = match observer.callback.get_mut() {
+                    Ok(callback) => callback,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                callback(&matching);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{self, SyncDoc};
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ObjType, ROOT};
+    use std::sync::{Arc, Mutex};
+
+    type Seen = Arc<Mutex<Vec<Patch>>>;
+
+    fn recorder() -> (Seen, impl FnMut(&[Patch]) + Send) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        (seen, move |patches: &[Patch]| {
+            log.lock().unwrap().extend_from_slice(patches)
+        })
+    }
+
+    #[test]
+    fn filters_by_object_and_path() {
+        let mut doc = AutoCommit::new();
+        let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+        doc.commit();
+
+        let (by_obj, cb) = recorder();
+        doc.observe(&todos, cb);
+        let (by_path, cb) = recorder();
+        doc.observe("/todos/0/done".parse::<Path>().unwrap(), cb);
+        let (other, cb) = recorder();
+        let other_id = doc.observe("/settings".parse::<Path>().unwrap(), cb);
+
+        let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+        doc.put(&todo, "done", false).unwrap();
+        doc.commit();
+        assert_eq!(by_obj.lock().unwrap().len(), 2);
+        // The insert of the todo replaces `/todos/0` and the put sets `/todos/0/done`
+        assert_eq!(by_path.lock().unwrap().len(), 2);
+        assert!(other.lock().unwrap().is_empty());
+
+        assert!(doc.unobserve(other_id));
+        assert!(!doc.unobserve(other_id));
+        doc.put(ROOT, "settings", "x").unwrap();
+        doc.commit();
+        assert!(other.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn remote_changes_are_observed() {
+        let mut doc1 = crate::Automerge::new();
+        let (seen, cb) = recorder();
+        doc1.observe(ROOT, TextRepresentation::String, cb);
+
+        let mut doc2 = AutoCommit::new();
+        doc2.put(ROOT, "a", 1).unwrap();
+        doc1.merge(&mut doc2.document().clone()).unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 1);
+
+        doc2.put(ROOT, "b", 2).unwrap();
+        doc1.load_incremental(&doc2.save_incremental()).unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 2);
+
+        doc2.put(ROOT, "c", 3).unwrap();
+        let (mut s1, mut s2) = (sync::State::new(), sync::State::new());
+        loop {
+            let one = doc1.generate_sync_message(&mut s1);
+            let two = doc2.sync().generate_sync_message(&mut s2);
+            if one.is_none() && two.is_none() {
+                break;
+            }
+            if let Some(msg) = one {
+                doc2.sync().receive_sync_message(&mut s2, msg).unwrap();
+            }
+            if let Some(msg) = two {
+                doc1.receive_sync_message(&mut s1, msg).unwrap();
+            }
+        }
+        assert_eq!(seen.lock().unwrap().len(), 3);
+
+        let mut tx = doc1.transaction();
+        tx.put(ROOT, "d", 4).unwrap();
+        tx.commit();
+        assert_eq!(seen.lock().unwrap().len(), 4);
+    }
+}
@@ -8,6 +8,7 @@ use crate::op_tree::{
 };
 use crate::parents::Parents;
 use crate::query::TreeQuery;
+use crate::text_value::TextEncoding;
 use crate::types::{
     self, ActorId, Export, Exportable, Key, ListEncoding, ObjId, ObjMeta, Op, OpId, OpIds, OpType,
     Prop,
@@ -32,6 +33,8 @@ pub(crate) struct OpSetInternal {
     length: usize,
     /// Metadata about the operations in this opset.
     pub(crate) m: OpSetMetadata,
+    /// The unit indices into text objects are measured in.
+    text_encoding: TextEncoding,
 }
 
 impl OpSetInternal {
@@ -49,9 +52,18 @@ impl OpSetInternal {
                 actors: IndexedCache::new(),
                 props: IndexedCache::new(),
             },
+            text_encoding: TextEncoding::default(),
         }
     }
 
+    pub(crate) fn text_encoding(&self) -> TextEncoding {
+        self.text_encoding
+    }
+
+    pub(crate) fn set_text_encoding(&mut self, encoding: TextEncoding) {
+        self.text_encoding = encoding;
+    }
+
     pub(crate) fn id_to_exid(&self, id: OpId) -> ExId {
         if id == types::ROOT {
             ExId::Root
@@ -258,7 +270,7 @@ impl OpSetInternal {
 
     pub(crate) fn type_and_encoding(&self, id: &ObjId) -> Option<(ObjType, ListEncoding)> {
         let objtype = self.trees.get(id).map(|tree| tree.objtype)?;
-        let encoding = objtype.into();
+        let encoding = ListEncoding::new(objtype, self.text_encoding);
         Some((objtype, encoding))
     }
 
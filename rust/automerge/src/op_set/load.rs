@@ -6,6 +6,7 @@ use super::{OpSet, OpTree};
 use crate::{
     op_tree::OpTreeInternal,
     storage::load::{DocObserver, LoadedObject},
+    text_value::TextEncoding,
     types::ObjId,
 };
 
@@ -47,6 +48,7 @@ impl DocObserver for OpSetBuilder {
             trees: self.completed_objects,
             length: len,
             m: metadata,
+            text_encoding: TextEncoding::default(),
         }
     }
 }
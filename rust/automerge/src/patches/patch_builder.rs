@@ -2,18 +2,28 @@ use core::fmt::Debug;
 use std::sync::Arc;
 
 use crate::marks::MarkSet;
+use crate::text_value::{TextEncoding, TextValue};
 use crate::{ObjId, Prop, ReadDoc, Value};
 
 use super::{Patch, PatchAction};
 use crate::{marks::Mark, sequence_tree::SequenceTree};
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub(crate) struct PatchBuilder {
     patches: Vec<Patch>,
     last_mark_set: Option<Arc<MarkSet>>, // keep this around for a quick pointer equality test
+    text_encoding: TextEncoding,
 }
 
 impl PatchBuilder {
+    pub(crate) fn new(text_encoding: TextEncoding) -> Self {
+        Self {
+            patches: Vec::new(),
+            last_mark_set: None,
+            text_encoding,
+        }
+    }
+
     pub(crate) fn get_path<R: ReadDoc>(
         &mut self,
         doc: &R,
@@ -96,7 +106,7 @@ impl PatchBuilder {
         if let Some(path) = self.get_path(doc, &obj) {
             let action = PatchAction::SpliceText {
                 index,
-                value: value.into(),
+                value: TextValue::new(value, self.text_encoding),
                 marks: marks.as_deref().cloned(),
             };
             self.push(Patch { obj, path, action });
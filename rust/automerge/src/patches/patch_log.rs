@@ -333,7 +333,7 @@ impl PatchLog {
         read_doc: &R,
         text_rep: TextRepresentation,
     ) -> Vec<Patch> {
-        let mut patch_builder = PatchBuilder::default();
+        let mut patch_builder = PatchBuilder::new(doc.text_encoding());
         for (obj, event) in events {
             let exid = doc.id_to_exid(obj.0);
             // ignore events on objects in the expose queue
@@ -0,0 +1,349 @@
+//! Paths to values in a document
+//!
+//! A [`Path`] is a sequence of [`Prop`]s leading from the root of a document to a value, e.g.
+//! `config/users/3/name`. Paths can be used with [`ReadDoc::get_path`] and the path methods on
+//! [`Transactable`] instead of looking up each object along the way.
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use automerge::{path::Path, transaction::Transactable, AutoCommit, ObjType, ReadDoc, Value};
+//!
+//! let mut doc = AutoCommit::new();
+//! let users: Path = "config/users".parse()?;
+//! doc.create_path(&users, ObjType::List)?;
+//! doc.create_path(&users.clone().join(0_usize), ObjType::Map)?;
+//! doc.put_path(&"config/users/0/name".parse()?, "Alice")?;
+//!
+//! let (name, _) = doc.get_path(&"config/users/0/name".parse()?)?.unwrap();
+//! assert_eq!(name, Value::str("Alice"));
+//!
+//! // The path of an object can be recovered from its parents
+//! let (_, alice) = doc.get_path(&"config/users/0".parse()?)?.unwrap();
+//! assert_eq!(Path::from(doc.parents(&alice)?).to_string(), "/config/users/0");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::exid::ExId;
+use crate::transaction::Transactable;
+use crate::{AutomergeError, ChangeHash, ObjType, Parents, Prop, ReadDoc, Value};
+
+/// A path from the root of a document to a value
+///
+/// As a string a path is a `/` separated list of props, with an optional leading `/`. Segments
+/// which are non-negative integers are parsed as [`Prop::Seq`] and all others as [`Prop::Map`].
+/// Within a key `~1` stands for `/` and `~0` for `~`. An index used to look up a value in a map
+/// is treated as the key with the same digits, so `scores/2023` reaches the key `"2023"` in a
+/// map.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Path(Vec<Prop>);
+
+/// An error parsing a [`Path`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid escape sequence in path segment `{0}`, expected `~0` or `~1`")]
+pub struct ParsePathError(String);
+
+impl Path {
+    /// The path of the root of the document
+    pub fn root() -> Self {
+        Path(Vec::new())
+    }
+
+    /// This path with `prop` appended
+    pub fn join<P: Into<Prop>>(mut self, prop: P) -> Self {
+        self.0.push(prop.into());
+        self
+    }
+
+    pub fn props(&self) -> &[Prop] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<Prop>> for Path {
+    fn from(props: Vec<Prop>) -> Self {
+        Path(props)
+    }
+}
+
+impl FromIterator<Prop> for Path {
+    fn from_iter<I: IntoIterator<Item = Prop>>(iter: I) -> Self {
+        Path(iter.into_iter().collect())
+    }
+}
+
+impl<'a> FromIterator<&'a Prop> for Path {
+    fn from_iter<I: IntoIterator<Item = &'a Prop>>(iter: I) -> Self {
+        iter.into_iter().cloned().collect()
+    }
+}
+
+impl<'a> From<Parents<'a>> for Path {
+    fn from(parents: Parents<'a>) -> Self {
+        parents.path().into_iter().map(|(_, prop)| prop).collect()
+    }
+}
+
+impl FromStr for Path {
+    type Err = ParsePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('/').unwrap_or(s);
+        if s.is_empty() {
+            return Ok(Path::root());
+        }
+        s.split('/')
+            .map(|segment| {
+                if !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) {
+                    if let Ok(index) = segment.parse() {
+                        return Ok(Prop::Seq(index));
+                    }
+                }
+                unescape(segment).map(Prop::Map)
+            })
+            .collect()
+    }
+}
+
+fn unescape(segment: &str) -> Result<String, ParsePathError> {
+    let mut key = String::with_capacity(segment.len());
+    let mut chars = segment.chars();
+    while let Some(c) = chars.next() {
+        if c == '~' {
+            match chars.next() {
+                Some('0') => key.push('~'),
+                Some('1') => key.push('/'),
+                _ => return Err(ParsePathError(segment.to_string())),
+            }
+        } else {
+            key.push(c);
+        }
+    }
+    Ok(key)
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "/");
+        }
+        for prop in &self.0 {
+            match prop {
+                Prop::Map(key) => write!(f, "/{}", key.replace('~', "~0").replace('/', "~1"))?,
+                Prop::Seq(index) => write!(f, "/{}", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(path: &Path, reason: &'static str) -> AutomergeError {
+    AutomergeError::InvalidPath {
+        path: path.clone(),
+        reason,
+    }
+}
+
+/// The prop to use for `prop` in an object of type `obj_type`
+fn prop_in(path: &Path, obj_type: ObjType, prop: &Prop) -> Result<Prop, AutomergeError> {
+    match (obj_type, prop) {
+        (ObjType::Map | ObjType::Table, Prop::Seq(index)) => Ok(Prop::Map(index.to_string())),
+        (ObjType::List | ObjType::Text, Prop::Map(_)) => {
+            Err(invalid(path, "a key cannot be used in a list"))
+        }
+        (_, prop) => Ok(prop.clone()),
+    }
+}
+
+pub(crate) fn get<'a, R: ReadDoc + ?Sized>(
+    doc: &'a R,
+    path: &Path,
+    heads: Option<&[ChangeHash]>,
+) -> Result<Option<(Value<'a>, ExId)>, AutomergeError> {
+    let mut value = Value::Object(ObjType::Map);
+    let mut obj = ExId::Root;
+    for prop in path.props() {
+        let obj_type = match value {
+            Value::Object(obj_type) => obj_type,
+            Value::Scalar(_) => return Ok(None),
+        };
+        let prop = prop_in(path, obj_type, prop)?;
+        let next = match heads {
+            Some(heads) => doc.get_at(&obj, prop, heads)?,
+            None => doc.get(&obj, prop)?,
+        };
+        match next {
+            Some((v, id)) => {
+                value = v;
+                obj = id;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((value, obj)))
+}
+
+/// The object containing the last prop of `path` and that prop
+pub(crate) fn parent<R: ReadDoc + ?Sized>(
+    doc: &R,
+    path: &Path,
+) -> Result<(ExId, Prop), AutomergeError> {
+    let (last, init) = path
+        .props()
+        .split_last()
+        .ok_or_else(|| invalid(path, "the root cannot be replaced or deleted"))?;
+    let parent = init.iter().collect::<Path>();
+    match get(doc, &parent, None)? {
+        Some((Value::Object(obj_type), obj)) => Ok((obj, prop_in(path, obj_type, last)?)),
+        Some((Value::Scalar(_), _)) => Err(invalid(path, "a scalar value is not an object")),
+        None => Err(invalid(path, "there is no object")),
+    }
+}
+
+pub(crate) fn create<T: Transactable + ?Sized>(
+    tx: &mut T,
+    path: &Path,
+    obj_type: ObjType,
+) -> Result<ExId, AutomergeError> {
+    let mut obj = ExId::Root;
+    let mut current_type = ObjType::Map;
+    let props = path.props();
+    for (i, prop) in props.iter().enumerate() {
+        let prop = prop_in(path, current_type, prop)?;
+        let wanted = match props.get(i + 1) {
+            Some(Prop::Seq(_)) => ObjType::List,
+            Some(Prop::Map(_)) => ObjType::Map,
+            None => obj_type,
+        };
+        match tx.get(&obj, prop.clone())? {
+            Some((Value::Object(existing), id)) => {
+                if i + 1 == props.len() && existing != obj_type {
+                    return Err(invalid(path, "there is already a different type of object"));
+                }
+                obj = id;
+                current_type = existing;
+            }
+            Some((Value::Scalar(_), _)) => {
+                return Err(invalid(path, "a scalar value is not an object"))
+            }
+            None => {
+                obj = match prop {
+                    Prop::Map(key) => tx.put_object(&obj, key, wanted)?,
+                    Prop::Seq(index) if index == tx.length(&obj) => {
+                        tx.insert_object(&obj, index, wanted)?
+                    }
+                    Prop::Seq(index) => return Err(AutomergeError::InvalidIndex(index)),
+                };
+                current_type = wanted;
+            }
+        }
+    }
+    Ok(obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AutoCommit, ROOT};
+
+    #[test]
+    fn parse_and_display() {
+        let path: Path = "/config/users/3/a~1b~0c".parse().unwrap();
+        assert_eq!(
+            path,
+            Path::root()
+                .join("config")
+                .join("users")
+                .join(3_usize)
+                .join("a/b~c")
+        );
+        assert_eq!(path.to_string(), "/config/users/3/a~1b~0c");
+        assert_eq!("".parse::<Path>().unwrap(), Path::root());
+        assert_eq!("/".parse::<Path>().unwrap().to_string(), "/");
+        assert!("a/b~2".parse::<Path>().is_err());
+    }
+
+    #[test]
+    fn get_put_and_delete() {
+        let mut doc = AutoCommit::new();
+        let config = doc.put_object(ROOT, "config", ObjType::Map).unwrap();
+        let users = doc.put_object(&config, "users", ObjType::List).unwrap();
+        let user = doc.insert_object(&users, 0, ObjType::Map).unwrap();
+        doc.put(&user, "name", "Alice").unwrap();
+        doc.put(&config, "2023", true).unwrap();
+        let heads = doc.get_heads();
+
+        let name: Path = "config/users/0/name".parse().unwrap();
+        assert_eq!(doc.get_path(&name).unwrap().unwrap().0, Value::str("Alice"));
+        assert_eq!(
+            doc.get_path(&"config/2023".parse().unwrap())
+                .unwrap()
+                .unwrap()
+                .0,
+            Value::from(true)
+        );
+        assert_eq!(
+            doc.get_path(&"config/users/1".parse().unwrap()).unwrap(),
+            None
+        );
+        assert_eq!(doc.get_path(&name.clone().join("x")).unwrap(), None);
+        assert!(doc
+            .get_path(&"config/users/first".parse().unwrap())
+            .is_err());
+
+        doc.put_path(&name, "Bob").unwrap();
+        assert_eq!(doc.get_path(&name).unwrap().unwrap().0, Value::str("Bob"));
+        assert_eq!(
+            doc.get_path_at(&name, &heads).unwrap().unwrap().0,
+            Value::str("Alice")
+        );
+
+        doc.delete_path(&name).unwrap();
+        assert_eq!(doc.get_path(&name).unwrap(), None);
+        assert!(doc.put_path(&"missing/key".parse().unwrap(), 1).is_err());
+        assert!(doc.delete_path(&Path::root()).is_err());
+    }
+
+    #[test]
+    fn create_intermediate_objects() {
+        let mut doc = AutoCommit::new();
+        let tags = doc
+            .create_path(&"posts/0/tags".parse().unwrap(), ObjType::List)
+            .unwrap();
+        assert_eq!(
+            doc.get_path(&"posts".parse().unwrap()).unwrap().unwrap().0,
+            Value::Object(ObjType::List)
+        );
+        assert_eq!(
+            doc.get_path(&"posts/0".parse().unwrap())
+                .unwrap()
+                .unwrap()
+                .0,
+            Value::Object(ObjType::Map)
+        );
+        assert_eq!(
+            Path::from(doc.parents(&tags).unwrap()).to_string(),
+            "/posts/0/tags"
+        );
+
+        // Existing objects are reused
+        assert_eq!(
+            doc.create_path(&"posts/0/tags".parse().unwrap(), ObjType::List)
+                .unwrap(),
+            tags
+        );
+        assert!(doc
+            .create_path(&"posts/0/tags".parse().unwrap(), ObjType::Map)
+            .is_err());
+        assert!(doc
+            .create_path(&"posts/5".parse().unwrap(), ObjType::Map)
+            .is_err());
+    }
+}
@@ -0,0 +1,481 @@
+//! Deciding which changes from other actors a document accepts
+//!
+//! A [`ChangePolicy`] registered with [`Automerge::with_change_policy`] or
+//! [`crate::AutoCommit::with_change_policy`] is asked about every change the document receives
+//! from [`Automerge::apply_changes`], [`Automerge::merge`], [`Automerge::load_incremental`] or
+//! [`crate::sync::SyncDoc::receive_sync_message`], once all of its dependencies have been applied.
+//! The policy sees the author of the change and its operations as an [`IncomingChange`] and may
+//! reject it with a reason. A rejected change is not applied and neither is any change which
+//! depends on it, whether it arrives in the same batch, was already waiting for its dependencies
+//! or arrives later. The other changes are applied as usual and the rejected ones are reported
+//! with [`crate::AutomergeError::Rejected`]. Changes made locally are not checked.
+//!
+//! When syncing, the [`crate::sync::State`] is updated before the rejections are reported, so the
+//! error can be logged and the sync carried on. We don't ask the peer for rejected changes again.
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use automerge::policy::IncomingChange;
+//! use automerge::{transaction::Transactable, AutoCommit, AutomergeError, ObjType, ReadDoc, ROOT};
+//!
+//! let mut doc = AutoCommit::new();
+//! let notes = doc.put_object(ROOT, "notes", ObjType::Map)?;
+//! doc.put(ROOT, "title", "shared")?;
+//! doc.commit();
+//!
+//! // Other actors may only edit the notes
+//! let mut guarded = doc.fork();
+//! let allowed = notes.clone();
+//! guarded.set_change_policy(move |change: &IncomingChange<'_>| {
+//!     match change.ops().iter().find(|op| !op.is_within(&allowed)) {
+//!         Some(op) => Err(format!("{} may not edit {}", change.actor(), op.obj)),
+//!         None => Ok(()),
+//!     }
+//! });
+//!
+//! doc.put(&notes, "monday", "ok")?;
+//! guarded.merge(&mut doc)?;
+//!
+//! doc.put(ROOT, "title", "mine")?;
+//! assert!(matches!(guarded.merge(&mut doc), Err(AutomergeError::Rejected(_))));
+//! assert_eq!(guarded.get(ROOT, "title")?.unwrap().0.to_str(), Some("shared"));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::exid::ExId;
+use crate::legacy::{self, ObjectId};
+use crate::path::Path;
+use crate::{ActorId, Automerge, Change, ChangeHash, ObjType, Prop, ReadDoc, ScalarValue};
+
+/// Decides whether a document accepts a change from another actor
+///
+/// This is implemented for closures taking an [`IncomingChange`].
+pub trait ChangePolicy: Send + Sync {
+    /// Accept `change`, or reject it with a description of why
+    fn check(&self, change: &IncomingChange<'_>) -> Result<(), String>;
+}
+
+impl<F> ChangePolicy for F
+where
+    F: Fn(&IncomingChange<'_>) -> Result<(), String> + Send + Sync,
+{
+    fn check(&self, change: &IncomingChange<'_>) -> Result<(), String> {
+        self(change)
+    }
+}
+
+/// A change which a [`ChangePolicy`] is asked about
+///
+/// All the dependencies of the change have been applied to [`Self::doc`], the change itself has
+/// not.
+#[derive(Debug)]
+pub struct IncomingChange<'a> {
+    doc: &'a Automerge,
+    change: &'a Change,
+    ops: Vec<ChangeOp>,
+}
+
+impl<'a> IncomingChange<'a> {
+    fn new(doc: &'a Automerge, change: &'a Change) -> Self {
+        let actor = change.actor_id();
+        let start_op = change.start_op().get();
+        // Where the objects created by this change are, by the counter of the op creating them
+        let mut created: HashMap<u64, (Option<Path>, Vec<ExId>)> = HashMap::new();
+        let mut ops = Vec::new();
+        for (i, op) in change.decode().operations.into_iter().enumerate() {
+            let obj = match &op.obj {
+                ObjectId::Root => ExId::Root,
+                ObjectId::Id(id) => ExId::Id(id.0, id.1.clone(), 0),
+            };
+            let (path, ancestors) = match &op.obj {
+                ObjectId::Root => (Some(Path::root()), Vec::new()),
+                ObjectId::Id(id) if &id.1 == actor && created.contains_key(&id.0) => {
+                    created[&id.0].clone()
+                }
+                ObjectId::Id(_) => match doc.parents(&obj) {
+                    Ok(parents) => {
+                        let path = parents.path();
+                        let ancestors = path.iter().rev().map(|(obj, _)| obj.clone()).collect();
+                        (
+                            Some(path.into_iter().map(|(_, prop)| prop).collect()),
+                            ancestors,
+                        )
+                    }
+                    Err(_) => (None, Vec::new()),
+                },
+            };
+            let key = match op.key {
+                legacy::Key::Map(key) => Some(key.to_string()),
+                legacy::Key::Seq(_) => None,
+            };
+            let action = Action::from(op.action);
+            if let Action::Make(_) = action {
+                let child_path = match (&path, &key) {
+                    (Some(path), Some(key)) => Some(path.clone().join(Prop::Map(key.clone()))),
+                    _ => None,
+                };
+                let mut child_ancestors = vec![obj.clone()];
+                child_ancestors.extend(ancestors.iter().cloned());
+                created.insert(start_op + i as u64, (child_path, child_ancestors));
+            }
+            ops.push(ChangeOp {
+                obj,
+                path,
+                ancestors,
+                key,
+                insert: op.insert,
+                action,
+            });
+        }
+        IncomingChange { doc, change, ops }
+    }
+
+    /// The document the change would be applied to
+    pub fn doc(&self) -> &Automerge {
+        self.doc
+    }
+
+    pub fn change(&self) -> &Change {
+        self.change
+    }
+
+    /// The actor which made the change
+    pub fn actor(&self) -> &ActorId {
+        self.change.actor_id()
+    }
+
+    /// The operations in the change
+    pub fn ops(&self) -> &[ChangeOp] {
+        &self.ops
+    }
+}
+
+/// An operation in an [`IncomingChange`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeOp {
+    /// The object the operation modifies
+    pub obj: ExId,
+    /// The path from the root of the document to `obj`, or `None` if it cannot be determined.
+    /// This is the case for objects which don't exist in the document and for objects which the
+    /// change creates inside a list, whose index is not known until the change is applied.
+    pub path: Option<Path>,
+    /// The objects containing `obj`, innermost first and ending with the root. This is known
+    /// whenever `obj` exists in the document or is created by the change.
+    pub ancestors: Vec<ExId>,
+    /// The key the operation modifies if `obj` is a map, `None` for lists and text
+    pub key: Option<String>,
+    /// Whether the operation inserts a new element into a list or text
+    pub insert: bool,
+    pub action: Action,
+}
+
+impl ChangeOp {
+    /// Whether the operation modifies `obj` or something nested inside it
+    pub fn is_within(&self, obj: &ExId) -> bool {
+        &self.obj == obj || self.ancestors.contains(obj)
+    }
+}
+
+/// What a [`ChangeOp`] does
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Create a new object
+    Make(ObjType),
+    /// Set a scalar value
+    Put(ScalarValue),
+    Delete,
+    /// Increment a counter
+    Increment(i64),
+    /// Begin a mark with the given name
+    MarkBegin(String),
+    MarkEnd,
+}
+
+impl From<legacy::OpType> for Action {
+    fn from(action: legacy::OpType) -> Self {
+        match action {
+            legacy::OpType::Make(obj_type) => Action::Make(obj_type),
+            legacy::OpType::Put(value) => Action::Put(value),
+            legacy::OpType::Delete => Action::Delete,
+            legacy::OpType::Increment(by) => Action::Increment(by),
+            legacy::OpType::MarkBegin(data) => Action::MarkBegin(data.name.to_string()),
+            legacy::OpType::MarkEnd(_) => Action::MarkEnd,
+        }
+    }
+}
+
+/// Why a change was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The [`ChangePolicy`] rejected the change with this reason
+    Policy(String),
+    /// The change depends on this change, which was rejected
+    Dependency(ChangeHash),
+    /// The change is a [`crate::Baseline`] which does not match the history it claims to
+    /// replace, or which can't be checked against our history and isn't trusted, or the change
+    /// claims to be rebased onto our baseline but doesn't match the change it was rebased from.
+    /// See [`crate::Automerge::compact`].
+    Unverified,
+    /// The change is a [`crate::Baseline`] which would replace the history of a document which
+    /// doesn't accept baselines. See [`crate::Automerge::set_accept_baselines`].
+    NotAccepted,
+}
+
+/// A change which was not applied because of the [`ChangePolicy`] of a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedChange {
+    pub hash: ChangeHash,
+    pub actor: ActorId,
+    pub reason: RejectReason,
+}
+
+impl fmt::Display for RejectedChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "change {} by {} ", self.hash, self.actor)?;
+        match &self.reason {
+            RejectReason::Policy(reason) => write!(f, "was rejected: {}", reason),
+            RejectReason::Dependency(dep) => write!(f, "depends on rejected change {}", dep),
+            RejectReason::Unverified => write!(f, "could not be verified"),
+            RejectReason::NotAccepted => write!(f, "is a baseline which was not accepted"),
+        }
+    }
+}
+
+/// The changes a document rejected, returned in [`crate::AutomergeError::Rejected`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub struct Rejected {
+    pub changes: Vec<RejectedChange>,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// The policy registered with a document
+#[derive(Clone)]
+pub(crate) struct Policy(Arc<dyn ChangePolicy>);
+
+impl Policy {
+    pub(crate) fn new<P: ChangePolicy + 'static>(policy: P) -> Self {
+        Policy(Arc::new(policy))
+    }
+
+    pub(crate) fn check(&self, doc: &Automerge, change: &Change) -> Result<(), RejectedChange> {
+        self.0
+            .check(&IncomingChange::new(doc, change))
+            .map_err(|reason| RejectedChange {
+                hash: change.hash(),
+                actor: change.actor_id().clone(),
+                reason: RejectReason::Policy(reason),
+            })
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Policy")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{self, SyncDoc};
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, AutomergeError, ROOT};
+
+    fn only_within(allowed: ExId) -> impl Fn(&IncomingChange<'_>) -> Result<(), String> {
+        move |change: &IncomingChange<'_>| {
+            if change.ops().iter().all(|op| op.is_within(&allowed)) {
+                Ok(())
+            } else {
+                Err("outside the allowed subtree".to_string())
+            }
+        }
+    }
+
+    fn rejected(result: Result<(), AutomergeError>) -> Vec<RejectedChange> {
+        match result {
+            Err(AutomergeError::Rejected(r)) => r.changes,
+            other => panic!("expected rejected changes, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ops_describe_paths_of_new_and_existing_objects() {
+        let mut doc = AutoCommit::new();
+        let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+        let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+        doc.commit();
+        let mut remote = doc.fork();
+        let tags = remote.put_object(&todo, "tags", ObjType::Map).unwrap();
+        remote.put(&tags, "urgent", true).unwrap();
+        let item = remote.insert_object(&todos, 1, ObjType::Map).unwrap();
+        remote.put(&item, "title", "new").unwrap();
+        let change = remote.get_last_local_change().unwrap().clone();
+
+        let incoming = IncomingChange::new(doc.document(), &change);
+        let ops = incoming.ops();
+        assert_eq!(ops.len(), 4);
+        assert_eq!(ops[0].path, Some("todos/0".parse().unwrap()));
+        assert_eq!(ops[0].key.as_deref(), Some("tags"));
+        assert_eq!(ops[0].action, Action::Make(ObjType::Map));
+        assert_eq!(ops[1].path, Some("todos/0/tags".parse().unwrap()));
+        assert_eq!(ops[1].action, Action::Put(ScalarValue::Boolean(true)));
+        assert!(ops[2].insert && ops[2].key.is_none());
+        assert_eq!(ops[3].obj, item);
+        assert_eq!(ops[3].path, None);
+        assert_eq!(ops[3].ancestors, vec![todos.clone(), ROOT]);
+        assert!(ops.iter().all(|op| op.is_within(&todos)));
+    }
+
+    #[test]
+    fn rejected_changes_and_their_dependents_are_reported() {
+        let mut doc = AutoCommit::new();
+        let notes = doc.put_object(ROOT, "notes", ObjType::Map).unwrap();
+        doc.commit();
+        let mut guarded = doc.fork();
+        guarded.set_change_policy(only_within(notes.clone()));
+
+        doc.put(ROOT, "title", "forbidden").unwrap();
+        doc.commit();
+        let bad = doc.get_last_local_change().unwrap().clone();
+        doc.put(&notes, "fine", "but depends on the forbidden change")
+            .unwrap();
+        doc.commit();
+        let dependent = doc.get_last_local_change().unwrap().clone();
+
+        // The dependent arrives first and waits for the change it depends on
+        guarded.apply_changes([dependent.clone()]).unwrap();
+        let changes = rejected(guarded.apply_changes([bad.clone()]));
+        assert_eq!(
+            changes.iter().map(|c| &c.reason).collect::<Vec<_>>(),
+            vec![
+                &RejectReason::Policy("outside the allowed subtree".to_string()),
+                &RejectReason::Dependency(bad.hash()),
+            ]
+        );
+        assert_eq!(changes[1].hash, dependent.hash());
+        assert!(guarded.document().get_missing_deps(&[]).is_empty());
+        assert_eq!(guarded.get(ROOT, "title").unwrap(), None);
+
+        // Changes which build on rejected ones are rejected as soon as they arrive
+        doc.put(&notes, "later", 1).unwrap();
+        doc.commit();
+        let later = doc.get_last_local_change().unwrap().clone();
+        let changes = rejected(guarded.apply_changes([later]));
+        assert_eq!(
+            changes[0].reason,
+            RejectReason::Dependency(dependent.hash())
+        );
+
+        // Changes which don't are applied
+        let mut other = guarded.fork();
+        other.put(&notes, "ok", true).unwrap();
+        guarded.merge(&mut other).unwrap();
+        assert!(guarded.get(&notes, "ok").unwrap().is_some());
+    }
+
+    #[test]
+    fn policies_apply_to_incremental_loads_and_sync() {
+        let mut remote = AutoCommit::new();
+        let notes = remote.put_object(ROOT, "notes", ObjType::Map).unwrap();
+        remote.commit();
+        remote.put(ROOT, "title", "forbidden").unwrap();
+        remote.commit();
+
+        let mut loaded = AutoCommit::new();
+        loaded.set_change_policy(|change: &IncomingChange<'_>| {
+            match change
+                .ops()
+                .iter()
+                .any(|op| op.key.as_deref() == Some("title"))
+            {
+                true => Err("no titles".to_string()),
+                false => Ok(()),
+            }
+        });
+        let mut synced = loaded.fork();
+        let result = loaded.load_incremental(&remote.save());
+        assert!(matches!(result, Err(AutomergeError::Rejected(_))));
+        assert!(loaded.get(ROOT, "notes").unwrap().is_some());
+        assert!(loaded.get(ROOT, "title").unwrap().is_none());
+
+        let (mut s1, mut s2) = (sync::State::new(), sync::State::new());
+        let mut rejections = 0;
+        for _ in 0..10 {
+            let one = remote.sync().generate_sync_message(&mut s1);
+            let two = synced.sync().generate_sync_message(&mut s2);
+            if one.is_none() && two.is_none() {
+                break;
+            }
+            if let Some(msg) = one {
+                if synced.sync().receive_sync_message(&mut s2, msg).is_err() {
+                    rejections += 1;
+                }
+            }
+            if let Some(msg) = two {
+                remote.sync().receive_sync_message(&mut s1, msg).unwrap();
+            }
+        }
+        assert!(rejections > 0);
+        assert_eq!(synced.get(ROOT, "notes").unwrap().unwrap().1, notes);
+        assert!(synced.get(ROOT, "title").unwrap().is_none());
+    }
+
+    #[test]
+    fn sync_stops_once_changes_are_rejected() {
+        for reconciliation in [
+            sync::Reconciliation::BloomFilter,
+            sync::Reconciliation::ActorSeqs,
+        ] {
+            let mut remote = AutoCommit::new();
+            let notes = remote.put_object(ROOT, "notes", ObjType::Map).unwrap();
+            remote.commit();
+            let mut guarded = remote.fork();
+            guarded.set_change_policy(only_within(notes.clone()));
+            remote.put(ROOT, "title", "forbidden").unwrap();
+            remote.commit();
+            remote.put(&notes, "after", "the forbidden change").unwrap();
+            remote.commit();
+
+            let mut s1 = sync::State::new().with_reconciliation(reconciliation);
+            let mut s2 = sync::State::new().with_reconciliation(reconciliation);
+            let mut rejections = Vec::new();
+            let mut rounds = 0;
+            loop {
+                rounds += 1;
+                assert!(rounds < 10, "sync did not terminate");
+                let one = remote.sync().generate_sync_message(&mut s1);
+                let two = guarded.sync().generate_sync_message(&mut s2);
+                if one.is_none() && two.is_none() {
+                    break;
+                }
+                if let Some(msg) = one {
+                    if let Err(e) = guarded.sync().receive_sync_message(&mut s2, msg) {
+                        rejections.extend(rejected(Err(e)));
+                    }
+                }
+                if let Some(msg) = two {
+                    remote.sync().receive_sync_message(&mut s1, msg).unwrap();
+                }
+            }
+            assert_eq!(rejections.len(), 2);
+            assert_eq!(s2.their_heads, Some(remote.get_heads()));
+            assert!(guarded.get(ROOT, "title").unwrap().is_none());
+        }
+    }
+}
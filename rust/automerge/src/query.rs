@@ -1,5 +1,6 @@
 use crate::marks::MarkData;
 use crate::op_tree::{OpSetMetadata, OpTree, OpTreeNode};
+use crate::text_value::TextEncoding;
 use crate::types::{Key, ListEncoding, Op, OpId, OpType};
 use fxhash::FxBuildHasher;
 use std::collections::{HashMap, HashSet};
@@ -77,12 +78,25 @@ pub(crate) enum QueryResult {
 
 #[derive(Clone, Debug, PartialEq)]
 struct TextWidth {
-    width: usize,
+    code_points: usize,
+    utf8: usize,
+    utf16: usize,
 }
 
 impl TextWidth {
+    fn get(&self, encoding: TextEncoding) -> usize {
+        match encoding {
+            TextEncoding::UnicodeCodePoint => self.code_points,
+            TextEncoding::Utf8CodeUnit => self.utf8,
+            TextEncoding::Utf16CodeUnit => self.utf16,
+        }
+    }
+
     fn add_op(&mut self, op: &Op) {
-        self.width += op.width(ListEncoding::Text);
+        let s = op.to_str();
+        self.code_points += TextEncoding::UnicodeCodePoint.width(s);
+        self.utf8 += TextEncoding::Utf8CodeUnit.width(s);
+        self.utf16 += TextEncoding::Utf16CodeUnit.width(s);
     }
 
     fn remove_op(&mut self, op: &Op) {
@@ -113,11 +127,22 @@ impl TextWidth {
         //
         // Really this is a sign that we should be tracking the type of the Index (List or Text) at
         // the type level, but for now we just look the other way.
-        self.width = self.width.saturating_sub(op.width(ListEncoding::Text));
+        let s = op.to_str();
+        self.code_points = self
+            .code_points
+            .saturating_sub(TextEncoding::UnicodeCodePoint.width(s));
+        self.utf8 = self
+            .utf8
+            .saturating_sub(TextEncoding::Utf8CodeUnit.width(s));
+        self.utf16 = self
+            .utf16
+            .saturating_sub(TextEncoding::Utf16CodeUnit.width(s));
     }
 
     fn merge(&mut self, other: &TextWidth) {
-        self.width += other.width;
+        self.code_points += other.code_points;
+        self.utf8 += other.utf8;
+        self.utf16 += other.utf16;
     }
 }
 
@@ -141,7 +166,11 @@ impl Index {
     pub(crate) fn new() -> Self {
         Index {
             visible: Default::default(),
-            visible_text: TextWidth { width: 0 },
+            visible_text: TextWidth {
+                code_points: 0,
+                utf8: 0,
+                utf16: 0,
+            },
             ops: Default::default(),
             never_seen_puts: true,
             mark_begin: Default::default(),
@@ -153,7 +182,7 @@ impl Index {
     pub(crate) fn visible_len(&self, encoding: ListEncoding) -> usize {
         match encoding {
             ListEncoding::List => self.visible.len(),
-            ListEncoding::Text => self.visible_text.width,
+            ListEncoding::Text(encoding) => self.visible_text.get(encoding),
         }
     }
 
@@ -4,6 +4,8 @@ use crate::{
     iter::{Keys, ListRange, MapRange, Values},
     marks::Mark,
     parents::Parents,
+    path::{self, Path},
+    selector::{self, Match, Selector},
     Change, ChangeHash, Cursor, ObjType, Prop, Value,
 };
 
@@ -228,6 +230,41 @@ pub trait ReadDoc {
         heads: &[ChangeHash],
     ) -> Result<Vec<(Value<'_>, ExId)>, AutomergeError>;
 
+    /// Get the value at `path`, see [`Self::get`]
+    ///
+    /// Returns `Ok(None)` if there is no value at some prop along the path, or if the path leads
+    /// through a scalar value.
+    ///
+    /// # Errors
+    ///
+    /// This will return an error if the path uses a key to look up a value in a list.
+    fn get_path(&self, path: &Path) -> Result<Option<(Value<'_>, ExId)>, AutomergeError> {
+        path::get(self, path, None)
+    }
+
+    /// Get the value at `path` as at `heads`, see [`Self::get_path`]
+    fn get_path_at(
+        &self,
+        path: &Path,
+        heads: &[ChangeHash],
+    ) -> Result<Option<(Value<'_>, ExId)>, AutomergeError> {
+        path::get(self, path, Some(heads))
+    }
+
+    /// Find every value matched by `selector`, in document order, see [`crate::selector`]
+    fn select(&self, selector: &Selector) -> Result<Vec<Match<'_>>, AutomergeError> {
+        selector::select(self, selector, None)
+    }
+
+    /// Find every value matched by `selector` as at `heads`, see [`Self::select`]
+    fn select_at(
+        &self,
+        selector: &Selector,
+        heads: &[ChangeHash],
+    ) -> Result<Vec<Match<'_>>, AutomergeError> {
+        selector::select(self, selector, Some(heads))
+    }
+
     /// Get the hashes of the changes in this document that aren't transitive dependencies of the
     /// given `heads`.
     fn get_missing_deps(&self, heads: &[ChangeHash]) -> Vec<ChangeHash>;
@@ -0,0 +1,693 @@
+//! # Syncing many documents with many peers
+//!
+//! [`crate::sync`] synchronises one document with one peer. A [`Repo`] builds on it to manage a
+//! collection of documents, each identified by a string ID, which are persisted to a
+//! [`Storage`] and synchronised with any number of peers over a single connection per peer.
+//!
+//! Sync messages for different documents are multiplexed over a connection by wrapping each of
+//! them in an [`Envelope`] which records the ID of the document it belongs to. The [`sync::State`]
+//! of each (peer, document) pair is created when it is first needed and is written to storage
+//! when the peer disconnects or the document is closed, so a peer which reconnects later only
+//! receives the changes it is missing.
+//!
+//! The repo does no I/O of its own beyond the storage. Outgoing messages are handed to a
+//! [`Transport`] by [`Repo::sync`] and incoming messages are passed to [`Repo::receive`], which
+//! makes it straightforward to drive a repo from whatever networking stack (or in-memory channel)
+//! you have.
+//!
+//! A message for a document which is neither open nor in storage is ignored unless the hook set
+//! with [`Repo::with_accept`] accepts it, so a peer can't make a repo create documents it doesn't
+//! want.
+//!
+//! ## Example
+//!
+//! ```
+//! use automerge::{
+//!     repo::{Repo, Transport},
+//!     store::MemoryStorage,
+//!     transaction::Transactable,
+//!     ReadDoc,
+//! };
+//!
+//! // A transport which just collects the messages we send
+//! #[derive(Default)]
+//! struct Outbox(Vec<(String, Vec<u8>)>);
+//!
+//! impl Transport for Outbox {
+//!     type Error = std::convert::Infallible;
+//!
+//!     fn send(&mut self, peer_id: &str, data: Vec<u8>) -> Result<(), Self::Error> {
+//!         self.0.push((peer_id.to_string(), data));
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut alice = Repo::new(MemoryStorage::new());
+//! // Bob creates any document Alice sends him
+//! let mut bob = Repo::new(MemoryStorage::new()).with_accept(|_peer_id, _doc_id| true);
+//! alice.connect("bob");
+//! bob.connect("alice");
+//!
+//! let doc = alice.open("todo")?;
+//! doc.put(automerge::ROOT, "title", "groceries")?;
+//! doc.commit();
+//! alice.save("todo")?;
+//!
+//! // Shuttle messages back and forth until neither repo has anything left to say
+//! loop {
+//!     let mut to_bob = Outbox::default();
+//!     let mut to_alice = Outbox::default();
+//!     alice.sync(&mut to_bob)?;
+//!     bob.sync(&mut to_alice)?;
+//!     if to_bob.0.is_empty() && to_alice.0.is_empty() {
+//!         break;
+//!     }
+//!     for (_, msg) in to_bob.0 {
+//!         bob.receive("alice", &msg)?;
+//!     }
+//!     for (_, msg) in to_alice.0 {
+//!         alice.receive("bob", &msg)?;
+//!     }
+//! }
+//!
+//! let doc = bob.get("todo").unwrap();
+//! assert_eq!(doc.get(automerge::ROOT, "title")?.unwrap().0.to_str(), Some("groceries"));
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::storage::parse;
+use crate::store::{self, SaveState, Storage, StorageKey, DEFAULT_COMPACTION_THRESHOLD};
+use crate::sync::{self, SyncDoc};
+use crate::{AutoCommit, AutomergeError};
+
+const ENVELOPE_TYPE: u8 = 0x45; // first byte of an encoded envelope, for identification
+
+/// A sync message addressed to a particular document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    /// The ID of the document the message is for
+    pub doc_id: String,
+    /// The sync message
+    pub message: sync::Message,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeEnvelopeError {
+    #[error("wrong type: expected one of {expected_one_of:?} but found {found}")]
+    WrongType { expected_one_of: Vec<u8>, found: u8 },
+    #[error("{0}")]
+    Parse(String),
+    #[error("invalid document ID")]
+    InvalidDocId,
+    #[error(transparent)]
+    Message(#[from] sync::ReadMessageError),
+    #[error("not enough input")]
+    NotEnoughInput,
+}
+
+impl From<parse::leb128::Error> for DecodeEnvelopeError {
+    fn from(e: parse::leb128::Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<parse::InvalidUtf8> for DecodeEnvelopeError {
+    fn from(_: parse::InvalidUtf8) -> Self {
+        Self::InvalidDocId
+    }
+}
+
+impl Envelope {
+    pub fn encode(self) -> Vec<u8> {
+        let mut buf = vec![ENVELOPE_TYPE];
+        leb128::write::unsigned(&mut buf, self.doc_id.len() as u64).unwrap();
+        buf.extend(self.doc_id.as_bytes());
+        buf.extend(self.message.encode());
+        buf
+    }
+
+    pub fn decode(input: &[u8]) -> Result<Self, DecodeEnvelopeError> {
+        let input = parse::Input::new(input);
+        match Self::parse(input) {
+            Ok((_, envelope)) => Ok(envelope),
+            Err(parse::ParseError::Error(e)) => Err(e),
+            Err(parse::ParseError::Incomplete(_)) => Err(DecodeEnvelopeError::NotEnoughInput),
+        }
+    }
+
+    fn parse(input: parse::Input<'_>) -> parse::ParseResult<'_, Self, DecodeEnvelopeError> {
+        let (i, envelope_type) = parse::take1(input)?;
+        if envelope_type != ENVELOPE_TYPE {
+            return Err(parse::ParseError::Error(DecodeEnvelopeError::WrongType {
+                expected_one_of: vec![ENVELOPE_TYPE],
+                found: envelope_type,
+            }));
+        }
+        let (i, doc_id_len) = parse::leb128_u64(i)?;
+        let (i, doc_id) = parse::utf_8(doc_id_len as usize, i)?;
+        let (i, message) = sync::Message::parse(i).map_err(|e| e.lift())?;
+        Ok((i, Envelope { doc_id, message }))
+    }
+}
+
+/// Sends encoded [`Envelope`]s to peers
+///
+/// This is the only hook a [`Repo`] needs into the network. Implementations are expected to
+/// deliver each message to the peer in order and pass it to [`Repo::receive`] on the other end.
+pub trait Transport {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    fn send(&mut self, peer_id: &str, data: Vec<u8>) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E: std::error::Error + 'static> {
+    #[error(transparent)]
+    Store(#[from] store::Error<E>),
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+    #[error("unable to decode envelope: {0}")]
+    DecodeEnvelope(#[from] DecodeEnvelopeError),
+    #[error("unable to decode sync state: {0}")]
+    DecodeState(#[from] sync::DecodeStateError),
+    #[error("peer {0:?} is not connected")]
+    UnknownPeer(String),
+    #[error("transport error: {0}")]
+    Transport(Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[derive(Debug)]
+struct OpenDoc {
+    doc: AutoCommit,
+    save_state: SaveState,
+}
+
+type AcceptFn = dyn Fn(&str, &str) -> bool + Send + Sync;
+
+/// Decides whether to create a document a peer sent a message for
+struct Accept(Box<AcceptFn>);
+
+impl fmt::Debug for Accept {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Accept").finish_non_exhaustive()
+    }
+}
+
+/// A collection of documents persisted to a [`Storage`] and synchronised with many peers
+///
+/// A `Repo` assumes that it is the only writer of the chunks of the documents it opens.
+#[derive(Debug)]
+pub struct Repo<S> {
+    storage: S,
+    docs: BTreeMap<String, OpenDoc>,
+    /// The sync state of each document we have synchronised with each connected peer
+    peers: BTreeMap<String, BTreeMap<String, sync::State>>,
+    compaction_threshold: usize,
+    accept: Option<Accept>,
+}
+
+impl<S: Storage> Repo<S> {
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            docs: BTreeMap::new(),
+            peers: BTreeMap::new(),
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            accept: None,
+        }
+    }
+
+    /// Set the hook which decides whether to create a document when a peer sends a message for
+    /// one which is neither open nor in storage
+    ///
+    /// The hook is called with the ID of the peer and the ID of the document. Without a hook such
+    /// messages are ignored.
+    pub fn with_accept<F>(mut self, accept: F) -> Self
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.accept = Some(Accept(Box::new(accept)));
+        self
+    }
+
+    /// Set the number of incremental chunks after which a document is compacted when it is
+    /// saved. A threshold of `0` disables automatic compaction.
+    ///
+    /// This only applies to documents opened after it is called.
+    pub fn with_compaction_threshold(mut self, threshold: usize) -> Self {
+        self.compaction_threshold = threshold;
+        self
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Close every document and disconnect every peer, returning the storage
+    pub fn into_storage(mut self) -> Result<S, Error<S::Error>> {
+        let peers = self.peers().map(String::from).collect::<Vec<_>>();
+        for peer_id in peers {
+            self.disconnect(&peer_id)?;
+        }
+        let doc_ids = self.doc_ids().map(String::from).collect::<Vec<_>>();
+        for doc_id in doc_ids {
+            self.close(&doc_id)?;
+        }
+        Ok(self.storage)
+    }
+
+    /// The IDs of the open documents
+    pub fn doc_ids(&self) -> impl Iterator<Item = &str> {
+        self.docs.keys().map(String::as_str)
+    }
+
+    /// The IDs of the connected peers
+    pub fn peers(&self) -> impl Iterator<Item = &str> {
+        self.peers.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, doc_id: &str) -> Option<&AutoCommit> {
+        self.docs.get(doc_id).map(|d| &d.doc)
+    }
+
+    /// Get an open document for editing
+    ///
+    /// Call [`Self::save`] after committing changes to persist them.
+    pub fn get_mut(&mut self, doc_id: &str) -> Option<&mut AutoCommit> {
+        self.docs.get_mut(doc_id).map(|d| &mut d.doc)
+    }
+
+    /// Open a document, loading it from storage if it isn't already open
+    ///
+    /// If nothing has been stored for `doc_id` then the document is created empty. Once a
+    /// document is open it is synchronised with every connected peer.
+    pub fn open(&mut self, doc_id: &str) -> Result<&mut AutoCommit, Error<S::Error>> {
+        if !self.docs.contains_key(doc_id) {
+            let mut save_state = SaveState::new(doc_id.to_string());
+            save_state.compaction_threshold = self.compaction_threshold;
+            let doc = save_state.load(&mut self.storage)?;
+            self.docs
+                .insert(doc_id.to_string(), OpenDoc { doc, save_state });
+        }
+        Ok(&mut self.docs.get_mut(doc_id).unwrap().doc)
+    }
+
+    /// Persist any changes made to an open document since it was last saved
+    ///
+    /// Saving a document which isn't open is a no-op.
+    pub fn save(&mut self, doc_id: &str) -> Result<(), Error<S::Error>> {
+        if let Some(open) = self.docs.get_mut(doc_id) {
+            open.save_state.save(&mut self.storage, &mut open.doc)?;
+        }
+        Ok(())
+    }
+
+    /// Save and close a document, persisting the sync state of every connected peer for it
+    ///
+    /// A closed document is no longer synchronised with peers unless a peer sends a message for
+    /// it, which reopens it.
+    pub fn close(&mut self, doc_id: &str) -> Result<(), Error<S::Error>> {
+        self.save(doc_id)?;
+        for (peer_id, states) in &mut self.peers {
+            if let Some(state) = states.remove(doc_id) {
+                persist_state(&mut self.storage, doc_id, peer_id, &state)?;
+            }
+        }
+        self.docs.remove(doc_id);
+        Ok(())
+    }
+
+    /// Start synchronising open documents with `peer_id`
+    ///
+    /// Connecting a peer which is already connected is a no-op.
+    pub fn connect(&mut self, peer_id: &str) {
+        self.peers.entry(peer_id.to_string()).or_default();
+    }
+
+    /// Stop synchronising with `peer_id`, persisting its sync state for every document
+    pub fn disconnect(&mut self, peer_id: &str) -> Result<(), Error<S::Error>> {
+        if let Some(states) = self.peers.remove(peer_id) {
+            for (doc_id, state) in states {
+                persist_state(&mut self.storage, &doc_id, peer_id, &state)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle an encoded [`Envelope`] received from `peer_id`
+    ///
+    /// The document the envelope is addressed to is opened if necessary, and any changes in the
+    /// message are applied and saved. Returns the ID of the document, or `None` if the message was
+    /// for a document which is neither open nor in storage and the hook set with
+    /// [`Self::with_accept`] didn't accept it.
+    pub fn receive(
+        &mut self,
+        peer_id: &str,
+        data: &[u8],
+    ) -> Result<Option<String>, Error<S::Error>> {
+        if !self.peers.contains_key(peer_id) {
+            return Err(Error::UnknownPeer(peer_id.to_string()));
+        }
+        let Envelope { doc_id, message } = Envelope::decode(data)?;
+        if !self.docs.contains_key(&doc_id) && !self.is_stored(&doc_id)? {
+            let accepted = match &self.accept {
+                Some(Accept(accept)) => accept(peer_id, &doc_id),
+                None => false,
+            };
+            if !accepted {
+                return Ok(None);
+            }
+        }
+        self.open(&doc_id)?;
+        let state = self.state(peer_id, &doc_id)?;
+        let mut state = std::mem::take(state);
+        let open = self.docs.get_mut(&doc_id).unwrap();
+        let result = open.doc.sync().receive_sync_message(&mut state, message);
+        *self.state(peer_id, &doc_id)? = state;
+        result?;
+        self.save(&doc_id)?;
+        Ok(Some(doc_id))
+    }
+
+    /// Generate the messages which should be sent to `peer_id`, one for each open document which
+    /// has something to say
+    pub fn generate_messages(&mut self, peer_id: &str) -> Result<Vec<Envelope>, Error<S::Error>> {
+        if !self.peers.contains_key(peer_id) {
+            return Err(Error::UnknownPeer(peer_id.to_string()));
+        }
+        let doc_ids = self.docs.keys().cloned().collect::<Vec<_>>();
+        let mut envelopes = Vec::new();
+        for doc_id in doc_ids {
+            let mut state = std::mem::take(self.state(peer_id, &doc_id)?);
+            let open = self.docs.get_mut(&doc_id).unwrap();
+            let message = open.doc.sync().generate_sync_message(&mut state);
+            *self.state(peer_id, &doc_id)? = state;
+            if let Some(message) = message {
+                envelopes.push(Envelope { doc_id, message });
+            }
+        }
+        Ok(envelopes)
+    }
+
+    /// Send any pending messages for every open document to every connected peer
+    pub fn sync<T: Transport>(&mut self, transport: &mut T) -> Result<(), Error<S::Error>> {
+        let peers = self.peers.keys().cloned().collect::<Vec<_>>();
+        for peer_id in peers {
+            for envelope in self.generate_messages(&peer_id)? {
+                transport
+                    .send(&peer_id, envelope.encode())
+                    .map_err(|e| Error::Transport(Box::new(e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether anything other than sync states has been stored for `doc_id`
+    fn is_stored(&self, doc_id: &str) -> Result<bool, Error<S::Error>> {
+        Ok(self
+            .storage
+            .list(doc_id)
+            .map_err(store::Error::Storage)?
+            .iter()
+            .any(|k| k.kind != store::ChunkKind::SyncState))
+    }
+
+    /// The sync state for `doc_id` with `peer_id`, loading it from storage the first time it is
+    /// needed
+    fn state(&mut self, peer_id: &str, doc_id: &str) -> Result<&mut sync::State, Error<S::Error>> {
+        let states = self
+            .peers
+            .get_mut(peer_id)
+            .ok_or_else(|| Error::UnknownPeer(peer_id.to_string()))?;
+        if !states.contains_key(doc_id) {
+            let key = StorageKey::sync_state(doc_id, peer_id);
+            let state = match self.storage.get(&key).map_err(store::Error::Storage)? {
+                Some(data) => sync::State::decode(&data)?,
+                None => sync::State::new(),
+            };
+            states.insert(doc_id.to_string(), state);
+        }
+        Ok(states.get_mut(doc_id).unwrap())
+    }
+}
+
+/// Store the sync state for `doc_id` with `peer_id` unless it is already stored
+///
+/// The state only changes when the peers agree on new heads, so most reconnections would
+/// otherwise store the same state again and grow an append-only storage for nothing.
+fn persist_state<S: Storage>(
+    storage: &mut S,
+    doc_id: &str,
+    peer_id: &str,
+    state: &sync::State,
+) -> Result<(), Error<S::Error>> {
+    let key = StorageKey::sync_state(doc_id, peer_id);
+    let data = state.encode();
+    if storage.get(&key).map_err(store::Error::Storage)?.as_deref() != Some(&data[..]) {
+        storage.put(&key, &data).map_err(store::Error::Storage)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::store::MemoryStorage;
+    use crate::{transaction::Transactable, ReadDoc, ROOT};
+
+    #[derive(Default)]
+    struct Outbox(VecDeque<Vec<u8>>);
+
+    impl Transport for Outbox {
+        type Error = std::convert::Infallible;
+
+        fn send(&mut self, _peer_id: &str, data: Vec<u8>) -> Result<(), Self::Error> {
+            self.0.push_back(data);
+            Ok(())
+        }
+    }
+
+    /// Run the sync protocol between `a` and `b` until it goes quiet, returning the number of
+    /// messages exchanged
+    fn sync<A: Storage, B: Storage>(a: (&str, &mut Repo<A>), b: (&str, &mut Repo<B>)) -> usize {
+        let ((a_id, a), (b_id, b)) = (a, b);
+        let mut exchanged = 0;
+        loop {
+            let (mut to_b, mut to_a) = (Outbox::default(), Outbox::default());
+            a.sync(&mut to_b).unwrap();
+            b.sync(&mut to_a).unwrap();
+            if to_a.0.is_empty() && to_b.0.is_empty() {
+                return exchanged;
+            }
+            exchanged += to_a.0.len() + to_b.0.len();
+            while let Some(msg) = to_b.0.pop_front() {
+                b.receive(a_id, &msg).unwrap();
+            }
+            while let Some(msg) = to_a.0.pop_front() {
+                a.receive(b_id, &msg).unwrap();
+            }
+        }
+    }
+
+    fn edit<S: Storage>(repo: &mut Repo<S>, doc_id: &str, key: &str, value: i64) {
+        let doc = repo.open(doc_id).unwrap();
+        doc.put(ROOT, key, value).unwrap();
+        doc.commit();
+        repo.save(doc_id).unwrap();
+    }
+
+    fn value(repo: &Repo<MemoryStorage>, doc_id: &str, key: &str) -> Option<i64> {
+        repo.get(doc_id)?
+            .get(ROOT, key)
+            .unwrap()
+            .and_then(|(v, _)| v.to_i64())
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "key", "value").unwrap();
+        let message = doc
+            .sync()
+            .generate_sync_message(&mut sync::State::new())
+            .unwrap();
+        let envelope = Envelope {
+            doc_id: "doc".to_string(),
+            message,
+        };
+        let decoded = Envelope::decode(&envelope.clone().encode()).unwrap();
+        assert_eq!(decoded, envelope);
+
+        assert!(matches!(
+            Envelope::decode(&[0x42]),
+            Err(DecodeEnvelopeError::WrongType { found: 0x42, .. })
+        ));
+        assert!(matches!(
+            Envelope::decode(&[ENVELOPE_TYPE, 5, b'a']),
+            Err(DecodeEnvelopeError::NotEnoughInput)
+        ));
+    }
+
+    fn accept_all(repo: Repo<MemoryStorage>) -> Repo<MemoryStorage> {
+        repo.with_accept(|_, _| true)
+    }
+
+    #[test]
+    fn syncs_many_documents_over_one_connection() {
+        let mut alice = Repo::new(MemoryStorage::new());
+        let mut bob = accept_all(Repo::new(MemoryStorage::new()));
+        alice.connect("bob");
+        bob.connect("alice");
+
+        edit(&mut alice, "one", "a", 1);
+        edit(&mut alice, "two", "a", 2);
+        edit(&mut bob, "two", "b", 3);
+        sync(("alice", &mut alice), ("bob", &mut bob));
+
+        assert_eq!(bob.doc_ids().collect::<Vec<_>>(), vec!["one", "two"]);
+        assert_eq!(value(&bob, "one", "a"), Some(1));
+        assert_eq!(value(&bob, "two", "a"), Some(2));
+        assert_eq!(value(&alice, "two", "b"), Some(3));
+        assert_eq!(
+            alice.get_mut("two").unwrap().get_heads(),
+            bob.get_mut("two").unwrap().get_heads()
+        );
+
+        // Received changes are persisted
+        let mut bob = Repo::new(bob.into_storage().unwrap());
+        bob.open("one").unwrap();
+        assert_eq!(value(&bob, "one", "a"), Some(1));
+    }
+
+    #[test]
+    fn ignores_unknown_documents_unless_accepted() {
+        let mut alice = Repo::new(MemoryStorage::new());
+        let mut bob = Repo::new(MemoryStorage::new())
+            .with_accept(|peer_id, doc_id| peer_id == "alice" && doc_id.starts_with("shared-"));
+        alice.connect("bob");
+        bob.connect("alice");
+        edit(&mut alice, "private", "a", 1);
+        edit(&mut alice, "shared-doc", "a", 2);
+        sync(("alice", &mut alice), ("bob", &mut bob));
+        assert_eq!(bob.doc_ids().collect::<Vec<_>>(), vec!["shared-doc"]);
+        assert_eq!(value(&bob, "shared-doc", "a"), Some(2));
+        assert!(bob.storage().list("private").unwrap().is_empty());
+
+        // Documents which are in storage are reopened without asking
+        let mut bob = Repo::new(bob.into_storage().unwrap());
+        bob.connect("alice");
+        edit(&mut alice, "shared-doc", "a", 3);
+        sync(("alice", &mut alice), ("bob", &mut bob));
+        assert_eq!(value(&bob, "shared-doc", "a"), Some(3));
+    }
+
+    #[test]
+    fn rejects_messages_from_unknown_peers() {
+        let mut alice = Repo::new(MemoryStorage::new());
+        let mut bob = Repo::new(MemoryStorage::new());
+        alice.connect("bob");
+        edit(&mut alice, "doc", "a", 1);
+        let envelope = alice.generate_messages("bob").unwrap().remove(0);
+        assert!(matches!(
+            bob.receive("alice", &envelope.encode()),
+            Err(Error::UnknownPeer(_))
+        ));
+    }
+
+    #[test]
+    fn sync_state_survives_reconnecting() {
+        let mut alice = Repo::new(MemoryStorage::new());
+        let mut bob = accept_all(Repo::new(MemoryStorage::new()));
+        alice.connect("bob");
+        bob.connect("alice");
+        edit(&mut alice, "doc", "a", 1);
+        sync(("alice", &mut alice), ("bob", &mut bob));
+
+        let mut alice = Repo::new(alice.into_storage().unwrap());
+        let mut bob = Repo::new(bob.into_storage().unwrap());
+        alice.open("doc").unwrap();
+        bob.open("doc").unwrap();
+        alice.connect("bob");
+        bob.connect("alice");
+        let synced_heads = alice.get_mut("doc").unwrap().get_heads();
+        assert_eq!(
+            alice.state("bob", "doc").unwrap().shared_heads,
+            synced_heads
+        );
+        assert_eq!(
+            bob.state("alice", "doc").unwrap().shared_heads,
+            synced_heads
+        );
+
+        edit(&mut alice, "doc", "a", 10);
+        sync(("alice", &mut alice), ("bob", &mut bob));
+        assert_eq!(value(&bob, "doc", "a"), Some(10));
+        assert_eq!(
+            alice.get_mut("doc").unwrap().get_heads(),
+            bob.get_mut("doc").unwrap().get_heads()
+        );
+    }
+
+    /// A storage which counts the sync states put into it
+    #[derive(Default)]
+    struct CountingStorage {
+        storage: MemoryStorage,
+        sync_state_puts: usize,
+    }
+
+    impl Storage for CountingStorage {
+        type Error = std::convert::Infallible;
+
+        fn put(&mut self, key: &StorageKey, data: &[u8]) -> Result<(), Self::Error> {
+            if key.kind == store::ChunkKind::SyncState {
+                self.sync_state_puts += 1;
+            }
+            self.storage.put(key, data)
+        }
+
+        fn get(&self, key: &StorageKey) -> Result<Option<Vec<u8>>, Self::Error> {
+            self.storage.get(key)
+        }
+
+        fn list(&self, doc_id: &str) -> Result<Vec<StorageKey>, Self::Error> {
+            self.storage.list(doc_id)
+        }
+
+        fn delete(&mut self, key: &StorageKey) -> Result<(), Self::Error> {
+            self.storage.delete(key)
+        }
+    }
+
+    #[test]
+    fn unchanged_sync_states_are_not_stored_again() {
+        let mut alice = Repo::new(CountingStorage::default());
+        let mut bob = accept_all(Repo::new(MemoryStorage::new()));
+        alice.connect("bob");
+        bob.connect("alice");
+        edit(&mut alice, "doc", "a", 1);
+        sync(("alice", &mut alice), ("bob", &mut bob));
+        alice.disconnect("bob").unwrap();
+        assert_eq!(alice.storage().sync_state_puts, 1);
+
+        for _ in 0..5 {
+            alice.connect("bob");
+            sync(("alice", &mut alice), ("bob", &mut bob));
+            alice.disconnect("bob").unwrap();
+        }
+        assert_eq!(alice.storage().sync_state_puts, 1);
+
+        // A state which has changed is stored again
+        alice.connect("bob");
+        edit(&mut alice, "doc", "a", 2);
+        sync(("alice", &mut alice), ("bob", &mut bob));
+        alice.close("doc").unwrap();
+        alice.disconnect("bob").unwrap();
+        assert_eq!(alice.storage().sync_state_puts, 2);
+    }
+}
@@ -0,0 +1,592 @@
+//! Constraining the shape of a document
+//!
+//! A [`Schema`] describes the types of the values a document may contain: which kind of object or
+//! which scalar types are allowed at each property, which keys a map must have, and the type of
+//! the elements of a list. It can be used in two ways:
+//!
+//! * Registered on a document with [`crate::Automerge::with_schema`] or
+//!   [`crate::AutoCommit::with_schema`], in which case local operations which would violate the
+//!   schema fail with [`crate::AutomergeError::SchemaViolation`] and leave the document
+//!   unchanged. Only the operation itself is checked, so a map with required keys can still be
+//!   created empty and filled in afterwards, but a required key cannot be deleted. Splicing into
+//!   a text object checks that the schema allows text where the object is, which catches text
+//!   objects created before the schema was registered or by another actor.
+//! * As a validator, with [`Schema::validate`] to check a whole document and
+//!   [`Schema::validate_changes`] to check changes received from other actors before applying
+//!   them. These report every [`Violation`] they find along with its path from the root of the
+//!   document.
+//!
+//! ```
+//! # use automerge::schema::{MapSchema, Schema, ScalarType};
+//! # use automerge::{transaction::Transactable, AutoCommit, AutomergeError, ObjType, ROOT};
+//! let todo = MapSchema::new()
+//!     .with_required("title", Schema::scalar([ScalarType::Str]))
+//!     .with_property("done", Schema::scalar([ScalarType::Boolean]));
+//! let schema = Schema::from(
+//!     MapSchema::new().with_required("todos", Schema::list(Schema::from(todo))),
+//! );
+//!
+//! let mut doc = AutoCommit::new().with_schema(schema);
+//! let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+//! let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+//! doc.put(&todo, "title", "water the plants").unwrap();
+//! assert!(matches!(
+//!     doc.put(&todo, "done", "yes"),
+//!     Err(AutomergeError::SchemaViolation(_))
+//! ));
+//! assert!(doc.delete(ROOT, "todos").is_err());
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::exid::ExId;
+use crate::legacy::ObjectId;
+use crate::{Automerge, AutomergeError, Change, ObjType, Prop, ReadDoc, ScalarValue, Value};
+
+/// The shape a value in a document must have
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schema {
+    /// Any value at all
+    Any,
+    /// A scalar value of one of the given types
+    Scalar(Vec<ScalarType>),
+    /// A map or table
+    Map(MapSchema),
+    /// A list whose elements all have the given shape
+    List(Box<Schema>),
+    /// A text object
+    Text,
+}
+
+/// The shape of a map, see [`Schema::Map`]
+///
+/// Keys which are not mentioned are allowed and may have any value unless restricted with
+/// [`Self::with_additional_properties`] or [`Self::without_additional_properties`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapSchema {
+    properties: BTreeMap<String, Schema>,
+    required: BTreeSet<String>,
+    additional: Option<Box<Schema>>,
+}
+
+/// The type of a [`ScalarValue`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScalarType {
+    Bytes,
+    Str,
+    Int,
+    Uint,
+    F64,
+    Counter,
+    Timestamp,
+    Boolean,
+    Unknown,
+    Null,
+}
+
+/// A value which does not match the schema
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{}: {kind}", display_path(.path))]
+pub struct Violation {
+    /// The path from the root of the document to the value
+    pub path: Vec<Prop>,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ViolationKind {
+    #[error("expected {expected} but found {found}")]
+    WrongType { expected: String, found: String },
+    #[error("required key {0:?} is missing")]
+    MissingKey(String),
+    #[error("key {0:?} is not allowed")]
+    UnexpectedKey(String),
+}
+
+static ANY: Schema = Schema::Any;
+
+impl Schema {
+    /// A scalar value of one of `types`
+    pub fn scalar<I: IntoIterator<Item = ScalarType>>(types: I) -> Self {
+        Schema::Scalar(types.into_iter().collect())
+    }
+
+    /// A list whose elements all have the shape `elements`
+    pub fn list(elements: Schema) -> Self {
+        Schema::List(Box::new(elements))
+    }
+
+    /// Check every value in `doc`
+    pub fn validate<R: ReadDoc>(&self, doc: &R) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.check(&Value::Object(ObjType::Map), &[], &mut violations);
+        if violations.is_empty() {
+            self.validate_object(doc, &ExId::Root, &mut Vec::new(), true, &mut violations);
+        }
+        violations
+    }
+
+    /// Check the objects in `doc` which `changes` would modify if they were applied
+    ///
+    /// The changes are applied to a copy of `doc`, which is left unchanged. Every object an
+    /// operation in the changes modifies or creates is checked, but objects within them are not.
+    ///
+    /// # Errors
+    ///
+    /// If the changes cannot be applied to the document
+    pub fn validate_changes(
+        &self,
+        doc: &Automerge,
+        changes: &[Change],
+    ) -> Result<Vec<Violation>, AutomergeError> {
+        let mut doc = doc.clone();
+        doc.apply_changes(changes.iter().cloned())?;
+
+        let mut touched = BTreeSet::new();
+        for change in changes {
+            let start_op = change.start_op().get();
+            let actor = change.actor_id();
+            for (i, op) in change.decode().operations.iter().enumerate() {
+                touched.insert(match &op.obj {
+                    ObjectId::Root => ExId::Root,
+                    ObjectId::Id(id) => ExId::Id(id.0, id.1.clone(), 0),
+                });
+                if let crate::legacy::OpType::Make(_) = op.action {
+                    touched.insert(ExId::Id(start_op + i as u64, actor.clone(), 0));
+                }
+            }
+        }
+
+        let mut violations = Vec::new();
+        for obj in touched {
+            let path = match doc.parents(&obj).ok().and_then(|p| p.visible_path()) {
+                Some(path) => path.into_iter().map(|(_, prop)| prop).collect::<Vec<_>>(),
+                None => continue,
+            };
+            // An object of the wrong type is reported by the check of the object containing it
+            let schema = match self.at(&path) {
+                Some(schema) if schema.accepts(&Value::Object(doc.object_type(&obj)?)) => schema,
+                _ => continue,
+            };
+            schema.validate_object(&doc, &obj, &mut path.clone(), false, &mut violations);
+        }
+        Ok(violations)
+    }
+
+    /// The schema of the value at `path`, or `None` if there may not be a value there
+    fn at(&self, path: &[Prop]) -> Option<&Schema> {
+        path.iter()
+            .try_fold(self, |schema, prop| schema.child(prop).ok())
+    }
+
+    /// The schema of the property `prop` of an object with this schema
+    fn child(&self, prop: &Prop) -> Result<&Schema, ViolationKind> {
+        match (self, prop) {
+            (Schema::Map(map), Prop::Map(key)) => map
+                .property(key)
+                .ok_or_else(|| ViolationKind::UnexpectedKey(key.clone())),
+            (Schema::List(elements), Prop::Seq(_)) => Ok(elements),
+            _ => Ok(&ANY),
+        }
+    }
+
+    fn accepts(&self, value: &Value<'_>) -> bool {
+        match (self, value) {
+            (Schema::Any, _) => true,
+            (Schema::Scalar(types), Value::Scalar(s)) => types.contains(&ScalarType::of(s)),
+            (Schema::Map(_), Value::Object(ObjType::Map | ObjType::Table)) => true,
+            (Schema::List(_), Value::Object(ObjType::List)) => true,
+            (Schema::Text, Value::Object(ObjType::Text)) => true,
+            _ => false,
+        }
+    }
+
+    fn check(&self, value: &Value<'_>, path: &[Prop], violations: &mut Vec<Violation>) {
+        if !self.accepts(value) {
+            violations.push(Violation {
+                path: path.to_vec(),
+                kind: ViolationKind::WrongType {
+                    expected: self.to_string(),
+                    found: describe(value),
+                },
+            });
+        }
+    }
+
+    /// Check the values in `obj`, which has already been checked against this schema
+    fn validate_object<R: ReadDoc>(
+        &self,
+        doc: &R,
+        obj: &ExId,
+        path: &mut Vec<Prop>,
+        deep: bool,
+        violations: &mut Vec<Violation>,
+    ) {
+        let values: Vec<(Prop, Value<'_>, ExId)> = match self {
+            Schema::Map(map) => {
+                for key in &map.required {
+                    if doc.get(obj, key.as_str()).ok().flatten().is_none() {
+                        violations.push(Violation {
+                            path: path.clone(),
+                            kind: ViolationKind::MissingKey(key.clone()),
+                        });
+                    }
+                }
+                doc.map_range(obj, ..)
+                    .map(|item| (Prop::Map(item.key.to_string()), item.value, item.id))
+                    .collect()
+            }
+            Schema::List(_) => doc
+                .list_range(obj, ..)
+                .map(|item| (Prop::Seq(item.index), item.value, item.id))
+                .collect(),
+            Schema::Any | Schema::Scalar(_) | Schema::Text => return,
+        };
+        for (prop, value, id) in values {
+            path.push(prop);
+            match self.child(path.last().unwrap()) {
+                Ok(child) => {
+                    let before = violations.len();
+                    child.check(&value, path, violations);
+                    if deep && violations.len() == before && value.is_object() {
+                        child.validate_object(doc, &id, path, deep, violations);
+                    }
+                }
+                Err(kind) => violations.push(Violation {
+                    path: path[..path.len() - 1].to_vec(),
+                    kind,
+                }),
+            }
+            path.pop();
+        }
+    }
+}
+
+impl From<MapSchema> for Schema {
+    fn from(map: MapSchema) -> Self {
+        Schema::Map(map)
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schema::Any => write!(f, "any value"),
+            Schema::Scalar(types) => {
+                let types = types.iter().map(|t| format!("{:?}", t)).collect::<Vec<_>>();
+                write!(f, "one of [{}]", types.join(", "))
+            }
+            Schema::Map(_) => write!(f, "a map"),
+            Schema::List(_) => write!(f, "a list"),
+            Schema::Text => write!(f, "text"),
+        }
+    }
+}
+
+impl MapSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow the key `key` with values of shape `schema`
+    pub fn with_property<S: Into<String>>(mut self, key: S, schema: Schema) -> Self {
+        self.properties.insert(key.into(), schema);
+        self
+    }
+
+    /// Require the key `key` with values of shape `schema`
+    pub fn with_required<S: Into<String>>(mut self, key: S, schema: Schema) -> Self {
+        let key = key.into();
+        self.required.insert(key.clone());
+        self.properties.insert(key, schema);
+        self
+    }
+
+    /// Allow keys not otherwise mentioned with values of shape `schema`
+    pub fn with_additional_properties(mut self, schema: Schema) -> Self {
+        self.additional = Some(Box::new(schema));
+        self
+    }
+
+    /// Disallow keys not otherwise mentioned
+    pub fn without_additional_properties(mut self) -> Self {
+        self.additional = None;
+        self
+    }
+
+    fn property(&self, key: &str) -> Option<&Schema> {
+        self.properties.get(key).or(self.additional.as_deref())
+    }
+}
+
+impl Default for MapSchema {
+    fn default() -> Self {
+        Self {
+            properties: BTreeMap::new(),
+            required: BTreeSet::new(),
+            additional: Some(Box::new(Schema::Any)),
+        }
+    }
+}
+
+impl ScalarType {
+    pub fn of(value: &ScalarValue) -> Self {
+        match value {
+            ScalarValue::Bytes(_) => ScalarType::Bytes,
+            ScalarValue::Str(_) => ScalarType::Str,
+            ScalarValue::Int(_) => ScalarType::Int,
+            ScalarValue::Uint(_) => ScalarType::Uint,
+            ScalarValue::F64(_) => ScalarType::F64,
+            ScalarValue::Counter(_) => ScalarType::Counter,
+            ScalarValue::Timestamp(_) => ScalarType::Timestamp,
+            ScalarValue::Boolean(_) => ScalarType::Boolean,
+            ScalarValue::Unknown { .. } => ScalarType::Unknown,
+            ScalarValue::Null => ScalarType::Null,
+        }
+    }
+}
+
+fn describe(value: &Value<'_>) -> String {
+    match value {
+        Value::Object(ObjType::Map | ObjType::Table) => "a map".to_string(),
+        Value::Object(ObjType::List) => "a list".to_string(),
+        Value::Object(ObjType::Text) => "text".to_string(),
+        Value::Scalar(s) => format!("{:?}", ScalarType::of(s)),
+    }
+}
+
+pub(crate) fn display_path(path: &[Prop]) -> String {
+    path.iter().collect::<crate::path::Path>().to_string()
+}
+
+/// Check that putting `value` at `prop` in `obj` does not violate the schema of `doc`
+pub(crate) fn check_put(
+    doc: &Automerge,
+    obj: &ExId,
+    prop: &Prop,
+    value: &Value<'_>,
+) -> Result<(), AutomergeError> {
+    let schema = match doc.schema() {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+    let mut path = path_to(doc, obj)?;
+    let schema = match schema.at(&path) {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+    let child = schema.child(prop).map_err(|kind| Violation {
+        path: path.clone(),
+        kind,
+    })?;
+    path.push(prop.clone());
+    let mut violations = Vec::new();
+    child.check(value, &path, &mut violations);
+    match violations.pop() {
+        Some(violation) => Err(violation.into()),
+        None => Ok(()),
+    }
+}
+
+/// Check that deleting `prop` from `obj` does not violate the schema of `doc`
+pub(crate) fn check_delete(doc: &Automerge, obj: &ExId, prop: &Prop) -> Result<(), AutomergeError> {
+    let schema = match doc.schema() {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+    let path = path_to(doc, obj)?;
+    if let (Some(Schema::Map(map)), Prop::Map(key)) = (schema.at(&path), prop) {
+        if map.required.contains(key) {
+            return Err(Violation {
+                path,
+                kind: ViolationKind::MissingKey(key.clone()),
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Check that the schema of `doc` allows the text object `obj` to be edited where it is
+pub(crate) fn check_splice_text(doc: &Automerge, obj: &ExId) -> Result<(), AutomergeError> {
+    let schema = match doc.schema() {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+    let path = path_to(doc, obj)?;
+    let mut violations = Vec::new();
+    if let Some(schema) = schema.at(&path) {
+        schema.check(&Value::Object(ObjType::Text), &path, &mut violations);
+    }
+    match violations.pop() {
+        Some(violation) => Err(violation.into()),
+        None => Ok(()),
+    }
+}
+
+fn path_to(doc: &Automerge, obj: &ExId) -> Result<Vec<Prop>, AutomergeError> {
+    Ok(doc
+        .parents(obj)?
+        .path()
+        .into_iter()
+        .map(|(_, prop)| prop)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ROOT};
+
+    fn todo_schema() -> Schema {
+        let todo = MapSchema::new()
+            .with_required("title", Schema::Text)
+            .with_property("done", Schema::scalar([ScalarType::Boolean]))
+            .without_additional_properties();
+        MapSchema::new()
+            .with_required("todos", Schema::list(todo.into()))
+            .with_property("count", Schema::scalar([ScalarType::Counter]))
+            .into()
+    }
+
+    fn violation(path: &[Prop], kind: ViolationKind) -> Violation {
+        Violation {
+            path: path.to_vec(),
+            kind,
+        }
+    }
+
+    fn wrong_type(expected: &str, found: &str) -> ViolationKind {
+        ViolationKind::WrongType {
+            expected: expected.to_string(),
+            found: found.to_string(),
+        }
+    }
+
+    #[test]
+    fn local_operations_are_checked() {
+        let mut doc = AutoCommit::new().with_schema(todo_schema());
+        let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+        let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+        let title = doc.put_object(&todo, "title", ObjType::Text).unwrap();
+        doc.splice_text(&title, 0, 0, "water the plants").unwrap();
+        doc.put(&todo, "done", false).unwrap();
+        doc.put(ROOT, "count", ScalarValue::counter(0)).unwrap();
+        doc.put(ROOT, "other", "anything").unwrap();
+
+        let err = doc.put(&todo, "done", "yes").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "/todos/0/done: expected one of [Boolean] but found Str"
+        );
+        assert!(matches!(
+            doc.put(&todo, "priority", 1),
+            Err(AutomergeError::SchemaViolation(v)) if v == violation(
+                &["todos".into(), 0.into()],
+                ViolationKind::UnexpectedKey("priority".into())
+            )
+        ));
+        assert!(doc.insert(&todos, 1, "not a todo").is_err());
+        assert!(doc.splice(&todos, 0, 0, [ScalarValue::Null]).is_err());
+        assert!(doc.put_object(ROOT, "count", ObjType::Map).is_err());
+        assert!(matches!(
+            doc.delete(&todo, "title"),
+            Err(AutomergeError::SchemaViolation(v)) if v.kind == ViolationKind::MissingKey("title".into())
+        ));
+        doc.delete(&todo, "done").unwrap();
+
+        assert_eq!(doc.get(&todo, "done").unwrap(), None);
+        assert_eq!(doc.length(&todos), 1);
+        assert!(todo_schema().validate(&doc).is_empty());
+    }
+
+    #[test]
+    fn validate_reports_every_violation() {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "count", 1).unwrap();
+        let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+        let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+        doc.put(&todo, "title", "not text").unwrap();
+        doc.put(&todo, "colour", "red").unwrap();
+        doc.insert_object(&todos, 1, ObjType::Map).unwrap();
+
+        assert_eq!(
+            todo_schema().validate(&doc),
+            vec![
+                violation(&["count".into()], wrong_type("one of [Counter]", "Int")),
+                violation(
+                    &["todos".into(), 0.into()],
+                    ViolationKind::UnexpectedKey("colour".into())
+                ),
+                violation(
+                    &["todos".into(), 0.into(), "title".into()],
+                    wrong_type("text", "Str")
+                ),
+                violation(
+                    &["todos".into(), 1.into()],
+                    ViolationKind::MissingKey("title".into())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_incoming_changes() {
+        let mut doc = AutoCommit::new();
+        let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+        let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+        doc.put_object(&todo, "title", ObjType::Text).unwrap();
+        doc.commit();
+        let schema = todo_schema();
+        let mut local = doc.fork().with_schema(schema.clone());
+
+        // A client which doesn't know about the schema
+        let heads = doc.get_heads();
+        doc.put(&todo, "done", "yes").unwrap();
+        doc.delete(&todo, "title").unwrap();
+        doc.insert(&todos, 1, 5).unwrap();
+        doc.commit();
+        let changes = doc
+            .get_changes(&heads)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let violations = schema.validate_changes(local.document(), &changes).unwrap();
+        assert_eq!(
+            violations,
+            vec![
+                violation(&["todos".into(), 1.into()], wrong_type("a map", "Int")),
+                violation(
+                    &["todos".into(), 0.into()],
+                    ViolationKind::MissingKey("title".into())
+                ),
+                violation(
+                    &["todos".into(), 0.into(), "done".into()],
+                    wrong_type("one of [Boolean]", "Str")
+                ),
+            ]
+        );
+        assert_eq!(local.length(&todos), 1);
+    }
+
+    #[test]
+    fn text_splices_are_checked() {
+        let mut doc = AutoCommit::new();
+        let todos = doc.put_object(ROOT, "todos", ObjType::List).unwrap();
+        let todo = doc.insert_object(&todos, 0, ObjType::Map).unwrap();
+        let title = doc.put_object(&todo, "title", ObjType::Text).unwrap();
+        // Text where the schema expects a scalar, created before the schema was registered
+        let done = doc.put_object(&todo, "done", ObjType::Text).unwrap();
+        let mut doc = doc.with_schema(todo_schema());
+
+        doc.splice_text(&title, 0, 0, "water the plants").unwrap();
+        let err = doc.splice_text(&done, 0, 0, "yes").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "/todos/0/done: expected one of [Boolean] but found text"
+        );
+        assert_eq!(doc.text(&done).unwrap(), "");
+    }
+}
@@ -0,0 +1,815 @@
+//! A JSONPath style language for selecting values in a document
+//!
+//! A [`Selector`] is evaluated directly against a [`ReadDoc`] with [`ReadDoc::select`] or, as at
+//! some point in history, [`ReadDoc::select_at`], without hydrating the document. It supports
+//!
+//! | syntax                 | selects                                                       |
+//! |------------------------|---------------------------------------------------------------|
+//! | `$`                    | the root of the document                                      |
+//! | `.key`, `['key']`      | the value at `key` in a map                                   |
+//! | `[2]`, `[-1]`          | the value at an index in a list, negative indices count back  |
+//! | `[1:3]`, `[::2]`       | a slice of a list, with an optional step                      |
+//! | `['a', 'b']`, `[0, 2]` | several keys or indices                                       |
+//! | `.*`, `[*]`            | every value in a map or list                                  |
+//! | `..key`, `..*`         | the same selection applied to every descendant, recursively   |
+//! | `[?(@.price < 10)]`    | every value in a map or list which matches a filter           |
+//!
+//! Filters compare a path relative to the candidate value (`@`, `@.key`, `@[0]`) with a literal
+//! number, string, `true`, `false` or `null` using `==`, `!=`, `<`, `<=`, `>` or `>=`, or check
+//! that a path exists with `@.key` or does not with `!@.key`. Filters combine with `&&`, `||` and
+//! parentheses. Numbers compare with any numeric scalar, including counters and timestamps, and
+//! strings compare with strings and text objects. Text objects are not descended into.
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use automerge::{selector::Selector, transaction::Transactable, AutoCommit, ObjType, ReadDoc};
+//!
+//! let mut doc = AutoCommit::new();
+//! let books = doc.put_object(automerge::ROOT, "books", ObjType::List)?;
+//! for (i, (title, price)) in [("Dune", 9), ("Emma", 12), ("Ubik", 7)].iter().enumerate() {
+//!     let book = doc.insert_object(&books, i, ObjType::Map)?;
+//!     doc.put(&book, "title", *title)?;
+//!     doc.put(&book, "price", *price)?;
+//! }
+//!
+//! let cheap: Selector = "$.books[?(@.price < 10)].title".parse()?;
+//! let titles = doc
+//!     .select(&cheap)?
+//!     .into_iter()
+//!     .map(|m| (m.path.to_string(), m.value.to_str().unwrap().to_string()))
+//!     .collect::<Vec<_>>();
+//! assert_eq!(
+//!     titles,
+//!     vec![
+//!         ("/books/0/title".to_string(), "Dune".to_string()),
+//!         ("/books/2/title".to_string(), "Ubik".to_string()),
+//!     ]
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::exid::ExId;
+use crate::path::Path;
+use crate::{AutomergeError, ChangeHash, ObjType, Prop, ReadDoc, ScalarValue, Value};
+
+/// A parsed selector, see the [module documentation](self)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// An error parsing a [`Selector`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at position {position}")]
+pub struct ParseSelectorError {
+    position: usize,
+    message: String,
+}
+
+/// A value selected by a [`Selector`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    /// The path from the root of the document to the value
+    pub path: Path,
+    pub value: Value<'a>,
+    /// The ID of the value, see [`ReadDoc::get`]
+    pub id: ExId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Child(Vec<Item>),
+    Descendant(Vec<Item>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Wildcard,
+    Key(String),
+    Index(i64),
+    Slice {
+        start: Option<i64>,
+        end: Option<i64>,
+        step: i64,
+    },
+    Filter(Filter),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Exists(Vec<Prop>),
+    Compare(Vec<Prop>, Op, Literal),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    Null,
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for Selector {
+    type Err = ParseSelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        parser.skip_whitespace();
+        parser.expect("$")?;
+        let mut segments = Vec::new();
+        loop {
+            parser.skip_whitespace();
+            if parser.at_end() {
+                break;
+            }
+            segments.push(parser.segment()?);
+        }
+        Ok(Selector {
+            source: s.to_string(),
+            segments,
+        })
+    }
+}
+
+struct Parser<'s> {
+    input: &'s str,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn rest(&self) -> &'s str {
+        &self.input[self.pos..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseSelectorError> {
+        Err(ParseSelectorError {
+            position: self.pos,
+            message: message.into(),
+        })
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseSelectorError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`", token))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.input.len() - trimmed.len();
+    }
+
+    fn segment(&mut self) -> Result<Segment, ParseSelectorError> {
+        if self.eat("..") {
+            let items = if self.peek() == Some('[') {
+                self.bracket()?
+            } else {
+                vec![self.dotted()?]
+            };
+            Ok(Segment::Descendant(items))
+        } else if self.eat(".") {
+            Ok(Segment::Child(vec![self.dotted()?]))
+        } else if self.peek() == Some('[') {
+            Ok(Segment::Child(self.bracket()?))
+        } else {
+            self.error("expected `.`, `..` or `[`")
+        }
+    }
+
+    /// The item after a `.` or `..`
+    fn dotted(&mut self) -> Result<Item, ParseSelectorError> {
+        if self.eat("*") {
+            Ok(Item::Wildcard)
+        } else {
+            Ok(Item::Key(self.name()?))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, ParseSelectorError> {
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return self.error("expected a key");
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn bracket(&mut self) -> Result<Vec<Item>, ParseSelectorError> {
+        self.expect("[")?;
+        self.skip_whitespace();
+        let items = if self.eat("*") {
+            vec![Item::Wildcard]
+        } else if self.eat("?") {
+            self.skip_whitespace();
+            vec![Item::Filter(self.filter()?)]
+        } else {
+            let mut items = vec![self.bracket_item()?];
+            loop {
+                self.skip_whitespace();
+                if !self.eat(",") {
+                    break;
+                }
+                self.skip_whitespace();
+                items.push(self.bracket_item()?);
+            }
+            items
+        };
+        self.skip_whitespace();
+        self.expect("]")?;
+        Ok(items)
+    }
+
+    fn bracket_item(&mut self) -> Result<Item, ParseSelectorError> {
+        if matches!(self.peek(), Some('\'' | '"')) {
+            return Ok(Item::Key(self.string()?));
+        }
+        let start = self.optional_integer()?;
+        self.skip_whitespace();
+        if !self.eat(":") {
+            return match start {
+                Some(index) => Ok(Item::Index(index)),
+                None => self.error("expected a key, index, slice, `*` or filter"),
+            };
+        }
+        self.skip_whitespace();
+        let end = self.optional_integer()?;
+        self.skip_whitespace();
+        let step = if self.eat(":") {
+            self.skip_whitespace();
+            self.optional_integer()?.unwrap_or(1)
+        } else {
+            1
+        };
+        if step <= 0 {
+            return self.error("the step of a slice must be positive");
+        }
+        Ok(Item::Slice { start, end, step })
+    }
+
+    fn optional_integer(&mut self) -> Result<Option<i64>, ParseSelectorError> {
+        let negative = self.rest().starts_with('-');
+        let digits = self.rest()[negative as usize..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest().len() - negative as usize);
+        if digits == 0 {
+            return if negative {
+                self.error("expected digits")
+            } else {
+                Ok(None)
+            };
+        }
+        let len = negative as usize + digits;
+        match self.rest()[..len].parse() {
+            Ok(n) => {
+                self.pos += len;
+                Ok(Some(n))
+            }
+            Err(_) => self.error("integer out of range"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseSelectorError> {
+        let quote = match self.peek() {
+            Some(q @ ('\'' | '"')) => q,
+            _ => return self.error("expected a quoted string"),
+        };
+        self.pos += 1;
+        let mut s = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => s.push(escaped),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                }
+                c => s.push(c),
+            }
+        }
+        self.pos = self.input.len();
+        self.error("unterminated string")
+    }
+
+    fn filter(&mut self) -> Result<Filter, ParseSelectorError> {
+        let mut filter = self.conjunction()?;
+        loop {
+            self.skip_whitespace();
+            if !self.eat("||") {
+                return Ok(filter);
+            }
+            self.skip_whitespace();
+            filter = Filter::Or(Box::new(filter), Box::new(self.conjunction()?));
+        }
+    }
+
+    fn conjunction(&mut self) -> Result<Filter, ParseSelectorError> {
+        let mut filter = self.condition()?;
+        loop {
+            self.skip_whitespace();
+            if !self.eat("&&") {
+                return Ok(filter);
+            }
+            self.skip_whitespace();
+            filter = Filter::And(Box::new(filter), Box::new(self.condition()?));
+        }
+    }
+
+    fn condition(&mut self) -> Result<Filter, ParseSelectorError> {
+        if self.eat("(") {
+            self.skip_whitespace();
+            let filter = self.filter()?;
+            self.skip_whitespace();
+            self.expect(")")?;
+            return Ok(filter);
+        }
+        if self.eat("!") {
+            self.skip_whitespace();
+            return Ok(Filter::Not(Box::new(self.condition()?)));
+        }
+        let path = self.relative_path()?;
+        self.skip_whitespace();
+        let op = if self.eat("==") {
+            Op::Eq
+        } else if self.eat("!=") {
+            Op::Ne
+        } else if self.eat("<=") {
+            Op::Le
+        } else if self.eat(">=") {
+            Op::Ge
+        } else if self.eat("<") {
+            Op::Lt
+        } else if self.eat(">") {
+            Op::Gt
+        } else {
+            return Ok(Filter::Exists(path));
+        };
+        self.skip_whitespace();
+        Ok(Filter::Compare(path, op, self.literal()?))
+    }
+
+    fn relative_path(&mut self) -> Result<Vec<Prop>, ParseSelectorError> {
+        self.expect("@")?;
+        let mut path = Vec::new();
+        loop {
+            if self.eat(".") {
+                path.push(Prop::Map(self.name()?));
+            } else if self.eat("[") {
+                self.skip_whitespace();
+                if matches!(self.peek(), Some('\'' | '"')) {
+                    path.push(Prop::Map(self.string()?));
+                } else {
+                    match self.optional_integer()? {
+                        Some(index) if index >= 0 => path.push(Prop::Seq(index as usize)),
+                        _ => return self.error("expected a key or a non-negative index"),
+                    }
+                }
+                self.skip_whitespace();
+                self.expect("]")?;
+            } else {
+                return Ok(path);
+            }
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal, ParseSelectorError> {
+        if matches!(self.peek(), Some('\'' | '"')) {
+            return Ok(Literal::Str(self.string()?));
+        }
+        for (keyword, literal) in [
+            ("true", Literal::Bool(true)),
+            ("false", Literal::Bool(false)),
+            ("null", Literal::Null),
+        ] {
+            if self.eat(keyword) {
+                return Ok(literal);
+            }
+        }
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')))
+            .unwrap_or(self.rest().len());
+        match self.rest()[..len].parse() {
+            Ok(n) if len > 0 => {
+                self.pos += len;
+                Ok(Literal::Number(n))
+            }
+            _ => self.error("expected a number, string, `true`, `false` or `null`"),
+        }
+    }
+}
+
+/// A value in the document along with where it is
+struct Node<'a> {
+    path: Path,
+    value: Value<'a>,
+    id: ExId,
+}
+
+struct Eval<'a, 'h, R: ?Sized> {
+    doc: &'a R,
+    heads: Option<&'h [ChangeHash]>,
+}
+
+impl<'a, 'h, R: ReadDoc + ?Sized> Eval<'a, 'h, R> {
+    fn children(&self, node: &Node<'a>) -> Vec<Node<'a>> {
+        match node.value {
+            Value::Object(ObjType::Map | ObjType::Table) => {
+                let items = match self.heads {
+                    Some(heads) => self.doc.map_range_at(&node.id, .., heads),
+                    None => self.doc.map_range(&node.id, ..),
+                };
+                items
+                    .map(|item| Node {
+                        path: node.path.clone().join(item.key),
+                        value: item.value,
+                        id: item.id,
+                    })
+                    .collect()
+            }
+            Value::Object(ObjType::List) => {
+                let items = match self.heads {
+                    Some(heads) => self.doc.list_range_at(&node.id, .., heads),
+                    None => self.doc.list_range(&node.id, ..),
+                };
+                items
+                    .map(|item| Node {
+                        path: node.path.clone().join(item.index),
+                        value: item.value,
+                        id: item.id,
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn child(&self, node: &Node<'a>, prop: Prop) -> Result<Option<Node<'a>>, AutomergeError> {
+        match (&node.value, &prop) {
+            (Value::Object(ObjType::Map | ObjType::Table), Prop::Map(_))
+            | (Value::Object(ObjType::List), Prop::Seq(_)) => {}
+            _ => return Ok(None),
+        }
+        let found = match self.heads {
+            Some(heads) => self.doc.get_at(&node.id, prop.clone(), heads)?,
+            None => self.doc.get(&node.id, prop.clone())?,
+        };
+        Ok(found.map(|(value, id)| Node {
+            path: node.path.clone().join(prop),
+            value,
+            id,
+        }))
+    }
+
+    fn length(&self, node: &Node<'a>) -> usize {
+        match self.heads {
+            Some(heads) => self.doc.length_at(&node.id, heads),
+            None => self.doc.length(&node.id),
+        }
+    }
+
+    fn select(
+        &self,
+        node: &Node<'a>,
+        item: &Item,
+        out: &mut Vec<Node<'a>>,
+    ) -> Result<(), AutomergeError> {
+        match item {
+            Item::Wildcard => out.extend(self.children(node)),
+            Item::Key(key) => out.extend(self.child(node, Prop::Map(key.clone()))?),
+            Item::Index(index) => {
+                if node.value != Value::Object(ObjType::List) {
+                    return Ok(());
+                }
+                let len = self.length(node) as i64;
+                let index = if *index < 0 { len + index } else { *index };
+                if (0..len).contains(&index) {
+                    out.extend(self.child(node, Prop::Seq(index as usize))?);
+                }
+            }
+            Item::Slice { start, end, step } => {
+                if node.value != Value::Object(ObjType::List) {
+                    return Ok(());
+                }
+                let len = self.length(node) as i64;
+                let bound = |i: i64| if i < 0 { (len + i).max(0) } else { i.min(len) };
+                let start = start.map(bound).unwrap_or(0);
+                let end = end.map(bound).unwrap_or(len);
+                let mut index = Some(start);
+                while let Some(i) = index.filter(|i| *i < end) {
+                    out.extend(self.child(node, Prop::Seq(i as usize))?);
+                    index = i.checked_add(*step);
+                }
+            }
+            Item::Filter(filter) => {
+                for child in self.children(node) {
+                    if self.matches(&child, filter)? {
+                        out.push(child);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn descendants(&self, node: Node<'a>, out: &mut Vec<Node<'a>>) {
+        let children = self.children(&node);
+        out.push(node);
+        for child in children {
+            self.descendants(child, out);
+        }
+    }
+
+    fn matches(&self, node: &Node<'a>, filter: &Filter) -> Result<bool, AutomergeError> {
+        Ok(match filter {
+            Filter::Or(a, b) => self.matches(node, a)? || self.matches(node, b)?,
+            Filter::And(a, b) => self.matches(node, a)? && self.matches(node, b)?,
+            Filter::Not(f) => !self.matches(node, f)?,
+            Filter::Exists(path) => self.relative(node, path)?.is_some(),
+            Filter::Compare(path, op, literal) => {
+                let ordering = match self.relative(node, path)? {
+                    Some(target) => self.compare(&target, literal)?,
+                    None => None,
+                };
+                match op {
+                    Op::Eq => ordering == Some(Ordering::Equal),
+                    Op::Ne => ordering != Some(Ordering::Equal),
+                    _ if !matches!(literal, Literal::Number(_) | Literal::Str(_)) => false,
+                    Op::Lt => ordering == Some(Ordering::Less),
+                    Op::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    Op::Gt => ordering == Some(Ordering::Greater),
+                    Op::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                }
+            }
+        })
+    }
+
+    fn relative(&self, node: &Node<'a>, path: &[Prop]) -> Result<Option<Node<'a>>, AutomergeError> {
+        let mut current = Node {
+            path: node.path.clone(),
+            value: node.value.clone(),
+            id: node.id.clone(),
+        };
+        for prop in path {
+            match self.child(&current, prop.clone())? {
+                Some(next) => current = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current))
+    }
+
+    /// How `node` compares with `literal`, or `None` if they are not comparable
+    fn compare(
+        &self,
+        node: &Node<'a>,
+        literal: &Literal,
+    ) -> Result<Option<Ordering>, AutomergeError> {
+        Ok(match (&node.value, literal) {
+            (Value::Object(ObjType::Text), Literal::Str(s)) => {
+                let text = match self.heads {
+                    Some(heads) => self.doc.text_at(&node.id, heads)?,
+                    None => self.doc.text(&node.id)?,
+                };
+                Some(text.as_str().cmp(s.as_str()))
+            }
+            (Value::Scalar(scalar), literal) => match (scalar.as_ref(), literal) {
+                (ScalarValue::Str(a), Literal::Str(b)) => Some(a.as_str().cmp(b.as_str())),
+                (ScalarValue::Boolean(a), Literal::Bool(b)) => Some(a.cmp(b)),
+                (ScalarValue::Null, Literal::Null) => Some(Ordering::Equal),
+                (scalar, Literal::Number(n)) => scalar.to_f64().and_then(|a| a.partial_cmp(n)),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+}
+
+pub(crate) fn select<'a, R: ReadDoc + ?Sized>(
+    doc: &'a R,
+    selector: &Selector,
+    heads: Option<&[ChangeHash]>,
+) -> Result<Vec<Match<'a>>, AutomergeError> {
+    let eval = Eval { doc, heads };
+    let mut nodes = vec![Node {
+        path: Path::root(),
+        value: Value::Object(ObjType::Map),
+        id: ExId::Root,
+    }];
+    for segment in &selector.segments {
+        let (items, nodes_in) = match segment {
+            Segment::Child(items) => (items, nodes),
+            Segment::Descendant(items) => {
+                let mut descendants = Vec::new();
+                for node in nodes {
+                    eval.descendants(node, &mut descendants);
+                }
+                (items, descendants)
+            }
+        };
+        let mut next = Vec::new();
+        for node in &nodes_in {
+            for item in items {
+                eval.select(node, item, &mut next)?;
+            }
+        }
+        nodes = next;
+    }
+    Ok(nodes
+        .into_iter()
+        .map(|Node { path, value, id }| Match { path, value, id })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ROOT};
+
+    fn store() -> AutoCommit {
+        let mut doc = AutoCommit::new();
+        let store = doc.put_object(ROOT, "store", ObjType::Map).unwrap();
+        let books = doc.put_object(&store, "books", ObjType::List).unwrap();
+        for (i, (title, price, tags)) in [
+            ("Dune", 9.5, vec!["scifi"]),
+            ("Emma", 12.0, vec!["romance", "classic"]),
+            ("Ubik", 7.0, vec!["scifi", "classic"]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let book = doc.insert_object(&books, i, ObjType::Map).unwrap();
+            let text = doc.put_object(&book, "title", ObjType::Text).unwrap();
+            doc.splice_text(&text, 0, 0, title).unwrap();
+            doc.put(&book, "price", price).unwrap();
+            let list = doc.put_object(&book, "tags", ObjType::List).unwrap();
+            for (j, tag) in tags.into_iter().enumerate() {
+                doc.insert(&list, j, tag).unwrap();
+            }
+        }
+        let bike = doc.put_object(&store, "bike", ObjType::Map).unwrap();
+        doc.put(&bike, "price", 100).unwrap();
+        doc.put(&bike, "sold", ScalarValue::counter(3)).unwrap();
+        doc
+    }
+
+    fn paths(doc: &AutoCommit, selector: &str) -> Vec<String> {
+        doc.select(&selector.parse().unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|m| m.path.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn children_indices_and_slices() {
+        let doc = store();
+        assert_eq!(paths(&doc, "$"), vec!["/"]);
+        assert_eq!(paths(&doc, "$.store.bike.price"), vec!["/store/bike/price"]);
+        assert_eq!(paths(&doc, "$['store'][\"bike\"]"), vec!["/store/bike"]);
+        assert_eq!(
+            paths(&doc, "$.store.*"),
+            vec!["/store/bike", "/store/books"]
+        );
+        assert_eq!(paths(&doc, "$.store.books[-1]"), vec!["/store/books/2"]);
+        assert_eq!(
+            paths(&doc, "$.store.books[0, 2].tags[0]"),
+            vec!["/store/books/0/tags/0", "/store/books/2/tags/0"]
+        );
+        assert_eq!(
+            paths(&doc, "$.store.books[1:]"),
+            vec!["/store/books/1", "/store/books/2"]
+        );
+        assert_eq!(
+            paths(&doc, "$.store.books[::2]"),
+            vec!["/store/books/0", "/store/books/2"]
+        );
+        assert_eq!(
+            paths(&doc, "$.store.books[1::9223372036854775807]"),
+            vec!["/store/books/1"]
+        );
+        assert!(paths(&doc, "$.store.books.title").is_empty());
+        assert!(paths(&doc, "$.store.bike[0]").is_empty());
+    }
+
+    #[test]
+    fn recursive_descent() {
+        let doc = store();
+        assert_eq!(
+            paths(&doc, "$..price"),
+            vec![
+                "/store/bike/price",
+                "/store/books/0/price",
+                "/store/books/1/price",
+                "/store/books/2/price",
+            ]
+        );
+        assert_eq!(paths(&doc, "$.store.bike..*").len(), 2);
+    }
+
+    #[test]
+    fn filters() {
+        let doc = store();
+        assert_eq!(
+            paths(&doc, "$.store.books[?(@.price < 10)].title"),
+            vec!["/store/books/0/title", "/store/books/2/title"]
+        );
+        assert_eq!(
+            paths(&doc, "$.store.books[?(@.title == 'Emma')]"),
+            vec!["/store/books/1"]
+        );
+        assert_eq!(
+            paths(&doc, "$..tags[?(@ == \"classic\")]"),
+            vec!["/store/books/1/tags/1", "/store/books/2/tags/1"]
+        );
+        assert_eq!(
+            paths(&doc, "$.store.books[?@.price > 8 && !(@.tags[1])]"),
+            vec!["/store/books/0"]
+        );
+        assert_eq!(
+            paths(&doc, "$.store.*[?(@ >= 3 || @ == 'x')]"),
+            vec!["/store/bike/price", "/store/bike/sold"]
+        );
+        assert_eq!(paths(&doc, "$.store[?(@.sold)]"), vec!["/store/bike"]);
+    }
+
+    #[test]
+    fn select_at_heads() {
+        let mut doc = store();
+        let heads = doc.get_heads();
+        let (_, bike) = doc.get(ROOT, "store").unwrap().unwrap();
+        doc.delete(&bike, "bike").unwrap();
+        let selector: Selector = "$.store.bike.price".parse().unwrap();
+        assert!(doc.select(&selector).unwrap().is_empty());
+        let found = doc.select_at(&selector, &heads).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].value, Value::int(100));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for bad in [
+            "",
+            "store",
+            "$.",
+            "$[",
+            "$[1:2:0]",
+            "$['a",
+            "$[?(@.a <)]",
+            "$.a b",
+        ] {
+            assert!(bad.parse::<Selector>().is_err(), "{}", bad);
+        }
+    }
+}
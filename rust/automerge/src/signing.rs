@@ -0,0 +1,425 @@
+//! Signing changes and verifying who made them
+//!
+//! Every [`Change`] names the actor which made it, but nothing stops a peer from making changes
+//! in the name of any actor. A document with a [`SigningKey`] signs each change it commits with
+//! Ed25519, and a document with a [`TrustStore`] checks that the changes it receives from
+//! [`Automerge::apply_changes`], [`Automerge::merge`], [`Automerge::load_incremental`],
+//! [`crate::sync::SyncDoc::receive_sync_message`] or [`Automerge::load_trusted`] were signed by
+//! the key the store has for their actor. Changes with a missing or invalid signature are handled
+//! according to the store's [`OnInvalidSignature`]. Changes made locally are not checked.
+//!
+//! The signature is kept in the [extra bytes](Change::extra_bytes) of the change and covers the
+//! rest of the change. The hash of a signed change includes its signature so it travels through
+//! sync and storage like any other change. Documents which don't verify signatures ignore them.
+//!
+//! The baseline of a compacted document (see [`Automerge::compact`]) isn't signed. A document
+//! checks a baseline it receives against its own history, and a document which doesn't have that
+//! history only adopts the baseline if its trust store trusts it, see
+//! [`TrustStore::insert_baseline`]. A change rebased onto a baseline is checked by verifying the
+//! signature of the change it was rebased from.
+//!
+//! This module requires the `signing` feature.
+//!
+//! ```
+//! # fn main() -> Result<(), automerge::AutomergeError> {
+//! use automerge::signing::{SigningKey, TrustStore};
+//! use automerge::{transaction::Transactable, ActorId, AutoCommit, ROOT};
+//!
+//! let key = SigningKey::generate();
+//! let alice = ActorId::random();
+//! let mut doc = AutoCommit::new()
+//!     .with_actor(alice.clone())
+//!     .with_signing_key(key.clone());
+//! doc.put(ROOT, "author", "alice")?;
+//!
+//! let trust = TrustStore::new().with_key(alice, key.verifying_key());
+//! let mut other = AutoCommit::new().with_trust_store(trust);
+//! other.merge(&mut doc)?;
+//!
+//! // Changes from actors the store doesn't know about are rejected
+//! let mut mallory = AutoCommit::new();
+//! mallory.put(ROOT, "author", "mallory")?;
+//! assert!(other.merge(&mut mallory).is_err());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use ed25519_dalek::{Signer, Verifier};
+
+use crate::automerge::rebased_original;
+use crate::{ActorId, Automerge, Baseline, Change, ChangeHash, ReadDoc};
+
+// The extra bytes of a signed change are the magic bytes, a version byte and then the 64 byte
+// Ed25519 signature of the change body up to the extra bytes
+const SIGNATURE_MAGIC: [u8; 4] = *b"AMsg";
+const SIGNATURE_VERSION: u8 = 0;
+const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// An Ed25519 key used to sign the changes committed by a document
+#[derive(Clone)]
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    /// Generate a new random key
+    pub fn generate() -> Self {
+        Self(ed25519_dalek::SigningKey::generate(&mut rand_core::OsRng))
+    }
+
+    pub fn from_bytes(secret: &[u8; 32]) -> Self {
+        Self(ed25519_dalek::SigningKey::from_bytes(secret))
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+
+    /// The key which verifies signatures made with this key, to add to a [`TrustStore`]
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+
+    /// Sign `change`, replacing any extra bytes it has with the signature
+    pub(crate) fn sign(&self, change: &Change) -> Change {
+        let signature = self.0.sign(change.signed_bytes());
+        let mut extra = Vec::with_capacity(SIGNATURE_MAGIC.len() + 1 + SIGNATURE_LEN);
+        extra.extend(SIGNATURE_MAGIC);
+        extra.push(SIGNATURE_VERSION);
+        extra.extend(signature.to_bytes());
+        change.with_extra_bytes(&extra)
+    }
+}
+
+// Don't leak the key into logs
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SigningKey")
+            .field(&self.verifying_key())
+            .finish()
+    }
+}
+
+/// The public half of a [`SigningKey`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl VerifyingKey {
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, InvalidVerifyingKey> {
+        ed25519_dalek::VerifyingKey::from_bytes(bytes)
+            .map(Self)
+            .map_err(|_| InvalidVerifyingKey)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid Ed25519 public key")]
+pub struct InvalidVerifyingKey;
+
+/// What to do with received changes which have a missing or invalid signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnInvalidSignature {
+    /// Discard the changes and apply the rest. Changes which depend on a discarded change are
+    /// not applied either and are reported in [`crate::AutomergeError::Rejected`].
+    Ignore,
+    /// Fail without applying any of the changes
+    Error,
+}
+
+impl Default for OnInvalidSignature {
+    fn default() -> Self {
+        Self::Error
+    }
+}
+
+/// Why a change failed verification
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SignatureError {
+    #[error("change {hash} is by actor {actor} which is not in the trust store")]
+    UnknownActor { hash: ChangeHash, actor: ActorId },
+    #[error("change {0} is not signed")]
+    Unsigned(ChangeHash),
+    #[error("change {0} has an invalid signature")]
+    Invalid(ChangeHash),
+    #[error("baseline {0} is not trusted")]
+    UntrustedBaseline(ChangeHash),
+}
+
+/// The keys which the changes of each actor must be signed with
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    keys: HashMap<ActorId, VerifyingKey>,
+    baselines: HashSet<ChangeHash>,
+    on_invalid: OnInvalidSignature,
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust changes from `actor` which are signed with `key`
+    pub fn with_key(mut self, actor: ActorId, key: VerifyingKey) -> Self {
+        self.insert(actor, key);
+        self
+    }
+
+    /// Trust the baseline with `hash`, see [`Self::insert_baseline`]
+    pub fn with_baseline(mut self, hash: ChangeHash) -> Self {
+        self.insert_baseline(hash);
+        self
+    }
+
+    /// What to do with changes which fail verification, [`OnInvalidSignature::Error`] by default
+    pub fn with_on_invalid(mut self, on_invalid: OnInvalidSignature) -> Self {
+        self.on_invalid = on_invalid;
+        self
+    }
+
+    /// Trust changes from `actor` which are signed with `key`, returning the key previously
+    /// trusted for `actor`
+    pub fn insert(&mut self, actor: ActorId, key: VerifyingKey) -> Option<VerifyingKey> {
+        self.keys.insert(actor, key)
+    }
+
+    /// Stop trusting changes from `actor`, returning the key which was trusted for it
+    pub fn remove(&mut self, actor: &ActorId) -> Option<VerifyingKey> {
+        self.keys.remove(actor)
+    }
+
+    /// Trust the baseline change with `hash`, returning whether it wasn't already trusted
+    ///
+    /// A document which doesn't have the history a [`Baseline`] replaced, such as a new peer,
+    /// can't check the baseline and only adopts it if it is trusted. The trust store of a
+    /// document returned by [`Automerge::compact`] trusts its baseline.
+    pub fn insert_baseline(&mut self, hash: ChangeHash) -> bool {
+        self.baselines.insert(hash)
+    }
+
+    /// Whether the baseline change with `hash` is trusted
+    pub fn trusts_baseline(&self, hash: &ChangeHash) -> bool {
+        self.baselines.contains(hash)
+    }
+
+    pub fn get(&self, actor: &ActorId) -> Option<&VerifyingKey> {
+        self.keys.get(actor)
+    }
+
+    pub fn on_invalid(&self) -> OnInvalidSignature {
+        self.on_invalid
+    }
+
+    /// Check that `change` was signed by the key trusted for its actor
+    ///
+    /// A baseline passes if it is trusted and a change rebased onto a baseline passes if the
+    /// change it was rebased from does.
+    pub fn verify(&self, change: &Change) -> Result<(), SignatureError> {
+        if Baseline::from_change(change).is_some() {
+            if self.trusts_baseline(&change.hash()) {
+                return Ok(());
+            }
+            return Err(SignatureError::UntrustedBaseline(change.hash()));
+        }
+        if let Some(original) = rebased_original(change) {
+            return self.verify(&original);
+        }
+        let key = self
+            .keys
+            .get(change.actor_id())
+            .ok_or_else(|| SignatureError::UnknownActor {
+                hash: change.hash(),
+                actor: change.actor_id().clone(),
+            })?;
+        let signature = parse_signature(change.extra_bytes())
+            .ok_or_else(|| SignatureError::Unsigned(change.hash()))?;
+        key.0
+            .verify(change.signed_bytes(), &signature)
+            .map_err(|_| SignatureError::Invalid(change.hash()))
+    }
+
+    /// Split `changes` into the changes which pass verification and the hashes of those which
+    /// don't, or return an error if `self.on_invalid` is [`OnInvalidSignature::Error`]. Changes
+    /// which are already in `doc` are not checked, and baselines are checked when they are
+    /// applied.
+    pub(crate) fn filter<I>(
+        &self,
+        doc: &Automerge,
+        changes: I,
+    ) -> Result<(Vec<Change>, Vec<ChangeHash>), SignatureError>
+    where
+        I: IntoIterator<Item = Change>,
+    {
+        let mut valid = Vec::new();
+        let mut invalid = Vec::new();
+        for change in changes {
+            let known = doc.get_change_by_hash(&change.hash()).is_some();
+            if known || Baseline::from_change(&change).is_some() {
+                valid.push(change);
+                continue;
+            }
+            match (self.verify(&change), self.on_invalid) {
+                (Ok(()), _) => valid.push(change),
+                (Err(e), OnInvalidSignature::Error) => return Err(e),
+                (Err(e), OnInvalidSignature::Ignore) => {
+                    tracing::warn!(err=?e, "ignoring change with invalid signature");
+                    invalid.push(change.hash());
+                }
+            }
+        }
+        Ok((valid, invalid))
+    }
+}
+
+fn parse_signature(extra_bytes: &[u8]) -> Option<ed25519_dalek::Signature> {
+    let rest = extra_bytes.strip_prefix(&SIGNATURE_MAGIC[..])?;
+    let (version, signature) = rest.split_first()?;
+    if *version != SIGNATURE_VERSION {
+        return None;
+    }
+    let signature: &[u8; SIGNATURE_LEN] = signature.try_into().ok()?;
+    Some(ed25519_dalek::Signature::from_bytes(signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{self, SyncDoc};
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ReadDoc, ROOT};
+
+    fn signed_doc(key: &SigningKey) -> AutoCommit {
+        let mut doc = AutoCommit::new().with_signing_key(key.clone());
+        doc.put(ROOT, "a", 1).unwrap();
+        doc.commit();
+        doc
+    }
+
+    #[test]
+    fn signed_changes_verify_and_survive_storage() {
+        let key = SigningKey::generate();
+        let mut doc = signed_doc(&key);
+        let trust = TrustStore::new().with_key(doc.get_actor().clone(), key.verifying_key());
+
+        let change = doc.get_last_local_change().unwrap().clone();
+        assert!(change.extra_bytes().starts_with(&SIGNATURE_MAGIC));
+        assert_eq!(trust.verify(&change), Ok(()));
+
+        let loaded = Automerge::load_trusted(&doc.save(), trust.clone()).unwrap();
+        assert_eq!(loaded.get_heads(), doc.get_heads());
+        let round_tripped = Change::from_bytes(change.raw_bytes().to_vec()).unwrap();
+        assert_eq!(trust.verify(&round_tripped), Ok(()));
+
+        let wrong_key = TrustStore::new().with_key(
+            doc.get_actor().clone(),
+            SigningKey::generate().verifying_key(),
+        );
+        assert_eq!(
+            wrong_key.verify(&change),
+            Err(SignatureError::Invalid(change.hash()))
+        );
+        let unknown = TrustStore::new();
+        assert!(matches!(
+            unknown.verify(&change),
+            Err(SignatureError::UnknownActor { .. })
+        ));
+        assert!(Automerge::load_trusted(&doc.save(), unknown).is_err());
+    }
+
+    #[test]
+    fn forged_changes_are_rejected_or_ignored() {
+        let key = SigningKey::generate();
+        let mut alice = signed_doc(&key);
+        // Mallory makes a change in alice's name without alice's key
+        let mut mallory = AutoCommit::new().with_actor(alice.get_actor().clone());
+        mallory.put(ROOT, "b", 2).unwrap();
+        let forged = mallory.get_last_local_change().unwrap().clone();
+        assert_eq!(
+            TrustStore::new()
+                .with_key(alice.get_actor().clone(), key.verifying_key())
+                .verify(&forged),
+            Err(SignatureError::Unsigned(forged.hash()))
+        );
+        alice.put(ROOT, "c", 3).unwrap();
+        alice.commit();
+
+        let trust = TrustStore::new().with_key(alice.get_actor().clone(), key.verifying_key());
+        let mut strict = AutoCommit::new().with_trust_store(trust.clone());
+        assert!(strict.apply_changes([forged.clone()]).is_err());
+        assert!(strict.get_heads().is_empty());
+
+        let mut lenient =
+            AutoCommit::new().with_trust_store(trust.with_on_invalid(OnInvalidSignature::Ignore));
+        lenient.apply_changes([forged]).unwrap();
+        assert!(lenient.get_heads().is_empty());
+
+        let (mut s1, mut s2) = (sync::State::new(), sync::State::new());
+        loop {
+            let one = alice.sync().generate_sync_message(&mut s1);
+            let two = lenient.sync().generate_sync_message(&mut s2);
+            if one.is_none() && two.is_none() {
+                break;
+            }
+            if let Some(msg) = one {
+                lenient.sync().receive_sync_message(&mut s2, msg).unwrap();
+            }
+            if let Some(msg) = two {
+                alice.sync().receive_sync_message(&mut s1, msg).unwrap();
+            }
+        }
+        assert_eq!(lenient.get_heads(), alice.get_heads());
+        assert_eq!(lenient.get(ROOT, "c").unwrap().unwrap().0.to_i64(), Some(3));
+    }
+
+    #[test]
+    fn dependents_of_ignored_changes_are_rejected() {
+        let (alice_key, bob_key) = (SigningKey::generate(), SigningKey::generate());
+        let mut alice = signed_doc(&alice_key);
+        let original = alice.get_last_local_change().unwrap().clone();
+        let mut mallory = AutoCommit::load(&alice.save()).unwrap();
+        mallory.set_actor(alice.get_actor().clone());
+        mallory.put(ROOT, "b", 2).unwrap();
+        mallory.commit();
+        let forged = mallory.get_last_local_change().unwrap().clone();
+        // Bob signs a change which builds on the forged one
+        let mut bob = AutoCommit::load(&mallory.save())
+            .unwrap()
+            .with_signing_key(bob_key.clone());
+        bob.put(ROOT, "c", 3).unwrap();
+        bob.commit();
+        let dependent = bob.get_last_local_change().unwrap().clone();
+
+        let trust = TrustStore::new()
+            .with_key(alice.get_actor().clone(), alice_key.verifying_key())
+            .with_key(bob.get_actor().clone(), bob_key.verifying_key())
+            .with_on_invalid(OnInvalidSignature::Ignore);
+        let mut lenient = AutoCommit::new().with_trust_store(trust.clone());
+        lenient.apply_changes([dependent.clone()]).unwrap();
+        match lenient.apply_changes([original, forged.clone()]) {
+            Err(crate::AutomergeError::Rejected(r)) => {
+                assert_eq!(r.changes.len(), 1);
+                assert_eq!(r.changes[0].hash, dependent.hash());
+                assert_eq!(
+                    r.changes[0].reason,
+                    crate::policy::RejectReason::Dependency(forged.hash())
+                );
+            }
+            other => panic!("expected the dependent to be rejected, got {:?}", other),
+        }
+        assert!(lenient.document().get_missing_deps(&[]).is_empty());
+        assert_eq!(lenient.get_heads(), alice.get_heads());
+
+        // Loading drops them too and keeps the configuration of the document
+        let mut loaded = AutoCommit::new()
+            .with_schema(crate::schema::Schema::Any)
+            .with_trust_store(trust);
+        loaded.load_incremental(&bob.save()).unwrap();
+        assert_eq!(loaded.get_heads(), alice.get_heads());
+        assert!(loaded.schema().is_some());
+        assert!(loaded.document().get_missing_deps(&[]).is_empty());
+    }
+}
@@ -2,21 +2,31 @@ use std::ops::Range;
 
 pub(crate) mod change;
 mod chunk;
+mod codec;
 mod columns;
 pub(crate) mod convert;
 mod document;
+mod encryption;
 pub(crate) mod load;
 pub(crate) mod parse;
 pub(crate) mod save;
 
+pub use codec::Codec;
+pub use encryption::{EncryptionKey, KeyProvider};
+
 pub(crate) use {
     change::{AsChangeOp, Change, ChangeOp, Compressed, ReadChangeOpError},
     chunk::{CheckSum, Chunk, ChunkType, Header},
+    codec::compress_chunk,
     columns::{Columns, MismatchingColumn, RawColumn, RawColumns},
     document::{AsChangeMeta, AsDocOp, ChangeMetadata, CompressConfig, DocOp, Document},
+    encryption::decrypt_chunks,
     load::VerificationMode,
 };
 
+#[cfg(feature = "encryption")]
+pub(crate) use encryption::encrypt_chunks;
+
 fn shift_range(range: Range<usize>, by: usize) -> Range<usize> {
     range.start + by..range.end + by
 }
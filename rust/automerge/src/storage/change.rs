@@ -254,6 +254,43 @@ impl<'a, O: OpReadState> Change<'a, O> {
         &self.bytes[self.header.len()..]
     }
 
+    /// The body of the change up to the extra bytes
+    #[cfg(feature = "signing")]
+    pub(crate) fn body_bytes_without_extra(&self) -> &[u8] {
+        &self.bytes[self.header.len()..self.extra_bytes.start]
+    }
+
+    /// A copy of this change with its extra bytes replaced by `extra_bytes`
+    #[cfg(feature = "signing")]
+    pub(crate) fn with_extra_bytes(&self, extra_bytes: &[u8]) -> Change<'static, O> {
+        let mut data = self.body_bytes_without_extra().to_vec();
+        let extra_start = data.len();
+        data.extend(extra_bytes);
+        let header = Header::new(ChunkType::Change, &data);
+        let mut bytes = Vec::with_capacity(header.len() + data.len());
+        header.write(&mut bytes);
+        bytes.extend(data);
+
+        let old_header_len = self.header.len();
+        let ops_data = (self.ops_data.start - old_header_len)..(self.ops_data.end - old_header_len);
+        let extra_bytes = extra_start..(extra_start + extra_bytes.len());
+        Change {
+            bytes: Cow::Owned(bytes),
+            ops_data: shift_range(ops_data, header.len()),
+            extra_bytes: shift_range(extra_bytes, header.len()),
+            header,
+            dependencies: self.dependencies.clone(),
+            actor: self.actor.clone(),
+            other_actors: self.other_actors.clone(),
+            seq: self.seq,
+            start_op: self.start_op,
+            timestamp: self.timestamp,
+            message: self.message.clone(),
+            ops_meta: self.ops_meta.clone(),
+            _phantom: PhantomData,
+        }
+    }
+
     pub(crate) fn bytes(&self) -> &[u8] {
         &self.bytes
     }
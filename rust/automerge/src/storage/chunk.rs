@@ -7,7 +7,10 @@ use std::{
 
 use sha2::{Digest, Sha256};
 
-use super::{change::Unverified, parse, Change, Compressed, Document, MAGIC_BYTES};
+use super::{
+    change::Unverified, codec, encryption, parse, Change, Compressed, Document, KeyProvider,
+    MAGIC_BYTES,
+};
 use crate::{columnar::encoding::leb128::ulebsize, ChangeHash};
 
 pub(crate) enum Chunk<'a> {
@@ -34,6 +37,32 @@ pub(crate) mod error {
         Document(#[from] document::ParseError),
         #[error("unable to decompresse compressed chunk")]
         Deflate,
+        #[error("unable to decompress {0} compressed chunk")]
+        Decompress(&'static str),
+        #[error("{0} compressed chunk decompresses to more than the limit of 1GiB")]
+        DecompressedTooLarge(&'static str),
+        #[error("chunk is compressed with {0} but automerge was built without the `{0}` feature")]
+        CodecNotEnabled(&'static str),
+        #[error("unknown compression codec: {0}")]
+        UnknownCodec(u8),
+        #[error("compressed chunk is missing its codec or chunk type")]
+        MissingCodec,
+        #[error("compressed chunk contains a chunk of type {0}, expected a document or change")]
+        BadCompressedChunkType(u8),
+        #[error("chunk is truncated")]
+        Truncated,
+        #[error("chunk is encrypted but no key provider was given")]
+        MissingKeyProvider,
+        #[error("chunk is encrypted with key {0} which the key provider doesn't have")]
+        UnknownKey(u32),
+        #[cfg(feature = "encryption")]
+        #[error("unable to decrypt chunk, either the key is wrong or the chunk has been modified")]
+        Decrypt,
+        #[cfg(not(feature = "encryption"))]
+        #[error("chunk is encrypted but automerge was built without the `encryption` feature")]
+        EncryptionNotEnabled,
+        #[error("encrypted chunk contains a chunk of unexpected type {0}")]
+        BadEncryptedChunkType(u8),
     }
 
     #[derive(thiserror::Error, Debug)]
@@ -50,6 +79,14 @@ pub(crate) mod error {
 impl<'a> Chunk<'a> {
     pub(crate) fn parse(
         input: parse::Input<'a>,
+    ) -> parse::ParseResult<'a, Chunk<'a>, error::Chunk> {
+        Self::parse_with(input, None)
+    }
+
+    /// Parse a chunk, decrypting it with `keys` if it is encrypted
+    pub(crate) fn parse_with(
+        input: parse::Input<'a>,
+        keys: Option<&dyn KeyProvider>,
     ) -> parse::ParseResult<'a, Chunk<'a>, error::Chunk> {
         let (i, header) = Header::parse::<error::Chunk>(input)?;
         let parse::Split {
@@ -95,10 +132,67 @@ impl<'a> Chunk<'a> {
                     Compressed::new(header.checksum, Cow::Borrowed(chunk_input.bytes())),
                 )
             }
+            ChunkType::CodecCompressed => {
+                let compressed = &input.unconsumed_bytes()[header.data_bytes()];
+                let (chunk_type, decompressed) =
+                    codec::decompress_chunk(compressed).map_err(parse::ParseError::Error)?;
+                let inner_header = header.with_data(chunk_type, &decompressed);
+                let mut inner_chunk = Vec::with_capacity(inner_header.len() + decompressed.len());
+                inner_header.write(&mut inner_chunk);
+                inner_chunk.extend(&decompressed);
+                let inner_input = parse::Input::new(&inner_chunk);
+                // The chunk we return can't borrow from `inner_chunk` so take ownership of it.
+                // Unlike `ChunkType::Compressed` we don't keep the compressed form, the change or
+                // document is re-encoded with the default compression when it is sent on.
+                match chunk_type {
+                    ChunkType::Document => {
+                        let (i, inner_header) = Header::parse::<error::Chunk>(inner_input)?;
+                        let parse::Split {
+                            first: doc_input,
+                            remaining,
+                        } = i.split(inner_header.data_bytes().len());
+                        let (_, doc) =
+                            Document::parse(doc_input, inner_header).map_err(|e| e.lift())?;
+                        if !remaining.is_empty() {
+                            return Err(parse::ParseError::Error(error::Chunk::LeftoverData));
+                        }
+                        Chunk::Document(doc.into_owned())
+                    }
+                    _ => {
+                        let (remaining, change) =
+                            Change::parse(inner_input).map_err(|e| e.lift())?;
+                        if !remaining.is_empty() {
+                            return Err(parse::ParseError::Error(error::Chunk::LeftoverData));
+                        }
+                        Chunk::Change(change.into_owned())
+                    }
+                }
+            }
+            ChunkType::Encrypted => {
+                let encrypted = &input.unconsumed_bytes()[header.data_bytes()];
+                let inner_chunk = encryption::decrypt_chunk(&header, encrypted, keys)
+                    .map_err(parse::ParseError::Error)?;
+                let (inner_remaining, chunk) =
+                    Chunk::parse_with(parse::Input::new(&inner_chunk), None)?;
+                if !inner_remaining.is_empty() {
+                    return Err(parse::ParseError::Error(error::Chunk::LeftoverData));
+                }
+                chunk.into_owned()
+            }
         };
         Ok((remaining, chunk))
     }
 
+    fn into_owned(self) -> Chunk<'static> {
+        match self {
+            Self::Document(d) => Chunk::Document(d.into_owned()),
+            Self::Change(c) => Chunk::Change(c.into_owned()),
+            Self::CompressedChange(c, compressed) => {
+                Chunk::CompressedChange(c, compressed.into_owned())
+            }
+        }
+    }
+
     pub(crate) fn checksum_valid(&self) -> bool {
         match self {
             Self::Document(d) => d.checksum_valid(),
@@ -115,6 +209,10 @@ pub(crate) enum ChunkType {
     Document,
     Change,
     Compressed,
+    /// A document or change compressed as a whole with a [`codec::Codec`]
+    CodecCompressed,
+    /// A chunk encrypted with a key from a [`KeyProvider`]
+    Encrypted,
 }
 
 impl TryFrom<u8> for ChunkType {
@@ -125,6 +223,8 @@ impl TryFrom<u8> for ChunkType {
             0 => Ok(Self::Document),
             1 => Ok(Self::Change),
             2 => Ok(Self::Compressed),
+            3 => Ok(Self::CodecCompressed),
+            4 => Ok(Self::Encrypted),
             other => Err(other),
         }
     }
@@ -136,6 +236,8 @@ impl From<ChunkType> for u8 {
             ChunkType::Document => 0,
             ChunkType::Change => 1,
             ChunkType::Compressed => 2,
+            ChunkType::CodecCompressed => 3,
+            ChunkType::Encrypted => 4,
         }
     }
 }
@@ -213,6 +315,10 @@ impl Header {
         self.header_size
     }
 
+    pub(crate) fn chunk_type(&self) -> ChunkType {
+        self.chunk_type
+    }
+
     pub(crate) fn write(&self, out: &mut Vec<u8>) {
         out.extend(MAGIC_BYTES);
         out.extend(self.checksum.bytes());
@@ -0,0 +1,276 @@
+use std::io::Read;
+
+use super::{chunk::error, parse, ChunkType, Header};
+
+const CODEC_DEFLATE: u8 = 0;
+const CODEC_ZSTD: u8 = 1;
+const CODEC_LZ4: u8 = 2;
+
+/// The largest chunk we will decompress. The sizes in compressed data come from whoever wrote it,
+/// so without a limit a small hostile chunk could make us allocate all of memory.
+pub(crate) const MAX_DECOMPRESSED_LEN: usize = 1 << 30;
+
+/// Each byte of LZ4 compressed data decompresses to at most this many bytes
+#[cfg(feature = "lz4")]
+const MAX_LZ4_RATIO: usize = 255;
+
+/// A compression codec for saved documents and changes
+///
+/// By default documents are saved with DEFLATE applied to each column and large changes are
+/// DEFLATEd as a whole, which any version of automerge can load. Documents and changes can
+/// instead be compressed as a whole with another codec using [`crate::SaveOptions::codec`] or
+/// [`crate::Change::compress_with`]. Chunks compressed like this can only be loaded by versions of
+/// automerge which support the codec; older versions fail to load them with an "unknown chunk
+/// type" error and versions built without the relevant cargo feature fail with an error naming
+/// the feature.
+///
+/// [`Codec::Uncompressed`] stores everything uncompressed, which costs more space but no CPU time
+/// to save or load and can be loaded by any version of automerge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Codec {
+    Uncompressed,
+    Deflate,
+    /// Zstandard, requires the `zstd` feature
+    #[cfg(feature = "zstd")]
+    Zstd,
+    /// LZ4, requires the `lz4` feature
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Codec {
+    /// The ID written in chunks compressed with this codec, or `None` if chunks are stored as they
+    /// are
+    fn id(self) -> Option<u8> {
+        match self {
+            Codec::Uncompressed => None,
+            Codec::Deflate => Some(CODEC_DEFLATE),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Some(CODEC_ZSTD),
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => Some(CODEC_LZ4),
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::Uncompressed => data.to_vec(),
+            Codec::Deflate => {
+                let mut deflater =
+                    flate2::bufread::DeflateEncoder::new(data, flate2::Compression::default());
+                let mut out = Vec::new();
+                deflater.read_to_end(&mut out).unwrap();
+                out
+            }
+            // Writing to a `Vec` cannot fail
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                zstd_codec::encode_all(data, zstd_codec::DEFAULT_COMPRESSION_LEVEL).unwrap()
+            }
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => lz4_flex::compress_prepend_size(data),
+        }
+    }
+}
+
+/// Read all of `decoder`, failing if it decompresses to more than `limit` bytes
+fn read_limited<R: Read>(
+    decoder: R,
+    codec: &'static str,
+    limit: usize,
+) -> Result<Vec<u8>, error::Chunk> {
+    let mut out = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| error::Chunk::Decompress(codec))?;
+    if out.len() > limit {
+        return Err(error::Chunk::DecompressedTooLarge(codec));
+    }
+    Ok(out)
+}
+
+#[allow(unreachable_patterns)]
+fn decompress(codec: u8, data: &[u8]) -> Result<Vec<u8>, error::Chunk> {
+    match codec {
+        CODEC_DEFLATE => {
+            let decoder = flate2::bufread::DeflateDecoder::new(data);
+            read_limited(decoder, "deflate", MAX_DECOMPRESSED_LEN)
+        }
+        #[cfg(feature = "zstd")]
+        CODEC_ZSTD => {
+            let decoder = zstd_codec::stream::read::Decoder::with_buffer(data)
+                .map_err(|_| error::Chunk::Decompress("zstd"))?;
+            read_limited(decoder, "zstd", MAX_DECOMPRESSED_LEN)
+        }
+        #[cfg(feature = "lz4")]
+        CODEC_LZ4 => {
+            // LZ4 allocates the size written before the data up front, so check it is one the
+            // data could actually decompress to
+            let (len, data) = lz4_flex::block::uncompressed_size(data)
+                .map_err(|_| error::Chunk::Decompress("lz4"))?;
+            if len > MAX_DECOMPRESSED_LEN || len > data.len().saturating_mul(MAX_LZ4_RATIO) {
+                return Err(error::Chunk::DecompressedTooLarge("lz4"));
+            }
+            lz4_flex::decompress(data, len).map_err(|_| error::Chunk::Decompress("lz4"))
+        }
+        CODEC_ZSTD => Err(error::Chunk::CodecNotEnabled("zstd")),
+        CODEC_LZ4 => Err(error::Chunk::CodecNotEnabled("lz4")),
+        other => Err(error::Chunk::UnknownCodec(other)),
+    }
+}
+
+/// Compress an entire document or change chunk with `codec`
+///
+/// The compressed chunk has the same checksum as `chunk`, its data is the codec, the type of
+/// `chunk` and then the compressed data of `chunk`. [`Codec::Uncompressed`] returns `chunk` as it
+/// is.
+pub(crate) fn compress_chunk(codec: Codec, chunk: &[u8]) -> Vec<u8> {
+    let id = match codec.id() {
+        Some(id) => id,
+        None => return chunk.to_vec(),
+    };
+    let (_, header) = Header::parse::<error::Header>(parse::Input::new(chunk))
+        .expect("we only compress chunks we wrote");
+    let mut data = vec![id, u8::from(header.chunk_type())];
+    data.extend(codec.compress(&chunk[header.data_bytes()]));
+    let header = header.with_data(ChunkType::CodecCompressed, &data);
+    let mut out = Vec::with_capacity(header.len() + data.len());
+    header.write(&mut out);
+    out.extend(data);
+    out
+}
+
+/// Decompress the data of a [`ChunkType::CodecCompressed`] chunk, returning the type and the
+/// uncompressed data of the chunk inside
+pub(crate) fn decompress_chunk(data: &[u8]) -> Result<(ChunkType, Vec<u8>), error::Chunk> {
+    let (codec, chunk_type, compressed) = match data {
+        [codec, chunk_type, rest @ ..] => (*codec, *chunk_type, rest),
+        _ => return Err(error::Chunk::MissingCodec),
+    };
+    let chunk_type = match ChunkType::try_from(chunk_type) {
+        Ok(t @ (ChunkType::Document | ChunkType::Change)) => t,
+        _ => return Err(error::Chunk::BadCompressedChunkType(chunk_type)),
+    };
+    Ok((chunk_type, decompress(codec, compressed)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, Automerge, Change, SaveOptions, ROOT};
+
+    fn codecs() -> Vec<Codec> {
+        vec![
+            Codec::Deflate,
+            #[cfg(feature = "zstd")]
+            Codec::Zstd,
+            #[cfg(feature = "lz4")]
+            Codec::Lz4,
+        ]
+    }
+
+    fn doc() -> AutoCommit {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", crate::ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, &"all work and no play ".repeat(50))
+            .unwrap();
+        doc.commit();
+        doc
+    }
+
+    #[test]
+    fn documents_and_changes_round_trip() {
+        let mut doc = doc();
+        for codec in codecs() {
+            let saved = doc.save_with_options(SaveOptions {
+                codec: Some(codec),
+                ..Default::default()
+            });
+            assert_eq!(saved[8], u8::from(ChunkType::CodecCompressed));
+            let loaded = Automerge::load(&saved).unwrap();
+            assert_eq!(loaded.get_heads(), doc.get_heads());
+            assert_eq!(loaded.save(), doc.save());
+
+            let change = doc.get_last_local_change().unwrap().clone();
+            let compressed = change.compress_with(codec);
+            assert!(compressed.len() < change.raw_bytes().len());
+            assert_eq!(Change::try_from(&compressed[..]).unwrap(), change);
+
+            let mut incremental = Automerge::new();
+            incremental
+                .load_incremental(&doc.document().save_after_with(&[], codec))
+                .unwrap();
+            assert_eq!(incremental.get_heads(), doc.get_heads());
+        }
+    }
+
+    #[test]
+    fn uncompressed_documents_and_changes_are_stored_as_they_are() {
+        let mut doc = doc();
+        let saved = doc.save_with_options(SaveOptions {
+            codec: Some(Codec::Uncompressed),
+            ..Default::default()
+        });
+        assert_eq!(saved, doc.save_nocompress());
+        assert_eq!(
+            Automerge::load(&saved).unwrap().get_heads(),
+            doc.get_heads()
+        );
+
+        let change = doc.get_last_local_change().unwrap().clone();
+        assert_eq!(
+            &change.compress_with(Codec::Uncompressed)[..],
+            change.raw_bytes()
+        );
+    }
+
+    #[test]
+    fn decompression_is_limited() {
+        let data = Codec::Deflate.compress(&[0; 1000]);
+        let decoder = || flate2::bufread::DeflateDecoder::new(&data[..]);
+        assert_eq!(
+            read_limited(decoder(), "deflate", 1000).unwrap().len(),
+            1000
+        );
+        assert!(matches!(
+            read_limited(decoder(), "deflate", 999),
+            Err(error::Chunk::DecompressedTooLarge("deflate"))
+        ));
+
+        // An LZ4 chunk which claims to be far larger than its data could decompress to
+        #[cfg(feature = "lz4")]
+        {
+            let mut data = Codec::Lz4.compress(&[0; 1000]);
+            data[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(matches!(
+                decompress(CODEC_LZ4, &data),
+                Err(error::Chunk::DecompressedTooLarge("lz4"))
+            ));
+        }
+    }
+
+    #[test]
+    fn unsupported_codecs_are_reported() {
+        let saved = doc().save_with_options(SaveOptions {
+            codec: Some(Codec::Deflate),
+            ..Default::default()
+        });
+        let (_, header) = Header::parse::<error::Header>(parse::Input::new(&saved)).unwrap();
+        let expected = vec![
+            (99, "unknown compression codec: 99"),
+            #[cfg(not(feature = "zstd"))]
+            (CODEC_ZSTD, "built without the `zstd` feature"),
+            #[cfg(not(feature = "lz4"))]
+            (CODEC_LZ4, "built without the `lz4` feature"),
+        ];
+        for (codec, message) in expected {
+            let mut bytes = saved.clone();
+            bytes[header.len()] = codec;
+            let err = Automerge::load(&bytes).unwrap_err().to_string();
+            assert!(err.contains(message), "{}", err);
+        }
+    }
+}
@@ -321,6 +321,21 @@ impl<'a> Document<'a> {
             .iter(&self.bytes[self.change_bytes.clone()])
     }
 
+    pub(crate) fn into_owned(self) -> Document<'static> {
+        Document {
+            bytes: Cow::Owned(self.bytes.into_owned()),
+            compressed_bytes: self.compressed_bytes.map(|b| Cow::Owned(b.into_owned())),
+            header: self.header,
+            actors: self.actors,
+            heads: self.heads,
+            op_metadata: self.op_metadata,
+            op_bytes: self.op_bytes,
+            change_metadata: self.change_metadata,
+            change_bytes: self.change_bytes,
+            head_indices: self.head_indices,
+        }
+    }
+
     pub(crate) fn into_bytes(self) -> Vec<u8> {
         if let Some(compressed) = self.compressed_bytes {
             compressed.into_owned()
@@ -0,0 +1,370 @@
+use std::fmt;
+
+use super::{chunk::error, parse, ChunkType, Header};
+
+/// The length of an XChaCha20-Poly1305 nonce
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+/// A 256 bit XChaCha20-Poly1305 key along with an identifier for it
+///
+/// The identifier is stored in the clear in each chunk encrypted with the key so that a
+/// [`KeyProvider`] can find the right key to decrypt it with, which allows keys to be rotated.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey {
+    id: u32,
+    #[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+    key: [u8; 32],
+}
+
+impl EncryptionKey {
+    pub fn new(id: u32, key: [u8; 32]) -> Self {
+        Self { id, key }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+// Don't leak the key into logs
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Supplies the keys used to encrypt and decrypt chunks
+///
+/// Encrypted chunks are written with [`crate::SaveOptions::encryption`] (which requires the
+/// `encryption` feature) and read by [`crate::Automerge::load_with_keys`]. Only the contents of a chunk
+/// are encrypted, the hashes of the changes in it are computed over the plaintext so heads and
+/// dependencies are the same whether or not a document is encrypted.
+///
+/// An [`EncryptionKey`] is a provider which only knows about itself.
+pub trait KeyProvider: fmt::Debug + Send + Sync {
+    /// The key to encrypt new chunks with
+    fn encryption_key(&self) -> EncryptionKey;
+
+    /// The key with the given id, or `None` if it is not known
+    fn decryption_key(&self, id: u32) -> Option<EncryptionKey>;
+}
+
+impl KeyProvider for EncryptionKey {
+    fn encryption_key(&self) -> EncryptionKey {
+        self.clone()
+    }
+
+    fn decryption_key(&self, id: u32) -> Option<EncryptionKey> {
+        if id == self.id {
+            Some(self.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// Encrypt a document or change chunk with the current key of `keys`
+///
+/// The encrypted chunk has the same checksum as `chunk`, its data is the id of the key, a random
+/// nonce and then the encrypted type and data of `chunk`. The checksum is authenticated along with
+/// the ciphertext.
+#[cfg(feature = "encryption")]
+fn encrypt_chunk(keys: &dyn KeyProvider, chunk: &[u8]) -> Vec<u8> {
+    use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+    use chacha20poly1305::XChaCha20Poly1305;
+
+    let (_, header) = Header::parse::<error::Header>(parse::Input::new(chunk))
+        .expect("we only encrypt chunks we wrote");
+    let key = keys.encryption_key();
+    let cipher = XChaCha20Poly1305::new(&key.key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let mut plaintext = vec![u8::from(header.chunk_type())];
+    plaintext.extend(&chunk[header.data_bytes()]);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: &plaintext,
+                aad: header.checksum().as_ref(),
+            },
+        )
+        .expect("encrypting a chunk cannot fail");
+
+    let mut data = Vec::with_capacity(5 + NONCE_LEN + ciphertext.len());
+    leb128::write::unsigned(&mut data, key.id as u64).unwrap();
+    data.extend(nonce);
+    data.extend(ciphertext);
+    let header = header.with_data(ChunkType::Encrypted, &data);
+    let mut out = Vec::with_capacity(header.len() + data.len());
+    header.write(&mut out);
+    out.extend(data);
+    out
+}
+
+/// Encrypt every chunk in `chunks`, which are the concatenated chunks produced by a save
+#[cfg(feature = "encryption")]
+pub(crate) fn encrypt_chunks(keys: &dyn KeyProvider, mut chunks: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(chunks.len());
+    while !chunks.is_empty() {
+        let (_, header) = Header::parse::<error::Header>(parse::Input::new(chunks))
+            .expect("we only encrypt chunks we wrote");
+        let end = header.data_bytes().end;
+        out.extend(encrypt_chunk(keys, &chunks[..end]));
+        chunks = &chunks[end..];
+    }
+    out
+}
+
+/// Decrypt every encrypted chunk in `chunks`, leaving any other chunks untouched
+pub(crate) fn decrypt_chunks(
+    keys: Option<&dyn KeyProvider>,
+    mut chunks: &[u8],
+) -> Result<Vec<u8>, error::Chunk> {
+    let mut out = Vec::with_capacity(chunks.len());
+    while !chunks.is_empty() {
+        let (_, header) =
+            Header::parse::<error::Chunk>(parse::Input::new(chunks)).map_err(|e| match e {
+                parse::ParseError::Error(e) => e,
+                parse::ParseError::Incomplete(_) => error::Chunk::Truncated,
+            })?;
+        let data = &chunks[header.data_bytes()];
+        if header.chunk_type() == ChunkType::Encrypted {
+            out.extend(decrypt_chunk(&header, data, keys)?);
+        } else {
+            out.extend(&chunks[..header.data_bytes().end]);
+        }
+        chunks = &chunks[header.data_bytes().end..];
+    }
+    Ok(out)
+}
+
+/// Decrypt the `data` of a [`ChunkType::Encrypted`] chunk with the given `header`, returning the
+/// bytes of the chunk inside it, header and all
+pub(crate) fn decrypt_chunk(
+    header: &Header,
+    data: &[u8],
+    keys: Option<&dyn KeyProvider>,
+) -> Result<Vec<u8>, error::Chunk> {
+    let keys = keys.ok_or(error::Chunk::MissingKeyProvider)?;
+    let (i, key_id) = parse::leb128_u32::<error::Chunk>(parse::Input::new(data))
+        .map_err(|_| error::Chunk::Truncated)?;
+    let key = keys
+        .decryption_key(key_id)
+        .ok_or(error::Chunk::UnknownKey(key_id))?;
+    let plaintext = decrypt(&key, header, i.unconsumed_bytes())?;
+    let (chunk_type, data) = match plaintext.split_first() {
+        Some((chunk_type, data)) => (*chunk_type, data),
+        None => return Err(error::Chunk::Truncated),
+    };
+    let chunk_type = match ChunkType::try_from(chunk_type) {
+        Ok(ChunkType::Encrypted) | Err(_) => {
+            return Err(error::Chunk::BadEncryptedChunkType(chunk_type))
+        }
+        Ok(t) => t,
+    };
+    let inner_header = header.with_data(chunk_type, data);
+    let mut inner_chunk = Vec::with_capacity(inner_header.len() + data.len());
+    inner_header.write(&mut inner_chunk);
+    inner_chunk.extend(data);
+    Ok(inner_chunk)
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt(key: &EncryptionKey, header: &Header, data: &[u8]) -> Result<Vec<u8>, error::Chunk> {
+    use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+    use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+    if data.len() < NONCE_LEN {
+        return Err(error::Chunk::Truncated);
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(&key.key.into())
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header.checksum().as_ref(),
+            },
+        )
+        .map_err(|_| error::Chunk::Decrypt)
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(_key: &EncryptionKey, _header: &Header, _data: &[u8]) -> Result<Vec<u8>, error::Chunk> {
+    Err(error::Chunk::EncryptionNotEnabled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::VerificationMode;
+    use crate::{Automerge, OnPartialLoad, PatchLog};
+
+    fn load(data: &[u8], keys: Option<&dyn KeyProvider>) -> Result<Automerge, String> {
+        let (on_error, mode, patch_log) = (
+            OnPartialLoad::Error,
+            VerificationMode::Check,
+            &mut PatchLog::null(),
+        );
+        match keys {
+            Some(keys) => Automerge::load_with_keys(data, on_error, mode, patch_log, keys),
+            None => Automerge::load_with(data, on_error, mode, patch_log),
+        }
+        .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn encrypted_chunks_need_a_key() {
+        let mut data = vec![1];
+        data.extend([0; 40]);
+        let header = Header::new(ChunkType::Encrypted, &data);
+        let mut chunk = Vec::new();
+        header.write(&mut chunk);
+        chunk.extend(data);
+
+        let err = load(&chunk, None).unwrap_err();
+        assert!(err.contains("no key provider"), "{}", err);
+        let err = load(&chunk, Some(&EncryptionKey::new(2, [0; 32]))).unwrap_err();
+        assert!(err.contains("key 1"), "{}", err);
+        let err = load(&chunk, Some(&EncryptionKey::new(1, [0; 32]))).unwrap_err();
+        #[cfg(feature = "encryption")]
+        assert!(err.contains("unable to decrypt"), "{}", err);
+        #[cfg(not(feature = "encryption"))]
+        assert!(err.contains("`encryption` feature"), "{}", err);
+    }
+
+    #[cfg(feature = "encryption")]
+    mod encryption {
+        use std::sync::Arc;
+
+        use super::*;
+        use crate::store::{MemoryStorage, Persister, Storage};
+        use crate::sync::{self, SyncDoc};
+        use crate::transaction::Transactable;
+        use crate::{AutoCommit, Codec, ReadDoc, SaveOptions, ROOT};
+
+        fn doc() -> AutoCommit {
+            let mut doc = AutoCommit::new();
+            doc.put(ROOT, "password", "hunter2").unwrap();
+            doc.commit();
+            doc
+        }
+
+        fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+            haystack.windows(needle.len()).any(|w| w == needle)
+        }
+
+        #[test]
+        fn documents_round_trip() {
+            let key = EncryptionKey::new(7, [42; 32]);
+            let mut doc = doc();
+            for codec in [None, Some(Codec::Deflate)] {
+                let saved = doc.save_with_options(SaveOptions {
+                    codec,
+                    encryption: Some(Arc::new(key.clone())),
+                    ..Default::default()
+                });
+                assert_eq!(saved[8], u8::from(ChunkType::Encrypted));
+                assert!(!contains(&saved, b"hunter2"));
+
+                let loaded = load(&saved, Some(&key)).unwrap();
+                assert_eq!(loaded.get_heads(), doc.get_heads());
+                assert_eq!(loaded.save(), doc.save());
+
+                let err = load(&saved, Some(&EncryptionKey::new(7, [0; 32]))).unwrap_err();
+                assert!(err.contains("unable to decrypt"), "{}", err);
+                let mut tampered = saved.clone();
+                tampered[4] ^= 1;
+                assert!(load(&tampered, Some(&key)).is_err());
+            }
+        }
+
+        #[test]
+        fn incremental_saves_round_trip() {
+            let key = EncryptionKey::new(3, [9; 32]);
+            let mut doc = doc();
+            let mut loaded = AutoCommit::new();
+            for value in ["hunter3", "hunter4"] {
+                doc.put(ROOT, "password", value).unwrap();
+                doc.commit();
+                let saved = doc.save_incremental_encrypted(&key);
+                assert!(!saved.is_empty() && !contains(&saved, value.as_bytes()));
+                loaded.load_incremental_with_keys(&saved, &key).unwrap();
+                assert_eq!(loaded.get_heads(), doc.get_heads());
+            }
+            assert!(AutoCommit::new()
+                .load_incremental(&doc.document().save_after_encrypted(&[], &key))
+                .is_err());
+            assert_eq!(
+                loaded.get(ROOT, "password").unwrap().unwrap().0.to_str(),
+                Some("hunter4")
+            );
+        }
+
+        #[test]
+        fn persisters_encrypt_every_chunk() {
+            let key: Arc<dyn KeyProvider> = Arc::new(EncryptionKey::new(5, [1; 32]));
+            let mut persister = Persister::new(MemoryStorage::new(), "doc")
+                .with_compaction_threshold(2)
+                .with_encryption(key.clone());
+            let mut doc = persister.load().unwrap();
+            for value in ["hunter2", "hunter3", "hunter4"] {
+                doc.put(ROOT, "password", value).unwrap();
+                doc.commit();
+                persister.save(&mut doc).unwrap();
+            }
+            let storage = persister.into_storage();
+            for key in storage.list("doc").unwrap() {
+                let chunk = storage.get(&key).unwrap().unwrap();
+                assert!(!contains(&chunk, b"hunter"));
+            }
+            assert!(Persister::new(storage.clone(), "doc").load().is_err());
+            let loaded = Persister::new(storage, "doc")
+                .with_encryption(key)
+                .load()
+                .unwrap();
+            assert_eq!(
+                loaded.get(ROOT, "password").unwrap().unwrap().0.to_str(),
+                Some("hunter4")
+            );
+        }
+
+        #[test]
+        fn peers_sharing_a_key_can_sync() {
+            let key = EncryptionKey::new(1, [7; 32]);
+            let mut doc1 = doc();
+            let mut doc2 = AutoCommit::new();
+            doc2.put(ROOT, "other", "value").unwrap();
+            let (mut s1, mut s2) = (sync::State::new(), sync::State::new());
+            loop {
+                let one = doc1.sync().generate_sync_message(&mut s1);
+                let two = doc2.sync().generate_sync_message(&mut s2);
+                if one.is_none() && two.is_none() {
+                    break;
+                }
+                if let Some(msg) = one {
+                    let has_data = !msg.changes.is_empty() || msg.snapshot.is_some();
+                    let encoded = msg.encode_encrypted(&key);
+                    assert!(!contains(&encoded, b"hunter2"));
+                    assert_eq!(sync::Message::decode(&encoded).is_err(), has_data);
+                    let msg = sync::Message::decode_encrypted(&encoded, &key).unwrap();
+                    doc2.sync().receive_sync_message(&mut s2, msg).unwrap();
+                }
+                if let Some(msg) = two {
+                    let msg =
+                        sync::Message::decode_encrypted(&msg.encode_encrypted(&key), &key).unwrap();
+                    doc1.sync().receive_sync_message(&mut s1, msg).unwrap();
+                }
+            }
+            assert_eq!(doc1.get_heads(), doc2.get_heads());
+            assert_eq!(
+                doc2.get(ROOT, "password").unwrap().unwrap().0.to_str(),
+                Some("hunter2")
+            );
+        }
+    }
+}
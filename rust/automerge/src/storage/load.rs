@@ -2,7 +2,7 @@ use tracing::instrument;
 
 use crate::{
     change::Change,
-    storage::{self, parse},
+    storage::{self, parse, KeyProvider},
 };
 
 mod change_collector;
@@ -51,11 +51,16 @@ pub(crate) enum LoadedChanges<'a> {
 /// or more changes. This means it is possible to partially load corrupted data if the first `n`
 /// chunks are valid. This function returns a `LoadedChanges` which you can examine to determine if
 /// this is the case.
-#[instrument(skip(data))]
-pub(crate) fn load_changes<'a>(mut data: parse::Input<'a>) -> LoadedChanges<'a> {
+///
+/// Encrypted chunks are decrypted with `keys`.
+#[instrument(skip(data, keys))]
+pub(crate) fn load_changes<'a>(
+    mut data: parse::Input<'a>,
+    keys: Option<&dyn KeyProvider>,
+) -> LoadedChanges<'a> {
     let mut changes = Vec::new();
     while !data.is_empty() {
-        let remaining = match load_next_change(data, &mut changes) {
+        let remaining = match load_next_change(data, keys, &mut changes) {
             Ok(d) => d,
             Err(e) => {
                 return LoadedChanges::Partial {
@@ -72,9 +77,11 @@ pub(crate) fn load_changes<'a>(mut data: parse::Input<'a>) -> LoadedChanges<'a>
 
 fn load_next_change<'a>(
     data: parse::Input<'a>,
+    keys: Option<&dyn KeyProvider>,
     changes: &mut Vec<Change>,
 ) -> Result<parse::Input<'a>, Error> {
-    let (remaining, chunk) = storage::Chunk::parse(data).map_err(|e| Error::Parse(Box::new(e)))?;
+    let (remaining, chunk) =
+        storage::Chunk::parse_with(data, keys).map_err(|e| Error::Parse(Box::new(e)))?;
     if !chunk.checksum_valid() {
         return Err(Error::BadChecksum);
     }
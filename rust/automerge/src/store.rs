@@ -0,0 +1,465 @@
+//! # Persisting documents
+//!
+//! A [`Storage`] is a key/value store for the chunks which make up a saved document. Each chunk
+//! is identified by a [`StorageKey`] made up of the ID of the document it belongs to, the
+//! [`ChunkKind`] and a hash:
+//!
+//! * An [`ChunkKind::Incremental`] chunk contains a single change and is keyed by the hash of that
+//!   change
+//! * A [`ChunkKind::Snapshot`] chunk contains the output of [`AutoCommit::save`] and is keyed by
+//!   the hash of the heads of the document at the time it was saved (see [`snapshot_hash`])
+//! * A [`ChunkKind::SyncState`] chunk contains the encoded sync state of a peer and is keyed by
+//!   the hash of the peer's ID (see [`peer_hash`]). These are ignored when loading and compacting
+//!   a document.
+//!
+//! A [`Persister`] keeps a document in sync with a [`Storage`]. Call [`Persister::save`] after
+//! each commit to append the new changes as incremental chunks. Once enough incremental chunks
+//! have accumulated they are compacted into a single snapshot chunk.
+//!
+//! [`MemoryStorage`] is an in-memory implementation, and [`FsStorage`] is an implementation which
+//! keeps an append-only log per document on the filesystem.
+//!
+//! ## Example
+//!
+//! ```
+//! use automerge::{store::{MemoryStorage, Persister}, transaction::Transactable, ReadDoc};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut persister = Persister::new(MemoryStorage::new(), "my-doc");
+//! let mut doc = persister.load()?;
+//! doc.put(automerge::ROOT, "key", "value")?;
+//! doc.commit();
+//! persister.save(&mut doc)?;
+//!
+//! let mut persister = Persister::new(persister.into_storage(), "my-doc");
+//! let doc = persister.load()?;
+//! assert_eq!(doc.get(automerge::ROOT, "key")?.unwrap().0.to_str(), Some("value"));
+//! # Ok(())
+//! # }
+//! ```
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+
+use crate::{AutoCommit, AutomergeError, ChangeHash, KeyProvider};
+
+mod fs;
+
+pub use fs::FsStorage;
+
+/// The number of incremental chunks after which [`Persister::save`] compacts a document
+pub const DEFAULT_COMPACTION_THRESHOLD: usize = 64;
+
+/// The kind of data stored in a chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkKind {
+    /// A single change, keyed by its hash
+    Incremental,
+    /// A whole document, keyed by the hash of its heads
+    Snapshot,
+    /// The encoded [`crate::sync::State`] of a peer syncing the document, keyed by
+    /// [`peer_hash`]
+    SyncState,
+}
+
+/// The key of a chunk in a [`Storage`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StorageKey {
+    pub doc_id: String,
+    pub kind: ChunkKind,
+    pub hash: ChangeHash,
+}
+
+impl StorageKey {
+    pub fn incremental<S: Into<String>>(doc_id: S, hash: ChangeHash) -> Self {
+        Self {
+            doc_id: doc_id.into(),
+            kind: ChunkKind::Incremental,
+            hash,
+        }
+    }
+
+    pub fn snapshot<S: Into<String>>(doc_id: S, heads: &[ChangeHash]) -> Self {
+        Self {
+            doc_id: doc_id.into(),
+            kind: ChunkKind::Snapshot,
+            hash: snapshot_hash(heads),
+        }
+    }
+
+    pub fn sync_state<S: Into<String>>(doc_id: S, peer_id: &str) -> Self {
+        Self {
+            doc_id: doc_id.into(),
+            kind: ChunkKind::SyncState,
+            hash: peer_hash(peer_id),
+        }
+    }
+}
+
+/// A store for the chunks of saved documents
+pub trait Storage {
+    type Error: std::error::Error + 'static;
+
+    /// Store `data` under `key`, replacing any data already stored under it
+    fn put(&mut self, key: &StorageKey, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Get the data stored under `key`, if any
+    fn get(&self, key: &StorageKey) -> Result<Option<Vec<u8>>, Self::Error>;
+
+    /// List the keys of every chunk stored for `doc_id` in the order in which they were first put
+    fn list(&self, doc_id: &str) -> Result<Vec<StorageKey>, Self::Error>;
+
+    /// Remove the data stored under `key`. Removing a key which doesn't exist is not an error.
+    fn delete(&mut self, key: &StorageKey) -> Result<(), Self::Error>;
+
+    /// Remove the data stored under each of `keys`
+    ///
+    /// The default implementation calls [`Self::delete`] for each key. Implementations for which
+    /// a delete is expensive should override this to remove all of the keys at once.
+    fn delete_many(&mut self, keys: &[StorageKey]) -> Result<(), Self::Error> {
+        for key in keys {
+            self.delete(key)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error<E: std::error::Error + 'static> {
+    #[error("storage error: {0}")]
+    Storage(E),
+    #[error(transparent)]
+    Automerge(#[from] AutomergeError),
+}
+
+/// The hash used to key a snapshot of a document with the given heads
+///
+/// This is the sha256 hash of the sorted heads, so it doesn't depend on the order in which the
+/// heads are given.
+pub fn snapshot_hash(heads: &[ChangeHash]) -> ChangeHash {
+    let mut heads = heads.to_vec();
+    heads.sort();
+    let mut hasher = Sha256::new();
+    for head in heads {
+        hasher.update(head.0);
+    }
+    ChangeHash(hasher.finalize().into())
+}
+
+/// The hash used to key the sync state of the peer with the given ID
+pub fn peer_hash(peer_id: &str) -> ChangeHash {
+    ChangeHash(Sha256::digest(peer_id.as_bytes()).into())
+}
+
+/// A [`Storage`] which keeps everything in memory
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    chunks: Vec<(StorageKey, Vec<u8>)>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    type Error = std::convert::Infallible;
+
+    fn put(&mut self, key: &StorageKey, data: &[u8]) -> Result<(), Self::Error> {
+        match self.chunks.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) => *existing = data.to_vec(),
+            None => self.chunks.push((key.clone(), data.to_vec())),
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &StorageKey) -> Result<Option<Vec<u8>>, Self::Error> {
+        Ok(self
+            .chunks
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, data)| data.clone()))
+    }
+
+    fn list(&self, doc_id: &str) -> Result<Vec<StorageKey>, Self::Error> {
+        Ok(self
+            .chunks
+            .iter()
+            .filter(|(k, _)| k.doc_id == doc_id)
+            .map(|(k, _)| k.clone())
+            .collect())
+    }
+
+    fn delete(&mut self, key: &StorageKey) -> Result<(), Self::Error> {
+        self.chunks.retain(|(k, _)| k != key);
+        Ok(())
+    }
+
+    fn delete_many(&mut self, keys: &[StorageKey]) -> Result<(), Self::Error> {
+        self.chunks.retain(|(k, _)| !keys.contains(k));
+        Ok(())
+    }
+}
+
+/// Keeps a single document in sync with a [`Storage`]
+///
+/// A `Persister` assumes that it is the only writer of its document's chunks.
+#[derive(Debug)]
+pub struct Persister<S> {
+    storage: S,
+    state: SaveState,
+}
+
+impl<S: Storage> Persister<S> {
+    pub fn new<D: Into<String>>(storage: S, doc_id: D) -> Self {
+        Self {
+            storage,
+            state: SaveState::new(doc_id.into()),
+        }
+    }
+
+    /// Set the number of incremental chunks after which [`Self::save`] compacts the document. A
+    /// threshold of `0` disables automatic compaction.
+    pub fn with_compaction_threshold(mut self, threshold: usize) -> Self {
+        self.state.compaction_threshold = threshold;
+        self
+    }
+
+    /// Encrypt every chunk this persister stores with the current key of `keys`, and decrypt the
+    /// chunks it loads with `keys`
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption(mut self, keys: Arc<dyn KeyProvider>) -> Self {
+        self.state.keys = Some(keys);
+        self
+    }
+
+    pub fn doc_id(&self) -> &str {
+        &self.state.doc_id
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+
+    /// Load the document from storage
+    ///
+    /// The chunks are concatenated in the order returned by [`Storage::list`] and loaded with
+    /// [`AutoCommit::load_incremental`]. If the final chunk was only partially written it is
+    /// ignored. If nothing has been stored for this document yet then an empty document is
+    /// returned.
+    pub fn load(&mut self) -> Result<AutoCommit, Error<S::Error>> {
+        self.state.load(&mut self.storage)
+    }
+
+    /// Append any changes made to `doc` since it was last loaded or saved as incremental chunks,
+    /// compacting the document if the compaction threshold has been reached.
+    pub fn save(&mut self, doc: &mut AutoCommit) -> Result<(), Error<S::Error>> {
+        self.state.save(&mut self.storage, doc)
+    }
+
+    /// Replace every chunk stored for this document with a single snapshot of `doc`
+    ///
+    /// The snapshot is stored before anything is removed, so an interrupted compaction never loses
+    /// data.
+    pub fn compact(&mut self, doc: &mut AutoCommit) -> Result<(), Error<S::Error>> {
+        self.state.compact(&mut self.storage, doc)
+    }
+}
+
+/// What has been saved of a single document, independently of the storage it was saved to
+#[derive(Debug)]
+pub(crate) struct SaveState {
+    doc_id: String,
+    saved_heads: Vec<ChangeHash>,
+    incremental_chunks: usize,
+    pub(crate) compaction_threshold: usize,
+    /// The keys chunks are encrypted with, if any
+    pub(crate) keys: Option<Arc<dyn KeyProvider>>,
+}
+
+impl SaveState {
+    pub(crate) fn new(doc_id: String) -> Self {
+        Self {
+            doc_id,
+            saved_heads: Vec::new(),
+            incremental_chunks: 0,
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            keys: None,
+        }
+    }
+
+    fn doc_keys<S: Storage>(&self, storage: &S) -> Result<Vec<StorageKey>, Error<S::Error>> {
+        Ok(storage
+            .list(&self.doc_id)
+            .map_err(Error::Storage)?
+            .into_iter()
+            .filter(|k| k.kind != ChunkKind::SyncState)
+            .collect())
+    }
+
+    pub(crate) fn load<S: Storage>(
+        &mut self,
+        storage: &mut S,
+    ) -> Result<AutoCommit, Error<S::Error>> {
+        let keys = self.doc_keys(storage)?;
+        let mut data = Vec::new();
+        for key in &keys {
+            if let Some(chunk) = storage.get(key).map_err(Error::Storage)? {
+                data.extend(chunk);
+            }
+        }
+        let mut doc = AutoCommit::new();
+        match &self.keys {
+            Some(keys) => doc.load_incremental_with_keys(&data, keys.as_ref())?,
+            None => doc.load_incremental(&data)?,
+        };
+        self.saved_heads = doc.get_heads();
+        self.incremental_chunks = keys
+            .iter()
+            .filter(|k| k.kind == ChunkKind::Incremental)
+            .count();
+        Ok(doc)
+    }
+
+    pub(crate) fn save<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        doc: &mut AutoCommit,
+    ) -> Result<(), Error<S::Error>> {
+        let changes = doc
+            .get_changes(&self.saved_heads)
+            .into_iter()
+            .map(|c| (c.hash(), self.encrypt(c.raw_bytes())))
+            .collect::<Vec<_>>();
+        for (hash, bytes) in &changes {
+            let key = StorageKey::incremental(self.doc_id.as_str(), *hash);
+            storage.put(&key, bytes).map_err(Error::Storage)?;
+        }
+        self.saved_heads = doc.get_heads();
+        self.incremental_chunks += changes.len();
+        if self.compaction_threshold > 0 && self.incremental_chunks >= self.compaction_threshold {
+            self.compact(storage, doc)?;
+        }
+        Ok(())
+    }
+
+    fn encrypt(&self, chunk: &[u8]) -> Vec<u8> {
+        match &self.keys {
+            #[cfg(feature = "encryption")]
+            Some(keys) => crate::storage::encrypt_chunks(keys.as_ref(), chunk),
+            _ => chunk.to_vec(),
+        }
+    }
+
+    pub(crate) fn compact<S: Storage>(
+        &mut self,
+        storage: &mut S,
+        doc: &mut AutoCommit,
+    ) -> Result<(), Error<S::Error>> {
+        let heads = doc.get_heads();
+        let key = StorageKey::snapshot(self.doc_id.as_str(), &heads);
+        let stale = self
+            .doc_keys(storage)?
+            .into_iter()
+            .filter(|k| *k != key)
+            .collect::<Vec<_>>();
+        let snapshot = doc.save_with_options(crate::SaveOptions {
+            #[cfg(feature = "encryption")]
+            encryption: self.keys.clone(),
+            ..Default::default()
+        });
+        storage.put(&key, &snapshot).map_err(Error::Storage)?;
+        storage.delete_many(&stale).map_err(Error::Storage)?;
+        self.saved_heads = heads;
+        self.incremental_chunks = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transaction::Transactable, ReadDoc, ROOT};
+
+    #[test]
+    fn save_appends_one_chunk_per_change() {
+        let mut persister = Persister::new(MemoryStorage::new(), "doc");
+        let mut doc = persister.load().unwrap();
+        doc.put(ROOT, "a", 1).unwrap();
+        doc.commit();
+        doc.put(ROOT, "b", 2).unwrap();
+        doc.commit();
+        persister.save(&mut doc).unwrap();
+        // Saving again without any new changes is a no-op
+        persister.save(&mut doc).unwrap();
+
+        let keys = persister.storage().list("doc").unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.iter().all(|k| k.kind == ChunkKind::Incremental));
+
+        let mut persister = Persister::new(persister.into_storage(), "doc");
+        let mut loaded = persister.load().unwrap();
+        assert_eq!(loaded.get_heads(), doc.get_heads());
+        assert_eq!(loaded.get(ROOT, "b").unwrap().unwrap().0.to_i64(), Some(2));
+    }
+
+    #[test]
+    fn save_compacts_at_threshold() {
+        let mut persister =
+            Persister::new(MemoryStorage::new(), "doc").with_compaction_threshold(3);
+        let mut doc = persister.load().unwrap();
+        for i in 0..2 {
+            doc.put(ROOT, "key", i).unwrap();
+            doc.commit();
+            persister.save(&mut doc).unwrap();
+        }
+        assert_eq!(persister.storage().list("doc").unwrap().len(), 2);
+
+        doc.put(ROOT, "key", 2).unwrap();
+        doc.commit();
+        persister.save(&mut doc).unwrap();
+        let keys = persister.storage().list("doc").unwrap();
+        assert_eq!(keys, vec![StorageKey::snapshot("doc", &doc.get_heads())]);
+
+        // Further changes are appended after the snapshot
+        doc.put(ROOT, "key", 3).unwrap();
+        doc.commit();
+        persister.save(&mut doc).unwrap();
+        assert_eq!(persister.storage().list("doc").unwrap().len(), 2);
+
+        let mut persister = Persister::new(persister.into_storage(), "doc");
+        let mut loaded = persister.load().unwrap();
+        assert_eq!(loaded.get_heads(), doc.get_heads());
+        assert_eq!(
+            loaded.get(ROOT, "key").unwrap().unwrap().0.to_i64(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn documents_are_stored_separately() {
+        let mut storage = MemoryStorage::new();
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "key", "value").unwrap();
+        let mut persister = Persister::new(storage, "one");
+        persister.save(&mut doc).unwrap();
+        storage = persister.into_storage();
+
+        assert_eq!(storage.list("one").unwrap().len(), 1);
+        assert!(storage.list("two").unwrap().is_empty());
+        let mut empty = Persister::new(storage, "two").load().unwrap();
+        assert!(empty.get_heads().is_empty());
+    }
+
+    #[test]
+    fn snapshot_hash_ignores_order() {
+        let a = ChangeHash([1; 32]);
+        let b = ChangeHash([2; 32]);
+        assert_eq!(snapshot_hash(&[a, b]), snapshot_hash(&[b, a]));
+        assert_ne!(snapshot_hash(&[a]), snapshot_hash(&[b]));
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{ChunkKind, Storage, StorageKey};
+use crate::ChangeHash;
+
+// Each record in a log is laid out as
+//
+// .------------------------------------------------------.
+// | kind   | hash     | data length      | data          |
+// +------------------------------------------------------+
+// | 1 byte | 32 bytes | 8 bytes (LE u64) | variable      |
+// '------------------------------------------------------'
+const HEADER_LEN: u64 = 1 + 32 + 8;
+
+const INCREMENTAL: u8 = 0;
+const SNAPSHOT: u8 = 1;
+const SYNC_STATE: u8 = 2;
+
+/// A [`Storage`] which keeps an append-only log file per document in a directory
+///
+/// Every [`Storage::put`] appends a record to the log of the document and syncs it to disk. If a
+/// write is interrupted then the final record of the log is left incomplete. An incomplete record
+/// is still listed and its data is returned truncated, so that loading a document ignores it in
+/// the same way as [`crate::Automerge::load_incremental`] ignores a partially written chunk. The
+/// incomplete record is dropped by the next write to the log.
+///
+/// [`Storage::delete`] rewrites the log without the deleted record and atomically replaces the
+/// old log with it. [`Storage::delete_many`] rewrites each log only once however many of its
+/// records are deleted. A rewrite keeps only the latest record of each key, so putting a key
+/// again only takes up space in the log until the next delete.
+///
+/// The log of a document is read once, when it is first used, to index the latest record of
+/// each key. The index is kept up to date by later writes, so [`Storage::get`] reads only the
+/// record it returns.
+///
+/// Like [`super::Persister`], an `FsStorage` assumes that it is the only writer of the logs in its
+/// directory.
+#[derive(Debug)]
+pub struct FsStorage {
+    root: PathBuf,
+    /// The index of each log this storage has read or written
+    indexes: Mutex<HashMap<String, Index>>,
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+    kind: ChunkKind,
+    hash: ChangeHash,
+    start: u64,
+    len: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Index {
+    /// The latest record of each key, in the order in which the keys were first put
+    records: Vec<Record>,
+    positions: HashMap<(ChunkKind, ChangeHash), usize>,
+    /// The number of records in the log, including those superseded by a later put of the same
+    /// key
+    written: usize,
+    /// The length of the log when it was indexed. An index of a log whose length has changed is
+    /// stale.
+    len: u64,
+    /// The offset of the end of the last complete record. Appending to a log for which this is
+    /// `len` doesn't need to check for an incomplete record.
+    complete_len: u64,
+}
+
+impl Clone for FsStorage {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            indexes: Mutex::new(self.indexes().clone()),
+        }
+    }
+}
+
+impl FsStorage {
+    /// Create a storage in the directory `root`, creating the directory if it doesn't exist
+    pub fn new<P: Into<PathBuf>>(root: P) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self {
+            root,
+            indexes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn log_path(&self, doc_id: &str) -> io::Result<PathBuf> {
+        let valid = !doc_id.is_empty()
+            && !doc_id.starts_with('.')
+            && doc_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid document ID {:?}", doc_id),
+            ));
+        }
+        Ok(self.root.join(format!("{}.log", doc_id)))
+    }
+
+    fn open(&self, doc_id: &str) -> io::Result<Option<File>> {
+        match File::open(self.log_path(doc_id)?) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn indexes(&self) -> MutexGuard<'_, HashMap<String, Index>> {
+        // The indexes are only replaced whole, so one left behind by a panic is still consistent
+        self.indexes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Call `f` with the index of the log of `doc_id`, scanning the log if it hasn't been indexed
+    /// or has changed since
+    fn with_index<R, F: FnOnce(&Index) -> R>(
+        &self,
+        doc_id: &str,
+        file: &mut File,
+        f: F,
+    ) -> io::Result<R> {
+        let file_len = file.metadata()?.len();
+        let mut indexes = self.indexes();
+        match indexes.get(doc_id) {
+            Some(index) if index.len == file_len => Ok(f(index)),
+            _ => {
+                let index = scan(file)?;
+                let result = f(&index);
+                indexes.insert(doc_id.to_string(), index);
+                Ok(result)
+            }
+        }
+    }
+
+    /// Rewrite the log of `doc_id` with only the latest complete record of each key for which
+    /// `keep` returns true and atomically replace the old log with it
+    fn rewrite<F: Fn(&Record) -> bool>(&mut self, doc_id: &str, keep: F) -> io::Result<()> {
+        let mut file = match self.open(doc_id)? {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let index = scan(&mut file)?;
+        let kept = index
+            .records
+            .iter()
+            .filter(|r| keep(r) && r.start + r.len <= index.complete_len)
+            .collect::<Vec<_>>();
+        if kept.len() == index.written {
+            self.indexes().insert(doc_id.to_string(), index);
+            return Ok(());
+        }
+        let mut log = Vec::new();
+        let mut rewritten = Index::default();
+        for record in kept {
+            let data = read_record(&mut file, record)?;
+            write_record(&mut log, record.kind, &record.hash, &data);
+            rewritten.push(Record {
+                start: log.len() as u64 - record.len,
+                ..record.clone()
+            });
+        }
+        rewritten.len = log.len() as u64;
+        rewritten.complete_len = rewritten.len;
+        let path = self.log_path(doc_id)?;
+        let tmp_path = path.with_extension("log.tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&log)?;
+        tmp.sync_data()?;
+        fs::rename(tmp_path, path)?;
+        self.indexes().insert(doc_id.to_string(), rewritten);
+        Ok(())
+    }
+}
+
+impl Index {
+    fn get(&self, kind: ChunkKind, hash: &ChangeHash) -> Option<&Record> {
+        self.positions
+            .get(&(kind, *hash))
+            .map(|&pos| &self.records[pos])
+    }
+
+    /// Add a record which was written after every record already in the index
+    fn push(&mut self, record: Record) {
+        self.written += 1;
+        match self.positions.get(&(record.kind, record.hash)) {
+            // A key which has been put more than once maps to the data from the latest put
+            Some(&pos) => self.records[pos] = record,
+            None => {
+                self.positions
+                    .insert((record.kind, record.hash), self.records.len());
+                self.records.push(record);
+            }
+        }
+    }
+}
+
+fn scan(file: &mut File) -> io::Result<Index> {
+    let file_len = file.metadata()?.len();
+    let mut index = Index {
+        len: file_len,
+        ..Index::default()
+    };
+    let mut offset = 0;
+    file.seek(SeekFrom::Start(0))?;
+    while offset + HEADER_LEN <= file_len {
+        let mut header = [0; HEADER_LEN as usize];
+        file.read_exact(&mut header)?;
+        let kind = match header[0] {
+            INCREMENTAL => ChunkKind::Incremental,
+            SNAPSHOT => ChunkKind::Snapshot,
+            SYNC_STATE => ChunkKind::SyncState,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid chunk kind {}", other),
+                ))
+            }
+        };
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&header[1..33]);
+        let mut len = [0; 8];
+        len.copy_from_slice(&header[33..]);
+        let start = offset + HEADER_LEN;
+        let end = start.saturating_add(u64::from_le_bytes(len));
+        index.push(Record {
+            kind,
+            hash: ChangeHash(hash),
+            start,
+            len: end.min(file_len) - start,
+        });
+        if end > file_len {
+            break;
+        }
+        file.seek(SeekFrom::Start(end))?;
+        offset = end;
+    }
+    index.complete_len = offset;
+    Ok(index)
+}
+
+fn read_record(file: &mut File, record: &Record) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; record.len as usize];
+    file.seek(SeekFrom::Start(record.start))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn write_record(out: &mut Vec<u8>, kind: ChunkKind, hash: &ChangeHash, data: &[u8]) {
+    out.push(match kind {
+        ChunkKind::Incremental => INCREMENTAL,
+        ChunkKind::Snapshot => SNAPSHOT,
+        ChunkKind::SyncState => SYNC_STATE,
+    });
+    out.extend_from_slice(&hash.0);
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+impl Record {
+    fn matches(&self, key: &StorageKey) -> bool {
+        self.kind == key.kind && self.hash == key.hash
+    }
+}
+
+impl Storage for FsStorage {
+    type Error = io::Error;
+
+    fn put(&mut self, key: &StorageKey, data: &[u8]) -> Result<(), Self::Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.log_path(&key.doc_id)?)?;
+        let file_len = file.metadata()?.len();
+        let indexes = self.indexes.get_mut().unwrap_or_else(|e| e.into_inner());
+        let mut index = match indexes.remove(&key.doc_id) {
+            Some(index) if index.len == file_len && index.complete_len == file_len => index,
+            _ => {
+                let index = scan(&mut file)?;
+                if index.complete_len == index.len {
+                    index
+                } else {
+                    // Drop an incomplete record left behind by an interrupted write before
+                    // appending. It may have superseded an earlier record of its key, so the
+                    // truncated log is indexed again.
+                    file.set_len(index.complete_len)?;
+                    scan(&mut file)?
+                }
+            }
+        };
+        file.seek(SeekFrom::Start(index.complete_len))?;
+        let mut record = Vec::with_capacity(HEADER_LEN as usize + data.len());
+        write_record(&mut record, key.kind, &key.hash, data);
+        file.write_all(&record)?;
+        file.sync_data()?;
+        index.push(Record {
+            kind: key.kind,
+            hash: key.hash,
+            start: index.complete_len + HEADER_LEN,
+            len: data.len() as u64,
+        });
+        index.len += record.len() as u64;
+        index.complete_len = index.len;
+        indexes.insert(key.doc_id.clone(), index);
+        Ok(())
+    }
+
+    fn get(&self, key: &StorageKey) -> Result<Option<Vec<u8>>, Self::Error> {
+        let mut file = match self.open(&key.doc_id)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let record = self.with_index(&key.doc_id, &mut file, |index| {
+            index.get(key.kind, &key.hash).cloned()
+        })?;
+        match record {
+            Some(record) => read_record(&mut file, &record).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn list(&self, doc_id: &str) -> Result<Vec<StorageKey>, Self::Error> {
+        let mut file = match self.open(doc_id)? {
+            Some(file) => file,
+            None => return Ok(Vec::new()),
+        };
+        self.with_index(doc_id, &mut file, |index| {
+            index
+                .records
+                .iter()
+                .map(|record| StorageKey {
+                    doc_id: doc_id.to_string(),
+                    kind: record.kind,
+                    hash: record.hash,
+                })
+                .collect()
+        })
+    }
+
+    fn delete(&mut self, key: &StorageKey) -> Result<(), Self::Error> {
+        self.delete_many(std::slice::from_ref(key))
+    }
+
+    fn delete_many(&mut self, keys: &[StorageKey]) -> Result<(), Self::Error> {
+        let mut doc_ids: Vec<&str> = Vec::new();
+        for key in keys {
+            if !doc_ids.contains(&key.doc_id.as_str()) {
+                doc_ids.push(&key.doc_id);
+            }
+        }
+        for doc_id in doc_ids {
+            let deleted = keys
+                .iter()
+                .filter(|k| k.doc_id == doc_id)
+                .collect::<Vec<_>>();
+            self.rewrite(doc_id, |record| !deleted.iter().any(|k| record.matches(k)))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Persister;
+    use crate::{transaction::Transactable, AutoCommit, ReadDoc, ROOT};
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("automerge-store-{}", uuid::Uuid::new_v4())))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn edit(doc: &mut AutoCommit, value: i64) {
+        doc.put(ROOT, "key", value).unwrap();
+        doc.commit();
+    }
+
+    #[test]
+    fn put_get_list_delete() {
+        let dir = TempDir::new();
+        let mut storage = FsStorage::new(&dir.0).unwrap();
+        let one = StorageKey::incremental("doc", ChangeHash([1; 32]));
+        let two = StorageKey::snapshot("doc", &[ChangeHash([2; 32])]);
+        storage.put(&one, b"one").unwrap();
+        storage.put(&two, b"two").unwrap();
+        storage.put(&one, b"uno").unwrap();
+
+        assert_eq!(storage.list("doc").unwrap(), vec![one.clone(), two.clone()]);
+        assert_eq!(storage.get(&one).unwrap(), Some(b"uno".to_vec()));
+        assert_eq!(storage.get(&two).unwrap(), Some(b"two".to_vec()));
+        assert!(storage.list("other").unwrap().is_empty());
+
+        storage.delete(&one).unwrap();
+        assert_eq!(storage.list("doc").unwrap(), vec![two.clone()]);
+        assert_eq!(storage.get(&one).unwrap(), None);
+        assert_eq!(storage.get(&two).unwrap(), Some(b"two".to_vec()));
+    }
+
+    #[test]
+    fn delete_many_across_logs() {
+        let dir = TempDir::new();
+        let mut storage = FsStorage::new(&dir.0).unwrap();
+        let keys = (0..4)
+            .map(|i| StorageKey::incremental("doc", ChangeHash([i; 32])))
+            .collect::<Vec<_>>();
+        let other = StorageKey::incremental("other", ChangeHash([0; 32]));
+        for key in keys.iter().chain(Some(&other)) {
+            storage.put(key, b"data").unwrap();
+        }
+
+        storage
+            .delete_many(&[keys[0].clone(), keys[2].clone(), other.clone()])
+            .unwrap();
+        assert_eq!(
+            storage.list("doc").unwrap(),
+            vec![keys[1].clone(), keys[3].clone()]
+        );
+        assert!(storage.list("other").unwrap().is_empty());
+
+        // Appending after a rewrite continues the rewritten log
+        storage.put(&keys[0], b"again").unwrap();
+        assert_eq!(storage.get(&keys[0]).unwrap(), Some(b"again".to_vec()));
+        assert_eq!(storage.list("doc").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn rewrites_drop_superseded_records() {
+        let dir = TempDir::new();
+        let mut storage = FsStorage::new(&dir.0).unwrap();
+        let state = StorageKey::sync_state("doc", "peer");
+        let change = StorageKey::incremental("doc", ChangeHash([1; 32]));
+        let deleted = StorageKey::incremental("doc", ChangeHash([2; 32]));
+        storage.put(&change, b"change").unwrap();
+        storage.put(&deleted, b"deleted").unwrap();
+        for i in 0..10 {
+            storage
+                .put(&state, format!("state {}", i).as_bytes())
+                .unwrap();
+        }
+
+        storage.delete(&deleted).unwrap();
+        let path = dir.0.join("doc.log");
+        let expected = 2 * HEADER_LEN + (b"change".len() + b"state 9".len()) as u64;
+        assert_eq!(fs::metadata(&path).unwrap().len(), expected);
+
+        // A fresh storage reads the same index from the rewritten log
+        let reopened = FsStorage::new(&dir.0).unwrap();
+        for storage in [&storage, &reopened] {
+            assert_eq!(
+                storage.list("doc").unwrap(),
+                vec![change.clone(), state.clone()]
+            );
+            assert_eq!(storage.get(&state).unwrap(), Some(b"state 9".to_vec()));
+            assert_eq!(storage.get(&change).unwrap(), Some(b"change".to_vec()));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_doc_ids() {
+        let dir = TempDir::new();
+        let mut storage = FsStorage::new(&dir.0).unwrap();
+        for doc_id in ["", "../escape", "a/b", ".hidden"] {
+            let key = StorageKey::incremental(doc_id, ChangeHash([0; 32]));
+            let err = storage.put(&key, b"data").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn load_ignores_a_torn_final_write() {
+        let dir = TempDir::new();
+        let mut persister =
+            Persister::new(FsStorage::new(&dir.0).unwrap(), "doc").with_compaction_threshold(3);
+        let mut doc = persister.load().unwrap();
+        for i in 0..3 {
+            edit(&mut doc, i);
+            persister.save(&mut doc).unwrap();
+        }
+        let heads = doc.get_heads();
+
+        // Simulate a crash part way through appending the next change
+        edit(&mut doc, 3);
+        persister.save(&mut doc).unwrap();
+        let path = dir.0.join("doc.log");
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 5)
+            .unwrap();
+
+        let mut persister = Persister::new(FsStorage::new(&dir.0).unwrap(), "doc");
+        let mut loaded = persister.load().unwrap();
+        assert_eq!(loaded.get_heads(), heads);
+        assert_eq!(
+            loaded.get(ROOT, "key").unwrap().unwrap().0.to_i64(),
+            Some(2)
+        );
+
+        // The next write replaces the torn record
+        edit(&mut loaded, 4);
+        persister.save(&mut loaded).unwrap();
+        let mut reloaded = Persister::new(FsStorage::new(&dir.0).unwrap(), "doc")
+            .load()
+            .unwrap();
+        assert_eq!(reloaded.get_heads(), loaded.get_heads());
+        assert_eq!(
+            reloaded.get(ROOT, "key").unwrap().unwrap().0.to_i64(),
+            Some(4)
+        );
+    }
+}
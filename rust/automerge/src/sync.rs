@@ -66,23 +66,76 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! ## Snapshots
+//!
+//! A peer which has none of a document would ordinarily be sent every change in its history, one
+//! at a time. Instead, if both peers set [`State::snapshots`], such a peer is sent a single
+//! compressed snapshot of the document (as produced by [`Automerge::save`]) in
+//! [`Message::snapshot`] and carries on with the normal protocol from there. Snapshots are off by
+//! default. The snapshot holds the same history as the sender, so sending one never changes the
+//! history of either peer.
+//!
+//! Support is negotiated using [`Capability`]s. Every message we send advertises the capabilities
+//! we support in [`Message::supported_capabilities`] and the capabilities of the other peer are
+//! recorded in [`State::their_capabilities`]. Peers running older versions of this library don't
+//! send any capabilities (and ignore ours), so they are always sent individual changes.
+//!
+//! ## Reconciliation
+//!
+//! Each message tells the other peer which changes we already have so it can send us the rest. By
+//! default this is a [`BloomFilter`] of the changes since the last sync, whose size and false
+//! positive rate can be set with [`State::bloom_config`]. A false positive means the peer doesn't
+//! send us a change we need, which costs another round trip once we notice it's missing. With
+//! peers which support [`Capability::ActorSeqs`] the [`Reconciliation`] in the [`State`] can
+//! instead send the number of changes we have from each actor, which is exact, or pick whichever
+//! of the two is smaller for each message.
+//!
+//! ## Compacted documents
+//!
+//! A document compacted with [`Automerge::compact`] starts with a baseline change which older
+//! versions of this library would apply as ordinary operations. Such a document only sends its
+//! changes to peers which advertise [`Capability::Baselines`], which a peer does if it has a
+//! baseline itself or has opted in with [`Automerge::set_accept_baselines`]. Other peers are sent
+//! nothing, though the compacted document still receives their changes.
+//!
+//! ## Ephemeral messages
+//!
+//! State which is shared between peers but shouldn't become part of the document's history, such
+//! as each user's [`Cursor`](crate::Cursor) or whether they are online, can be sent as an
+//! [`EphemeralMessage`] over the same connection as the sync messages. The first byte of an
+//! encoded message identifies whether it is a [`Message`] or an [`EphemeralMessage`]. A
+//! [`Presence`] keeps track of the latest state of each peer and forgets peers which go quiet.
+//!
+//! ## Encryption
+//!
+//! With the `encryption` feature, peers which share a key can exchange messages encoded with
+//! [`Message::encode_encrypted`] and decoded with [`Message::decode_encrypted`], which encrypt the
+//! changes and snapshot in each message using a [`KeyProvider`]. Change hashes are computed over
+//! the plaintext so the rest of the protocol is unaffected, which also means the heads and hashes
+//! in a message are visible to anyone relaying it.
 
 use itertools::Itertools;
 use serde::ser::SerializeMap;
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 
 use crate::{
     patches::{PatchLog, TextRepresentation},
-    storage::{parse, Change as StoredChange, ReadChangeOpError},
-    Automerge, AutomergeError, Change, ChangeHash, ReadDoc,
+    storage::{self, parse, Change as StoredChange, KeyProvider, ReadChangeOpError},
+    Automerge, AutomergeError, Baseline, Change, ChangeHash, ReadDoc,
 };
 
 mod bloom;
+mod ephemeral;
+mod seqs;
 mod state;
 
-pub use bloom::{BloomFilter, DecodeError as DecodeBloomError};
+pub use bloom::{BloomConfig, BloomFilter, DecodeError as DecodeBloomError};
+pub use ephemeral::{EphemeralMessage, Payload, Presence};
+pub use seqs::ActorSeqs;
 pub use state::DecodeError as DecodeStateError;
-pub use state::{Have, State};
+pub use state::{Have, Reconciliation, State};
 
 /// A document which can take part in the sync protocol
 ///
@@ -141,11 +194,12 @@ impl SyncDoc for Automerge {
         } else {
             HashSet::new()
         };
-        let our_have = if our_need.iter().all(|hash| their_heads_set.contains(hash)) {
-            vec![self.make_bloom_filter(sync_state.shared_heads.clone())]
-        } else {
-            Vec::new()
-        };
+        let (our_have, our_actor_seqs) =
+            if our_need.iter().all(|hash| their_heads_set.contains(hash)) {
+                self.make_have(sync_state)
+            } else {
+                (Vec::new(), None)
+            };
 
         if let Some(ref their_have) = sync_state.their_have {
             if let Some(first_have) = their_have.first().as_ref() {
@@ -159,6 +213,9 @@ impl SyncDoc for Automerge {
                         need: Vec::new(),
                         have: vec![Have::default()],
                         changes: Vec::new(),
+                        supported_capabilities: Some(self.supported_capabilities(sync_state)),
+                        snapshot: None,
+                        actor_seqs: None,
                     };
                     return Some(reset_msg);
                 }
@@ -169,16 +226,28 @@ impl SyncDoc for Automerge {
             sync_state.their_have.as_ref(),
             sync_state.their_need.as_ref(),
         ) {
-            self.get_changes_to_send(their_have, their_need)
+            self.get_changes_to_send(their_have, their_need, sync_state.their_actor_seqs.as_ref())
                 .expect("Should have only used hashes that are in the document")
         } else {
             Vec::new()
         };
+        let withheld = self.withholds_changes(sync_state.their_capabilities.as_deref());
+        let changes_to_send = if withheld {
+            Vec::new()
+        } else {
+            changes_to_send
+        };
 
         let heads_unchanged = sync_state.last_sent_heads == our_heads;
 
+        // Heads of theirs which we rejected will never be ours, so they don't mean they have
+        // anything more to send us
         let heads_equal = if let Some(their_heads) = sync_state.their_heads.as_ref() {
             their_heads == &our_heads
+                || (their_heads.iter().any(|h| self.is_rejected(h))
+                    && their_heads
+                        .iter()
+                        .all(|h| self.is_rejected(h) || our_heads.contains(h)))
         } else {
             false
         };
@@ -196,7 +265,9 @@ impl SyncDoc for Automerge {
             .collect::<Vec<_>>();
 
         if heads_unchanged {
-            if heads_equal && changes_to_send.is_empty() {
+            // A peer we don't send changes to would keep asking for our heads, so we only tell it
+            // about them when they change or when we need something from it
+            if (withheld && our_need.is_empty()) || (heads_equal && changes_to_send.is_empty()) {
                 return None;
             }
             if sync_state.in_flight {
@@ -209,11 +280,31 @@ impl SyncDoc for Automerge {
             .sent_hashes
             .extend(changes_to_send.iter().map(|c| c.hash()));
 
+        // If they have nothing at all and we both asked for it then send them a snapshot of the
+        // whole document rather than every change in its history
+        let their_heads_empty = sync_state
+            .their_heads
+            .as_ref()
+            .map(|h| h.is_empty())
+            .unwrap_or(false);
+        let (changes_to_send, snapshot) = if their_heads_empty
+            && !changes_to_send.is_empty()
+            && sync_state.snapshots
+            && sync_state.supports(Capability::Snapshot)
+        {
+            (Vec::new(), Some(self.save()))
+        } else {
+            (changes_to_send, None)
+        };
+
         let sync_message = Message {
             heads: our_heads,
             have: our_have,
             need: our_need,
             changes: changes_to_send,
+            supported_capabilities: Some(self.supported_capabilities(sync_state)),
+            snapshot,
+            actor_seqs: our_actor_seqs,
         };
 
         sync_state.in_flight = true;
@@ -240,12 +331,108 @@ impl SyncDoc for Automerge {
 }
 
 impl Automerge {
-    fn make_bloom_filter(&self, last_sync: Vec<ChangeHash>) -> Have {
-        let new_changes = self.get_changes(&last_sync);
-        let hashes = new_changes.iter().map(|change| change.hash());
+    /// The capabilities we advertise to the peer of `sync_state`
+    ///
+    /// We only claim to understand baselines if we have one or may adopt one, so that a peer
+    /// which has compacted its document doesn't send us its baseline otherwise.
+    fn supported_capabilities(&self, sync_state: &State) -> Vec<Capability> {
+        let mut capabilities = vec![Capability::ActorSeqs];
+        if sync_state.snapshots {
+            capabilities.push(Capability::Snapshot);
+        }
+        if self.baseline().is_some() || self.accepts_baselines() {
+            capabilities.push(Capability::Baselines);
+        }
+        capabilities
+    }
+
+    /// Whether we keep our changes from a peer which advertised `capabilities`
+    ///
+    /// Every change in a compacted document descends from its baseline, which a peer that doesn't
+    /// understand baselines would apply as ordinary operations.
+    fn withholds_changes(&self, capabilities: Option<&[Capability]>) -> bool {
+        self.baseline().is_some()
+            && !capabilities.map_or(false, |c| c.contains(&Capability::Baselines))
+    }
+
+    /// Describe the changes we have to the peer using the reconciliation strategy of
+    /// `sync_state`
+    fn make_have(&self, sync_state: &State) -> (Vec<Have>, Option<ActorSeqs>) {
+        // The history of a compacted document doesn't have the earlier changes of each actor
+        let usable = self.baseline().is_none() && sync_state.supports(Capability::ActorSeqs);
+        let bloom_have = || {
+            // A peer we don't send changes to has none of the changes after the baseline, and a
+            // `last_sync` it doesn't have would make it ask us to start over
+            if self.withholds_changes(sync_state.their_capabilities.as_deref()) {
+                self.make_bloom_filter_since_baseline(sync_state.bloom_config)
+            } else {
+                self.make_bloom_filter(sync_state.shared_heads.clone(), sync_state.bloom_config)
+            }
+        };
+        let seqs_have = || Have {
+            last_sync: sync_state.shared_heads.clone(),
+            bloom: BloomFilter::default(),
+        };
+        match sync_state.reconciliation {
+            Reconciliation::ActorSeqs if usable => {
+                (vec![seqs_have()], Some(ActorSeqs::from_doc(self)))
+            }
+            Reconciliation::Adaptive if usable => {
+                let have = bloom_have();
+                let seqs = ActorSeqs::from_doc(self);
+                if seqs.encoded_len() <= have.bloom.to_bytes().len() {
+                    (vec![seqs_have()], Some(seqs))
+                } else {
+                    (vec![have], None)
+                }
+            }
+            _ => (vec![bloom_have()], None),
+        }
+    }
+
+    fn make_bloom_filter(&self, last_sync: Vec<ChangeHash>, config: BloomConfig) -> Have {
+        // A compacted document always has its baseline in common with the peer, and adds the
+        // history it replaced so that a peer which hasn't compacted doesn't send us that history.
+        // A peer which hasn't compacted has the originals of the changes we rebased, so they go
+        // in the filter too.
+        let (last_sync, bloom_since) = match self.baseline() {
+            Some(baseline) => {
+                let since = if last_sync.is_empty() {
+                    vec![baseline.hash()]
+                } else {
+                    last_sync
+                };
+                (baseline.expand_heads(&since), since)
+            }
+            None => (last_sync.clone(), last_sync),
+        };
+        let new_changes = self.get_changes(&bloom_since);
+        let mut hashes = new_changes
+            .iter()
+            .map(|change| change.hash())
+            .collect::<HashSet<_>>();
+        if self.baseline().is_some() {
+            let originals = self.originals_of(&hashes).collect::<Vec<_>>();
+            hashes.extend(originals);
+        }
         Have {
             last_sync,
-            bloom: BloomFilter::from_hashes(hashes),
+            bloom: BloomFilter::from_hashes_with_config(hashes.into_iter(), config),
+        }
+    }
+
+    /// A Bloom filter of every change in this compacted document and every change they replaced
+    fn make_bloom_filter_since_baseline(&self, config: BloomConfig) -> Have {
+        let mut hashes = self
+            .get_changes(&[])
+            .iter()
+            .map(|change| change.hash())
+            .collect::<HashSet<_>>();
+        let originals = self.originals_of(&hashes).collect::<Vec<_>>();
+        hashes.extend(originals);
+        Have {
+            last_sync: Vec::new(),
+            bloom: BloomFilter::from_hashes_with_config(hashes.into_iter(), config),
         }
     }
 
@@ -253,7 +440,11 @@ impl Automerge {
         &self,
         have: &[Have],
         need: &[ChangeHash],
+        actor_seqs: Option<&ActorSeqs>,
     ) -> Result<Vec<&Change>, AutomergeError> {
+        // Our history may not be described by the seqs of the peer if we have compacted it, in
+        // which case the (empty) Bloom filters it sent alongside are used
+        let actor_seqs = actor_seqs.filter(|_| self.baseline().is_none());
         if have.is_empty() {
             Ok(need
                 .iter()
@@ -283,10 +474,13 @@ impl Automerge {
                     dependents.entry(*dep).or_default().push(change.hash());
                 }
 
-                if bloom_filters
-                    .iter()
-                    .all(|bloom| !bloom.contains_hash(&change.hash()))
-                {
+                let they_have = match actor_seqs {
+                    Some(seqs) => seqs.contains(change),
+                    None => bloom_filters
+                        .iter()
+                        .any(|bloom| bloom.contains_hash(&change.hash())),
+                };
+                if !they_have {
                     hashes_to_send.insert(change.hash());
                 }
             }
@@ -326,18 +520,60 @@ impl Automerge {
         message: Message,
         patch_log: &mut PatchLog,
     ) -> Result<(), AutomergeError> {
-        let before_heads = self.get_heads();
+        let mut before_heads = self.get_heads();
+        let before_baseline = self.baseline().map(Baseline::hash);
 
         let Message {
-            heads: message_heads,
-            changes: message_changes,
-            need: message_need,
-            have: message_have,
+            heads: mut message_heads,
+            changes: mut message_changes,
+            need: mut message_need,
+            have: mut message_have,
+            supported_capabilities,
+            snapshot,
+            actor_seqs: message_actor_seqs,
         } = message;
 
-        let changes_is_empty = message_changes.is_empty();
+        // Changes rejected by the change policy are reported once the state is up to date, so
+        // that the peer doesn't send them again
+        let mut rejected = None;
+        let changes_is_empty = message_changes.is_empty() && snapshot.is_none();
         if !changes_is_empty {
-            self.apply_changes_log_patches(message_changes, patch_log)?;
+            if let Some(snapshot) = snapshot {
+                if self.is_empty() {
+                    match self.load_incremental_log_patches(&snapshot, patch_log) {
+                        Err(AutomergeError::Rejected(r)) => rejected = Some(r),
+                        other => {
+                            other?;
+                        }
+                    }
+                } else {
+                    // Apply the changes of the snapshot like any other, so that a baseline in it
+                    // is checked against our history
+                    let loaded = Automerge::load(&snapshot)?;
+                    let mut changes = loaded
+                        .get_changes(&[])
+                        .into_iter()
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    changes.extend(message_changes);
+                    message_changes = changes;
+                }
+            }
+            match self.apply_changes_log_patches(message_changes, patch_log) {
+                Err(AutomergeError::Rejected(r)) => match &mut rejected {
+                    Some(rejected) => rejected.changes.extend(r.changes),
+                    None => rejected = Some(r),
+                },
+                other => other?,
+            }
+            if self.baseline().map(Baseline::hash) != before_baseline {
+                // We adopted a baseline, which replaced changes we had in common with the peer.
+                // The peer hasn't seen our new heads yet, so `last_sent_heads` is left alone to
+                // make sure we tell it about them.
+                before_heads = self.translate_heads(&before_heads);
+                sync_state.shared_heads = self.translate_heads(&sync_state.shared_heads);
+                sync_state.sent_hashes.clear();
+            }
             sync_state.shared_heads = advance_heads(
                 &before_heads.iter().collect(),
                 &self.get_heads().into_iter().collect(),
@@ -345,6 +581,24 @@ impl Automerge {
             );
         }
 
+        // A peer which hasn't compacted to our baseline describes what it has in terms of the
+        // changes the baseline and the changes rebased onto it replaced. Treat it as not having
+        // any of those so that we send it the baseline and the rebased changes rather than asking
+        // for the old ones. A peer which has compacted to our baseline mentions the baseline
+        // alongside the history it replaced, see `make_bloom_filter`.
+        if let Some(baseline) = self.baseline() {
+            let old = |hash: &ChangeHash| self.is_replaced(hash);
+            message_heads.retain(|h| !old(h));
+            message_need.retain(|h| !old(h));
+            for have in &mut message_have {
+                if have.last_sync.contains(&baseline.hash()) {
+                    have.last_sync = self.translate_heads(&have.last_sync);
+                } else {
+                    have.last_sync.retain(|h| !old(h));
+                }
+            }
+        }
+
         // trim down the sent hashes to those that we know they haven't seen
         self.filter_changes(&message_heads, &mut sync_state.sent_hashes)?;
 
@@ -364,7 +618,9 @@ impl Automerge {
             sync_state.shared_heads = message_heads.clone();
             sync_state.in_flight = false;
             // If the remote peer has lost all its data, reset our state to perform a full resync
-            if message_heads.is_empty() {
+            if message_heads.is_empty()
+                && !self.withholds_changes(supported_capabilities.as_deref())
+            {
                 sync_state.last_sent_heads = Default::default();
                 sync_state.sent_hashes = Default::default();
             }
@@ -382,8 +638,13 @@ impl Automerge {
         sync_state.their_have = Some(message_have);
         sync_state.their_heads = Some(message_heads);
         sync_state.their_need = Some(message_need);
+        sync_state.their_capabilities = supported_capabilities;
+        sync_state.their_actor_seqs = message_actor_seqs;
 
-        Ok(())
+        match rejected {
+            Some(rejected) => Err(rejected.into()),
+            None => Ok(()),
+        }
     }
 }
 
@@ -405,6 +666,12 @@ impl From<parse::leb128::Error> for ReadMessageError {
     }
 }
 
+impl From<parse::InvalidUtf8> for ReadMessageError {
+    fn from(e: parse::InvalidUtf8) -> Self {
+        ReadMessageError::Parse(e.to_string())
+    }
+}
+
 impl From<bloom::ParseError> for ReadMessageError {
     fn from(e: bloom::ParseError) -> Self {
         ReadMessageError::Parse(e.to_string())
@@ -433,7 +700,11 @@ impl From<parse::ParseError<ReadMessageError>> for ReadMessageError {
 }
 
 /// The sync message to be sent.
-#[derive(Clone, Debug, PartialEq)]
+///
+/// Fields may be added to this struct as the protocol grows, so it is `#[non_exhaustive]`. Code
+/// outside this crate builds a message by setting the fields of [`Message::default`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
 pub struct Message {
     /// The heads of the sender.
     pub heads: Vec<ChangeHash>,
@@ -443,6 +714,60 @@ pub struct Message {
     pub have: Vec<Have>,
     /// The changes for the recipient to apply.
     pub changes: Vec<Change>,
+    /// The capabilities the sender supports, or `None` if the sender predates capabilities.
+    pub supported_capabilities: Option<Vec<Capability>>,
+    /// A snapshot of the sender's document, as produced by [`Automerge::save`], for the recipient
+    /// to load. This is only sent to peers which support [`Capability::Snapshot`].
+    pub snapshot: Option<Vec<u8>>,
+    /// The changes the sender has, which the recipient uses instead of the Bloom filters in
+    /// [`Self::have`]. This is only sent to peers which support [`Capability::ActorSeqs`].
+    pub actor_seqs: Option<ActorSeqs>,
+}
+
+/// A feature of the sync protocol which a peer may support
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize)]
+pub enum Capability {
+    /// The peer can load a [`Message::snapshot`]
+    Snapshot,
+    /// The peer can read [`Message::actor_seqs`]
+    ActorSeqs,
+    /// The peer understands the baselines of compacted documents, see [`Automerge::compact`]
+    Baselines,
+    /// A capability this version of the library doesn't know about
+    Unknown(u8),
+}
+
+const CAPABILITY_SNAPSHOT: u8 = 1;
+const CAPABILITY_ACTOR_SEQS: u8 = 2;
+const CAPABILITY_BASELINES: u8 = 3;
+
+// Fields added to the message since the original format. Each is written after the changes as
+// its tag followed by its length prefixed value, and only if it is present. Older peers stop
+// reading after the changes and newer ones skip tags they don't know.
+const FIELD_CAPABILITIES: u8 = 1;
+const FIELD_SNAPSHOT: u8 = 2;
+const FIELD_ACTOR_SEQS: u8 = 3;
+
+impl From<u8> for Capability {
+    fn from(value: u8) -> Self {
+        match value {
+            CAPABILITY_SNAPSHOT => Self::Snapshot,
+            CAPABILITY_ACTOR_SEQS => Self::ActorSeqs,
+            CAPABILITY_BASELINES => Self::Baselines,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl From<Capability> for u8 {
+    fn from(value: Capability) -> Self {
+        match value {
+            Capability::Snapshot => CAPABILITY_SNAPSHOT,
+            Capability::ActorSeqs => CAPABILITY_ACTOR_SEQS,
+            Capability::Baselines => CAPABILITY_BASELINES,
+            Capability::Unknown(other) => other,
+        }
+    }
 }
 
 impl serde::Serialize for Message {
@@ -450,7 +775,7 @@ impl serde::Serialize for Message {
     where
         S: serde::Serializer,
     {
-        let mut map = serializer.serialize_map(Some(4))?;
+        let mut map = serializer.serialize_map(Some(7))?;
         map.serialize_entry("heads", &self.heads)?;
         map.serialize_entry("need", &self.need)?;
         map.serialize_entry("have", &self.have)?;
@@ -462,6 +787,9 @@ impl serde::Serialize for Message {
                 .map(crate::ExpandedChange::from)
                 .collect::<Vec<_>>(),
         )?;
+        map.serialize_entry("supportedCapabilities", &self.supported_capabilities)?;
+        map.serialize_entry("snapshot", &self.snapshot)?;
+        map.serialize_entry("actorSeqs", &self.actor_seqs)?;
         map.end()
     }
 }
@@ -473,10 +801,36 @@ fn parse_have(input: parse::Input<'_>) -> parse::ParseResult<'_, Have, ReadMessa
     Ok((i, Have { last_sync, bloom }))
 }
 
+fn decrypt<'a>(
+    chunks: &'a [u8],
+    keys: Option<&dyn KeyProvider>,
+) -> Result<Cow<'a, [u8]>, ReadMessageError> {
+    match keys {
+        Some(keys) => storage::decrypt_chunks(Some(keys), chunks)
+            .map(Cow::Owned)
+            .map_err(|e| ReadMessageError::Parse(format!("error decrypting changes: {}", e))),
+        None => Ok(Cow::Borrowed(chunks)),
+    }
+}
+
 impl Message {
     pub fn decode(input: &[u8]) -> Result<Self, ReadMessageError> {
+        Self::decode_with(input, None)
+    }
+
+    /// Decode a message encoded with [`Self::encode_encrypted`], decrypting the changes and
+    /// snapshot in it with `keys`
+    #[cfg(feature = "encryption")]
+    pub fn decode_encrypted(
+        input: &[u8],
+        keys: &dyn KeyProvider,
+    ) -> Result<Self, ReadMessageError> {
+        Self::decode_with(input, Some(keys))
+    }
+
+    fn decode_with(input: &[u8], keys: Option<&dyn KeyProvider>) -> Result<Self, ReadMessageError> {
         let input = parse::Input::new(input);
-        match Self::parse(input) {
+        match Self::parse_with(input, keys) {
             Ok((_, msg)) => Ok(msg),
             Err(parse::ParseError::Error(e)) => Err(e),
             Err(parse::ParseError::Incomplete(_)) => Err(ReadMessageError::NotEnoughInput),
@@ -484,6 +838,13 @@ impl Message {
     }
 
     pub(crate) fn parse(input: parse::Input<'_>) -> parse::ParseResult<'_, Self, ReadMessageError> {
+        Self::parse_with(input, None)
+    }
+
+    fn parse_with<'a>(
+        input: parse::Input<'a>,
+        keys: Option<&dyn KeyProvider>,
+    ) -> parse::ParseResult<'a, Self, ReadMessageError> {
         let (i, message_type) = parse::take1(input)?;
         if message_type != MESSAGE_TYPE_SYNC {
             return Err(parse::ParseError::Error(ReadMessageError::WrongType {
@@ -498,18 +859,44 @@ impl Message {
 
         let change_parser = |i| {
             let (i, bytes) = parse::length_prefixed_bytes(i)?;
+            let bytes = decrypt(bytes, keys)?;
             let (_, change) =
-                StoredChange::parse(parse::Input::new(bytes)).map_err(|e| e.lift())?;
-            Ok((i, change))
+                StoredChange::parse(parse::Input::new(&bytes)).map_err(|e| e.lift())?;
+            Ok((i, change.into_owned()))
         };
         let (i, stored_changes) = parse::length_prefixed(change_parser)(i)?;
+
+        let mut supported_capabilities = None;
+        let mut snapshot = None;
+        let mut actor_seqs = None;
+        let mut i = i;
+        while !i.is_empty() {
+            let (rest, tag) = parse::take1(i)?;
+            let (rest, field) = parse::length_prefixed_bytes(rest)?;
+            match tag {
+                FIELD_CAPABILITIES => {
+                    let (_, capabilities) =
+                        parse::length_prefixed(parse::take1)(parse::Input::new(field))?;
+                    supported_capabilities =
+                        Some(capabilities.into_iter().map(Capability::from).collect());
+                }
+                FIELD_SNAPSHOT => snapshot = Some(decrypt(field, keys)?.into_owned()),
+                FIELD_ACTOR_SEQS => {
+                    let (_, seqs) = ActorSeqs::parse(parse::Input::new(field))?;
+                    actor_seqs = Some(seqs);
+                }
+                // A field added by a newer version of the library
+                _ => {}
+            }
+            i = rest;
+        }
         let changes_len = stored_changes.len();
         let changes: Vec<Change> = stored_changes
             .into_iter()
             .try_fold::<_, _, Result<_, ReadMessageError>>(
                 Vec::with_capacity(changes_len),
                 |mut acc, stored| {
-                    let change = Change::new_from_unverified(stored.into_owned(), None)
+                    let change = Change::new_from_unverified(stored, None)
                         .map_err(ReadMessageError::ReadChangeOps)?;
                     acc.push(change);
                     Ok(acc)
@@ -523,11 +910,33 @@ impl Message {
                 need,
                 have,
                 changes,
+                supported_capabilities,
+                snapshot,
+                actor_seqs,
             },
         ))
     }
 
-    pub fn encode(mut self) -> Vec<u8> {
+    pub fn encode(self) -> Vec<u8> {
+        self.encode_with(|chunks| Cow::Borrowed(chunks))
+    }
+
+    /// Encode this message with the changes and snapshot in it encrypted with the current key of
+    /// `keys`, for peers which share the key and decode it with [`Self::decode_encrypted`]
+    ///
+    /// The heads and hashes in the message are not encrypted. As with encrypted documents the hash
+    /// of each change is computed over its plaintext so the sync protocol works as usual.
+    #[cfg(feature = "encryption")]
+    pub fn encode_encrypted(self, keys: &dyn KeyProvider) -> Vec<u8> {
+        self.encode_with(|chunks| Cow::Owned(storage::encrypt_chunks(keys, chunks)))
+    }
+
+    /// Encode this message, passing the bytes of each change and of the snapshot through
+    /// `encode_chunks`
+    fn encode_with<F>(mut self, encode_chunks: F) -> Vec<u8>
+    where
+        F: Fn(&[u8]) -> Cow<'_, [u8]>,
+    {
         let mut buf = vec![MESSAGE_TYPE_SYNC];
 
         encode_hashes(&mut buf, &self.heads);
@@ -539,10 +948,27 @@ impl Message {
         });
 
         encode_many(&mut buf, self.changes.iter_mut(), |buf, change| {
-            leb128::write::unsigned(buf, change.raw_bytes().len() as u64).unwrap();
-            buf.extend::<&[u8]>(change.raw_bytes().as_ref())
+            let bytes = encode_chunks(change.raw_bytes());
+            leb128::write::unsigned(buf, bytes.len() as u64).unwrap();
+            buf.extend::<&[u8]>(bytes.as_ref())
         });
 
+        if let Some(capabilities) = self.supported_capabilities {
+            let mut field = Vec::new();
+            encode_many(&mut field, capabilities.into_iter(), |buf, capability| {
+                buf.push(capability.into())
+            });
+            encode_field(&mut buf, FIELD_CAPABILITIES, &field);
+        }
+        if let Some(snapshot) = self.snapshot {
+            encode_field(&mut buf, FIELD_SNAPSHOT, &encode_chunks(&snapshot));
+        }
+        if let Some(actor_seqs) = self.actor_seqs {
+            let mut field = Vec::new();
+            actor_seqs.encode(&mut field);
+            encode_field(&mut buf, FIELD_ACTOR_SEQS, &field);
+        }
+
         buf
     }
 }
@@ -558,6 +984,12 @@ where
     }
 }
 
+fn encode_field(buf: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    buf.push(tag);
+    leb128::write::unsigned(buf, value.len() as u64).unwrap();
+    buf.extend(value);
+}
+
 fn encode_hashes(buf: &mut Vec<u8>, hashes: &[ChangeHash]) {
     debug_assert!(
         hashes.windows(2).all(|h| h[0] <= h[1]),
@@ -636,11 +1068,41 @@ mod tests {
                 need,
                 have,
                 changes,
+                ..Default::default()
             }
         }
 
     }
 
+    fn gen_actor_seqs() -> impl Strategy<Value = ActorSeqs> {
+        proptest::collection::vec(
+            (
+                proptest::array::uniform32(any::<u8>()).prop_map(ActorId::from),
+                1..100_u64,
+            ),
+            0..3,
+        )
+        .prop_map(|seqs| seqs.into_iter().collect())
+    }
+
+    prop_compose! {
+        fn gen_extended_sync_message()(
+            msg in gen_sync_message(),
+            supported_capabilities in proptest::option::of(
+                proptest::collection::vec(any::<u8>().prop_map(Capability::from), 0..3)
+            ),
+            snapshot in proptest::option::of(proptest::collection::vec(any::<u8>(), 0..10)),
+            actor_seqs in proptest::option::of(gen_actor_seqs()),
+        ) -> Message {
+            Message {
+                supported_capabilities,
+                snapshot,
+                actor_seqs,
+                ..msg
+            }
+        }
+    }
+
     #[test]
     fn encode_decode_empty_message() {
         let msg = Message {
@@ -648,6 +1110,7 @@ mod tests {
             need: vec![],
             have: vec![],
             changes: vec![],
+            ..Default::default()
         };
         let encoded = msg.encode();
         Message::parse(Input::new(&encoded)).unwrap();
@@ -661,6 +1124,14 @@ mod tests {
             assert!(i.is_empty());
             assert_eq!(msg, decoded);
         }
+
+        #[test]
+        fn encode_decode_extended_message(msg in gen_extended_sync_message()) {
+            let encoded = msg.clone().encode();
+            let (i, decoded) = Message::parse(Input::new(&encoded)).unwrap();
+            assert!(i.is_empty());
+            assert_eq!(msg, decoded);
+        }
     }
 
     #[test]
@@ -903,6 +1374,118 @@ mod tests {
         assert_eq!(doc2.get_heads(), all_heads);
     }
 
+    #[test]
+    fn bloom_config_sets_false_positive_rate() {
+        assert_eq!(
+            BloomConfig::with_false_positive_rate(0.01),
+            BloomConfig::default()
+        );
+        let config = BloomConfig::with_false_positive_rate(0.0001);
+        let hash = |i: u32| {
+            use sha2::{Digest, Sha256};
+            ChangeHash(Sha256::digest(i.to_le_bytes()).into())
+        };
+        let default = BloomFilter::from_hashes((0..1000).map(hash));
+        let precise = BloomFilter::from_hashes_with_config((0..1000).map(hash), config);
+        assert!(precise.to_bytes().len() > default.to_bytes().len());
+        assert!((0..1000).map(hash).all(|h| precise.contains_hash(&h)));
+
+        let false_positives = |bloom: &BloomFilter| {
+            (1000..21000)
+                .filter(|i| bloom.contains_hash(&hash(*i)))
+                .count()
+        };
+        assert!(false_positives(&default) > 100);
+        assert!(false_positives(&precise) < 20);
+    }
+
+    #[test]
+    fn actor_seqs_are_not_fooled_by_false_positives() {
+        let mut doc1 = crate::AutoCommit::new();
+        doc1.put(crate::ROOT, "x", 0).unwrap();
+        let mut doc2 = doc1.fork();
+        doc1.put(crate::ROOT, "x", 1).unwrap();
+        doc1.commit();
+        let last_sync = doc2.get_heads();
+        let bloom = BloomFilter::from_hashes(doc1.get_heads().into_iter());
+
+        // Find a change which is a false positive in the filter and build on it
+        let mut i = 0;
+        let mut doc2 = loop {
+            let mut doc = doc2.fork();
+            doc.put(crate::ROOT, "y", i).unwrap();
+            doc.commit();
+            if bloom.contains_hash(&doc.get_heads()[0]) {
+                break doc;
+            }
+            i += 1;
+        };
+        doc2.put(crate::ROOT, "y", "final").unwrap();
+        doc2.commit();
+
+        let have = [Have { last_sync, bloom }];
+        let seqs = ActorSeqs::from_doc(doc1.document());
+        let doc2 = doc2.document();
+        assert_eq!(doc2.get_changes_to_send(&have, &[], None).unwrap().len(), 1);
+        assert_eq!(
+            doc2.get_changes_to_send(&have, &[], Some(&seqs))
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn reconciliation_is_negotiated() {
+        let mut doc1 = crate::AutoCommit::new();
+        for i in 0..100 {
+            doc1.put(crate::ROOT, "x", i).unwrap();
+            doc1.commit();
+        }
+        let mut doc2 = crate::AutoCommit::new();
+        let mut s1 = State::new();
+        let mut s2 = State::new().with_reconciliation(Reconciliation::ActorSeqs);
+
+        // Nothing is known about the peer's capabilities in the first message
+        let msg = doc1.sync().generate_sync_message(&mut s1).unwrap();
+        assert!(msg.actor_seqs.is_none());
+        doc2.sync().receive_sync_message(&mut s2, msg).unwrap();
+        let msg = doc2.sync().generate_sync_message(&mut s2).unwrap();
+        assert!(msg.actor_seqs.is_some());
+        let msg = Message::decode(&msg.encode()).unwrap();
+        doc1.sync().receive_sync_message(&mut s1, msg).unwrap();
+        sync(&mut doc1, &mut doc2, &mut s1, &mut s2);
+        assert_eq!(doc1.get_heads(), doc2.get_heads());
+
+        // One actor with lots of new changes is described more compactly by its seq
+        let adaptive = s2.clone().with_reconciliation(Reconciliation::Adaptive);
+        for i in 0..100 {
+            doc2.put(crate::ROOT, "x", i).unwrap();
+            doc2.commit();
+        }
+        let (have, seqs) = doc2.document().make_have(&adaptive);
+        assert!(seqs.is_some());
+        assert_eq!(have[0].bloom, BloomFilter::default());
+
+        // Lots of actors with few new changes are described more compactly by a Bloom filter
+        for _ in 0..20 {
+            let mut other = doc2.fork();
+            other.put(crate::ROOT, "y", 1).unwrap();
+            doc2.merge(&mut other).unwrap();
+        }
+        sync(&mut doc1, &mut doc2, &mut s1, &mut s2);
+        doc2.put(crate::ROOT, "z", 1).unwrap();
+        doc2.commit();
+        let adaptive = s2.clone().with_reconciliation(Reconciliation::Adaptive);
+        let (have, seqs) = doc2.document().make_have(&adaptive);
+        assert!(seqs.is_none());
+        assert_ne!(have[0].bloom, BloomFilter::default());
+
+        // Peers which don't support actor seqs are always sent a Bloom filter
+        s2.their_capabilities = Some(vec![Capability::Snapshot]);
+        assert!(doc2.document().make_have(&s2).1.is_none());
+    }
+
     #[test]
     fn should_handle_lots_of_branching_and_merging() {
         let mut doc1 = crate::AutoCommit::new().with_actor(ActorId::try_from("01234567").unwrap());
@@ -948,6 +1531,181 @@ mod tests {
         assert_eq!(doc1.get_heads(), doc2.get_heads());
     }
 
+    /// Run the sync protocol between `a` and `b`, starting both from `state` and passing every
+    /// message from `b` through `b_to_a` before `a` receives it, and return the messages `a` sent
+    fn sync_with(
+        a: &mut crate::AutoCommit,
+        b: &mut crate::AutoCommit,
+        state: State,
+        b_to_a: impl Fn(Message) -> Message,
+    ) -> Vec<Message> {
+        let (mut a_state, mut b_state) = (state.clone(), state);
+        let mut sent = Vec::new();
+        loop {
+            let a_msg = a.sync().generate_sync_message(&mut a_state);
+            if let Some(msg) = a_msg.clone() {
+                sent.push(msg.clone());
+                b.sync().receive_sync_message(&mut b_state, msg).unwrap();
+            }
+            let b_msg = b.sync().generate_sync_message(&mut b_state);
+            if let Some(msg) = b_msg.clone() {
+                a.sync()
+                    .receive_sync_message(&mut a_state, b_to_a(msg))
+                    .unwrap();
+            }
+            if a_msg.is_none() && b_msg.is_none() {
+                return sent;
+            }
+        }
+    }
+
+    #[test]
+    fn new_peers_are_sent_a_snapshot() {
+        let mut doc1 = crate::AutoCommit::new();
+        for i in 0..20 {
+            doc1.put(crate::ROOT, "key", i).unwrap();
+            doc1.commit();
+        }
+        let mut doc2 = crate::AutoCommit::new();
+        let state = State::new().with_snapshots(true);
+
+        let sent = sync_with(&mut doc1, &mut doc2, state.clone(), |msg| msg);
+        assert!(sent.iter().all(|msg| msg.changes.is_empty()));
+        assert_eq!(sent.iter().filter(|msg| msg.snapshot.is_some()).count(), 1);
+        assert_eq!(doc2.get_heads(), doc1.get_heads());
+        assert_eq!(doc2.get(crate::ROOT, "key").unwrap().unwrap().0, 19.into());
+        // Neither peer's history changes
+        assert_eq!(doc1.get_changes(&[]).len(), 20);
+        assert_eq!(doc2.get_changes(&[]).len(), 20);
+
+        // Once they share a history, peers exchange individual changes again
+        doc1.put(crate::ROOT, "key", 20).unwrap();
+        let sent = sync_with(&mut doc1, &mut doc2, state, |msg| msg);
+        assert!(sent.iter().all(|msg| msg.snapshot.is_none()));
+        assert_eq!(sent.iter().map(|msg| msg.changes.len()).sum::<usize>(), 1);
+        assert_eq!(doc2.get_heads(), doc1.get_heads());
+        assert_eq!(doc2.get(crate::ROOT, "key").unwrap().unwrap().0, 20.into());
+    }
+
+    #[test]
+    fn snapshots_are_off_by_default() {
+        let mut doc1 = crate::AutoCommit::new();
+        for i in 0..20 {
+            doc1.put(crate::ROOT, "key", i).unwrap();
+            doc1.commit();
+        }
+        let mut doc2 = crate::AutoCommit::new();
+
+        let sent = sync_with(&mut doc1, &mut doc2, State::new(), |msg| msg);
+        assert!(sent.iter().all(|msg| msg.snapshot.is_none()));
+        assert_eq!(sent.iter().map(|msg| msg.changes.len()).sum::<usize>(), 20);
+
+        // Both peers have to ask for snapshots
+        let mut doc3 = crate::AutoCommit::new();
+        let (mut s1, mut s3) = (State::new().with_snapshots(true), State::new());
+        let msg = doc3.sync().generate_sync_message(&mut s3).unwrap();
+        doc1.sync().receive_sync_message(&mut s1, msg).unwrap();
+        let msg = doc1.sync().generate_sync_message(&mut s1).unwrap();
+        assert!(msg.snapshot.is_none());
+    }
+
+    #[test]
+    fn peers_which_do_not_support_snapshots_are_sent_changes() {
+        let mut doc1 = crate::AutoCommit::new();
+        for i in 0..20 {
+            doc1.put(crate::ROOT, "key", i).unwrap();
+            doc1.commit();
+        }
+        let mut doc2 = crate::AutoCommit::new();
+
+        // An older peer doesn't send any capabilities
+        let sent = sync_with(
+            &mut doc1,
+            &mut doc2,
+            State::new().with_snapshots(true),
+            |msg| {
+                let decoded = Message::decode(&msg.encode()).unwrap();
+                Message {
+                    supported_capabilities: None,
+                    ..decoded
+                }
+            },
+        );
+        assert!(sent.iter().all(|msg| msg.snapshot.is_none()));
+        assert_eq!(sent.iter().map(|msg| msg.changes.len()).sum::<usize>(), 20);
+        assert_eq!(doc2.get_heads(), doc1.get_heads());
+    }
+
+    #[test]
+    fn messages_without_capabilities_are_decoded() {
+        let mut doc = crate::AutoCommit::new();
+        doc.put(crate::ROOT, "key", "value").unwrap();
+        let mut state = State::new().with_snapshots(true);
+        let msg = doc.sync().generate_sync_message(&mut state).unwrap();
+        assert_eq!(
+            msg.supported_capabilities,
+            Some(vec![Capability::ActorSeqs, Capability::Snapshot])
+        );
+
+        // This is the encoding an older peer produces
+        let old = Message {
+            supported_capabilities: None,
+            ..msg.clone()
+        }
+        .encode();
+        assert!(msg.clone().encode().starts_with(&old));
+        let decoded = Message::decode(&old).unwrap();
+        assert_eq!(decoded.supported_capabilities, None);
+        assert_eq!(decoded.heads, msg.heads);
+    }
+
+    #[test]
+    fn optional_fields_are_decoded_independently() {
+        // A snapshot without capabilities was ambiguous when the fields were written in order
+        let msg = Message {
+            snapshot: Some(vec![1, 2, 3]),
+            ..Default::default()
+        };
+        assert_eq!(Message::decode(&msg.clone().encode()).unwrap(), msg);
+
+        // Fields added by newer versions of the library are skipped
+        let mut encoded = Message {
+            supported_capabilities: Some(vec![Capability::Snapshot]),
+            ..Default::default()
+        }
+        .encode();
+        encoded.extend([200, 2, 7, 7]);
+        let decoded = Message::decode(&encoded).unwrap();
+        assert_eq!(
+            decoded.supported_capabilities,
+            Some(vec![Capability::Snapshot])
+        );
+        assert_eq!(decoded.snapshot, None);
+    }
+
+    #[test]
+    fn serialize_includes_every_field() {
+        let mut doc = crate::AutoCommit::new();
+        doc.put(crate::ROOT, "key", "value").unwrap();
+        doc.commit();
+        let msg = Message {
+            snapshot: Some(vec![1, 2]),
+            actor_seqs: Some(ActorSeqs::from_doc(&doc.doc)),
+            ..doc.sync().generate_sync_message(&mut State::new()).unwrap()
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(
+            json["supportedCapabilities"],
+            serde_json::json!(["ActorSeqs"])
+        );
+        assert_eq!(json["snapshot"], serde_json::json!([1, 2]));
+        assert_eq!(
+            json["actorSeqs"][doc.get_actor().to_hex_string()],
+            serde_json::json!(1)
+        );
+        assert_eq!(json.as_object().unwrap().len(), 7);
+    }
+
     fn sync(
         a: &mut crate::AutoCommit,
         b: &mut crate::AutoCommit,
@@ -9,6 +9,55 @@ use crate::ChangeHash;
 const BITS_PER_ENTRY: u32 = 10;
 const NUM_PROBES: u32 = 7;
 
+/// The parameters used to build the Bloom filters we send to a peer
+///
+/// The default of 10 bits per entry and 7 probes gives about 1% false positives. More bits per
+/// entry make false positives, which cost an extra round trip to resolve, rarer at the cost of
+/// larger messages. The parameters are sent along with each filter so peers don't need to agree
+/// on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BloomConfig {
+    bits_per_entry: u32,
+    num_probes: u32,
+}
+
+impl Default for BloomConfig {
+    fn default() -> Self {
+        BloomConfig {
+            bits_per_entry: BITS_PER_ENTRY,
+            num_probes: NUM_PROBES,
+        }
+    }
+}
+
+impl BloomConfig {
+    /// A config with the given number of bits per entry and probes, each at least 1
+    pub fn new(bits_per_entry: u32, num_probes: u32) -> Self {
+        BloomConfig {
+            bits_per_entry: bits_per_entry.max(1),
+            num_probes: num_probes.max(1),
+        }
+    }
+
+    /// The smallest config with a false positive rate of at most `rate`, which is clamped to
+    /// between one in a million and one half
+    pub fn with_false_positive_rate(rate: f64) -> Self {
+        let rate = rate.clamp(1e-6, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits_per_entry = (-rate.ln() / (ln2 * ln2)).ceil();
+        let num_probes = (bits_per_entry * ln2).round();
+        Self::new(bits_per_entry as u32, num_probes as u32)
+    }
+
+    pub fn bits_per_entry(&self) -> u32 {
+        self.bits_per_entry
+    }
+
+    pub fn num_probes(&self) -> u32 {
+        self.num_probes
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
 pub struct BloomFilter {
     num_entries: u32,
@@ -108,7 +157,7 @@ impl BloomFilter {
     }
 
     pub fn contains_hash(&self, hash: &ChangeHash) -> bool {
-        if self.num_entries == 0 {
+        if self.num_entries == 0 || self.bits.is_empty() {
             false
         } else {
             for probe in self.get_probes(hash) {
@@ -123,9 +172,17 @@ impl BloomFilter {
     }
 
     pub fn from_hashes<H: Borrow<ChangeHash>>(hashes: impl ExactSizeIterator<Item = H>) -> Self {
+        Self::from_hashes_with_config(hashes, BloomConfig::default())
+    }
+
+    /// Build a filter containing `hashes` with the parameters in `config`
+    pub fn from_hashes_with_config<H: Borrow<ChangeHash>>(
+        hashes: impl ExactSizeIterator<Item = H>,
+        config: BloomConfig,
+    ) -> Self {
         let num_entries = hashes.len() as u32;
-        let num_bits_per_entry = BITS_PER_ENTRY;
-        let num_probes = NUM_PROBES;
+        let num_bits_per_entry = config.bits_per_entry;
+        let num_probes = config.num_probes;
         let bits = vec![0; bits_capacity(num_entries, num_bits_per_entry)];
         let mut filter = Self {
             num_entries,
@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::ReadMessageError;
+use crate::storage::parse;
+use crate::{ActorId, ScalarValue};
+
+const MESSAGE_TYPE_EPHEMERAL: u8 = 0x44; // first byte of an ephemeral message, for identification
+
+// The same type codes as are used for values in the columnar encoding
+const TYPE_NULL: u8 = 0;
+const TYPE_FALSE: u8 = 1;
+const TYPE_TRUE: u8 = 2;
+const TYPE_UINT: u8 = 3;
+const TYPE_INT: u8 = 4;
+const TYPE_F64: u8 = 5;
+const TYPE_STR: u8 = 6;
+const TYPE_BYTES: u8 = 7;
+const TYPE_COUNTER: u8 = 8;
+const TYPE_TIMESTAMP: u8 = 9;
+const TYPE_UNKNOWN: u8 = 15;
+
+const PAYLOAD_BYTES: u8 = 0;
+const PAYLOAD_MAP: u8 = 1;
+
+/// A message which is sent alongside the sync protocol but is not part of the document history,
+/// such as the cursor position of a user
+///
+/// Ephemeral messages are not acknowledged or retried, a peer simply sends a new one whenever its
+/// state changes. Each sender numbers its messages so that a receiver can discard any which
+/// arrive after a newer one, see [`Presence`]. The numbering starts again at 1 in every session,
+/// i.e. every time the sender creates a new [`Presence`], so a sender which restarts with the same
+/// actor isn't mistaken for a stale one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EphemeralMessage {
+    /// The actor which sent the message
+    pub sender: ActorId,
+    /// A random ID of the session of `sender` which sent the message
+    pub session: u64,
+    /// The position of this message amongst the messages sent by `sender` in `session`, starting
+    /// at 1
+    pub seq: u64,
+    pub payload: Payload,
+}
+
+/// The contents of an [`EphemeralMessage`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Payload {
+    /// Bytes in whatever format the application likes
+    Bytes(Vec<u8>),
+    /// Scalar values by key. A [`crate::Cursor`] can be sent as
+    /// [`ScalarValue::Str`] using its `Display` implementation.
+    Map(BTreeMap<String, ScalarValue>),
+}
+
+impl EphemeralMessage {
+    pub fn decode(input: &[u8]) -> Result<Self, ReadMessageError> {
+        let input = parse::Input::new(input);
+        match Self::parse(input) {
+            Ok((_, msg)) => Ok(msg),
+            Err(parse::ParseError::Error(e)) => Err(e),
+            Err(parse::ParseError::Incomplete(_)) => Err(ReadMessageError::NotEnoughInput),
+        }
+    }
+
+    pub(crate) fn parse(input: parse::Input<'_>) -> parse::ParseResult<'_, Self, ReadMessageError> {
+        let (i, message_type) = parse::take1(input)?;
+        if message_type != MESSAGE_TYPE_EPHEMERAL {
+            return Err(parse::ParseError::Error(ReadMessageError::WrongType {
+                expected_one_of: vec![MESSAGE_TYPE_EPHEMERAL],
+                found: message_type,
+            }));
+        }
+        let (i, sender) = parse::actor_id(i)?;
+        let (i, session) = parse::leb128_u64(i)?;
+        let (i, seq) = parse::leb128_u64(i)?;
+        let (i, payload_type) = parse::take1(i)?;
+        let (i, payload) = match payload_type {
+            PAYLOAD_BYTES => {
+                let (i, bytes) = parse::length_prefixed_bytes(i)?;
+                (i, Payload::Bytes(bytes.to_vec()))
+            }
+            PAYLOAD_MAP => {
+                let (i, entries) = parse::length_prefixed(parse_entry)(i)?;
+                (i, Payload::Map(entries.into_iter().collect()))
+            }
+            other => {
+                return Err(parse::ParseError::Error(ReadMessageError::Parse(format!(
+                    "unknown payload type {}",
+                    other
+                ))))
+            }
+        };
+        Ok((
+            i,
+            EphemeralMessage {
+                sender,
+                session,
+                seq,
+                payload,
+            },
+        ))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![MESSAGE_TYPE_EPHEMERAL];
+        let actor = self.sender.to_bytes();
+        leb128::write::unsigned(&mut buf, actor.len() as u64).unwrap();
+        buf.extend(actor);
+        leb128::write::unsigned(&mut buf, self.session).unwrap();
+        leb128::write::unsigned(&mut buf, self.seq).unwrap();
+        match &self.payload {
+            Payload::Bytes(bytes) => {
+                buf.push(PAYLOAD_BYTES);
+                encode_bytes(&mut buf, bytes);
+            }
+            Payload::Map(entries) => {
+                buf.push(PAYLOAD_MAP);
+                leb128::write::unsigned(&mut buf, entries.len() as u64).unwrap();
+                for (key, value) in entries {
+                    encode_bytes(&mut buf, key.as_bytes());
+                    encode_scalar(&mut buf, value);
+                }
+            }
+        }
+        buf
+    }
+}
+
+fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    leb128::write::unsigned(buf, bytes.len() as u64).unwrap();
+    buf.extend(bytes);
+}
+
+fn encode_scalar(buf: &mut Vec<u8>, value: &ScalarValue) {
+    match value {
+        ScalarValue::Null => buf.push(TYPE_NULL),
+        ScalarValue::Boolean(false) => buf.push(TYPE_FALSE),
+        ScalarValue::Boolean(true) => buf.push(TYPE_TRUE),
+        ScalarValue::Uint(n) => {
+            buf.push(TYPE_UINT);
+            leb128::write::unsigned(buf, *n).unwrap();
+        }
+        ScalarValue::Int(n) => {
+            buf.push(TYPE_INT);
+            leb128::write::signed(buf, *n).unwrap();
+        }
+        ScalarValue::F64(n) => {
+            buf.push(TYPE_F64);
+            buf.extend(n.to_le_bytes());
+        }
+        ScalarValue::Str(s) => {
+            buf.push(TYPE_STR);
+            encode_bytes(buf, s.as_bytes());
+        }
+        ScalarValue::Bytes(bytes) => {
+            buf.push(TYPE_BYTES);
+            encode_bytes(buf, bytes);
+        }
+        ScalarValue::Counter(c) => {
+            buf.push(TYPE_COUNTER);
+            leb128::write::signed(buf, c.current).unwrap();
+        }
+        ScalarValue::Timestamp(n) => {
+            buf.push(TYPE_TIMESTAMP);
+            leb128::write::signed(buf, *n).unwrap();
+        }
+        ScalarValue::Unknown { type_code, bytes } => {
+            buf.push(TYPE_UNKNOWN);
+            buf.push(*type_code);
+            encode_bytes(buf, bytes);
+        }
+    }
+}
+
+fn parse_entry(
+    input: parse::Input<'_>,
+) -> parse::ParseResult<'_, (String, ScalarValue), ReadMessageError> {
+    let (i, len) = parse::leb128_u64(input)?;
+    let (i, key) = parse::utf_8(len as usize, i)?;
+    let (i, value) = parse_scalar(i)?;
+    Ok((i, (key, value)))
+}
+
+fn parse_scalar(input: parse::Input<'_>) -> parse::ParseResult<'_, ScalarValue, ReadMessageError> {
+    let (i, type_code) = parse::take1(input)?;
+    match type_code {
+        TYPE_NULL => Ok((i, ScalarValue::Null)),
+        TYPE_FALSE => Ok((i, ScalarValue::Boolean(false))),
+        TYPE_TRUE => Ok((i, ScalarValue::Boolean(true))),
+        TYPE_UINT => {
+            let (i, n) = parse::leb128_u64(i)?;
+            Ok((i, ScalarValue::Uint(n)))
+        }
+        TYPE_INT => {
+            let (i, n) = parse::leb128_i64(i)?;
+            Ok((i, ScalarValue::Int(n)))
+        }
+        TYPE_F64 => {
+            let (i, bytes) = parse::take_n(8, i)?;
+            let bytes: [u8; 8] = bytes.try_into().expect("we took 8 bytes");
+            Ok((i, ScalarValue::F64(f64::from_le_bytes(bytes))))
+        }
+        TYPE_STR => {
+            let (i, len) = parse::leb128_u64(i)?;
+            let (i, s) = parse::utf_8(len as usize, i)?;
+            Ok((i, ScalarValue::Str(s.into())))
+        }
+        TYPE_BYTES => {
+            let (i, bytes) = parse::length_prefixed_bytes(i)?;
+            Ok((i, ScalarValue::Bytes(bytes.to_vec())))
+        }
+        TYPE_COUNTER => {
+            let (i, n) = parse::leb128_i64(i)?;
+            Ok((i, ScalarValue::counter(n)))
+        }
+        TYPE_TIMESTAMP => {
+            let (i, n) = parse::leb128_i64(i)?;
+            Ok((i, ScalarValue::Timestamp(n)))
+        }
+        TYPE_UNKNOWN => {
+            let (i, type_code) = parse::take1(i)?;
+            let (i, bytes) = parse::length_prefixed_bytes(i)?;
+            Ok((
+                i,
+                ScalarValue::Unknown {
+                    type_code,
+                    bytes: bytes.to_vec(),
+                },
+            ))
+        }
+        other => Err(parse::ParseError::Error(ReadMessageError::Parse(format!(
+            "unknown value type {}",
+            other
+        )))),
+    }
+}
+
+/// The latest [`Payload`] from each peer we have heard from recently
+///
+/// `Presence` numbers the messages we send with [`Self::message`] and, on receipt, keeps only the
+/// newest message from each peer. Each `Presence` is a new session, so a peer which restarts and
+/// starts numbering its messages from 1 again replaces whatever we had from its previous session.
+/// The previous session is then retired, so that a message from it which arrives late doesn't
+/// replace the new one. A peer which hasn't sent anything for longer than the timeout is
+/// forgotten the next time [`Self::expire`] is called, so peers should resend their state at an
+/// interval shorter than the timeout even when it hasn't changed.
+///
+/// Times are milliseconds since any fixed point, such as the UNIX epoch used for change
+/// timestamps. They are passed in rather than read from a clock so that this works the same on
+/// every platform.
+///
+/// ```
+/// use automerge::{sync::{EphemeralMessage, Payload, Presence}, ActorId, ScalarValue};
+///
+/// let mut alice = Presence::new(ActorId::random(), 30_000);
+/// let mut bob = Presence::new(ActorId::random(), 30_000);
+///
+/// let state = [("cursor".to_string(), ScalarValue::from("3@aabbcc"))].into_iter().collect();
+/// let bytes = alice.message(Payload::Map(state)).encode();
+/// assert!(bob.receive(EphemeralMessage::decode(&bytes).unwrap(), 1_000));
+/// assert_eq!(bob.peers().count(), 1);
+///
+/// assert!(bob.expire(60_000).len() == 1);
+/// assert_eq!(bob.peers().count(), 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Presence {
+    actor: ActorId,
+    session: u64,
+    seq: u64,
+    timeout: i64,
+    peers: HashMap<ActorId, Peer>,
+    /// The sessions which have been replaced by a newer session of the same actor, and when. Any
+    /// message from one of them which is still in flight will arrive within the timeout.
+    retired: HashMap<(ActorId, u64), i64>,
+}
+
+#[derive(Clone, Debug)]
+struct Peer {
+    session: u64,
+    seq: u64,
+    payload: Payload,
+    last_seen: i64,
+}
+
+impl Presence {
+    /// Track the presence of peers, forgetting those we haven't heard from for `timeout`
+    /// milliseconds. `actor` is used as the sender of our messages.
+    pub fn new(actor: ActorId, timeout: i64) -> Self {
+        Presence {
+            actor,
+            session: uuid::Uuid::new_v4().as_u64_pair().0,
+            seq: 0,
+            timeout,
+            peers: HashMap::new(),
+            retired: HashMap::new(),
+        }
+    }
+
+    /// The next message to send to every peer
+    pub fn message(&mut self, payload: Payload) -> EphemeralMessage {
+        self.seq += 1;
+        EphemeralMessage {
+            sender: self.actor.clone(),
+            session: self.session,
+            seq: self.seq,
+            payload,
+        }
+    }
+
+    /// Record a message received at `now`, returning `false` if it was ignored because it was
+    /// our own, we have already seen a newer message from the same session of its sender, or its
+    /// sender has since started a new session
+    pub fn receive(&mut self, message: EphemeralMessage, now: i64) -> bool {
+        if message.sender == self.actor
+            || self
+                .retired
+                .contains_key(&(message.sender.clone(), message.session))
+        {
+            return false;
+        }
+        if let Some(peer) = self.peers.get(&message.sender) {
+            if peer.session == message.session {
+                if peer.seq >= message.seq {
+                    return false;
+                }
+            } else {
+                self.retired
+                    .insert((message.sender.clone(), peer.session), now);
+            }
+        }
+        self.peers.insert(
+            message.sender,
+            Peer {
+                session: message.session,
+                seq: message.seq,
+                payload: message.payload,
+                last_seen: now,
+            },
+        );
+        true
+    }
+
+    /// Forget peers we haven't heard from within the timeout of `now`, returning their actors
+    pub fn expire(&mut self, now: i64) -> Vec<ActorId> {
+        let timeout = self.timeout;
+        let expired = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| now - peer.last_seen > timeout)
+            .map(|(actor, _)| actor.clone())
+            .collect::<Vec<_>>();
+        for actor in &expired {
+            self.peers.remove(actor);
+        }
+        self.retired
+            .retain(|_, retired_at| now - *retired_at <= timeout);
+        expired
+    }
+
+    /// Forget a peer immediately, e.g. because it disconnected
+    pub fn remove(&mut self, actor: &ActorId) -> Option<Payload> {
+        self.peers.remove(actor).map(|peer| peer.payload)
+    }
+
+    /// The latest payload from `actor`
+    pub fn get(&self, actor: &ActorId) -> Option<&Payload> {
+        self.peers.get(actor).map(|peer| &peer.payload)
+    }
+
+    /// The latest payload from every peer we know of
+    pub fn peers(&self) -> impl Iterator<Item = (&ActorId, &Payload)> {
+        self.peers
+            .iter()
+            .map(|(actor, peer)| (actor, &peer.payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let payload = Payload::Map(
+            [
+                ("null", ScalarValue::Null),
+                ("flag", ScalarValue::Boolean(true)),
+                ("uint", ScalarValue::Uint(u64::MAX)),
+                ("int", ScalarValue::Int(-5)),
+                ("f64", ScalarValue::F64(1.5)),
+                ("str", ScalarValue::from("héllo")),
+                ("bytes", ScalarValue::Bytes(vec![1, 2, 3])),
+                ("counter", ScalarValue::counter(7)),
+                ("time", ScalarValue::Timestamp(1_700_000_000_000)),
+                (
+                    "unknown",
+                    ScalarValue::Unknown {
+                        type_code: 12,
+                        bytes: vec![9],
+                    },
+                ),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+        );
+        for payload in [payload, Payload::Bytes(b"raw".to_vec())] {
+            let msg = EphemeralMessage {
+                sender: ActorId::random(),
+                session: 12345,
+                seq: 300,
+                payload,
+            };
+            assert_eq!(EphemeralMessage::decode(&msg.encode()).unwrap(), msg);
+        }
+        assert!(matches!(
+            EphemeralMessage::decode(&[0x42]),
+            Err(ReadMessageError::WrongType { found: 0x42, .. })
+        ));
+        let encoded = Presence::new(ActorId::random(), 1)
+            .message(Payload::Bytes(vec![1]))
+            .encode();
+        assert!(matches!(
+            EphemeralMessage::decode(&encoded[..encoded.len() - 1]),
+            Err(ReadMessageError::NotEnoughInput)
+        ));
+    }
+
+    #[test]
+    fn presence_keeps_newest_and_expires() {
+        let mut alice = Presence::new(ActorId::random(), 100);
+        let mut bob = Presence::new(ActorId::random(), 100);
+        let first = alice.message(Payload::Bytes(vec![1]));
+        let second = alice.message(Payload::Bytes(vec![2]));
+
+        assert!(bob.receive(second, 0));
+        assert!(!bob.receive(first, 10));
+        assert_eq!(bob.get(&alice.actor), Some(&Payload::Bytes(vec![2])));
+        let own = alice.message(Payload::Bytes(vec![3]));
+        assert!(!alice.receive(own, 0));
+
+        assert!(bob.expire(100).is_empty());
+        assert!(bob.receive(alice.message(Payload::Bytes(vec![4])), 50));
+        assert!(bob.expire(120).is_empty());
+        assert_eq!(bob.expire(151), vec![alice.actor.clone()]);
+        assert_eq!(bob.get(&alice.actor), None);
+    }
+
+    #[test]
+    fn presence_accepts_a_restarted_peer() {
+        let actor = ActorId::random();
+        let mut alice = Presence::new(actor.clone(), 100);
+        let mut bob = Presence::new(ActorId::random(), 100);
+        for i in 0..3 {
+            assert!(bob.receive(alice.message(Payload::Bytes(vec![i])), 0));
+        }
+
+        // Alice restarts with the same actor and numbers her messages from 1 again
+        let mut alice = Presence::new(actor.clone(), 100);
+        let restarted = alice.message(Payload::Bytes(vec![10]));
+        assert_eq!(restarted.seq, 1);
+        assert!(bob.receive(restarted, 10));
+        assert_eq!(bob.get(&actor), Some(&Payload::Bytes(vec![10])));
+    }
+
+    #[test]
+    fn presence_ignores_late_messages_from_a_previous_session() {
+        let actor = ActorId::random();
+        let mut old = Presence::new(actor.clone(), 100);
+        let mut bob = Presence::new(ActorId::random(), 100);
+        assert!(bob.receive(old.message(Payload::Bytes(vec![1])), 0));
+        let late = old.message(Payload::Bytes(vec![2]));
+
+        let mut new = Presence::new(actor.clone(), 100);
+        assert!(bob.receive(new.message(Payload::Bytes(vec![10])), 10));
+        assert!(!bob.receive(late.clone(), 20));
+        assert_eq!(bob.get(&actor), Some(&Payload::Bytes(vec![10])));
+
+        // Retired sessions are forgotten once anything they sent would have timed out
+        assert!(bob.expire(110).is_empty());
+        assert!(!bob.receive(late, 110));
+        assert_eq!(bob.expire(111), vec![actor]);
+        assert!(bob.retired.is_empty());
+    }
+}
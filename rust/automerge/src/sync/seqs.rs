@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use crate::storage::parse;
+use crate::{ActorId, Automerge, Change};
+
+/// The number of changes by each actor which the sender of a message has
+///
+/// Every change in a document is preceded by all the earlier changes of the same actor, so this
+/// describes exactly which changes the sender has, without the false positives of a
+/// [`super::BloomFilter`]. Its size grows with the number of actors who ever edited the document
+/// rather than with the number of changes since the last sync. See [`super::Reconciliation`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize)]
+pub struct ActorSeqs(BTreeMap<ActorId, u64>);
+
+impl ActorSeqs {
+    pub(crate) fn from_doc(doc: &Automerge) -> Self {
+        ActorSeqs(
+            doc.actor_seqs()
+                .map(|(actor, seq)| (actor.clone(), seq))
+                .collect(),
+        )
+    }
+
+    /// The number of changes by `actor` the sender has
+    pub fn seq(&self, actor: &ActorId) -> u64 {
+        self.0.get(actor).copied().unwrap_or(0)
+    }
+
+    /// Whether the sender has `change`
+    pub fn contains(&self, change: &Change) -> bool {
+        change.seq() <= self.seq(change.actor_id())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ActorId, u64)> {
+        self.0.iter().map(|(actor, seq)| (actor, *seq))
+    }
+
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        leb128::write::unsigned(buf, self.0.len() as u64).unwrap();
+        for (actor, seq) in &self.0 {
+            leb128::write::unsigned(buf, actor.to_bytes().len() as u64).unwrap();
+            buf.extend(actor.to_bytes());
+            leb128::write::unsigned(buf, *seq).unwrap();
+        }
+    }
+
+    /// The number of bytes [`Self::encode`] writes
+    pub(crate) fn encoded_len(&self) -> usize {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf.len()
+    }
+
+    pub(crate) fn parse<E>(input: parse::Input<'_>) -> parse::ParseResult<'_, Self, E>
+    where
+        E: From<parse::leb128::Error>,
+    {
+        let entry = |i| {
+            let (i, actor) = parse::actor_id(i)?;
+            let (i, seq) = parse::leb128_u64(i)?;
+            Ok((i, (actor, seq)))
+        };
+        let (i, entries) = parse::length_prefixed(entry)(input)?;
+        Ok((i, ActorSeqs(entries.into_iter().collect())))
+    }
+}
+
+impl FromIterator<(ActorId, u64)> for ActorSeqs {
+    fn from_iter<I: IntoIterator<Item = (ActorId, u64)>>(iter: I) -> Self {
+        ActorSeqs(iter.into_iter().collect())
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::BTreeSet;
 
-use super::{encode_hashes, BloomFilter};
+use super::{encode_hashes, ActorSeqs, BloomConfig, BloomFilter, Capability};
 use crate::storage::parse;
 use crate::ChangeHash;
 
@@ -27,7 +27,11 @@ impl From<parse::leb128::Error> for DecodeError {
 /// This should be persisted using [`Self::encode`] when you know you will be interacting with the
 /// same peer in multiple sessions. [`Self::encode`] only encodes state which should be reused
 /// across connections.
+///
+/// Fields may be added to this struct as the protocol grows, so it is `#[non_exhaustive]`. Code
+/// outside this crate builds a state with [`Self::new`] and the `with_*` methods.
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub struct State {
     /// The hashes which we know both peers have
     pub shared_heads: Vec<ChangeHash>,
@@ -50,6 +54,52 @@ pub struct State {
     /// there are in fact changes to send). If it is `true` then we don't. This flag is cleared
     /// in `receive_sync_message`.
     pub in_flight: bool,
+
+    /// The capabilities the peer advertised in the last message we received from it, or `None`
+    /// if it hasn't advertised any. This is not persisted by [`Self::encode`] as the peer may be
+    /// running a different version of the library next time we connect.
+    pub their_capabilities: Option<Vec<Capability>>,
+
+    /// The changes the peer said it has in the last message we received from it, if it sent
+    /// [`ActorSeqs`] rather than only a Bloom filter
+    pub their_actor_seqs: Option<ActorSeqs>,
+
+    /// The parameters of the Bloom filters we send to the peer. Like [`Self::reconciliation`]
+    /// this is local configuration which is not persisted by [`Self::encode`].
+    pub bloom_config: BloomConfig,
+
+    /// How we tell the peer which changes we have
+    pub reconciliation: Reconciliation,
+
+    /// Whether to send the peer a snapshot of the whole document if it has none of it, and to
+    /// ask for one if we have none of it. A snapshot is only exchanged if both peers set this.
+    /// Like [`Self::bloom_config`] this is local configuration which is not persisted by
+    /// [`Self::encode`].
+    pub snapshots: bool,
+}
+
+/// How a peer tells the other peer which changes it already has
+///
+/// The other peer sends it everything else. Strategies other than [`Self::BloomFilter`] are only
+/// used with peers which support [`Capability::ActorSeqs`] and documents which have not been
+/// compacted, otherwise a Bloom filter is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reconciliation {
+    /// A [`BloomFilter`] of the changes since the last sync, built with [`State::bloom_config`].
+    /// The filter is small but a false positive means the peer doesn't send a change we need,
+    /// which takes another round trip to resolve.
+    BloomFilter,
+    /// The number of changes by each actor, see [`ActorSeqs`]. This is exact but grows with the
+    /// number of actors who ever edited the document.
+    ActorSeqs,
+    /// Whichever of the above encodes to fewer bytes, preferring [`Self::ActorSeqs`] on a tie
+    Adaptive,
+}
+
+impl Default for Reconciliation {
+    fn default() -> Self {
+        Reconciliation::BloomFilter
+    }
 }
 
 /// A summary of the changes that the sender of the message already has.
@@ -69,6 +119,32 @@ impl State {
         Default::default()
     }
 
+    /// Use `config` for the Bloom filters we send to the peer
+    pub fn with_bloom_config(mut self, config: BloomConfig) -> Self {
+        self.bloom_config = config;
+        self
+    }
+
+    /// Tell the peer which changes we have using `reconciliation`
+    pub fn with_reconciliation(mut self, reconciliation: Reconciliation) -> Self {
+        self.reconciliation = reconciliation;
+        self
+    }
+
+    /// Exchange snapshots with the peer if `snapshots` is true, see [`Self::snapshots`]
+    pub fn with_snapshots(mut self, snapshots: bool) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    /// Whether the peer has told us it supports `capability`
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.their_capabilities
+            .as_ref()
+            .map(|c| c.contains(&capability))
+            .unwrap_or(false)
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut buf = vec![SYNC_STATE_TYPE];
         encode_hashes(&mut buf, &self.shared_heads);
@@ -104,6 +180,11 @@ impl State {
                 their_have: Some(Vec::new()),
                 sent_hashes: BTreeSet::new(),
                 in_flight: false,
+                their_capabilities: None,
+                their_actor_seqs: None,
+                bloom_config: BloomConfig::default(),
+                reconciliation: Reconciliation::default(),
+                snapshots: false,
             },
         ))
     }
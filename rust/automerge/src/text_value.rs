@@ -1,133 +1,272 @@
 use core::fmt::Debug;
 
-use crate::sequence_tree::SequenceTree;
+use crate::sequence_tree::{self, SequenceTree};
 
-#[cfg(not(any(target_family = "wasm", feature = "utf8-indexing")))]
-#[derive(Clone, PartialEq, Default)]
-pub struct TextValue(SequenceTree<char>);
-
-#[cfg(target_family = "wasm")]
-#[derive(Clone, PartialEq, Default)]
-pub struct TextValue(SequenceTree<u16>);
-
-#[cfg(feature = "utf8-indexing")]
-#[derive(Clone, PartialEq, Default)]
-pub struct TextValue(SequenceTree<u8>);
+/// The unit in which indices into text objects are measured
+///
+/// Every method which takes or returns an index into a text object (e.g.
+/// [`crate::transaction::Transactable::splice_text`], [`crate::ReadDoc::length`],
+/// [`crate::ReadDoc::get_cursor_position`], [`crate::ReadDoc::marks`] and the indices in
+/// [`crate::PatchAction::SpliceText`] and [`crate::PatchAction::DeleteSeq`]) interprets the index
+/// in the text encoding of the document, which can be changed at any time with
+/// [`crate::Automerge::set_text_encoding`]. The encoding only affects how indices are counted,
+/// the stored document is the same whichever encoding is used.
+///
+/// The default is [`Self::Utf16CodeUnit`] on wasm targets, [`Self::Utf8CodeUnit`] when the
+/// `utf8-indexing` feature is enabled and [`Self::UnicodeCodePoint`] otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextEncoding {
+    /// Indices count unicode code points, i.e. rust `char`s
+    UnicodeCodePoint,
+    /// Indices count bytes of the UTF-8 encoding of the text
+    Utf8CodeUnit,
+    /// Indices count 16 bit code units of the UTF-16 encoding of the text, as javascript does
+    Utf16CodeUnit,
+}
 
-#[cfg(not(any(target_family = "wasm", feature = "utf8-indexing")))]
-impl TextValue {
-    pub(crate) fn new(s: &str) -> Self {
-        let mut v = SequenceTree::new();
-        for ch in s.chars() {
-            v.push(ch)
+impl Default for TextEncoding {
+    fn default() -> Self {
+        if cfg!(feature = "utf8-indexing") {
+            TextEncoding::Utf8CodeUnit
+        } else if cfg!(target_family = "wasm") {
+            TextEncoding::Utf16CodeUnit
+        } else {
+            TextEncoding::UnicodeCodePoint
         }
-        Self(v)
     }
+}
 
-    pub(crate) fn splice(&mut self, index: usize, value: &str) {
-        for (n, ch) in value.chars().enumerate() {
-            self.0.insert(index + n, ch)
+impl TextEncoding {
+    /// The length of `s` in this encoding
+    pub fn width(&self, s: &str) -> usize {
+        match self {
+            TextEncoding::UnicodeCodePoint => s.chars().count(),
+            TextEncoding::Utf8CodeUnit => s.len(),
+            TextEncoding::Utf16CodeUnit => s.encode_utf16().count(),
         }
     }
 
-    pub(crate) fn splice_text_value(&mut self, index: usize, value: &TextValue) {
-        for (n, ch) in value.chars().enumerate() {
-            self.0.insert(index + n, ch)
+    fn char_width(&self, c: char) -> usize {
+        match self {
+            TextEncoding::UnicodeCodePoint => 1,
+            TextEncoding::Utf8CodeUnit => c.len_utf8(),
+            TextEncoding::Utf16CodeUnit => c.len_utf16(),
         }
     }
 
-    pub fn make_string(&self) -> String {
-        self.0.iter().collect()
+    /// Convert `index`, an index into `s` in this encoding, to an index into `s` in the `to`
+    /// encoding
+    ///
+    /// An index which falls inside a character is moved back to the start of that character and
+    /// an index past the end of `s` is clamped to the length of `s`.
+    ///
+    /// ```
+    /// # use automerge::TextEncoding;
+    /// let s = "a🐻b";
+    /// assert_eq!(TextEncoding::UnicodeCodePoint.convert_index(s, 2, TextEncoding::Utf8CodeUnit), 5);
+    /// assert_eq!(TextEncoding::UnicodeCodePoint.convert_index(s, 2, TextEncoding::Utf16CodeUnit), 3);
+    /// assert_eq!(TextEncoding::Utf16CodeUnit.convert_index(s, 3, TextEncoding::UnicodeCodePoint), 2);
+    /// ```
+    pub fn convert_index(&self, s: &str, index: usize, to: TextEncoding) -> usize {
+        let mut from_pos = 0;
+        let mut to_pos = 0;
+        for c in s.chars() {
+            let width = self.char_width(c);
+            if from_pos + width > index {
+                break;
+            }
+            from_pos += width;
+            to_pos += to.char_width(c);
+        }
+        to_pos
     }
 
-    pub(crate) fn width(s: &str) -> usize {
-        s.chars().count()
+    /// The byte offset in `s` of `index`, an index in this encoding
+    ///
+    /// As with [`Self::convert_index`] an index inside a character is moved back to the start of
+    /// that character, so the result is always a char boundary of `s`.
+    pub fn byte_offset(&self, s: &str, index: usize) -> usize {
+        self.convert_index(s, index, TextEncoding::Utf8CodeUnit)
     }
+}
 
-    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
-        self.0.iter().cloned()
-    }
+/// The text in a [`crate::PatchAction::SpliceText`] or a hydrated text object, stored so that it
+/// can be indexed in the [`TextEncoding`] of the document it came from
+#[derive(Clone, PartialEq)]
+pub struct TextValue(Units);
+
+#[derive(Clone, PartialEq)]
+enum Units {
+    CodePoints(SequenceTree<char>),
+    Utf8(SequenceTree<u8>),
+    Utf16(SequenceTree<u16>),
 }
 
-#[cfg(target_family = "wasm")]
 impl TextValue {
-    pub(crate) fn new(s: &str) -> Self {
-        let mut v = SequenceTree::new();
-        for ch in s.encode_utf16() {
-            v.push(ch)
+    pub(crate) fn new(s: &str, encoding: TextEncoding) -> Self {
+        let mut v = TextValue(match encoding {
+            TextEncoding::UnicodeCodePoint => Units::CodePoints(SequenceTree::new()),
+            TextEncoding::Utf8CodeUnit => Units::Utf8(SequenceTree::new()),
+            TextEncoding::Utf16CodeUnit => Units::Utf16(SequenceTree::new()),
+        });
+        v.splice(0, s);
+        v
+    }
+
+    /// The encoding in which indices into this text are measured
+    pub fn encoding(&self) -> TextEncoding {
+        match &self.0 {
+            Units::CodePoints(_) => TextEncoding::UnicodeCodePoint,
+            Units::Utf8(_) => TextEncoding::Utf8CodeUnit,
+            Units::Utf16(_) => TextEncoding::Utf16CodeUnit,
         }
-        Self(v)
     }
 
     pub(crate) fn splice(&mut self, index: usize, value: &str) {
-        for (n, ch) in value.encode_utf16().enumerate() {
-            self.0.insert(index + n, ch)
+        match &mut self.0 {
+            Units::CodePoints(t) => {
+                for (n, ch) in value.chars().enumerate() {
+                    t.insert(index + n, ch)
+                }
+            }
+            Units::Utf8(t) => {
+                for (n, ch) in value.bytes().enumerate() {
+                    t.insert(index + n, ch)
+                }
+            }
+            Units::Utf16(t) => {
+                for (n, ch) in value.encode_utf16().enumerate() {
+                    t.insert(index + n, ch)
+                }
+            }
         }
     }
 
+    /// Splice `value` in at `index`, which is measured in the encoding of `value`. If this text
+    /// uses a different encoding it is re-encoded to the encoding of `value` first.
     pub(crate) fn splice_text_value(&mut self, index: usize, value: &TextValue) {
-        for (n, ch) in value.chars().enumerate() {
-            self.0.insert(index + n, ch)
+        if self.encoding() != value.encoding() {
+            *self = TextValue::new(&self.make_string(), value.encoding());
+        }
+        match (&mut self.0, &value.0) {
+            (Units::CodePoints(t), Units::CodePoints(v)) => {
+                for (n, ch) in v.iter().enumerate() {
+                    t.insert(index + n, *ch)
+                }
+            }
+            (Units::Utf8(t), Units::Utf8(v)) => {
+                for (n, ch) in v.iter().enumerate() {
+                    t.insert(index + n, *ch)
+                }
+            }
+            (Units::Utf16(t), Units::Utf16(v)) => {
+                for (n, ch) in v.iter().enumerate() {
+                    t.insert(index + n, *ch)
+                }
+            }
+            _ => unreachable!("encodings were made equal above"),
         }
     }
 
     pub fn make_string(&self) -> String {
-        let bytes: Vec<_> = self.0.iter().cloned().collect();
-        String::from_utf16_lossy(bytes.as_slice())
-    }
-
-    pub(crate) fn width(s: &str) -> usize {
-        s.encode_utf16().count()
-    }
-
-    pub(crate) fn chars(&self) -> impl Iterator<Item = u16> + '_ {
-        self.0.iter().cloned()
-    }
-}
-
-#[cfg(feature = "utf8-indexing")]
-impl TextValue {
-    pub(crate) fn new(s: &str) -> Self {
-        let mut v = SequenceTree::new();
-        for ch in s.bytes() {
-            v.push(ch)
+        match &self.0 {
+            Units::CodePoints(t) => t.iter().collect(),
+            Units::Utf8(t) => {
+                let bytes: Vec<_> = t.iter().cloned().collect();
+                String::from_utf8_lossy(bytes.as_slice()).to_string()
+            }
+            Units::Utf16(t) => {
+                let units: Vec<_> = t.iter().cloned().collect();
+                String::from_utf16_lossy(units.as_slice())
+            }
         }
-        Self(v)
     }
 
-    pub(crate) fn splice(&mut self, index: usize, value: &str) {
-        for (n, ch) in value.bytes().enumerate() {
-            self.0.insert(index + n, ch)
+    /// Iterate over the characters of this text
+    ///
+    /// Whatever the [`TextEncoding`] of the text this yields `char`s, decoding the stored code
+    /// units as it goes and replacing invalid sequences with [`char::REPLACEMENT_CHARACTER`]. Note
+    /// that with the `utf8-indexing` feature or on wasm this used to yield the raw `u8` or `u16`
+    /// code units instead.
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        match &self.0 {
+            Units::CodePoints(t) => Chars::CodePoints(t.iter()),
+            Units::Utf8(t) => Chars::Utf8(t.iter().copied().peekable()),
+            Units::Utf16(t) => Chars::Utf16(char::decode_utf16(t.iter().copied())),
         }
     }
 
-    pub(crate) fn splice_text_value(&mut self, index: usize, value: &TextValue) {
-        for (n, ch) in value.chars().enumerate() {
-            self.0.insert(index + n, ch)
+    /// The length of this text in its [`TextEncoding`]
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Units::CodePoints(t) => t.len(),
+            Units::Utf8(t) => t.len(),
+            Units::Utf16(t) => t.len(),
         }
     }
 
-    pub fn make_string(&self) -> String {
-        let bytes: Vec<_> = self.0.iter().cloned().collect();
-        String::from_utf8_lossy(bytes.as_slice()).to_string()
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub(crate) fn width(s: &str) -> usize {
-        s.len()
+    pub fn remove(&mut self, index: usize) {
+        match &mut self.0 {
+            Units::CodePoints(t) => {
+                t.remove(index);
+            }
+            Units::Utf8(t) => {
+                t.remove(index);
+            }
+            Units::Utf16(t) => {
+                t.remove(index);
+            }
+        }
     }
+}
 
-    pub(crate) fn chars(&self) -> impl Iterator<Item = u8> + '_ {
-        self.0.iter().cloned()
-    }
+enum Chars<'a> {
+    CodePoints(sequence_tree::Iter<'a, char>),
+    Utf8(std::iter::Peekable<std::iter::Copied<sequence_tree::Iter<'a, u8>>>),
+    Utf16(std::char::DecodeUtf16<std::iter::Copied<sequence_tree::Iter<'a, u16>>>),
 }
 
-impl TextValue {
-    pub fn len(&self) -> usize {
-        self.0.len()
+impl<'a> Iterator for Chars<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self {
+            Chars::CodePoints(chars) => chars.next().copied(),
+            Chars::Utf8(bytes) => {
+                let first = bytes.next()?;
+                let width = match first {
+                    0x00..=0x7f => return Some(char::from(first)),
+                    0xc0..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf7 => 4,
+                    _ => return Some(char::REPLACEMENT_CHARACTER),
+                };
+                let mut buf = [first, 0, 0, 0];
+                for b in buf.iter_mut().take(width).skip(1) {
+                    match bytes.next_if(|b| b & 0xc0 == 0x80) {
+                        Some(next) => *b = next,
+                        None => return Some(char::REPLACEMENT_CHARACTER),
+                    }
+                }
+                Some(
+                    std::str::from_utf8(&buf[..width])
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or(char::REPLACEMENT_CHARACTER),
+                )
+            }
+            Chars::Utf16(units) => Some(units.next()?.unwrap_or(char::REPLACEMENT_CHARACTER)),
+        }
     }
+}
 
-    pub fn remove(&mut self, index: usize) {
-        self.0.remove(index);
+impl Default for TextValue {
+    fn default() -> Self {
+        TextValue::new("", TextEncoding::default())
     }
 }
 
@@ -141,13 +280,13 @@ impl Debug for TextValue {
 
 impl From<&str> for TextValue {
     fn from(s: &str) -> Self {
-        TextValue::new(s)
+        TextValue::new(s, TextEncoding::default())
     }
 }
 
 impl From<String> for TextValue {
     fn from(s: String) -> Self {
-        TextValue::new(&s)
+        TextValue::new(&s, TextEncoding::default())
     }
 }
 
@@ -156,3 +295,158 @@ impl From<&TextValue> for String {
         s.make_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{TextEncoding, TextValue};
+    use crate::marks::{ExpandMark, Mark};
+    use crate::transaction::Transactable;
+    use crate::{AutoCommit, ObjType, PatchAction, ReadDoc, ROOT};
+
+    const ALL: [TextEncoding; 3] = [
+        TextEncoding::UnicodeCodePoint,
+        TextEncoding::Utf8CodeUnit,
+        TextEncoding::Utf16CodeUnit,
+    ];
+
+    #[test]
+    fn indices_are_measured_in_the_document_encoding() {
+        // 'a' is one unit in every encoding, '🐻' is 1 code point, 4 utf8 bytes and 2 utf16
+        // code units
+        let mut doc = AutoCommit::new().with_text_encoding(TextEncoding::UnicodeCodePoint);
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "a🐻b").unwrap();
+        let cursor = doc.get_cursor(&text, 2, None).unwrap();
+
+        for (encoding, bear_end) in ALL.into_iter().zip([2, 5, 3]) {
+            doc.set_text_encoding(encoding);
+            assert_eq!(doc.text_encoding(), encoding);
+            assert_eq!(doc.length(&text), bear_end + 1);
+            assert_eq!(
+                doc.get_cursor_position(&text, &cursor, None).unwrap(),
+                bear_end
+            );
+        }
+
+        doc.set_text_encoding(TextEncoding::Utf16CodeUnit);
+        doc.splice_text(&text, 3, 1, "c").unwrap();
+        doc.splice_text(&text, 1, 2, "").unwrap();
+        assert_eq!(doc.text(&text).unwrap(), "ac");
+
+        doc.set_text_encoding(TextEncoding::Utf8CodeUnit);
+        doc.splice_text(&text, 1, 0, "🐻").unwrap();
+        assert_eq!(doc.text(&text).unwrap(), "a🐻c");
+        doc.splice_text(&text, 5, 1, "").unwrap();
+        assert_eq!(doc.text(&text).unwrap(), "a🐻");
+    }
+
+    #[test]
+    fn mark_ranges_use_the_document_encoding() {
+        let mut doc = AutoCommit::new().with_text_encoding(TextEncoding::Utf8CodeUnit);
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "🐻🐻🐻").unwrap();
+        doc.mark(
+            &text,
+            Mark::new("bold".to_string(), true, 4, 8),
+            ExpandMark::None,
+        )
+        .unwrap();
+
+        let marks = doc.marks(&text).unwrap();
+        assert_eq!((marks[0].start, marks[0].end), (4, 8));
+
+        doc.set_text_encoding(TextEncoding::Utf16CodeUnit);
+        let marks = doc.marks(&text).unwrap();
+        assert_eq!((marks[0].start, marks[0].end), (2, 4));
+
+        doc.set_text_encoding(TextEncoding::UnicodeCodePoint);
+        let marks = doc.marks(&text).unwrap();
+        assert_eq!((marks[0].start, marks[0].end), (1, 2));
+    }
+
+    #[test]
+    fn patches_use_the_document_encoding() {
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, "🐻🐻").unwrap();
+        doc.update_diff_cursor();
+
+        let mut other = doc.fork().with_text_encoding(TextEncoding::Utf16CodeUnit);
+        other.update_diff_cursor();
+        other.splice_text(&text, 4, 0, "!").unwrap();
+        other.splice_text(&text, 0, 2, "").unwrap();
+
+        for (encoding, index, length) in [
+            (TextEncoding::UnicodeCodePoint, 2, 1),
+            (TextEncoding::Utf8CodeUnit, 8, 4),
+            (TextEncoding::Utf16CodeUnit, 4, 2),
+        ] {
+            let mut doc = doc.fork().with_text_encoding(encoding);
+            doc.update_diff_cursor();
+            doc.merge(&mut other).unwrap();
+            let patches = doc.diff_incremental();
+            let actions = patches.into_iter().map(|p| p.action).collect::<Vec<_>>();
+            match &actions[..] {
+                [PatchAction::SpliceText {
+                    index: splice_index,
+                    value,
+                    ..
+                }, PatchAction::DeleteSeq {
+                    index: 0,
+                    length: deleted,
+                }] => {
+                    assert_eq!(*splice_index, index);
+                    assert_eq!(value.encoding(), encoding);
+                    assert_eq!(value.make_string(), "!");
+                    assert_eq!(*deleted, length);
+                }
+                other => panic!("unexpected patches: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn text_value_chars_decode_every_encoding() {
+        let s = "a🐻ü\u{10ffff}b";
+        for encoding in ALL {
+            let mut text = TextValue::new(s, encoding);
+            assert_eq!(text.chars().collect::<String>(), s);
+            // Removing a unit in the middle of a character leaves an invalid sequence
+            if encoding != TextEncoding::UnicodeCodePoint {
+                text.remove(2);
+                assert_eq!(text.chars().collect::<String>(), text.make_string());
+            }
+        }
+    }
+
+    #[test]
+    fn convert_text_indices() {
+        let s = "a🐻b";
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(from.convert_index(s, 0, to), 0);
+                assert_eq!(from.convert_index(s, from.width(s), to), to.width(s));
+                assert_eq!(from.convert_index(s, 100, to), to.width(s));
+            }
+        }
+        // an index inside the bear moves back to its start
+        assert_eq!(
+            TextEncoding::Utf8CodeUnit.convert_index(s, 3, TextEncoding::Utf16CodeUnit),
+            1
+        );
+        assert_eq!(TextEncoding::Utf16CodeUnit.byte_offset(s, 3), 5);
+
+        let mut doc = AutoCommit::new();
+        let text = doc.put_object(ROOT, "text", ObjType::Text).unwrap();
+        doc.splice_text(&text, 0, 0, s).unwrap();
+        assert_eq!(
+            doc.convert_text_index(
+                &text,
+                2,
+                TextEncoding::UnicodeCodePoint,
+                TextEncoding::Utf8CodeUnit
+            ),
+            Ok(5)
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::num::NonZeroU64;
 use std::sync::Arc;
 
@@ -5,10 +6,12 @@ use crate::exid::ExId;
 use crate::marks::{ExpandMark, Mark, MarkSet};
 use crate::patches::{PatchLog, TextRepresentation};
 use crate::query::{self, OpIdSearch};
+use crate::schema;
 use crate::storage::Change as StoredChange;
+use crate::text_value::TextEncoding;
 use crate::types::{Clock, Key, ListEncoding, ObjId, OpId, OpIds};
 use crate::{op_tree::OpSetMetadata, types::Op, Automerge, Change, ChangeHash, Prop};
-use crate::{AutomergeError, ObjType, OpType, ScalarValue};
+use crate::{AutomergeError, ObjType, OpType, ScalarValue, Value};
 
 #[derive(Debug, Clone)]
 pub(crate) struct TransactionInner {
@@ -106,6 +109,8 @@ impl TransactionInner {
 
         let num_ops = self.pending_ops();
         let change = self.export(&doc.ops().m);
+        #[cfg(feature = "signing")]
+        let change = doc.sign(change);
         let hash = change.hash();
         #[cfg(not(debug_assertions))]
         tracing::trace!(commit=?hash, deps=?change.deps(), "committing transaction");
@@ -114,7 +119,11 @@ impl TransactionInner {
             let ops = change.iter_ops().collect::<Vec<_>>();
             tracing::trace!(commit=?hash, ?ops, deps=?change.deps(), "committing transaction");
         }
+        let before = doc.observing().then(|| doc.get_heads());
         doc.update_history(change, num_ops);
+        if let Some(before) = before {
+            doc.notify_observers(&before);
+        }
         //debug_assert_eq!(doc.get_heads(), vec![hash]);
         hash
     }
@@ -208,11 +217,12 @@ impl TransactionInner {
         let value = value.into();
         let prop = prop.into();
         match (&prop, obj.typ) {
-            (Prop::Map(_), ObjType::Map) => Ok(()),
+            (Prop::Map(_), ObjType::Map | ObjType::Table) => Ok(()),
             (Prop::Seq(_), ObjType::List) => Ok(()),
             (Prop::Seq(_), ObjType::Text) => Ok(()),
             _ => Err(AutomergeError::InvalidOp(obj.typ)),
         }?;
+        schema::check_put(doc, ex_obj, &prop, &Value::Scalar(Cow::Borrowed(&value)))?;
         self.local_op(doc, patch_log, obj.id, prop, value.into())?;
         Ok(())
     }
@@ -241,10 +251,11 @@ impl TransactionInner {
         let obj = doc.exid_to_obj(ex_obj)?;
         let prop = prop.into();
         match (&prop, obj.typ) {
-            (Prop::Map(_), ObjType::Map) => Ok(()),
+            (Prop::Map(_), ObjType::Map | ObjType::Table) => Ok(()),
             (Prop::Seq(_), ObjType::List) => Ok(()),
             _ => Err(AutomergeError::InvalidOp(obj.typ)),
         }?;
+        schema::check_put(doc, ex_obj, &prop, &Value::Object(value))?;
         let id = self
             .local_op(doc, patch_log, obj.id, prop, value.into())?
             .unwrap();
@@ -311,6 +322,12 @@ impl TransactionInner {
             return Err(AutomergeError::InvalidOp(obj.typ));
         }
         let value = value.into();
+        schema::check_put(
+            doc,
+            ex_obj,
+            &Prop::Seq(index),
+            &Value::Scalar(Cow::Borrowed(&value)),
+        )?;
         tracing::trace!(obj=?obj, value=?value, "inserting value");
         self.do_insert(
             doc,
@@ -335,6 +352,7 @@ impl TransactionInner {
         if !matches!(obj.typ, ObjType::List | ObjType::Text) {
             return Err(AutomergeError::InvalidOp(obj.typ));
         }
+        schema::check_put(doc, ex_obj, &Prop::Seq(index), &Value::Object(value))?;
         let id = self.do_insert(
             doc,
             patch_log,
@@ -535,6 +553,7 @@ impl TransactionInner {
                 },
             )?;
         } else {
+            schema::check_delete(doc, ex_obj, &prop)?;
             self.local_op(doc, patch_log, obj.id, prop, OpType::Delete)?;
         }
         Ok(())
@@ -555,7 +574,15 @@ impl TransactionInner {
         if !matches!(obj.typ, ObjType::List | ObjType::Text) {
             return Err(AutomergeError::InvalidOp(obj.typ));
         }
-        let values = vals.into_iter().collect();
+        let values: Vec<ScalarValue> = vals.into_iter().collect();
+        for (i, value) in values.iter().enumerate() {
+            schema::check_put(
+                doc,
+                ex_obj,
+                &Prop::Seq(index + i),
+                &Value::Scalar(Cow::Borrowed(value)),
+            )?;
+        }
         self.inner_splice(
             doc,
             patch_log,
@@ -583,6 +610,7 @@ impl TransactionInner {
         if obj.typ != ObjType::Text {
             return Err(AutomergeError::InvalidOp(obj.typ));
         }
+        schema::check_splice_text(doc, ex_obj)?;
         let values = text.chars().map(ScalarValue::from).collect();
         self.inner_splice(
             doc,
@@ -619,7 +647,7 @@ impl TransactionInner {
         }
 
         //let ex_obj = doc.ops().id_to_exid(obj.0);
-        let encoding = splice_type.encoding();
+        let encoding = splice_type.encoding(doc.text_encoding());
         // delete `del` items - performing the query for each one
         let mut deleted: usize = 0;
         while deleted < (del as usize) {
@@ -820,10 +848,10 @@ enum SpliceType<'a> {
 }
 
 impl<'a> SpliceType<'a> {
-    fn encoding(&self) -> ListEncoding {
+    fn encoding(&self, text_encoding: TextEncoding) -> ListEncoding {
         match self {
             SpliceType::List => ListEncoding::List,
-            SpliceType::Text(_) => ListEncoding::Text,
+            SpliceType::Text(_) => ListEncoding::Text(text_encoding),
         }
     }
 }
@@ -1,5 +1,6 @@
 use crate::exid::ExId;
 use crate::marks::{ExpandMark, Mark};
+use crate::path::{self, Path};
 use crate::{AutomergeError, ChangeHash, ObjType, Prop, ReadDoc, ScalarValue};
 
 /// A way of mutating a document within a single change.
@@ -112,4 +113,55 @@ pub trait Transactable: ReadDoc {
 
     /// The heads this transaction will be based on
     fn base_heads(&self) -> Vec<ChangeHash>;
+
+    /// Set the value at `path` to `value`, see [`Self::put`]
+    ///
+    /// # Errors
+    ///
+    /// This will return an error if `path` is the root or if there is no object at the parent of
+    /// `path`. Use [`Self::create_path`] to create the objects along a path first.
+    fn put_path<V: Into<ScalarValue>>(
+        &mut self,
+        path: &Path,
+        value: V,
+    ) -> Result<(), AutomergeError> {
+        let (obj, prop) = path::parent(self, path)?;
+        self.put(obj, prop, value)
+    }
+
+    /// Set the value at `path` to a new object, see [`Self::put_object`]
+    ///
+    /// # Errors
+    ///
+    /// This will return an error if `path` is the root or if there is no object at the parent of
+    /// `path`.
+    fn put_object_path(&mut self, path: &Path, object: ObjType) -> Result<ExId, AutomergeError> {
+        let (obj, prop) = path::parent(self, path)?;
+        self.put_object(obj, prop, object)
+    }
+
+    /// Delete the value at `path`, see [`Self::delete`]
+    fn delete_path(&mut self, path: &Path) -> Result<(), AutomergeError> {
+        let (obj, prop) = path::parent(self, path)?;
+        self.delete(obj, prop)
+    }
+
+    /// Make sure there is an object of type `object` at `path`, creating it and any missing
+    /// objects along the way
+    ///
+    /// A missing object along the path is created as a list if the next prop in the path is an
+    /// index and as a map otherwise. In a list a missing object can only be created at the end of
+    /// the list.
+    ///
+    /// # Returns
+    ///
+    /// The id of the object at `path`, which is not replaced if it already exists.
+    ///
+    /// # Errors
+    ///
+    /// This will return an error if there is a scalar value along the path, if the object at
+    /// `path` is of a different type or if an index is past the end of a list.
+    fn create_path(&mut self, path: &Path, object: ObjType) -> Result<ExId, AutomergeError> {
+        path::create(self, path, object)
+    }
 }
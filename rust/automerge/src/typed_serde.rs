@@ -0,0 +1,534 @@
+use std::fmt;
+
+use base64::Engine;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+
+use crate::transaction::Transactable;
+use crate::{AutomergeError, ObjId, ObjType, Prop, ReadDoc, ScalarValue, Value};
+
+const COUNTER: &str = "$counter";
+const TIMESTAMP: &str = "$timestamp";
+const BYTES: &str = "$bytes";
+const UINT: &str = "$uint";
+const UNKNOWN: &str = "$unknown";
+const TEXT: &str = "$text";
+const TABLE: &str = "$table";
+
+/// A wrapper type which implements [`serde::Serialize`] for a [`ReadDoc`] without losing the
+/// automerge type of any value.
+///
+/// Unlike [`crate::AutoSerde`], which writes counters and timestamps as plain numbers and text
+/// as a list of characters, values which have no natural serde representation are written as a
+/// map with a single key naming the type:
+///
+/// | value       | representation                                        |
+/// |-------------|-------------------------------------------------------|
+/// | `Counter`   | `{"$counter": 5}`                                     |
+/// | `Timestamp` | `{"$timestamp": 1700000000000}` (milliseconds)        |
+/// | `Bytes`     | `{"$bytes": "AAH/"}` (standard base64)                |
+/// | `Uint`      | `{"$uint": 5}`                                        |
+/// | `Unknown`   | `{"$unknown": {"typeCode": 12, "bytes": "AAH/"}}`     |
+/// | text        | `{"$text": "hello"}`                                  |
+/// | table       | `{"$table": {"row": {...}}}`                          |
+///
+/// Maps are written as maps, lists as sequences and all other scalars as the corresponding serde
+/// primitive. To keep this unambiguous, map keys which start with `$` are escaped by doubling
+/// the `$`. The output can be read back with [`TypedDoc`].
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use automerge::{AutoCommit, ScalarValue, TypedSerde, transaction::Transactable};
+/// let mut doc = AutoCommit::new();
+/// doc.put(automerge::ROOT, "views", ScalarValue::counter(3))?;
+///
+/// let serialized = serde_json::to_string(&TypedSerde::from(&doc))?;
+///
+/// assert_eq!(serialized, r#"{"views":{"$counter":3}}"#);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TypedSerde<'a, R: ReadDoc>(&'a R);
+
+impl<'a, R: ReadDoc> From<&'a R> for TypedSerde<'a, R> {
+    fn from(doc: &'a R) -> Self {
+        TypedSerde(doc)
+    }
+}
+
+impl<'a, R: ReadDoc> serde::Serialize for TypedSerde<'a, R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        TypedSerdeMap {
+            doc: self.0,
+            obj: ObjId::Root,
+        }
+        .serialize(serializer)
+    }
+}
+
+struct TypedSerdeMap<'a, R> {
+    doc: &'a R,
+    obj: ObjId,
+}
+
+impl<'a, R: ReadDoc> serde::Serialize for TypedSerdeMap<'a, R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map_ser = serializer.serialize_map(Some(self.doc.length(&self.obj)))?;
+        for item in self.doc.map_range(&self.obj, ..) {
+            let key = if item.key.starts_with('$') {
+                format!("${}", item.key)
+            } else {
+                item.key.to_string()
+            };
+            let val = TypedSerdeVal {
+                doc: self.doc,
+                val: item.value,
+                obj: item.id,
+            };
+            map_ser.serialize_entry(&key, &val)?;
+        }
+        map_ser.end()
+    }
+}
+
+struct TypedSerdeSeq<'a, R> {
+    doc: &'a R,
+    obj: ObjId,
+}
+
+impl<'a, R: ReadDoc> serde::Serialize for TypedSerdeSeq<'a, R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut seq_ser = serializer.serialize_seq(Some(self.doc.length(&self.obj)))?;
+        for item in self.doc.list_range(&self.obj, ..) {
+            seq_ser.serialize_element(&TypedSerdeVal {
+                doc: self.doc,
+                val: item.value,
+                obj: item.id,
+            })?;
+        }
+        seq_ser.end()
+    }
+}
+
+struct TypedSerdeVal<'a, R> {
+    doc: &'a R,
+    val: Value<'a>,
+    obj: ObjId,
+}
+
+impl<'a, R: ReadDoc> serde::Serialize for TypedSerdeVal<'a, R> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let map = || TypedSerdeMap {
+            doc: self.doc,
+            obj: self.obj.clone(),
+        };
+        match &self.val {
+            Value::Object(ObjType::Map) => map().serialize(serializer),
+            Value::Object(ObjType::Table) => tagged(serializer, TABLE, &map()),
+            Value::Object(ObjType::List) => TypedSerdeSeq {
+                doc: self.doc,
+                obj: self.obj.clone(),
+            }
+            .serialize(serializer),
+            Value::Object(ObjType::Text) => {
+                // SAFETY: This only errors if the object ID is unknown, but we construct this
+                // type with a known real object ID
+                let text = self.doc.text(&self.obj).unwrap();
+                tagged(serializer, TEXT, &text)
+            }
+            Value::Scalar(v) => match v.as_ref() {
+                ScalarValue::Bytes(b) => tagged(serializer, BYTES, &encode(b)),
+                ScalarValue::Str(s) => serializer.serialize_str(s),
+                ScalarValue::Int(n) => serializer.serialize_i64(*n),
+                ScalarValue::Uint(n) => tagged(serializer, UINT, n),
+                ScalarValue::F64(n) => serializer.serialize_f64(*n),
+                ScalarValue::Counter(c) => tagged(serializer, COUNTER, &i64::from(c)),
+                ScalarValue::Timestamp(n) => tagged(serializer, TIMESTAMP, n),
+                ScalarValue::Boolean(b) => serializer.serialize_bool(*b),
+                ScalarValue::Unknown { type_code, bytes } => {
+                    let unknown = UnknownRepr {
+                        type_code: *type_code,
+                        bytes: encode(bytes),
+                    };
+                    tagged(serializer, UNKNOWN, &unknown)
+                }
+                ScalarValue::Null => serializer.serialize_unit(),
+            },
+        }
+    }
+}
+
+/// The representation of [`ScalarValue::Unknown`]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct UnknownRepr {
+    #[serde(rename = "typeCode")]
+    type_code: u8,
+    bytes: String,
+}
+
+fn tagged<S: serde::Serializer, T: serde::Serialize + ?Sized>(
+    serializer: S,
+    tag: &str,
+    value: &T,
+) -> Result<S::Ok, S::Error> {
+    let mut map_ser = serializer.serialize_map(Some(1))?;
+    map_ser.serialize_entry(tag, value)?;
+    map_ser.end()
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(bytes)
+}
+
+/// The state of a document as written by [`TypedSerde`], which implements
+/// [`serde::Deserialize`] and can be written into a document with [`TypedDoc::apply`].
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use automerge::{AutoCommit, ReadDoc, ScalarValue, TypedDoc, Value};
+/// let snapshot: TypedDoc = serde_json::from_str(r#"{"views": {"$counter": 3}}"#)?;
+///
+/// let mut doc = AutoCommit::new();
+/// snapshot.apply(&mut doc)?;
+///
+/// assert_eq!(
+///     doc.get(automerge::ROOT, "views")?.unwrap().0,
+///     Value::Scalar(std::borrow::Cow::Owned(ScalarValue::counter(3)))
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypedDoc(Vec<(String, Node)>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Map(Vec<(String, Node)>),
+    Table(Vec<(String, Node)>),
+    List(Vec<Node>),
+    Text(String),
+    Scalar(ScalarValue),
+}
+
+impl TypedDoc {
+    /// Make the root of the document the same as this snapshot
+    ///
+    /// Every key in the snapshot is put into the root map, replacing whatever was there, and
+    /// keys in the root which are not in the snapshot are deleted.
+    pub fn apply<T: Transactable>(&self, tx: &mut T) -> Result<(), AutomergeError> {
+        let stale = tx
+            .keys(ObjId::Root)
+            .filter(|key| !self.0.iter().any(|(k, _)| k == key))
+            .collect::<Vec<_>>();
+        for key in stale {
+            tx.delete(ObjId::Root, key)?;
+        }
+        for (key, node) in &self.0 {
+            node.put(tx, &ObjId::Root, key.as_str().into())?;
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    fn put<T: Transactable>(
+        &self,
+        tx: &mut T,
+        obj: &ObjId,
+        prop: Prop,
+    ) -> Result<(), AutomergeError> {
+        let obj_type = match self {
+            Node::Scalar(s) => return tx.put(obj, prop, s.clone()),
+            Node::Map(_) => ObjType::Map,
+            Node::Table(_) => ObjType::Table,
+            Node::List(_) => ObjType::List,
+            Node::Text(_) => ObjType::Text,
+        };
+        let id = tx.put_object(obj, prop, obj_type)?;
+        self.fill(tx, &id)
+    }
+
+    fn insert<T: Transactable>(
+        &self,
+        tx: &mut T,
+        obj: &ObjId,
+        index: usize,
+    ) -> Result<(), AutomergeError> {
+        let obj_type = match self {
+            Node::Scalar(s) => return tx.insert(obj, index, s.clone()),
+            Node::Map(_) => ObjType::Map,
+            Node::Table(_) => ObjType::Table,
+            Node::List(_) => ObjType::List,
+            Node::Text(_) => ObjType::Text,
+        };
+        let id = tx.insert_object(obj, index, obj_type)?;
+        self.fill(tx, &id)
+    }
+
+    /// Fill the newly created object `obj` with the contents of this node
+    fn fill<T: Transactable>(&self, tx: &mut T, obj: &ObjId) -> Result<(), AutomergeError> {
+        match self {
+            Node::Map(entries) | Node::Table(entries) => {
+                for (key, node) in entries {
+                    node.put(tx, obj, key.as_str().into())?;
+                }
+            }
+            Node::List(nodes) => {
+                for (index, node) in nodes.iter().enumerate() {
+                    node.insert(tx, obj, index)?;
+                }
+            }
+            Node::Text(text) => tx.splice_text(obj, 0, 0, text)?,
+            Node::Scalar(_) => {}
+        }
+        Ok(())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for TypedDoc {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match Node::deserialize(deserializer)? {
+            Node::Map(entries) => Ok(TypedDoc(entries)),
+            _ => Err(de::Error::custom("the root of a document must be a map")),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Node {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a value written by TypedSerde")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Node, E> {
+        Ok(Node::Scalar(ScalarValue::Boolean(v)))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Node, E> {
+        Ok(Node::Scalar(ScalarValue::Int(v)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Node, E> {
+        // Plain integers are signed unless they don't fit, unsigned integers are tagged
+        Ok(Node::Scalar(match i64::try_from(v) {
+            Ok(v) => ScalarValue::Int(v),
+            Err(_) => ScalarValue::Uint(v),
+        }))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Node, E> {
+        Ok(Node::Scalar(ScalarValue::F64(v)))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Node, E> {
+        Ok(Node::Scalar(ScalarValue::Str(v.into())))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Node, E> {
+        Ok(Node::Scalar(ScalarValue::Null))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Node, E> {
+        self.visit_unit()
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        let mut nodes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(node) = seq.next_element()? {
+            nodes.push(node);
+        }
+        Ok(Node::List(nodes))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let first = match map.next_key::<String>()? {
+            Some(key) => key,
+            None => return Ok(Node::Map(Vec::new())),
+        };
+        if first.starts_with('$') && !first.starts_with("$$") {
+            let node = match first.as_str() {
+                COUNTER => Node::Scalar(ScalarValue::counter(map.next_value()?)),
+                TIMESTAMP => Node::Scalar(ScalarValue::Timestamp(map.next_value()?)),
+                UINT => Node::Scalar(ScalarValue::Uint(map.next_value()?)),
+                BYTES => Node::Scalar(ScalarValue::Bytes(decode(map.next_value()?)?)),
+                UNKNOWN => {
+                    let unknown: UnknownRepr = map.next_value()?;
+                    Node::Scalar(ScalarValue::Unknown {
+                        type_code: unknown.type_code,
+                        bytes: decode(unknown.bytes)?,
+                    })
+                }
+                TEXT => Node::Text(map.next_value()?),
+                TABLE => match map.next_value()? {
+                    Node::Map(entries) => Node::Table(entries),
+                    _ => return Err(de::Error::custom("expected a map in a $table")),
+                },
+                other => {
+                    return Err(de::Error::custom(format!(
+                        "unknown type {}, map keys starting with $ must be escaped as $$",
+                        other
+                    )))
+                }
+            };
+            if map.next_key::<String>()?.is_some() {
+                return Err(de::Error::custom(format!(
+                    "{} must be the only key in its map",
+                    first
+                )));
+            }
+            return Ok(node);
+        }
+        let mut entries = vec![(unescape(first)?, map.next_value()?)];
+        while let Some(key) = map.next_key::<String>()? {
+            entries.push((unescape(key)?, map.next_value()?));
+        }
+        Ok(Node::Map(entries))
+    }
+}
+
+fn unescape<E: de::Error>(key: String) -> Result<String, E> {
+    if key.starts_with("$$") {
+        Ok(key[1..].to_string())
+    } else if key.starts_with('$') {
+        Err(E::custom(format!(
+            "{} must be the only key in its map, map keys starting with $ must be escaped as $$",
+            key
+        )))
+    } else {
+        Ok(key)
+    }
+}
+
+fn decode<E: de::Error>(encoded: String) -> Result<Vec<u8>, E> {
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(E::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AutoCommit, ROOT};
+
+    fn example() -> AutoCommit {
+        let mut doc = AutoCommit::new();
+        doc.put(ROOT, "views", ScalarValue::counter(3)).unwrap();
+        doc.put(ROOT, "at", ScalarValue::Timestamp(1_600_000_000_123))
+            .unwrap();
+        doc.put(ROOT, "data", ScalarValue::Bytes(vec![0, 1, 255]))
+            .unwrap();
+        doc.put(ROOT, "big", ScalarValue::Uint(7)).unwrap();
+        doc.put(ROOT, "ratio", 1.0).unwrap();
+        doc.put(ROOT, "$price", 3).unwrap();
+        doc.put(
+            ROOT,
+            "future",
+            ScalarValue::Unknown {
+                type_code: 12,
+                bytes: vec![1, 2],
+            },
+        )
+        .unwrap();
+        let note = doc.put_object(ROOT, "note", ObjType::Text).unwrap();
+        doc.splice_text(&note, 0, 0, "hello").unwrap();
+        let rows = doc.put_object(ROOT, "rows", ObjType::Table).unwrap();
+        let row = doc.put_object(&rows, "a", ObjType::Map).unwrap();
+        doc.put(&row, "done", false).unwrap();
+        let list = doc.put_object(ROOT, "list", ObjType::List).unwrap();
+        doc.insert(&list, 0, ScalarValue::Null).unwrap();
+        doc.insert(&list, 1, "x").unwrap();
+        doc
+    }
+
+    #[test]
+    fn serializes_types_explicitly() {
+        let doc = example();
+        let json = serde_json::to_value(TypedSerde::from(&doc)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "views": {"$counter": 3},
+                "at": {"$timestamp": 1_600_000_000_123_i64},
+                "data": {"$bytes": "AAH/"},
+                "big": {"$uint": 7},
+                "ratio": 1.0,
+                "$$price": 3,
+                "future": {"$unknown": {"typeCode": 12, "bytes": "AQI="}},
+                "note": {"$text": "hello"},
+                "rows": {"$table": {"a": {"done": false}}},
+                "list": [null, "x"],
+            })
+        );
+        assert!(serde_json::to_string(&TypedSerde::from(&doc))
+            .unwrap()
+            .contains(r#""ratio":1.0"#));
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let doc = example();
+        let json = serde_json::to_string(&TypedSerde::from(&doc)).unwrap();
+        let snapshot: TypedDoc = serde_json::from_str(&json).unwrap();
+        let mut restored = AutoCommit::new();
+        restored.put(ROOT, "stale", "gone").unwrap();
+        snapshot.apply(&mut restored).unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&TypedSerde::from(&restored)).unwrap(),
+            json
+        );
+        let reloaded = AutoCommit::load(&restored.save()).unwrap();
+        assert_eq!(
+            serde_json::to_string(&TypedSerde::from(&reloaded)).unwrap(),
+            json
+        );
+        assert_eq!(
+            restored.get(ROOT, "ratio").unwrap().unwrap().0,
+            Value::from(1.0)
+        );
+        assert_eq!(
+            restored.get(ROOT, "rows").unwrap().unwrap().0,
+            Value::Object(ObjType::Table)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_unescaped_keys() {
+        assert!(serde_json::from_str::<TypedDoc>(r#"{"a": {"$nope": 1}}"#).is_err());
+        assert!(serde_json::from_str::<TypedDoc>(r#"{"a": {"$counter": 1, "b": 2}}"#).is_err());
+        assert!(serde_json::from_str::<TypedDoc>(r#"{"a": {"b": 1, "$c": 2}}"#).is_err());
+        assert!(serde_json::from_str::<TypedDoc>(r#"[1]"#).is_err());
+    }
+}
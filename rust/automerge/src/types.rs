@@ -1,6 +1,6 @@
 use crate::error;
 use crate::legacy as amp;
-use crate::text_value::TextValue;
+use crate::text_value::TextEncoding;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::Eq;
@@ -580,7 +580,7 @@ impl ObjMeta {
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub(crate) enum ListEncoding {
     List,
-    Text,
+    Text(TextEncoding),
 }
 
 impl Default for ListEncoding {
@@ -589,20 +589,10 @@ impl Default for ListEncoding {
     }
 }
 
-impl From<Option<ObjType>> for ListEncoding {
-    fn from(obj: Option<ObjType>) -> Self {
-        if obj == Some(ObjType::Text) {
-            ListEncoding::Text
-        } else {
-            ListEncoding::List
-        }
-    }
-}
-
-impl From<ObjType> for ListEncoding {
-    fn from(obj: ObjType) -> Self {
+impl ListEncoding {
+    pub(crate) fn new(obj: ObjType, text_encoding: TextEncoding) -> Self {
         if obj == ObjType::Text {
-            ListEncoding::Text
+            ListEncoding::Text(text_encoding)
         } else {
             ListEncoding::List
         }
@@ -701,7 +691,7 @@ impl Op {
     pub(crate) fn width(&self, encoding: ListEncoding) -> usize {
         match encoding {
             ListEncoding::List => 1,
-            ListEncoding::Text => TextValue::width(self.to_str()),
+            ListEncoding::Text(encoding) => encoding.width(self.to_str()),
         }
     }
 
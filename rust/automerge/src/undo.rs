@@ -0,0 +1,952 @@
+//! Undo and redo of local changes
+//!
+//! An [`UndoManager`] wraps an [`AutoCommit`] and records an inverse of every local change which
+//! is committed through it. [`UndoManager::undo`] applies the inverse of the most recent group of
+//! changes as a new change, which in turn is recorded so that it can be redone with
+//! [`UndoManager::redo`].
+//!
+//! Inverses refer to the operations and list elements a change touched rather than to keys and
+//! indices, so undoing a change which has since been merged with concurrent changes from other
+//! actors only reverts what the local change did:
+//!
+//! * A value which has been overwritten by another actor is left alone
+//! * Elements inserted by the change are deleted wherever they now are, unless another actor has
+//!   already deleted them
+//! * Deleted elements are reinserted where they used to be, relative to the elements around them
+//! * Counter increments are reverted by incrementing by the opposite amount
+//! * Marks are removed and any marks with the same name which they replaced are restored
+//!
+//! ```
+//! # use automerge::{transaction::Transactable, undo::UndoManager, AutoCommit, ReadDoc, ROOT};
+//! let mut manager = UndoManager::new(AutoCommit::new());
+//! manager.doc_mut().put(ROOT, "title", "draft").unwrap();
+//! manager.commit();
+//! manager.doc_mut().put(ROOT, "title", "final").unwrap();
+//! manager.commit();
+//!
+//! manager.undo().unwrap();
+//! assert_eq!(manager.doc().get(ROOT, "title").unwrap().unwrap().0.to_str(), Some("draft"));
+//! manager.redo().unwrap();
+//! assert_eq!(manager.doc().get(ROOT, "title").unwrap().unwrap().0.to_str(), Some("final"));
+//! ```
+//!
+//! ## Undo groups
+//!
+//! Each call to [`UndoManager::commit_with`] ends an undo group, so everything in a commit is
+//! undone together. Changes made by our actor which were committed some other way, for example
+//! by [`AutoCommit::merge`] committing an open transaction, become a group of their own.
+//!
+//! Text editors usually want a run of typed characters to be undone as a whole rather than one
+//! character at a time. [`UndoManager::with_merge_typing`] merges a commit which only inserts
+//! text directly after the text inserted by the previous commit into the previous group.
+use std::collections::HashMap;
+
+use crate::exid::ExId;
+use crate::legacy::{self, ElementId, ObjectId};
+use crate::marks::{ExpandMark, Mark};
+use crate::transaction::{CommitOptions, Transactable};
+use crate::types::{Clock, ElemId, Key as InternalKey};
+use crate::{AutoCommit, Automerge, AutomergeError, ChangeHash, ObjType, Prop, ReadDoc};
+use crate::{ScalarValue, Value};
+
+/// Records local changes to an [`AutoCommit`] so that they can be undone and redone
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct UndoManager {
+    doc: AutoCommit,
+    undo: Vec<Group>,
+    redo: Vec<Group>,
+    /// The heads of the document when we last looked for local changes to record
+    seen: Vec<ChangeHash>,
+    /// Operations and elements which an undo or redo has recreated, and what recreated them
+    replaced: HashMap<ExId, ExId>,
+    merge_typing: bool,
+}
+
+/// The steps which are undone or redone together, in the order the changes were made
+type Group = Vec<Step>;
+
+#[derive(Debug, Clone)]
+struct Step {
+    /// The heads the change was made on top of, from which previous values are read
+    heads: Vec<ChangeHash>,
+    inverse: Vec<Inverse>,
+    typing: Option<Typing>,
+}
+
+/// A change which only inserted a run of characters into a text object
+#[derive(Debug, Clone)]
+struct Typing {
+    obj: ExId,
+    /// The element the first character was inserted after, `None` for the start of the text
+    after: Option<ExId>,
+    /// The last character inserted
+    last: ExId,
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Key(String),
+    Elem(ExId),
+}
+
+#[derive(Debug, Clone)]
+enum Inverse {
+    /// The operation `id` put a value, restore the previous value if `id` is still current
+    Restore { obj: ExId, target: Target, id: ExId },
+    /// A map key was deleted, restore the previous value if the key is still absent
+    Undelete { obj: ExId, key: String },
+    /// Elements were inserted, delete those which are still visible
+    Remove { obj: ExId, elems: Vec<ExId> },
+    /// Elements were deleted, insert their previous values where they used to be
+    Reinsert { obj: ExId, elems: Vec<ExId> },
+    /// The counter `counter` was incremented by `by`
+    Decrement {
+        obj: ExId,
+        target: Target,
+        counter: ExId,
+        by: i64,
+    },
+    /// A mark was added between the mark operations `begin` and `end`
+    Unmark {
+        obj: ExId,
+        name: String,
+        begin: ExId,
+        end: ExId,
+    },
+}
+
+impl UndoManager {
+    /// Start recording local changes made to `doc` from now on
+    pub fn new(mut doc: AutoCommit) -> Self {
+        let seen = doc.get_heads();
+        Self {
+            doc,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            seen,
+            replaced: HashMap::new(),
+            merge_typing: false,
+        }
+    }
+
+    /// Merge consecutive commits which type text into a single undo group
+    pub fn with_merge_typing(mut self, merge_typing: bool) -> Self {
+        self.merge_typing = merge_typing;
+        self
+    }
+
+    pub fn doc(&self) -> &AutoCommit {
+        &self.doc
+    }
+
+    /// The document being recorded
+    ///
+    /// Changes made through this are recorded when they are committed with [`Self::commit`] or
+    /// [`Self::commit_with`].
+    pub fn doc_mut(&mut self) -> &mut AutoCommit {
+        &mut self.doc
+    }
+
+    pub fn into_inner(self) -> AutoCommit {
+        self.doc
+    }
+
+    /// Commit any uncommitted changes as a new undo group
+    ///
+    /// Returns `None` if there were no operations to commit
+    pub fn commit(&mut self) -> Option<ChangeHash> {
+        self.commit_with(CommitOptions::default())
+    }
+
+    /// Commit any uncommitted changes as a new undo group with some options
+    ///
+    /// Returns `None` if there were no operations to commit
+    pub fn commit_with(&mut self, options: CommitOptions) -> Option<ChangeHash> {
+        let hash = self.doc.commit_with(options);
+        self.record_local_changes();
+        hash
+    }
+
+    pub fn can_undo(&mut self) -> bool {
+        self.record_local_changes();
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&mut self) -> bool {
+        self.record_local_changes();
+        !self.redo.is_empty()
+    }
+
+    /// Revert the most recent undo group
+    ///
+    /// Any uncommitted changes are committed first. Returns the hash of the change which reverts
+    /// the group, or `None` if there was nothing to undo or nothing in the group could be reverted
+    /// without overwriting changes made since by other actors.
+    pub fn undo(&mut self) -> Result<Option<ChangeHash>, AutomergeError> {
+        self.commit();
+        let group = match self.undo.pop() {
+            Some(group) => group,
+            None => return Ok(None),
+        };
+        match self.apply(&group) {
+            Ok(Some((hash, step))) => {
+                self.redo.push(vec![step]);
+                Ok(Some(hash))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.undo.push(group);
+                Err(e)
+            }
+        }
+    }
+
+    /// Reapply the most recently undone group
+    ///
+    /// Returns the hash of the change which reapplies the group, or `None` if there was nothing to
+    /// redo.
+    pub fn redo(&mut self) -> Result<Option<ChangeHash>, AutomergeError> {
+        self.commit();
+        let group = match self.redo.pop() {
+            Some(group) => group,
+            None => return Ok(None),
+        };
+        match self.apply(&group) {
+            Ok(Some((hash, step))) => {
+                self.undo.push(vec![step]);
+                Ok(Some(hash))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.redo.push(group);
+                Err(e)
+            }
+        }
+    }
+
+    /// Forget all recorded groups
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.replaced.clear();
+    }
+
+    /// Record every change made by our actor since we last looked, each as its own group
+    fn record_local_changes(&mut self) {
+        let heads = self.doc.get_heads();
+        if heads == self.seen {
+            return;
+        }
+        let actor = self.doc.get_actor().clone();
+        let hashes = self
+            .doc
+            .get_changes(&self.seen)
+            .into_iter()
+            .filter(|c| c.actor_id() == &actor)
+            .map(|c| c.hash())
+            .collect::<Vec<_>>();
+        self.seen = heads;
+        for hash in hashes {
+            if let Some(step) = self.record(hash) {
+                self.push(step);
+            }
+        }
+    }
+
+    fn push(&mut self, step: Step) {
+        self.redo.clear();
+        if self.merge_typing {
+            let last = self.undo.last_mut().and_then(|g| g.last());
+            if let (Some(prev), Some(next)) =
+                (last.and_then(|s| s.typing.as_ref()), step.typing.as_ref())
+            {
+                if prev.obj == next.obj && next.after.as_ref() == Some(&prev.last) {
+                    self.undo.last_mut().unwrap().push(step);
+                    return;
+                }
+            }
+        }
+        self.undo.push(vec![step]);
+    }
+
+    /// Work out the inverse of the change `hash`
+    fn record(&mut self, hash: ChangeHash) -> Option<Step> {
+        let change = self.doc.get_change_by_hash(&hash)?;
+        let heads = change.deps().to_vec();
+        let actor = change.actor_id().clone();
+        let start_op = change.start_op().get();
+        let expanded = change.decode();
+
+        let is_new = |id: &legacy::OpId| id.1 == actor && id.0 >= start_op;
+        let exid = |id: &legacy::OpId| ExId::Id(id.0, id.1.clone(), 0);
+        let mut inverse = Vec::new();
+        let mut mark_begin = None;
+        let mut inserted_text = true;
+        for (i, op) in expanded.operations.iter().enumerate() {
+            let id = ExId::Id(start_op + i as u64, actor.clone(), 0);
+            let obj = match &op.obj {
+                ObjectId::Root => ExId::Root,
+                // Objects created by this change are removed along with the operation which
+                // created them
+                ObjectId::Id(o) if is_new(o) => continue,
+                ObjectId::Id(o) => exid(o),
+            };
+            let target = match &op.key {
+                legacy::Key::Map(key) => Target::Key(key.to_string()),
+                legacy::Key::Seq(ElementId::Id(e)) if is_new(e) && !op.insert => continue,
+                legacy::Key::Seq(ElementId::Id(e)) => Target::Elem(exid(e)),
+                legacy::Key::Seq(ElementId::Head) if !op.insert => continue,
+                legacy::Key::Seq(ElementId::Head) => Target::Elem(ExId::Root),
+            };
+            inserted_text &= op.insert && matches!(op.action, legacy::OpType::Put(_));
+            match (&op.action, target) {
+                (legacy::OpType::MarkBegin(data), _) => {
+                    mark_begin = Some((data.name.to_string(), id));
+                }
+                (legacy::OpType::MarkEnd(_), _) => {
+                    if let Some((name, begin)) = mark_begin.take() {
+                        inverse.push(Inverse::Unmark {
+                            obj,
+                            name,
+                            begin,
+                            end: id,
+                        });
+                    }
+                }
+                (_, _) if op.insert => match inverse.last_mut() {
+                    Some(Inverse::Remove { obj: o, elems }) if *o == obj => elems.push(id),
+                    _ => inverse.push(Inverse::Remove {
+                        obj,
+                        elems: vec![id],
+                    }),
+                },
+                (legacy::OpType::Delete, Target::Key(key)) => {
+                    inverse.push(Inverse::Undelete { obj, key })
+                }
+                (legacy::OpType::Delete, Target::Elem(elem)) => match inverse.last_mut() {
+                    Some(Inverse::Reinsert { obj: o, elems }) if *o == obj => elems.push(elem),
+                    _ => inverse.push(Inverse::Reinsert {
+                        obj,
+                        elems: vec![elem],
+                    }),
+                },
+                (legacy::OpType::Increment(by), target) => {
+                    if let Some(counter) = op.pred.iter().next() {
+                        inverse.push(Inverse::Decrement {
+                            obj,
+                            target,
+                            counter: exid(counter),
+                            by: *by,
+                        })
+                    }
+                }
+                (_, target) => inverse.push(Inverse::Restore { obj, target, id }),
+            }
+        }
+
+        let typing = match (inserted_text, inverse.as_slice()) {
+            (true, [Inverse::Remove { obj, elems }])
+                if self.doc.object_type(obj) == Ok(ObjType::Text) =>
+            {
+                // Only a run of characters each inserted after the one before it is typing
+                let ops = &expanded.operations;
+                let consecutive = ops.windows(2).zip(elems.iter()).all(|(pair, prev)| {
+                    matches!(&pair[1].key, legacy::Key::Seq(ElementId::Id(e)) if exid(e) == *prev)
+                });
+                let after = match &ops[0].key {
+                    legacy::Key::Seq(ElementId::Id(e)) => Some(exid(e)),
+                    _ => None,
+                };
+                consecutive.then(|| Typing {
+                    obj: obj.clone(),
+                    after,
+                    last: elems[elems.len() - 1].clone(),
+                })
+            }
+            _ => None,
+        };
+        Some(Step {
+            heads,
+            inverse,
+            typing,
+        })
+    }
+
+    /// Apply the inverse of every step in `group` as a single change and record its inverse
+    fn apply(&mut self, group: &[Step]) -> Result<Option<(ChangeHash, Step)>, AutomergeError> {
+        let result = group.iter().rev().try_for_each(|step| {
+            step.inverse
+                .iter()
+                .rev()
+                .try_for_each(|inverse| self.invert(&step.heads, inverse))
+        });
+        if let Err(e) = result {
+            self.doc.rollback();
+            return Err(e);
+        }
+        let hash = self.doc.commit();
+        self.seen = self.doc.get_heads();
+        Ok(hash.and_then(|hash| Some((hash, self.record(hash)?))))
+    }
+
+    fn invert(&mut self, heads: &[ChangeHash], inverse: &Inverse) -> Result<(), AutomergeError> {
+        match inverse {
+            Inverse::Restore { obj, target, id } => {
+                let prop = match self.current_prop(obj, target)? {
+                    Some(prop) => prop,
+                    None => return Ok(()),
+                };
+                let current = self.doc.get(obj, prop.clone())?.map(|(_, current)| current);
+                if current != Some(self.resolve(id)) {
+                    return Ok(());
+                }
+                let before = match target {
+                    Target::Key(key) => Prop::Map(key.clone()),
+                    Target::Elem(elem) => match self.element(obj, elem, Some(heads))? {
+                        Some((index, true)) => Prop::Seq(index),
+                        _ => return Ok(()),
+                    },
+                };
+                match self.value_at(obj, before, heads)? {
+                    Some(value) => {
+                        let source = value.1.clone();
+                        self.put_value(obj, prop.clone(), heads, value)?;
+                        self.replaced_by(&[source], obj, prop)
+                    }
+                    None => self.doc.delete(obj, prop),
+                }
+            }
+            Inverse::Undelete { obj, key } => {
+                if self.doc.get(obj, key.as_str())?.is_some() {
+                    return Ok(());
+                }
+                match self.value_at(obj, key.as_str().into(), heads)? {
+                    Some(value) => {
+                        let source = value.1.clone();
+                        self.put_value(obj, key.as_str().into(), heads, value)?;
+                        self.replaced_by(&[source], obj, key.as_str().into())
+                    }
+                    None => Ok(()),
+                }
+            }
+            Inverse::Remove { obj, elems } => {
+                for elem in elems.iter().rev() {
+                    if let Some((index, true)) = self.element(obj, &self.resolve(elem), None)? {
+                        self.doc.delete(obj, index)?;
+                    }
+                }
+                Ok(())
+            }
+            Inverse::Reinsert { obj, elems } => {
+                let mut deleted = Vec::new();
+                for elem in elems {
+                    if let Some((_, true)) = self.element(obj, &self.resolve(elem), None)? {
+                        continue;
+                    }
+                    if let Some((before, true)) = self.element(obj, elem, Some(heads))? {
+                        deleted.push((before, elem));
+                    }
+                }
+                // Reinsert in document order so that each element is placed after the one
+                // reinserted before it
+                deleted.sort_by_key(|(before, _)| *before);
+                for (before, elem) in deleted {
+                    let index = match self.element(obj, elem, None)? {
+                        Some((index, _)) => index,
+                        None => continue,
+                    };
+                    if let Some(value) = self.value_at(obj, before.into(), heads)? {
+                        let source = value.1.clone();
+                        self.insert_value(obj, index, heads, value)?;
+                        self.replaced_by(&[elem.clone(), source], obj, index.into())?;
+                    }
+                }
+                Ok(())
+            }
+            Inverse::Decrement {
+                obj,
+                target,
+                counter,
+                by,
+            } => {
+                let prop = match self.current_prop(obj, target)? {
+                    Some(prop) => prop,
+                    None => return Ok(()),
+                };
+                match self.doc.get(obj, prop.clone())? {
+                    Some((Value::Scalar(s), id))
+                        if matches!(s.as_ref(), ScalarValue::Counter(_))
+                            && id == self.resolve(counter) =>
+                    {
+                        self.doc.increment(obj, prop, -by)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            Inverse::Unmark {
+                obj,
+                name,
+                begin,
+                end,
+            } => {
+                let (start, end) = match (
+                    self.element(obj, begin, None)?,
+                    self.element(obj, end, None)?,
+                ) {
+                    (Some((start, _)), Some((end, _))) if start < end => (start, end),
+                    _ => return Ok(()),
+                };
+                let mut previous = Vec::new();
+                for mark in self.doc.marks_at(obj, heads)? {
+                    if mark.name() == name && mark.start < mark.end {
+                        previous.push((mark.start, mark.end, mark.value().clone()));
+                    }
+                }
+                self.doc.unmark(obj, name, start, end, ExpandMark::None)?;
+                for (old_start, old_end, value) in previous {
+                    let first = self.doc.get_cursor(obj, old_start, Some(heads))?;
+                    let last = self.doc.get_cursor(obj, old_end - 1, Some(heads))?;
+                    let old_start = self.doc.get_cursor_position(obj, &first, None)?;
+                    let old_end = self.doc.get_cursor_position(obj, &last, None)? + 1;
+                    let (start, end) = (start.max(old_start), end.min(old_end));
+                    if start < end {
+                        let mark = Mark::new(name.clone(), value, start, end);
+                        self.doc.mark(obj, mark, ExpandMark::default())?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The current property of `target`, or `None` if it is a deleted element
+    fn current_prop(&self, obj: &ExId, target: &Target) -> Result<Option<Prop>, AutomergeError> {
+        match target {
+            Target::Key(key) => Ok(Some(Prop::Map(key.clone()))),
+            Target::Elem(elem) => Ok(match self.element(obj, &self.resolve(elem), None)? {
+                Some((index, true)) => Some(Prop::Seq(index)),
+                _ => None,
+            }),
+        }
+    }
+
+    /// The operation which has taken the place of `id` after it was undone and redone
+    fn resolve(&self, id: &ExId) -> ExId {
+        let mut id = id;
+        while let Some(next) = self.replaced.get(id) {
+            id = next;
+        }
+        id.clone()
+    }
+
+    /// Note that the values of `sources` have been recreated as the current value of `prop`
+    fn replaced_by(
+        &mut self,
+        sources: &[ExId],
+        obj: &ExId,
+        prop: Prop,
+    ) -> Result<(), AutomergeError> {
+        if let Some((_, new)) = self.doc.get(obj, prop)? {
+            for source in sources {
+                if *source != new {
+                    self.replaced.insert(source.clone(), new.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The index of `elem` in the sequence `obj` and whether it is visible
+    ///
+    /// The index of an element which is not visible is the index an element inserted in its place
+    /// would have.
+    fn element(
+        &self,
+        obj: &ExId,
+        elem: &ExId,
+        heads: Option<&[ChangeHash]>,
+    ) -> Result<Option<(usize, bool)>, AutomergeError> {
+        // Not `AutoCommit::document`, which would commit the inverse we are in the middle of
+        let doc = &self.doc.doc;
+        let clock = heads.map(|heads| doc.clock_at(heads));
+        element(doc, obj, elem, clock.as_ref())
+    }
+
+    fn value_at(
+        &self,
+        obj: &ExId,
+        prop: Prop,
+        heads: &[ChangeHash],
+    ) -> Result<Option<(Value<'static>, ExId)>, AutomergeError> {
+        Ok(self
+            .doc
+            .get_at(obj, prop, heads)?
+            .map(|(value, id)| (value.into_owned(), id)))
+    }
+
+    fn put_value(
+        &mut self,
+        obj: &ExId,
+        prop: Prop,
+        heads: &[ChangeHash],
+        (value, id): (Value<'static>, ExId),
+    ) -> Result<(), AutomergeError> {
+        match value {
+            Value::Object(typ) => {
+                let new_obj = self.doc.put_object(obj, prop, typ)?;
+                self.copy_object(&id, &new_obj, heads)
+            }
+            Value::Scalar(s) => self.doc.put(obj, prop, copy_scalar(s.into_owned())),
+        }
+    }
+
+    fn insert_value(
+        &mut self,
+        obj: &ExId,
+        index: usize,
+        heads: &[ChangeHash],
+        (value, id): (Value<'static>, ExId),
+    ) -> Result<(), AutomergeError> {
+        match value {
+            Value::Object(typ) => {
+                let new_obj = self.doc.insert_object(obj, index, typ)?;
+                self.copy_object(&id, &new_obj, heads)
+            }
+            Value::Scalar(s) => match s.into_owned() {
+                ScalarValue::Str(s) if self.doc.object_type(obj)? == ObjType::Text => {
+                    self.doc.splice_text(obj, index, 0, &s)
+                }
+                s => self.doc.insert(obj, index, copy_scalar(s)),
+            },
+        }
+    }
+
+    /// Recreate the contents of `obj` as of `heads` in the new object `new_obj`
+    fn copy_object(
+        &mut self,
+        obj: &ExId,
+        new_obj: &ExId,
+        heads: &[ChangeHash],
+    ) -> Result<(), AutomergeError> {
+        match self.doc.object_type(obj)? {
+            ObjType::Map | ObjType::Table => {
+                let entries = self
+                    .doc
+                    .map_range_at(obj, .., heads)
+                    .map(|item| (item.key.to_string(), item.value.into_owned(), item.id))
+                    .collect::<Vec<_>>();
+                for (key, value, id) in entries {
+                    self.put_value(new_obj, key.into(), heads, (value, id))?;
+                }
+            }
+            ObjType::List => {
+                let values = self
+                    .doc
+                    .list_range_at(obj, .., heads)
+                    .map(|item| (item.value.into_owned(), item.id))
+                    .collect::<Vec<_>>();
+                for (index, value) in values.into_iter().enumerate() {
+                    self.insert_value(new_obj, index, heads, value)?;
+                }
+            }
+            ObjType::Text => {
+                let text = self.doc.text_at(obj, heads)?;
+                self.doc.splice_text(new_obj, 0, 0, &text)?;
+                let marks = self
+                    .doc
+                    .marks_at(obj, heads)?
+                    .into_iter()
+                    .map(|m| Mark::new(m.name().to_string(), m.value().clone(), m.start, m.end))
+                    .collect::<Vec<_>>();
+                for mark in marks {
+                    self.doc.mark(new_obj, mark, ExpandMark::default())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A counter is restored with its value at the time rather than its initial value
+fn copy_scalar(value: ScalarValue) -> ScalarValue {
+    match value {
+        ScalarValue::Counter(c) => ScalarValue::counter(i64::from(&c)),
+        other => other,
+    }
+}
+
+fn element(
+    doc: &Automerge,
+    obj: &ExId,
+    elem: &ExId,
+    clock: Option<&Clock>,
+) -> Result<Option<(usize, bool)>, AutomergeError> {
+    let obj = doc.exid_to_obj(obj)?;
+    let id = doc.exid_to_opid(elem)?;
+    if clock.map(|c| !c.covers(&id)).unwrap_or(false) {
+        return Ok(None);
+    }
+    let found = match doc.ops().seek_opid(&obj.id, id, clock) {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let index = found.index;
+    let visible = doc
+        .ops()
+        .seek_ops_by_prop(&obj.id, index.into(), obj.encoding, clock)
+        .ops
+        .first()
+        .map(|op| op.elemid_or_key() == InternalKey::Seq(ElemId(id)))
+        .unwrap_or(false);
+    Ok(Some((index, visible)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marks::Mark;
+    use crate::{ActorId, ROOT};
+
+    fn manager(actor: &str) -> UndoManager {
+        UndoManager::new(AutoCommit::new().with_actor(ActorId::from(actor.as_bytes())))
+    }
+
+    fn get_str(doc: &AutoCommit, obj: &ExId, prop: impl Into<Prop>) -> Option<String> {
+        doc.get(obj, prop)
+            .unwrap()
+            .and_then(|(v, _)| v.to_str().map(String::from))
+    }
+
+    #[test]
+    fn undo_and_redo_map_puts_and_deletes() {
+        let mut m = manager("aa");
+        m.doc_mut().put(ROOT, "a", "one").unwrap();
+        m.doc_mut().put(ROOT, "b", "keep").unwrap();
+        m.commit();
+        m.doc_mut().put(ROOT, "a", "two").unwrap();
+        m.doc_mut().delete(ROOT, "b").unwrap();
+        m.commit();
+
+        m.undo().unwrap();
+        assert_eq!(get_str(m.doc(), &ROOT, "a").as_deref(), Some("one"));
+        assert_eq!(get_str(m.doc(), &ROOT, "b").as_deref(), Some("keep"));
+        m.undo().unwrap();
+        assert_eq!(m.doc().get(ROOT, "a").unwrap(), None);
+        assert!(!m.can_undo());
+
+        m.redo().unwrap();
+        m.redo().unwrap();
+        assert_eq!(get_str(m.doc(), &ROOT, "a").as_deref(), Some("two"));
+        assert_eq!(m.doc().get(ROOT, "b").unwrap(), None);
+        assert!(!m.can_redo());
+    }
+
+    #[test]
+    fn undo_restores_deleted_objects() {
+        let mut m = manager("aa");
+        let list = m.doc_mut().put_object(ROOT, "list", ObjType::List).unwrap();
+        m.doc_mut().insert(&list, 0, 1).unwrap();
+        let map = m.doc_mut().insert_object(&list, 1, ObjType::Map).unwrap();
+        m.doc_mut().put(&map, "x", ScalarValue::counter(3)).unwrap();
+        m.commit();
+        m.doc_mut().delete(&list, 1).unwrap();
+        m.commit();
+
+        m.undo().unwrap();
+        assert_eq!(m.doc().length(&list), 2);
+        let (_, restored) = m.doc().get(&list, 1).unwrap().unwrap();
+        assert_eq!(
+            m.doc().get(&restored, "x").unwrap().unwrap().0,
+            Value::counter(3)
+        );
+    }
+
+    #[test]
+    fn undo_text_splices() {
+        let mut m = manager("aa");
+        let text = m.doc_mut().put_object(ROOT, "text", ObjType::Text).unwrap();
+        m.doc_mut().splice_text(&text, 0, 0, "hello world").unwrap();
+        m.commit();
+        m.doc_mut().splice_text(&text, 5, 6, "").unwrap();
+        m.doc_mut().splice_text(&text, 0, 1, "J").unwrap();
+        m.commit();
+        assert_eq!(m.doc().text(&text).unwrap(), "Jello");
+
+        m.undo().unwrap();
+        assert_eq!(m.doc().text(&text).unwrap(), "hello world");
+        m.redo().unwrap();
+        assert_eq!(m.doc().text(&text).unwrap(), "Jello");
+    }
+
+    #[test]
+    fn undo_elements_recreated_by_an_earlier_undo() {
+        let mut m = manager("aa");
+        let list = m.doc_mut().put_object(ROOT, "list", ObjType::List).unwrap();
+        m.commit();
+        for (index, value) in ["a", "b", "c"].into_iter().enumerate() {
+            m.doc_mut().insert(&list, index, value).unwrap();
+        }
+        m.commit();
+        m.doc_mut().delete(&list, 1).unwrap();
+        m.commit();
+
+        m.undo().unwrap();
+        assert_eq!(get_str(m.doc(), &list, 1).as_deref(), Some("b"));
+        m.undo().unwrap();
+        assert_eq!(m.doc().length(&list), 0);
+        m.redo().unwrap();
+        m.redo().unwrap();
+        assert_eq!(m.doc().length(&list), 2);
+        assert_eq!(get_str(m.doc(), &list, 1).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn undo_counter_increments() {
+        let mut m = manager("aa");
+        m.doc_mut()
+            .put(ROOT, "count", ScalarValue::counter(1))
+            .unwrap();
+        m.commit();
+        m.doc_mut().increment(ROOT, "count", 5).unwrap();
+        m.commit();
+
+        let mut other = m.doc_mut().fork().with_actor(ActorId::from(b"bb"));
+        other.increment(ROOT, "count", 10).unwrap();
+        m.doc_mut().merge(&mut other).unwrap();
+
+        m.undo().unwrap();
+        assert_eq!(
+            m.doc().get(ROOT, "count").unwrap().unwrap().0,
+            Value::counter(11)
+        );
+    }
+
+    #[test]
+    fn undo_marks() {
+        let mut m = manager("aa");
+        let text = m.doc_mut().put_object(ROOT, "text", ObjType::Text).unwrap();
+        m.doc_mut().splice_text(&text, 0, 0, "hello world").unwrap();
+        m.doc_mut()
+            .mark(
+                &text,
+                Mark::new("bold".into(), true, 0, 5),
+                ExpandMark::None,
+            )
+            .unwrap();
+        m.commit();
+        m.doc_mut()
+            .mark(
+                &text,
+                Mark::new("bold".into(), false, 2, 8),
+                ExpandMark::None,
+            )
+            .unwrap();
+        m.commit();
+
+        m.undo().unwrap();
+        let marks = m.doc().marks(&text).unwrap();
+        assert_eq!(marks, vec![Mark::new("bold".into(), true, 0, 5)]);
+    }
+
+    #[test]
+    fn undo_does_not_clobber_concurrent_edits() {
+        let mut m = manager("aa");
+        let text = m.doc_mut().put_object(ROOT, "text", ObjType::Text).unwrap();
+        m.doc_mut().splice_text(&text, 0, 0, "world").unwrap();
+        m.doc_mut().put(ROOT, "title", "one").unwrap();
+        m.commit();
+        let mut other = m.doc_mut().fork().with_actor(ActorId::from(b"bb"));
+
+        m.doc_mut().splice_text(&text, 0, 0, "hello ").unwrap();
+        m.doc_mut().put(ROOT, "title", "two").unwrap();
+        m.doc_mut().put(ROOT, "subtitle", "local").unwrap();
+        m.commit();
+
+        other.splice_text(&text, 5, 0, "!").unwrap();
+        other.put(ROOT, "subtitle", "remote").unwrap();
+        other.commit();
+        m.doc_mut().merge(&mut other).unwrap();
+        let subtitle = get_str(m.doc(), &ROOT, "subtitle");
+
+        m.undo().unwrap();
+        assert_eq!(m.doc().text(&text).unwrap(), "world!");
+        assert_eq!(get_str(m.doc(), &ROOT, "title").as_deref(), Some("one"));
+        // Whichever value won the conflict is left alone if it is not ours
+        if subtitle.as_deref() == Some("remote") {
+            assert_eq!(
+                get_str(m.doc(), &ROOT, "subtitle").as_deref(),
+                Some("remote")
+            );
+        } else {
+            assert_eq!(m.doc().get(ROOT, "subtitle").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn undo_ignores_remote_changes() {
+        let mut m = manager("aa");
+        m.doc_mut().put(ROOT, "a", 1).unwrap();
+        m.commit();
+        let mut other = m.doc_mut().fork().with_actor(ActorId::from(b"bb"));
+        other.put(ROOT, "b", 2).unwrap();
+        other.commit();
+        m.doc_mut().merge(&mut other).unwrap();
+
+        m.undo().unwrap();
+        assert_eq!(m.doc().get(ROOT, "a").unwrap(), None);
+        assert!(m.doc().get(ROOT, "b").unwrap().is_some());
+        assert!(!m.can_undo());
+    }
+
+    #[test]
+    fn commits_are_undo_groups() {
+        let mut m = manager("aa");
+        let text = m.doc_mut().put_object(ROOT, "text", ObjType::Text).unwrap();
+        m.commit();
+        for c in ["a", "b", "c"] {
+            let len = m.doc().length(&text);
+            m.doc_mut().splice_text(&text, len, 0, c).unwrap();
+            m.commit();
+        }
+        m.undo().unwrap();
+        assert_eq!(m.doc().text(&text).unwrap(), "ab");
+    }
+
+    #[test]
+    fn merge_typing_into_one_group() {
+        let mut m = manager("aa").with_merge_typing(true);
+        let text = m.doc_mut().put_object(ROOT, "text", ObjType::Text).unwrap();
+        m.doc_mut().splice_text(&text, 0, 0, "hello").unwrap();
+        m.commit();
+        for c in [" ", "w", "o"] {
+            let len = m.doc().length(&text);
+            m.doc_mut().splice_text(&text, len, 0, c).unwrap();
+            m.commit();
+        }
+        // Typing somewhere else starts a new group
+        m.doc_mut().splice_text(&text, 0, 0, ">").unwrap();
+        m.commit();
+        assert_eq!(m.doc().text(&text).unwrap(), ">hello wo");
+
+        m.undo().unwrap();
+        assert_eq!(m.doc().text(&text).unwrap(), "hello wo");
+        m.undo().unwrap();
+        assert_eq!(m.doc().text(&text).unwrap(), "hello");
+        m.redo().unwrap();
+        assert_eq!(m.doc().text(&text).unwrap(), "hello wo");
+    }
+
+    #[test]
+    fn new_changes_clear_redo() {
+        let mut m = manager("aa");
+        m.doc_mut().put(ROOT, "a", 1).unwrap();
+        m.commit();
+        m.undo().unwrap();
+        assert!(m.can_redo());
+        m.doc_mut().put(ROOT, "b", 1).unwrap();
+        m.commit();
+        assert!(!m.can_redo());
+    }
+}